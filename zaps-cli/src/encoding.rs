@@ -0,0 +1,99 @@
+use std::fmt;
+use std::str::FromStr;
+
+use base64::{
+    engine::general_purpose::STANDARD,
+    Engine,
+};
+
+/// How frames are written in files and on the terminal
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Encoding {
+    Hex,
+    /// The bytes as they are on the wire
+    Raw,
+    Base64,
+}
+
+impl Encoding {
+    /// The frame held in the input. Whitespace is ignored in hex and base64; raw input is taken byte for byte.
+    pub fn decode(&self, input: &[u8]) -> Result<Vec<u8>, String> {
+        match self {
+            Encoding::Hex => hex::decode(strip_whitespace(input))
+                .map_err(|e| format!("invalid hex: {}", e)),
+            Encoding::Raw => Ok(input.to_vec()),
+            Encoding::Base64 => STANDARD.decode(strip_whitespace(input))
+                .map_err(|e| format!("invalid base64: {}", e)),
+        }
+    }
+
+    /// The frame ready for output, with a trailing newline unless raw
+    pub fn encode(&self, frame: &[u8]) -> Vec<u8> {
+        match self {
+            Encoding::Hex => format!("{}\n", hex::encode_upper(frame)).into_bytes(),
+            Encoding::Raw => frame.to_vec(),
+            Encoding::Base64 => format!("{}\n", STANDARD.encode(frame)).into_bytes(),
+        }
+    }
+}
+
+fn strip_whitespace(input: &[u8]) -> Vec<u8> {
+    input.iter()
+        .filter(|b| !b.is_ascii_whitespace())
+        .copied()
+        .collect()
+}
+
+impl fmt::Display for Encoding {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Encoding::Hex => write!(f, "hex"),
+            Encoding::Raw => write!(f, "raw"),
+            Encoding::Base64 => write!(f, "base64"),
+        }
+    }
+}
+
+impl FromStr for Encoding {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "hex" => Ok(Encoding::Hex),
+            "raw" => Ok(Encoding::Raw),
+            "base64" => Ok(Encoding::Base64),
+            _ => Err(format!("invalid encoding: {}", s)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    macro_rules! decode_tests {
+        ($($name:ident: $encoding:ident $input:literal => $expected:expr,)*) => {
+            $(
+                #[test]
+                fn $name() {
+                    assert_eq!($expected, Encoding::$encoding.decode($input));
+                }
+            )*
+        };
+    }
+
+    decode_tests!(
+        decode_hex: Hex b"30 32\n3030\n" => Ok(b"0200".to_vec()),
+        decode_hex_invalid: Hex b"3G" => Err("invalid hex: Invalid character 'G' at position 1".to_string()),
+        decode_raw: Raw b"0200" => Ok(b"0200".to_vec()),
+        decode_raw_binary: Raw b"02\x00\r\n" => Ok(b"02\x00\r\n".to_vec()),
+        decode_base64: Base64 b"MDIw\nMA==" => Ok(b"0200".to_vec()),
+    );
+
+    #[test]
+    fn encode() {
+        assert_eq!(b"30320A\n".to_vec(), Encoding::Hex.encode(b"02\n"));
+        assert_eq!(b"02\n".to_vec(), Encoding::Raw.encode(b"02\n"));
+        assert_eq!(b"MDIwMA==\n".to_vec(), Encoding::Base64.encode(b"0200"));
+    }
+}
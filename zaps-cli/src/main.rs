@@ -0,0 +1,332 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+use std::process;
+use std::str::FromStr;
+
+use clap::{Parser, Subcommand};
+use zaps::{
+    core::{
+        Parser as _,
+        Unparser,
+    },
+    iso8583::{
+        BinaryEncoding,
+        Iso8583Engine,
+        Message,
+        MTI_FIELD,
+        spec::{
+            DataType,
+            Field,
+            FieldType,
+            Spec,
+        },
+        translate::{
+            Mapping,
+            Translator,
+        },
+    },
+    util::Charset,
+};
+
+mod encoding;
+mod message;
+
+use encoding::Encoding;
+
+#[derive(Parser)]
+#[command(name = "zaps", about = "Parse, build and convert ISO8583 messages offline")]
+struct Args {
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Print a message field by field, or as JSON
+    Parse {
+        /// The spec file the message follows
+        #[arg(long)]
+        spec: PathBuf,
+        /// How the message is written
+        #[arg(long, default_value_t = Encoding::Hex)]
+        input: Encoding,
+        /// Print a `dump` or `json`, with sensitive fields masked either way
+        #[arg(long, default_value_t = Format::Dump)]
+        format: Format,
+        /// Write binary values in JSON as `hex` or `base64`
+        #[arg(long, default_value_t = BinaryEncoding::Hex)]
+        binary: BinaryEncoding,
+        /// The message, or stdin if not given
+        file: Option<PathBuf>,
+    },
+    /// Build a message from a JSON object or TOML table of field values, keyed by field number with the MTI as `mti`
+    Build {
+        #[arg(long)]
+        spec: PathBuf,
+        #[arg(long, default_value_t = Encoding::Hex)]
+        output: Encoding,
+        /// How binary values are written, `hex` or `base64`
+        #[arg(long, default_value_t = BinaryEncoding::Hex)]
+        binary: BinaryEncoding,
+        /// The field values, or stdin if not given
+        file: Option<PathBuf>,
+    },
+    /// Check a message against a spec, exiting non-zero if it doesn't follow it
+    Validate {
+        #[arg(long)]
+        spec: PathBuf,
+        #[arg(long, default_value_t = Encoding::Hex)]
+        input: Encoding,
+        file: Option<PathBuf>,
+    },
+    /// Rewrite a message for another spec, or with a different bitmap or charset
+    Convert {
+        /// The spec file the message follows
+        #[arg(long)]
+        spec: PathBuf,
+        /// The spec file to convert to, otherwise the same spec with any other changes asked for
+        #[arg(long)]
+        to_spec: Option<PathBuf>,
+        /// Write the bitmap in `ascii` hex or `binary`
+        #[arg(long)]
+        bitmap: Option<Bitmap>,
+        /// Write characters in `ascii` or `ebcdic`
+        #[arg(long)]
+        charset: Option<Charset>,
+        /// Map MTIs and fields between the specs with this mapping file
+        #[arg(long)]
+        mapping: Option<PathBuf>,
+        #[arg(long, default_value_t = Encoding::Hex)]
+        input: Encoding,
+        #[arg(long, default_value_t = Encoding::Hex)]
+        output: Encoding,
+        file: Option<PathBuf>,
+    },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Format {
+    Dump,
+    Json,
+}
+
+impl fmt::Display for Format {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Format::Dump => write!(f, "dump"),
+            Format::Json => write!(f, "json"),
+        }
+    }
+}
+
+impl FromStr for Format {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "dump" => Ok(Format::Dump),
+            "json" => Ok(Format::Json),
+            _ => Err(format!("invalid format: {}", s)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Bitmap {
+    Ascii,
+    Binary,
+}
+
+impl FromStr for Bitmap {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "ascii" => Ok(Bitmap::Ascii),
+            "binary" => Ok(Bitmap::Binary),
+            _ => Err(format!("invalid bitmap: {}", s)),
+        }
+    }
+}
+
+fn main() {
+    let args = Args::parse();
+
+    let result = match args.command {
+        Command::Parse{ spec, input, format, binary, file } => parse(&spec, input, format, binary, file.as_deref()),
+        Command::Build{ spec, output, binary, file } => build(&spec, output, binary, file.as_deref()),
+        Command::Validate{ spec, input, file } => validate(&spec, input, file.as_deref()),
+        Command::Convert{ spec, to_spec, bitmap, charset, mapping, input, output, file } => {
+            convert_specs(&spec, to_spec.as_deref(), bitmap, charset)
+                .and_then(|(from, to)| convert(from, to, mapping.as_deref(), input, output, file.as_deref()))
+        },
+    };
+
+    match result {
+        Ok(true) => {},
+        Ok(false) => process::exit(1),
+        Err(e) => {
+            eprintln!("zaps: {}", e);
+            process::exit(2);
+        },
+    }
+}
+
+fn load_spec(path: &Path) -> Result<Spec, String> {
+    Spec::load(path)
+        .map_err(|e| format!("unable to load spec {}: {}", path.display(), e))
+}
+
+/// The contents of the file, or stdin if there isn't one or it's `-`
+fn read_input(file: Option<&Path>) -> Result<Vec<u8>, String> {
+    match file {
+        Some(path) if path != Path::new("-") => fs::read(path)
+            .map_err(|e| format!("unable to read {}: {}", path.display(), e)),
+        _ => {
+            let mut input = vec![];
+            io::stdin().read_to_end(&mut input)
+                .map_err(|e| format!("unable to read stdin: {}", e))?;
+            Ok(input)
+        },
+    }
+}
+
+fn write_output(output: &[u8]) -> Result<(), String> {
+    io::stdout().write_all(output)
+        .map_err(|e| format!("unable to write output: {}", e))
+}
+
+fn read_message(engine: &Iso8583Engine, input: Encoding, file: Option<&Path>) -> Result<HashMap<u16, String>, String> {
+    let frame = input.decode(&read_input(file)?)?;
+    engine.parse(&frame)
+        .map_err(|e| format!("unable to parse message: {}", e))
+}
+
+fn parse(spec: &Path, input: Encoding, format: Format, binary: BinaryEncoding, file: Option<&Path>) -> Result<bool, String> {
+    let engine = Iso8583Engine::new(load_spec(spec)?);
+    let tokens = read_message(&engine, input, file)?;
+
+    match format {
+        Format::Dump => println!("{}", engine.dump(&tokens)),
+        Format::Json => {
+            let mut message = Message::from_tokens(engine.spec(), &tokens, binary);
+            message.mask(engine.spec());
+            let json = serde_json::to_string(&message)
+                .map_err(|e| format!("unable to write JSON: {}", e))?;
+            println!("{}", json);
+        },
+    }
+    Ok(true)
+}
+
+fn build(spec: &Path, output: Encoding, binary: BinaryEncoding, file: Option<&Path>) -> Result<bool, String> {
+    let engine = Iso8583Engine::new(load_spec(spec)?);
+    let input = String::from_utf8(read_input(file)?)
+        .map_err(|_e| "field values must be UTF-8".to_string())?;
+    let tokens = message::from_values(&input)?
+        .to_tokens(engine.spec(), binary)
+        .map_err(|e| e.to_string())?;
+
+    let mut frame = vec![];
+    engine.unparse(&tokens, &mut frame)
+        .map_err(|e| format!("unable to build message: {}", e))?;
+    write_output(&output.encode(&frame))?;
+    Ok(true)
+}
+
+/// Checks the message parses, that every value suits its data type and that nothing is left over after the last field
+fn validate(spec: &Path, input: Encoding, file: Option<&Path>) -> Result<bool, String> {
+    let engine = Iso8583Engine::new(load_spec(spec)?);
+    let frame = input.decode(&read_input(file)?)?;
+    let tokens = match engine.parse(&frame) {
+        Ok(tokens) => tokens,
+        Err(e) => {
+            println!("invalid: {}", e);
+            return Ok(false);
+        },
+    };
+
+    let mut problems = vec![];
+    let mti_spec = engine.spec().get_mti_spec(&tokens[&MTI_FIELD]);
+    let mut fields = tokens.keys()
+        .filter(|field| **field != MTI_FIELD && **field != 0)
+        .collect::<Vec<_>>();
+    fields.sort();
+    for field in fields {
+        let definition = mti_spec.and_then(|mti_spec| mti_spec.get(field));
+        if let Some(definition) = definition {
+            if !definition.data_type.is_valid(&tokens[field]) {
+                problems.push(format!("field {} is not {:?} data", field, definition.data_type));
+            }
+        }
+    }
+
+    let mut rebuilt = vec![];
+    match engine.unparse(&tokens, &mut rebuilt) {
+        Ok(()) if frame.len() > rebuilt.len() && frame.starts_with(&rebuilt) => {
+            problems.push(format!("{} bytes left over after the last field", frame.len() - rebuilt.len()));
+        },
+        Ok(()) if frame != rebuilt => problems.push("message doesn't rebuild to the same bytes".to_string()),
+        Ok(()) => {},
+        Err(e) => problems.push(format!("message doesn't rebuild: {}", e)),
+    }
+
+    if problems.is_empty() {
+        println!("valid");
+    }
+    for problem in &problems {
+        println!("invalid: {}", problem);
+    }
+    Ok(problems.is_empty())
+}
+
+/// The spec to convert from, and the spec to convert to with any other changes asked for
+fn convert_specs(spec: &Path, to_spec: Option<&Path>, bitmap: Option<Bitmap>, charset: Option<Charset>) -> Result<(Spec, Spec), String> {
+    let from_spec = load_spec(spec)?;
+    let mut to_spec = match to_spec {
+        Some(path) => load_spec(path)?,
+        None => from_spec.clone(),
+    };
+    if let Some(bitmap) = bitmap {
+        set_bitmaps(&mut to_spec, bitmap);
+    }
+    if let Some(charset) = charset {
+        to_spec.set_charset(charset);
+    }
+    Ok((from_spec, to_spec))
+}
+
+fn convert(from_spec: Spec, to_spec: Spec, mapping: Option<&Path>, input: Encoding, output: Encoding, file: Option<&Path>) -> Result<bool, String> {
+    let mapping = match mapping {
+        Some(path) => Mapping::load(path)
+            .map_err(|e| format!("unable to load mapping {}: {}", path.display(), e))?,
+        None => Mapping::new(),
+    };
+    let translator = Translator::new(Iso8583Engine::new(from_spec), Iso8583Engine::new(to_spec), mapping);
+
+    let payload = input.decode(&read_input(file)?)?;
+    let mut frame = vec![];
+    translator.translate(&payload, &mut frame)
+        .map_err(|e| format!("unable to convert message: {}", e))?;
+    write_output(&output.encode(&frame))?;
+    Ok(true)
+}
+
+/// Rewrites every bitmap in the spec with the same number of bits in the new form
+fn set_bitmaps(spec: &mut Spec, bitmap: Bitmap) {
+    let (ftype, data_type) = match bitmap {
+        Bitmap::Ascii => (FieldType::AsciiBitmap, DataType::Packed),
+        Bitmap::Binary => (FieldType::Bitmap, DataType::Binary),
+    };
+
+    for (mti, fields) in spec.get_mti_specs().clone() {
+        for (field_num, field) in fields {
+            if field.ftype.is_bitmap() {
+                spec.set_mti_field(&mti, field_num, Field::new(ftype.clone(), field.raw_size, data_type.clone()));
+            }
+        }
+    }
+}
@@ -0,0 +1,76 @@
+use std::collections::HashMap;
+
+use serde_json::Value;
+use zaps::iso8583::{
+    Message,
+    MTI_KEY,
+};
+
+/// Reads field values from a JSON object, or a TOML table if it doesn't look like JSON, keyed by field number with the
+/// MTI under `mti`. Values must be strings so that leading zeros aren't lost.
+pub fn from_values(input: &str) -> Result<Message, String> {
+    let values = if input.trim_start().starts_with('{') {
+        serde_json::from_str::<HashMap<String, Value>>(input)
+            .map_err(|e| format!("invalid JSON: {}", e))?
+            .into_iter()
+            .map(|(key, value)| match value {
+                Value::String(value) => Ok((key, value)),
+                value => Err(format!("field {} must be a string but was {}", key, value)),
+            })
+            .collect::<Result<Vec<_>, _>>()?
+    } else {
+        toml::from_str::<toml::Table>(input)
+            .map_err(|e| format!("invalid TOML: {}", e))?
+            .into_iter()
+            .map(|(key, value)| match value {
+                toml::Value::String(value) => Ok((key, value)),
+                value => Err(format!("field {} must be a string but was {}", key, value)),
+            })
+            .collect::<Result<Vec<_>, _>>()?
+    };
+
+    let mut message = Message::default();
+    for (key, value) in values {
+        match &key[..] {
+            MTI_KEY => message.mti = Some(value),
+            key => {
+                let field = key.parse::<u16>().map_err(|_e| format!("invalid field {}", key))?;
+                message.fields.insert(field, value);
+            },
+        }
+    }
+    Ok(message)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn message() -> Message {
+        Message {
+            mti: Some("0200".to_string()),
+            fields: [(2, "4111111111111111"), (11, "000001")]
+                .iter()
+                .map(|(k, v)| (*k, v.to_string()))
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn json() {
+        let values = from_values(r#"{"mti": "0200", "2": "4111111111111111", "11": "000001"}"#).unwrap();
+        assert_eq!(message(), values);
+    }
+
+    #[test]
+    fn toml() {
+        let values = from_values("mti = \"0200\"\n2 = \"4111111111111111\"\n11 = \"000001\"\n").unwrap();
+        assert_eq!(message(), values);
+    }
+
+    #[test]
+    fn errors() {
+        assert_eq!(Err("invalid field x".to_string()), from_values(r#"{"x": "1"}"#));
+        assert_eq!(Err("field 4 must be a string but was 1000".to_string()), from_values(r#"{"4": 1000}"#));
+    }
+}
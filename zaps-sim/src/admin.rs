@@ -0,0 +1,434 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::Arc;
+
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    routing::{get, post, put},
+    Json,
+    Router,
+};
+use serde::{Deserialize, Serialize};
+use tokio::net::TcpListener;
+use tracing::{error, info};
+use zaps::{
+    core::Unparser,
+    iso8583::MTI_FIELD,
+};
+
+use crate::{
+    ConnectionInfo,
+    Simulator,
+    SimulatorError,
+    faults::FaultProfile,
+    logging::MaskFields,
+    rules::{Rule, RuleSet},
+};
+
+/// The key for the MTI in JSON messages, as in scripts
+const MTI_KEY: &str = "mti";
+/// The key for a raw frame in JSON messages, sent as is in place of the fields
+const RAW_KEY: &str = "raw";
+
+/// An error for the API caller, returned as `{"error": "..."}`
+#[derive(Debug)]
+struct AdminError(StatusCode, String);
+
+impl AdminError {
+    fn bad_request<E: fmt::Display>(e: E) -> Self {
+        AdminError(StatusCode::BAD_REQUEST, e.to_string())
+    }
+}
+
+impl From<SimulatorError> for AdminError {
+    fn from(e: SimulatorError) -> Self {
+        let status = match e {
+            SimulatorError::NoConnection(_) => StatusCode::NOT_FOUND,
+            SimulatorError::UnknownProfile(_) | SimulatorError::InvalidMessage(_) => StatusCode::BAD_REQUEST,
+        };
+        AdminError(status, e.to_string())
+    }
+}
+
+#[derive(Serialize)]
+struct ErrorBody {
+    error: String,
+}
+
+impl IntoResponse for AdminError {
+    fn into_response(self) -> Response {
+        let AdminError(status, error) = self;
+        (status, Json(ErrorBody{ error })).into_response()
+    }
+}
+
+#[derive(Deserialize)]
+struct ProfileName {
+    profile: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct ProfileSettings {
+    settings: String,
+}
+
+#[derive(Serialize)]
+struct FaultsBody {
+    /// Settings by profile name
+    profiles: HashMap<String, String>,
+    /// Profile names by peer
+    connections: HashMap<String, String>,
+    default: Option<String>,
+}
+
+struct Admin<T, R> {
+    sim: Arc<Simulator<T, R>>,
+    rules: Arc<RuleSet>,
+}
+
+impl<T, R> Clone for Admin<T, R> {
+    fn clone(&self) -> Self {
+        Admin {
+            sim: self.sim.clone(),
+            rules: self.rules.clone(),
+        }
+    }
+}
+
+type AdminState<T, R> = State<Admin<T, R>>;
+
+/// Serves the admin API until the listener fails:
+///
+/// | Method | Path | |
+/// |---|---|---|
+/// | `GET` | `/connections` | List connections and their stats |
+/// | `GET` | `/connections/{id}` | Show a connection and its stats |
+/// | `DELETE` | `/connections/{id}` | Close a connection |
+/// | `PUT` | `/connections/{id}/fault` | Set the fault profile of a connection with `{"profile": "<name>"}`, or `null` for none |
+/// | `POST` | `/connections/{id}/messages` | Send an unsolicited message, as a map of field number to value with the MTI under `mti`, or `{"raw": "<frame>"}` |
+/// | `GET` | `/rules` | List the response rules |
+/// | `PUT` | `/rules` | Replace the response rules |
+/// | `POST` | `/rules` | Add a response rule after the others |
+/// | `GET` | `/faults` | List the fault profiles and which connections they apply to |
+/// | `PUT` | `/faults/profiles/{name}` | Add or replace a fault profile with `{"settings": "<settings>"}` |
+/// | `PUT` | `/faults/default` | Set the profile for new connections with `{"profile": "<name>"}`, or `null` for none |
+pub async fn serve<T, R>(addr: &str, sim: Arc<Simulator<T, R>>, rules: Arc<RuleSet>)
+where
+    T: 'static + Unparser<u16> + MaskFields<u16> + Send + Sync,
+    <T as Unparser<u16>>::Err: fmt::Display,
+    R: 'static + Send + Sync,
+{
+    let listener = TcpListener::bind(addr)
+        .await
+        .unwrap_or_else(|e| panic!("Unable to listen on {}: {}", addr, e));
+
+    info!("Admin API listening on {}", addr);
+
+    if let Err(e) = axum::serve(listener, router(sim, rules)).await {
+        error!("Admin API failed: {}", e);
+    }
+}
+
+fn router<T, R>(sim: Arc<Simulator<T, R>>, rules: Arc<RuleSet>) -> Router
+where
+    T: 'static + Unparser<u16> + MaskFields<u16> + Send + Sync,
+    <T as Unparser<u16>>::Err: fmt::Display,
+    R: 'static + Send + Sync,
+{
+    Router::new()
+        .route("/connections", get(list_connections))
+        .route("/connections/{id}", get(get_connection).delete(close_connection))
+        .route("/connections/{id}/fault", put(set_connection_fault))
+        .route("/connections/{id}/messages", post(inject_message))
+        .route("/rules", get(list_rules).put(set_rules).post(add_rule))
+        .route("/faults", get(list_faults))
+        .route("/faults/profiles/{name}", put(set_fault_profile))
+        .route("/faults/default", put(set_default_fault))
+        .with_state(Admin{ sim, rules })
+}
+
+async fn list_connections<T, R>(State(admin): AdminState<T, R>) -> Json<Vec<ConnectionInfo>> {
+    Json(admin.sim.connections())
+}
+
+async fn get_connection<T, R>(State(admin): AdminState<T, R>, Path(id): Path<u64>) -> Result<Json<ConnectionInfo>, AdminError> {
+    admin.sim.connection(id)
+        .map(Json)
+        .ok_or_else(|| SimulatorError::NoConnection(id).into())
+}
+
+async fn close_connection<T, R>(State(admin): AdminState<T, R>, Path(id): Path<u64>) -> Result<StatusCode, AdminError> {
+    admin.sim.close(id)?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+async fn set_connection_fault<T, R>(State(admin): AdminState<T, R>, Path(id): Path<u64>, Json(body): Json<ProfileName>) -> Result<StatusCode, AdminError> {
+    admin.sim.set_fault_profile(id, body.profile.as_deref())?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+async fn inject_message<T, R>(State(admin): AdminState<T, R>, Path(id): Path<u64>, Json(mut body): Json<HashMap<String, String>>) -> Result<StatusCode, AdminError>
+where
+    T: Unparser<u16> + MaskFields<u16>,
+    <T as Unparser<u16>>::Err: fmt::Display,
+{
+    if let Some(raw) = body.remove(RAW_KEY) {
+        admin.sim.inject_raw(id, raw.into_bytes())?;
+        return Ok(StatusCode::ACCEPTED);
+    }
+
+    let message = body.into_iter()
+        .map(|(key, value)| {
+            let field = match &key[..] {
+                MTI_KEY => MTI_FIELD,
+                key => key.parse::<u16>().map_err(|_e| AdminError::bad_request(format!("invalid field {}", key)))?,
+            };
+            Ok((field, value))
+        })
+        .collect::<Result<HashMap<_, _>, AdminError>>()?;
+    admin.sim.inject(id, &message)?;
+    Ok(StatusCode::ACCEPTED)
+}
+
+async fn list_rules<T, R>(State(admin): AdminState<T, R>) -> Json<Vec<Rule>> {
+    Json(admin.rules.rules())
+}
+
+async fn set_rules<T, R>(State(admin): AdminState<T, R>, Json(rules): Json<Vec<Rule>>) -> StatusCode {
+    admin.rules.set(rules);
+    StatusCode::NO_CONTENT
+}
+
+async fn add_rule<T, R>(State(admin): AdminState<T, R>, Json(rule): Json<Rule>) -> StatusCode {
+    admin.rules.push(rule);
+    StatusCode::NO_CONTENT
+}
+
+async fn list_faults<T, R>(State(admin): AdminState<T, R>) -> Json<FaultsBody> {
+    let faults = admin.sim.faults().read().unwrap();
+    Json(FaultsBody {
+        profiles: faults.profiles()
+            .iter()
+            .map(|(name, profile)| (name.clone(), profile.to_string()))
+            .collect(),
+        connections: faults.peers().clone(),
+        default: faults.default_profile().map(|name| name.to_string()),
+    })
+}
+
+async fn set_fault_profile<T, R>(State(admin): AdminState<T, R>, Path(name): Path<String>, Json(body): Json<ProfileSettings>) -> Result<StatusCode, AdminError> {
+    let profile = body.settings.parse::<FaultProfile>()
+        .map_err(AdminError::bad_request)?;
+    admin.sim.faults().write().unwrap().add_profile(&name, profile);
+    Ok(StatusCode::NO_CONTENT)
+}
+
+async fn set_default_fault<T, R>(State(admin): AdminState<T, R>, Json(body): Json<ProfileName>) -> Result<StatusCode, AdminError> {
+    admin.sim.faults().write().unwrap()
+        .set_default(body.profile.as_deref())
+        .map_err(AdminError::bad_request)?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+    use super::*;
+    use axum::{
+        body::{self, Body},
+        http::{Method, Request},
+    };
+    use serde_json::{json, Value};
+    use tokio::net::TcpStream;
+    use tower::ServiceExt;
+    use zaps::iso8583::{
+        spec::Spec,
+        Iso8583Engine,
+    };
+    use crate::{
+        framing::{FrameReader, Framing},
+        parse_frame,
+        responder::AutoResponder,
+        store::TransactionStore,
+    };
+
+    type Sim = Simulator<Iso8583Engine, AutoResponder>;
+
+    fn engine() -> Iso8583Engine {
+        let spec = "
+            field 0 AsciiBitmap(64)
+            field 11 Fixed(6:n)
+            field 39 Fixed(2:an)
+            mti 0800 0810
+        ".parse::<Spec>().unwrap();
+        Iso8583Engine::new(spec)
+    }
+
+    fn simulator() -> Arc<Sim> {
+        Arc::new(Simulator::new(engine(), AutoResponder::new(TransactionStore::new())))
+    }
+
+    /// A simulator with a client connected to it, and the connection's ID
+    async fn connected() -> (Arc<Sim>, u64, TcpStream) {
+        let sim = simulator();
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(sim.clone().accept::<u16>(listener));
+
+        let client = TcpStream::connect(addr).await.unwrap();
+        loop {
+            if let Some(connection) = sim.connections().first() {
+                return (sim.clone(), connection.id, client);
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+    }
+
+    /// The response's status and its JSON body, `null` if it has none, or a string if it isn't JSON like axum's own
+    /// rejections
+    async fn call(app: &Router, method: Method, uri: &str, body: Option<Value>) -> (StatusCode, Value) {
+        let request = Request::builder()
+            .method(method)
+            .uri(uri)
+            .header("content-type", "application/json")
+            .body(body.map(|body| Body::from(body.to_string())).unwrap_or_default())
+            .unwrap();
+        let response = app.clone().oneshot(request).await.unwrap();
+        let status = response.status();
+        let body = body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let body = match body.is_empty() {
+            true => Value::Null,
+            false => serde_json::from_slice(&body)
+                .unwrap_or_else(|_e| Value::String(String::from_utf8_lossy(&body).to_string())),
+        };
+        (status, body)
+    }
+
+    #[tokio::test]
+    async fn connections() {
+        let (sim, id, mut client) = connected().await;
+        let app = router(sim, Arc::new(RuleSet::new()));
+
+        let (status, body) = call(&app, Method::GET, "/connections", None).await;
+        assert_eq!(StatusCode::OK, status);
+        assert_eq!(vec![json!(id)], body.as_array().unwrap().iter().map(|connection| connection["id"].clone()).collect::<Vec<_>>());
+
+        let (status, body) = call(&app, Method::GET, &format!("/connections/{}", id), None).await;
+        assert_eq!(StatusCode::OK, status);
+        assert_eq!(json!(id), body["id"]);
+        assert_eq!(json!(client.local_addr().unwrap().to_string()), body["peer"]);
+
+        let (status, body) = call(&app, Method::GET, "/connections/99", None).await;
+        assert_eq!(StatusCode::NOT_FOUND, status);
+        assert!(body["error"].is_string());
+        let (status, _body) = call(&app, Method::GET, "/connections/first", None).await;
+        assert_eq!(StatusCode::BAD_REQUEST, status);
+
+        let (status, _body) = call(&app, Method::DELETE, "/connections/99", None).await;
+        assert_eq!(StatusCode::NOT_FOUND, status);
+        let (status, body) = call(&app, Method::DELETE, &format!("/connections/{}", id), None).await;
+        assert_eq!((StatusCode::NO_CONTENT, Value::Null), (status, body));
+        let closed = tokio::time::timeout(Duration::from_secs(1), FrameReader::new(&mut client, Framing::Newline).next()).await;
+        assert!(matches!(closed, Ok(Ok(None)) | Ok(Err(_))));
+    }
+
+    #[tokio::test]
+    async fn connection_fault() {
+        let (sim, id, _client) = connected().await;
+        let app = router(sim, Arc::new(RuleSet::new()));
+        let uri = format!("/connections/{}/fault", id);
+
+        let (status, body) = call(&app, Method::PUT, &uri, Some(json!({"profile": "flaky"}))).await;
+        assert_eq!(StatusCode::BAD_REQUEST, status);
+        assert!(body["error"].is_string());
+        let (status, _body) = call(&app, Method::PUT, "/connections/99/fault", Some(json!({"profile": null}))).await;
+        assert_eq!(StatusCode::NOT_FOUND, status);
+
+        call(&app, Method::PUT, "/faults/profiles/flaky", Some(json!({"settings": "drop=50"}))).await;
+        let (status, _body) = call(&app, Method::PUT, &uri, Some(json!({"profile": "flaky"}))).await;
+        assert_eq!(StatusCode::NO_CONTENT, status);
+        let (_status, body) = call(&app, Method::GET, &format!("/connections/{}", id), None).await;
+        assert_eq!(json!("flaky"), body["fault_profile"]);
+
+        let (status, _body) = call(&app, Method::PUT, &uri, Some(json!({"profile": null}))).await;
+        assert_eq!(StatusCode::NO_CONTENT, status);
+        let (_status, body) = call(&app, Method::GET, &format!("/connections/{}", id), None).await;
+        assert_eq!(Value::Null, body["fault_profile"]);
+    }
+
+    #[tokio::test]
+    async fn messages() {
+        let (sim, id, client) = connected().await;
+        let app = router(sim, Arc::new(RuleSet::new()));
+        let uri = format!("/connections/{}/messages", id);
+        let mut frames = FrameReader::new(client, Framing::Newline);
+
+        let (status, _body) = call(&app, Method::POST, &uri, Some(json!({"mti": "0800", "11": "000001"}))).await;
+        assert_eq!(StatusCode::ACCEPTED, status);
+        let message = parse_frame(&engine(), &frames.next().await.unwrap().unwrap()).unwrap().unwrap();
+        assert_eq!(("0800", "000001"), (&message[&MTI_FIELD][..], &message[&11][..]));
+
+        let (status, _body) = call(&app, Method::POST, &uri, Some(json!({"raw": "ping"}))).await;
+        assert_eq!(StatusCode::ACCEPTED, status);
+        assert_eq!(b"ping".to_vec(), frames.next().await.unwrap().unwrap());
+
+        let (status, body) = call(&app, Method::POST, &uri, Some(json!({"mti": "0800", "eleven": "000001"}))).await;
+        assert_eq!((StatusCode::BAD_REQUEST, json!({"error": "invalid field eleven"})), (status, body));
+        let (status, _body) = call(&app, Method::POST, &uri, Some(json!({"mti": "0800", "12": "000001"}))).await;
+        assert_eq!(StatusCode::BAD_REQUEST, status);
+        let (status, _body) = call(&app, Method::POST, "/connections/99/messages", Some(json!({"raw": "ping"}))).await;
+        assert_eq!(StatusCode::NOT_FOUND, status);
+    }
+
+    #[tokio::test]
+    async fn rules() {
+        let rules = Arc::new(RuleSet::new());
+        let app = router(simulator(), rules.clone());
+        let decline = json!({"mti": "0200", "fields": {}, "set": {"39": "51"}, "action": "respond"});
+        let drop = json!({"mti": null, "fields": {"4": "000000000000"}, "set": {}, "action": "drop"});
+
+        let (status, body) = call(&app, Method::GET, "/rules", None).await;
+        assert_eq!((StatusCode::OK, json!([])), (status, body));
+
+        let (status, _body) = call(&app, Method::PUT, "/rules", Some(json!([decline]))).await;
+        assert_eq!(StatusCode::NO_CONTENT, status);
+        let (status, _body) = call(&app, Method::POST, "/rules", Some(drop.clone())).await;
+        assert_eq!(StatusCode::NO_CONTENT, status);
+        let (status, body) = call(&app, Method::GET, "/rules", None).await;
+        assert_eq!((StatusCode::OK, json!([decline, drop])), (status, body));
+
+        let (status, _body) = call(&app, Method::PUT, "/rules", Some(json!([{"action": "explode"}]))).await;
+        assert_eq!(StatusCode::UNPROCESSABLE_ENTITY, status);
+        let (status, _body) = call(&app, Method::POST, "/rules", Some(json!({"action": "delay"}))).await;
+        assert_eq!(StatusCode::UNPROCESSABLE_ENTITY, status);
+        assert_eq!(2, rules.rules().len());
+    }
+
+    #[tokio::test]
+    async fn faults() {
+        let app = router(simulator(), Arc::new(RuleSet::new()));
+
+        let (status, body) = call(&app, Method::GET, "/faults", None).await;
+        assert_eq!((StatusCode::OK, json!({"profiles": {}, "connections": {}, "default": null})), (status, body));
+
+        let (status, body) = call(&app, Method::PUT, "/faults/profiles/flaky", Some(json!({"settings": "drop=101"}))).await;
+        assert_eq!(StatusCode::BAD_REQUEST, status);
+        assert!(body["error"].is_string());
+        let (status, _body) = call(&app, Method::PUT, "/faults/default", Some(json!({"profile": "flaky"}))).await;
+        assert_eq!(StatusCode::BAD_REQUEST, status);
+
+        let (status, _body) = call(&app, Method::PUT, "/faults/profiles/flaky", Some(json!({"settings": "drop=50"}))).await;
+        assert_eq!(StatusCode::NO_CONTENT, status);
+        let (status, _body) = call(&app, Method::PUT, "/faults/default", Some(json!({"profile": "flaky"}))).await;
+        assert_eq!(StatusCode::NO_CONTENT, status);
+        let (_status, body) = call(&app, Method::GET, "/faults", None).await;
+        assert_eq!(json!({"profiles": {"flaky": "drop=50"}, "connections": {}, "default": "flaky"}), body);
+
+        let (status, _body) = call(&app, Method::PUT, "/faults/default", Some(json!({"profile": null}))).await;
+        assert_eq!(StatusCode::NO_CONTENT, status);
+        let (_status, body) = call(&app, Method::GET, "/faults", None).await;
+        assert_eq!(Value::Null, body["default"]);
+    }
+}
@@ -0,0 +1,270 @@
+use std::error;
+use std::fmt;
+use std::io;
+use std::path::Path;
+use std::str::FromStr;
+use std::time::{
+    SystemTime,
+    UNIX_EPOCH,
+};
+
+use tokio::{
+    fs::{File, OpenOptions},
+    io::{AsyncBufReadExt, AsyncWriteExt, BufReader, BufWriter},
+    sync::mpsc,
+};
+use tracing::error;
+
+use crate::escape::unescape;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Direction {
+    Inbound,
+    Outbound,
+}
+
+impl fmt::Display for Direction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Inbound => write!(f, "in"),
+            Self::Outbound => write!(f, "out"),
+        }
+    }
+}
+
+impl FromStr for Direction {
+    type Err = CaptureParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "in" => Ok(Self::Inbound),
+            "out" => Ok(Self::Outbound),
+            _ => Err(CaptureParseError::InvalidDirection(s.to_string())),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub enum CaptureParseError {
+    MissingColumn(&'static str),
+    InvalidTimestamp(String),
+    InvalidConnection(String),
+    InvalidDirection(String),
+    InvalidRaw(String),
+    InvalidField(String),
+}
+
+impl fmt::Display for CaptureParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::MissingColumn(column) => write!(f, "missing column {}", column),
+            Self::InvalidTimestamp(ts) => write!(f, "invalid timestamp: {}", ts),
+            Self::InvalidConnection(conn) => write!(f, "invalid connection: {}", conn),
+            Self::InvalidDirection(dir) => write!(f, "invalid direction: {}", dir),
+            Self::InvalidRaw(raw) => write!(f, "invalid raw bytes: {}", raw),
+            Self::InvalidField(field) => write!(f, "invalid field: {}", field),
+        }
+    }
+}
+
+impl error::Error for CaptureParseError {}
+
+/// A single frame seen by the simulator.
+///
+/// Records are written one per line as tab separated columns:
+/// `<timestamp us> <connection> <in|out> <raw hex> [<field>=<value>...]`
+/// with field values escaped so they never contain tabs or newlines.
+#[derive(Debug, PartialEq)]
+pub struct CaptureRecord {
+    /// Microseconds since the unix epoch
+    pub timestamp: u64,
+    pub connection: u64,
+    pub direction: Direction,
+    pub raw: Vec<u8>,
+    pub fields: Vec<(String, String)>,
+}
+
+impl CaptureRecord {
+    pub fn new(connection: u64, direction: Direction, raw: &[u8], fields: Vec<(String, String)>) -> Self {
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_micros() as u64)
+            .unwrap_or(0);
+
+        CaptureRecord {
+            timestamp,
+            connection,
+            direction,
+            raw: raw.to_vec(),
+            fields,
+        }
+    }
+}
+
+impl fmt::Display for CaptureRecord {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}\t{}\t{}\t{}", self.timestamp, self.connection, self.direction, hex::encode(&self.raw))?;
+        for (field, value) in &self.fields {
+            write!(f, "\t{}={}", field, value.escape_default())?;
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for CaptureRecord {
+    type Err = CaptureParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut columns = s.split('\t');
+
+        let s_timestamp = columns.next()
+            .ok_or(CaptureParseError::MissingColumn("timestamp"))?;
+        let timestamp = s_timestamp.parse::<u64>()
+            .map_err(|_e| CaptureParseError::InvalidTimestamp(s_timestamp.to_string()))?;
+
+        let s_connection = columns.next()
+            .ok_or(CaptureParseError::MissingColumn("connection"))?;
+        let connection = s_connection.parse::<u64>()
+            .map_err(|_e| CaptureParseError::InvalidConnection(s_connection.to_string()))?;
+
+        let direction = columns.next()
+            .ok_or(CaptureParseError::MissingColumn("direction"))?
+            .parse::<Direction>()?;
+
+        let s_raw = columns.next()
+            .ok_or(CaptureParseError::MissingColumn("raw"))?;
+        let raw = hex::decode(s_raw)
+            .map_err(|_e| CaptureParseError::InvalidRaw(s_raw.to_string()))?;
+
+        let fields = columns
+            .map(|column| {
+                let (field, value) = column.split_once('=')
+                    .ok_or_else(|| CaptureParseError::InvalidField(column.to_string()))?;
+                let value = unescape(value)
+                    .ok_or_else(|| CaptureParseError::InvalidField(column.to_string()))?;
+                Ok((field.to_string(), value))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        Ok(CaptureRecord {
+            timestamp,
+            connection,
+            direction,
+            raw,
+            fields,
+        })
+    }
+}
+
+/// Handle for appending records to a capture file.
+///
+/// Records are handed off to a background task so recording never blocks a connection.
+#[derive(Clone)]
+pub struct Capture {
+    tx: mpsc::UnboundedSender<CaptureRecord>,
+}
+
+impl Capture {
+    pub async fn create<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .await?;
+        let (tx, mut rx) = mpsc::unbounded_channel::<CaptureRecord>();
+
+        tokio::spawn(async move {
+            let mut writer = BufWriter::new(file);
+            while let Some(record) = rx.recv().await {
+                let line = format!("{}\n", record);
+                if let Err(e) = writer.write_all(line.as_bytes()).await {
+                    error!("Unable to write capture record: {}", e);
+                    break;
+                }
+                if rx.is_empty() {
+                    let _ = writer.flush().await;
+                }
+            }
+            let _ = writer.flush().await;
+        });
+
+        Ok(Capture {
+            tx,
+        })
+    }
+
+    pub fn record(&self, connection: u64, direction: Direction, raw: &[u8], fields: Vec<(String, String)>) {
+        // the writer only goes away on an IO error which has already been reported
+        let _ = self.tx.send(CaptureRecord::new(connection, direction, raw, fields));
+    }
+}
+
+pub async fn read_capture<P: AsRef<Path>>(path: P) -> io::Result<Vec<CaptureRecord>> {
+    let file = File::open(path).await?;
+    let mut lines = BufReader::new(file).lines();
+    let mut records = Vec::new();
+
+    while let Some(line) = lines.next_line().await? {
+        if line.is_empty() {
+            continue;
+        }
+        let record = line.parse::<CaptureRecord>()
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        records.push(record);
+    }
+
+    Ok(records)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn record_round_trip() {
+        let record = CaptureRecord {
+            timestamp: 1634567890123456,
+            connection: 3,
+            direction: Direction::Inbound,
+            raw: b"iso8583:020081003ABC0123456789abcde".to_vec(),
+            fields: vec![
+                ("1".to_string(), "A\tB\\C\n".to_string()),
+                ("8".to_string(), "0123456789abcde".to_string()),
+                ("9".to_string(), "\u{1}=é".to_string()),
+            ],
+        };
+
+        let line = record.to_string();
+        assert!(!line.contains('\n'));
+        assert_eq!(record, line.parse::<CaptureRecord>().unwrap());
+    }
+
+    #[test]
+    fn record_without_fields() {
+        let record = "12\t1\tout\t414243".parse::<CaptureRecord>().unwrap();
+        assert_eq!(Direction::Outbound, record.direction);
+        assert_eq!(b"ABC".to_vec(), record.raw);
+        assert!(record.fields.is_empty());
+    }
+
+    macro_rules! parse_error_tests {
+        ($($name:ident: $str:expr => $expect_err:expr,)*) => {
+            $(
+                #[test]
+                fn $name() {
+                    assert_eq!(Err($expect_err), $str.parse::<CaptureRecord>());
+                }
+            )*
+        };
+    }
+
+    parse_error_tests! {
+        error_timestamp: "abc\t1\tin\t00" => CaptureParseError::InvalidTimestamp("abc".to_string()),
+        error_connection: "1\tx\tin\t00" => CaptureParseError::InvalidConnection("x".to_string()),
+        error_direction: "1\t1\tsideways\t00" => CaptureParseError::InvalidDirection("sideways".to_string()),
+        error_missing_raw: "1\t1\tin" => CaptureParseError::MissingColumn("raw"),
+        error_raw: "1\t1\tin\t0g" => CaptureParseError::InvalidRaw("0g".to_string()),
+        error_field: "1\t1\tin\t00\tnoequals" => CaptureParseError::InvalidField("noequals".to_string()),
+        error_field_escape: "1\t1\tin\t00\t1=\\q" => CaptureParseError::InvalidField("1=\\q".to_string()),
+    }
+}
@@ -0,0 +1,511 @@
+use std::collections::HashMap;
+use std::error;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::str::FromStr;
+use zaps::{
+    card::{
+        is_expired,
+        is_valid_expiry,
+        is_valid_pan,
+        BinTable,
+        Clock,
+        Track1,
+        Track2,
+    },
+    crypto::{
+        pin::{self, PinFormat},
+        Key,
+    },
+    util::string_to_bytes,
+};
+
+use crate::responder::FIELD_PAN;
+
+pub const FIELD_EXPIRY: u16 = 14;
+pub const FIELD_TRACK_2: u16 = 35;
+pub const FIELD_TRACK_1: u16 = 45;
+
+pub const RC_INVALID_CARD: &str = "14";
+pub const RC_LOST_CARD: &str = "41";
+pub const RC_STOLEN_CARD: &str = "43";
+pub const RC_INSUFFICIENT_FUNDS: &str = "51";
+pub const RC_EXPIRED_CARD: &str = "54";
+pub const RC_INCORRECT_PIN: &str = "55";
+pub const RC_NOT_PERMITTED: &str = "57";
+pub const RC_EXCEEDS_LIMIT: &str = "61";
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CardStatus {
+    Active,
+    Lost,
+    Stolen,
+}
+
+impl fmt::Display for CardStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(self, f)
+    }
+}
+
+impl FromStr for CardStatus {
+    type Err = CardParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match &s.to_ascii_lowercase()[..] {
+            "active" => Ok(Self::Active),
+            "lost" => Ok(Self::Lost),
+            "stolen" => Ok(Self::Stolen),
+            _ => Err(CardParseError::InvalidStatus(s.to_string())),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub enum CardParseError {
+    MissingColumn(&'static str),
+    InvalidPan(String),
+    InvalidExpiry(String),
+    InvalidStatus(String),
+    InvalidAmount(String),
+    InvalidCurrency(String),
+    /// Not 4 to 12 digits, the PIN itself is left out of the error
+    InvalidPin,
+    JunkTrail(String),
+}
+
+impl fmt::Display for CardParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::MissingColumn(column) => write!(f, "missing column {}", column),
+            Self::InvalidPan(pan) => write!(f, "invalid PAN: {}", pan),
+            Self::InvalidExpiry(expiry) => write!(f, "invalid expiry, must be YYMM: {}", expiry),
+            Self::InvalidStatus(status) => write!(f, "invalid status: {}", status),
+            Self::InvalidAmount(amount) => write!(f, "invalid amount: {}", amount),
+            Self::InvalidCurrency(currency) => write!(f, "invalid currency: {}", currency),
+            Self::InvalidPin => write!(f, "invalid PIN, must be 4 to 12 digits"),
+            Self::JunkTrail(junk) => write!(f, "unexpected trailing columns: {}", junk),
+        }
+    }
+}
+
+impl error::Error for CardParseError {}
+
+/// A card and the account behind it.
+///
+/// Amounts are in minor units of the currency.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Card {
+    pub pan: String,
+    /// YYMM
+    pub expiry: String,
+    pub status: CardStatus,
+    pub balance: i64,
+    pub currency: String,
+    pub daily_limit: i64,
+    /// Requests with a PIN block are checked against this, if set
+    pub pin: Option<String>,
+    spent: i64,
+    spent_date: String,
+}
+
+impl Card {
+    pub fn new(pan: &str, expiry: &str, status: CardStatus, balance: i64, currency: &str, daily_limit: i64) -> Self {
+        Card {
+            pan: pan.to_string(),
+            expiry: expiry.to_string(),
+            status,
+            balance,
+            currency: currency.to_string(),
+            daily_limit,
+            pin: None,
+            spent: 0,
+            spent_date: String::new(),
+        }
+    }
+
+    pub fn with_pin(mut self, pin: &str) -> Self {
+        self.pin = Some(pin.to_string());
+        self
+    }
+
+    /// Amount spent against the daily limit on `date`
+    pub fn spent(&self, date: &str) -> i64 {
+        if self.spent_date == date {
+            self.spent
+        } else {
+            0
+        }
+    }
+}
+
+/// Parses a line of the card file: `pan,expiry,status,balance,currency,daily_limit[,pin]`
+impl FromStr for Card {
+    type Err = CardParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut columns = s.split(',').map(|c| c.trim());
+        let mut next = |name| columns.next()
+            .filter(|c| !c.is_empty())
+            .ok_or(CardParseError::MissingColumn(name));
+
+        let pan = next("pan")?;
+        if pan.len() < 12 || pan.len() > 19 || !pan.bytes().all(|b| b.is_ascii_digit()) {
+            return Err(CardParseError::InvalidPan(pan.to_string()));
+        }
+
+        let expiry = next("expiry")?;
+        if !is_valid_expiry(expiry) {
+            return Err(CardParseError::InvalidExpiry(expiry.to_string()));
+        }
+
+        let status = next("status")?.parse::<CardStatus>()?;
+
+        let s_balance = next("balance")?;
+        let balance = s_balance.parse::<i64>()
+            .map_err(|_e| CardParseError::InvalidAmount(s_balance.to_string()))?;
+
+        let currency = next("currency")?;
+        if currency.len() != 3 {
+            return Err(CardParseError::InvalidCurrency(currency.to_string()));
+        }
+
+        let s_daily_limit = next("daily_limit")?;
+        let daily_limit = s_daily_limit.parse::<i64>()
+            .map_err(|_e| CardParseError::InvalidAmount(s_daily_limit.to_string()))?;
+
+        let pin = columns.next().filter(|c| !c.is_empty());
+        if let Some(pin) = pin {
+            if pin.len() < 4 || pin.len() > 12 || !pin.bytes().all(|b| b.is_ascii_digit()) {
+                return Err(CardParseError::InvalidPin);
+            }
+        }
+
+        let junk_trail = columns.collect::<Vec<_>>().join(",");
+        if !junk_trail.is_empty() {
+            return Err(CardParseError::JunkTrail(junk_trail));
+        }
+
+        let card = Card::new(pan, expiry, status, balance, currency, daily_limit);
+        Ok(match pin {
+            Some(pin) => card.with_pin(pin),
+            None => card,
+        })
+    }
+}
+
+/// The cards the auto-responder authorises against, keyed by PAN.
+#[derive(Debug, Default)]
+pub struct CardDatabase {
+    cards: HashMap<String, Card>,
+}
+
+impl CardDatabase {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Loads a card file, one card per line with blank lines and lines starting `#` ignored.
+    pub fn load<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let mut db = Self::new();
+
+        for (num, line) in fs::read_to_string(path)?.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let card = line.parse::<Card>()
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, format!("line {}: {}", num + 1, e)))?;
+            db.add(card);
+        }
+
+        Ok(db)
+    }
+
+    pub fn add(&mut self, card: Card) {
+        self.cards.insert(card.pan.clone(), card);
+    }
+
+    pub fn get(&self, pan: &str) -> Option<&Card> {
+        self.cards.get(pan)
+    }
+
+    /// Checks the PIN in a PIN block from field 52 against the card's, declining with 55 if it doesn't decrypt under
+    /// the key or doesn't match. Cards without a PIN accept any block.
+    pub fn verify_pin(&self, pan: &str, pin_block: &str, key: &Key, format: PinFormat) -> Result<(), &'static str> {
+        let card = self.cards.get(pan)
+            .ok_or(RC_INVALID_CARD)?;
+        let expected = match &card.pin {
+            Some(expected) => expected,
+            None => return Ok(()),
+        };
+
+        let pin = string_to_bytes(pin_block)
+            .and_then(|block| pin::decrypt(key, format, &block, pan).ok());
+        match pin {
+            Some(pin) if &pin == expected => Ok(()),
+            _ => Err(RC_INCORRECT_PIN),
+        }
+    }
+
+    /// Checks the currency of a request, from field 49, is the card's, declining with 57 if it isn't
+    pub fn verify_currency(&self, pan: &str, currency: &str) -> Result<(), &'static str> {
+        let card = self.cards.get(pan)
+            .ok_or(RC_INVALID_CARD)?;
+        if card.currency != currency {
+            return Err(RC_NOT_PERMITTED);
+        }
+        Ok(())
+    }
+
+    /// Checks a debit of `amount` against the card, returning the decline response code if it should not be
+    /// approved. On approval the balance and daily spend are updated.
+    ///
+    /// `date` identifies the day the daily limit applies to and `today` is the current YYMM for expiry checks.
+    pub fn debit(&mut self, pan: &str, amount: i64, date: &str, today: &str) -> Result<(), &'static str> {
+        let card = self.cards.get_mut(pan)
+            .ok_or(RC_INVALID_CARD)?;
+
+        match card.status {
+            CardStatus::Lost => return Err(RC_LOST_CARD),
+            CardStatus::Stolen => return Err(RC_STOLEN_CARD),
+            CardStatus::Active => (),
+        }
+        if card.expiry.as_str() < today {
+            return Err(RC_EXPIRED_CARD);
+        }
+        if amount > card.balance {
+            return Err(RC_INSUFFICIENT_FUNDS);
+        }
+        let spent = card.spent(date);
+        if spent + amount > card.daily_limit {
+            return Err(RC_EXCEEDS_LIMIT);
+        }
+
+        card.balance -= amount;
+        card.spent = spent + amount;
+        card.spent_date = date.to_string();
+        Ok(())
+    }
+
+    /// Returns a previously debited amount e.g. on reversal, including against the daily limit for `date`. A negative
+    /// amount debits the card without any checks e.g. when a completion exceeds its pre-authorisation.
+    pub fn credit(&mut self, pan: &str, amount: i64, date: &str) {
+        if let Some(card) = self.cards.get_mut(pan) {
+            card.balance += amount;
+            if card.spent_date == date {
+                card.spent = (card.spent - amount).max(0);
+            }
+        }
+    }
+}
+
+/// The PAN of the card in a request, from field 2 or else the track data
+pub fn request_pan(request: &HashMap<u16, String>) -> Option<String> {
+    request.get(&FIELD_PAN).cloned()
+        .or_else(|| track_data(request).map(|(pan, _expiry)| pan))
+}
+
+/// The expiry of the card in a request, from field 14 or else the track data
+pub fn request_expiry(request: &HashMap<u16, String>) -> Option<String> {
+    request.get(&FIELD_EXPIRY).cloned()
+        .or_else(|| track_data(request).map(|(_pan, expiry)| expiry))
+}
+
+/// The card's PAN and expiry from track 2 equivalent data (field 35) or else track 1 (field 45)
+fn track_data(request: &HashMap<u16, String>) -> Option<(String, String)> {
+    if let Some(track) = request.get(&FIELD_TRACK_2) {
+        return track.parse::<Track2>().ok()
+            .map(|track| (track.pan, track.expiry));
+    }
+    request.get(&FIELD_TRACK_1)?
+        .parse::<Track1>().ok()
+        .map(|track| (track.pan, track.expiry))
+}
+
+/// Checks of the card in a request which don't need the card database: requests are declined with 14 when their
+/// PAN, from field 2 or the track data, is missing, fails the Luhn check or isn't in any of the BIN ranges, and with 54
+/// when the expiry, from field 14 or the track data, has passed.
+#[derive(Debug, Clone, Default)]
+pub struct CardChecks {
+    bins: Option<BinTable>,
+}
+
+impl CardChecks {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Only accept cards in these BIN ranges
+    pub fn with_bins(mut self, bins: BinTable) -> Self {
+        self.bins = Some(bins);
+        self
+    }
+
+    pub fn check(&self, request: &HashMap<u16, String>, clock: &dyn Clock) -> Result<(), &'static str> {
+        let has_track = request.contains_key(&FIELD_TRACK_2) || request.contains_key(&FIELD_TRACK_1);
+        let track = track_data(request);
+        if has_track && track.is_none() {
+            return Err(RC_INVALID_CARD);
+        }
+
+        let pan = request.get(&FIELD_PAN)
+            .or(track.as_ref().map(|(pan, _expiry)| pan))
+            .ok_or(RC_INVALID_CARD)?;
+        if !is_valid_pan(pan) {
+            return Err(RC_INVALID_CARD);
+        }
+        if let Some(bins) = &self.bins {
+            bins.lookup(pan).ok_or(RC_INVALID_CARD)?;
+        }
+
+        let expiry = request.get(&FIELD_EXPIRY)
+            .or(track.as_ref().map(|(_pan, expiry)| expiry));
+        match expiry {
+            Some(expiry) if is_expired(expiry, clock) => Err(RC_EXPIRED_CARD),
+            _ => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use zaps::card::{
+        BinRange,
+        FixedClock,
+    };
+
+    const PAN: &str = "4111111111111111";
+
+    fn db(status: CardStatus, expiry: &str) -> CardDatabase {
+        let mut db = CardDatabase::new();
+        db.add(Card::new(PAN, expiry, status, 10_000, "826", 5_000));
+        db
+    }
+
+    #[test]
+    fn parse_card() {
+        let card = " 4111111111111111, 2912 ,Lost,100000,826,50000".parse::<Card>().unwrap();
+        assert_eq!(Card::new(PAN, "2912", CardStatus::Lost, 100_000, "826", 50_000), card);
+    }
+
+    macro_rules! parse_error_tests {
+        ($($name:ident: $str:expr => $expect_err:expr,)*) => {
+            $(
+                #[test]
+                fn $name() {
+                    assert_eq!(Err($expect_err), $str.parse::<Card>());
+                }
+            )*
+        };
+    }
+
+    parse_error_tests! {
+        error_pan: "41111111x1111111,2912,active,1,826,1" => CardParseError::InvalidPan("41111111x1111111".to_string()),
+        error_expiry: "4111111111111111,2913,active,1,826,1" => CardParseError::InvalidExpiry("2913".to_string()),
+        error_status: "4111111111111111,2912,frozen,1,826,1" => CardParseError::InvalidStatus("frozen".to_string()),
+        error_balance: "4111111111111111,2912,active,lots,826,1" => CardParseError::InvalidAmount("lots".to_string()),
+        error_currency: "4111111111111111,2912,active,1,GBPX,1" => CardParseError::InvalidCurrency("GBPX".to_string()),
+        error_missing: "4111111111111111,2912,active,1,826" => CardParseError::MissingColumn("daily_limit"),
+        error_pin: "4111111111111111,2912,active,1,826,1,12x4" => CardParseError::InvalidPin,
+        error_junk: "4111111111111111,2912,active,1,826,1,1234,x" => CardParseError::JunkTrail("x".to_string()),
+    }
+
+    #[test]
+    fn parse_card_with_pin() {
+        let card = "4111111111111111,2912,active,100000,826,50000,1234".parse::<Card>().unwrap();
+        assert_eq!(Card::new(PAN, "2912", CardStatus::Active, 100_000, "826", 50_000).with_pin("1234"), card);
+    }
+
+    #[test]
+    fn verify_pin() {
+        let key = "tdes:0123456789ABCDEFFEDCBA9876543210".parse::<Key>().unwrap();
+        let block = |pin| {
+            let block = pin::encrypt(&key, PinFormat::Iso0, pin, PAN).unwrap();
+            block.iter().map(|b| *b as char).collect::<String>()
+        };
+        let mut db = CardDatabase::new();
+        db.add(Card::new(PAN, "2912", CardStatus::Active, 10_000, "826", 5_000).with_pin("1234"));
+        db.add(Card::new("4000000000000002", "2912", CardStatus::Active, 10_000, "826", 5_000));
+
+        assert_eq!(Ok(()), db.verify_pin(PAN, &block("1234"), &key, PinFormat::Iso0));
+        assert_eq!(Err(RC_INCORRECT_PIN), db.verify_pin(PAN, &block("4321"), &key, PinFormat::Iso0));
+        assert_eq!(Err(RC_INCORRECT_PIN), db.verify_pin(PAN, "not a block", &key, PinFormat::Iso0));
+        assert_eq!(Ok(()), db.verify_pin("4000000000000002", &block("4321"), &key, PinFormat::Iso0));
+        assert_eq!(Err(RC_INVALID_CARD), db.verify_pin("4000000000000028", &block("1234"), &key, PinFormat::Iso0));
+    }
+
+    #[test]
+    fn currency_checks() {
+        let db = db(CardStatus::Active, "2912");
+        assert_eq!(Ok(()), db.verify_currency(PAN, "826"));
+        assert_eq!(Err(RC_NOT_PERMITTED), db.verify_currency(PAN, "978"));
+        assert_eq!(Err(RC_INVALID_CARD), db.verify_currency("4000000000000002", "826"));
+    }
+
+    #[test]
+    fn debit_decisions() {
+        assert_eq!(Err(RC_INVALID_CARD), db(CardStatus::Active, "2912").debit("4000000000000002", 1, "1019", "2610"));
+        assert_eq!(Err(RC_LOST_CARD), db(CardStatus::Lost, "2912").debit(PAN, 1, "1019", "2610"));
+        assert_eq!(Err(RC_STOLEN_CARD), db(CardStatus::Stolen, "2912").debit(PAN, 1, "1019", "2610"));
+        assert_eq!(Err(RC_EXPIRED_CARD), db(CardStatus::Active, "2609").debit(PAN, 1, "1019", "2610"));
+        assert_eq!(Ok(()), db(CardStatus::Active, "2610").debit(PAN, 1, "1019", "2610"));
+        assert_eq!(Err(RC_INSUFFICIENT_FUNDS), db(CardStatus::Active, "2912").debit(PAN, 10_001, "1019", "2610"));
+        assert_eq!(Err(RC_EXCEEDS_LIMIT), db(CardStatus::Active, "2912").debit(PAN, 5_001, "1019", "2610"));
+    }
+
+    #[test]
+    fn balance_and_limit_tracking() {
+        let mut db = db(CardStatus::Active, "2912");
+
+        assert_eq!(Ok(()), db.debit(PAN, 3_000, "1019", "2610"));
+        assert_eq!(Err(RC_EXCEEDS_LIMIT), db.debit(PAN, 3_000, "1019", "2610"));
+        assert_eq!(7_000, db.get(PAN).unwrap().balance);
+
+        db.credit(PAN, 3_000, "1019");
+        assert_eq!(10_000, db.get(PAN).unwrap().balance);
+        assert_eq!(Ok(()), db.debit(PAN, 5_000, "1019", "2610"));
+
+        // a new day resets the limit but not the balance
+        assert_eq!(Ok(()), db.debit(PAN, 5_000, "1020", "2610"));
+        assert_eq!(Err(RC_INSUFFICIENT_FUNDS), db.debit(PAN, 1, "1021", "2610"));
+    }
+
+    #[test]
+    fn card_checks() {
+        let clock = FixedClock("2610".to_string());
+        let checks = CardChecks::new();
+        let check = |checks: &CardChecks, fields: &[(u16, &str)]| {
+            let request = fields.iter().map(|(k, v)| (*k, v.to_string())).collect::<HashMap<_, _>>();
+            checks.check(&request, &clock)
+        };
+
+        assert_eq!(Ok(()), check(&checks, &[(2, PAN)]));
+        assert_eq!(Ok(()), check(&checks, &[(2, PAN), (14, "2610")]));
+        assert_eq!(Err(RC_EXPIRED_CARD), check(&checks, &[(2, PAN), (14, "2609")]));
+        assert_eq!(Err(RC_INVALID_CARD), check(&checks, &[(2, "4111111111111112")]));
+        assert_eq!(Err(RC_INVALID_CARD), check(&checks, &[(4, "000000001000")]));
+        assert_eq!(Ok(()), check(&checks, &[(35, "4111111111111111=2912101")]));
+        assert_eq!(Err(RC_EXPIRED_CARD), check(&checks, &[(35, "4111111111111111=2512101")]));
+        assert_eq!(Err(RC_EXPIRED_CARD), check(&checks, &[(45, "B4111111111111111^SMITH/JOHN^2512101")]));
+        assert_eq!(Err(RC_INVALID_CARD), check(&checks, &[(2, PAN), (35, "not a track")]));
+
+        let mut bins = BinTable::new();
+        bins.add(BinRange::new("411111", "411111", "test"));
+        let checks = checks.with_bins(bins);
+        assert_eq!(Ok(()), check(&checks, &[(2, PAN)]));
+        assert_eq!(Err(RC_INVALID_CARD), check(&checks, &[(2, "4000000000000002")]));
+    }
+
+    #[test]
+    fn request_cards() {
+        let request = |fields: &[(u16, &str)]| fields.iter().map(|(k, v)| (*k, v.to_string())).collect::<HashMap<_, _>>();
+        assert_eq!(Some(PAN.to_string()), request_pan(&request(&[(2, PAN), (35, "4000000000000002=2912101")])));
+        assert_eq!(Some(PAN.to_string()), request_pan(&request(&[(35, "4111111111111111=2912101")])));
+        assert_eq!(None, request_pan(&request(&[(4, "000000001000")])));
+        assert_eq!(Some("2912".to_string()), request_expiry(&request(&[(45, "B4111111111111111^SMITH/JOHN^2912101")])));
+    }
+}
@@ -0,0 +1,245 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::str::FromStr;
+
+use zaps::{
+    card::Track2,
+    crypto::{
+        cvv::{
+            self,
+            CvvError,
+            CVV2_SERVICE_CODE,
+            CVV_LENGTH,
+            ICVV_SERVICE_CODE,
+        },
+        Key,
+    },
+    iso8583::spec::{
+        Mask,
+        Spec,
+    },
+};
+
+use crate::cards::{
+    request_expiry,
+    request_pan,
+    FIELD_TRACK_2,
+};
+use crate::emv::FIELD_ICC_DATA;
+
+pub const FIELD_POS_ENTRY_MODE: u16 = 22;
+
+/// Declines for a CVV or iCVV in the track data which isn't the card's
+pub const RC_CVV_FAILED: &str = "82";
+/// Declines for a CVV2 which isn't the card's
+pub const RC_CVV2_FAILED: &str = "N7";
+
+/// Where the CVV is in track 2 discretionary data, after the PIN verification key index and the PVV
+pub const DEFAULT_TRACK_CVV_OFFSET: usize = 5;
+
+/// POS entry modes for data read from a chip, contact or contactless
+const CHIP_ENTRY_MODES: [&str; 2] = ["05", "07"];
+
+/// Where a value is in a request: a whole field e.g. `126`, or a subelement of a field made of `<2 digit tag><2 digit
+/// length><value>` e.g. `48.92`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FieldLocation {
+    pub field: u16,
+    pub tag: Option<String>,
+}
+
+impl FieldLocation {
+    pub fn find<'a>(&self, request: &'a HashMap<u16, String>) -> Option<&'a str> {
+        let value = request.get(&self.field)?;
+        let tag = match &self.tag {
+            Some(tag) => tag,
+            None => return Some(value),
+        };
+
+        let mut rest = &value[..];
+        while let (Some(element_tag), Some(length)) = (rest.get(..2), rest.get(2..4)) {
+            let length = length.parse::<usize>().ok()?;
+            let element = rest.get(4..4 + length)?;
+            if element_tag == tag {
+                return Some(element);
+            }
+            rest = &rest[4 + length..];
+        }
+        None
+    }
+
+    /// Hides the value in logs and echoes of messages in the spec. Masks apply to whole fields, so all of the field
+    /// holding a subelement is redacted.
+    pub fn redact(&self, spec: &mut Spec) {
+        spec.set_mask(self.field, Some(Mask::Redact));
+    }
+}
+
+impl fmt::Display for FieldLocation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.tag {
+            Some(tag) => write!(f, "{}.{}", self.field, tag),
+            None => write!(f, "{}", self.field),
+        }
+    }
+}
+
+impl FromStr for FieldLocation {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (field, tag) = match s.split_once('.') {
+            Some((field, tag)) => (field, Some(tag)),
+            None => (s, None),
+        };
+        let field = field.parse::<u16>()
+            .map_err(|_e| format!("invalid field: {}", field))?;
+        if let Some(tag) = tag {
+            if tag.len() != 2 || !tag.bytes().all(|b| b.is_ascii_digit()) {
+                return Err(format!("invalid subelement tag, must be 2 digits: {}", tag));
+            }
+        }
+        Ok(FieldLocation {
+            field,
+            tag: tag.map(|tag| tag.to_string()),
+        })
+    }
+}
+
+/// Checks card verification values under a test CVK pair. The CVV in track 2 data (field 35) is checked as an iCVV for
+/// chip transactions, those with ICC data or a chip POS entry mode, and declined with 82 if it isn't the card's. A
+/// CVV2 in the configured field is checked against the PAN and expiry and declined with N7.
+#[derive(Debug, Clone)]
+pub struct CvvChecks {
+    cvk: Key,
+    track_offset: usize,
+    cvv2: Option<FieldLocation>,
+}
+
+impl CvvChecks {
+    pub fn new(cvk: Key) -> Result<Self, CvvError> {
+        // any card will do to check the key
+        cvv::generate(&cvk, "4111111111111111", "2912", CVV2_SERVICE_CODE)?;
+        Ok(CvvChecks {
+            cvk,
+            track_offset: DEFAULT_TRACK_CVV_OFFSET,
+            cvv2: None,
+        })
+    }
+
+    /// Where the CVV is in track 2 discretionary data
+    pub fn with_track_offset(mut self, offset: usize) -> Self {
+        self.track_offset = offset;
+        self
+    }
+
+    /// Where requests carry the CVV2
+    pub fn with_cvv2(mut self, location: FieldLocation) -> Self {
+        self.cvv2 = Some(location);
+        self
+    }
+
+    pub fn check(&self, request: &HashMap<u16, String>) -> Result<(), &'static str> {
+        if let Some(track) = request.get(&FIELD_TRACK_2) {
+            let track = track.parse::<Track2>()
+                .map_err(|_e| RC_CVV_FAILED)?;
+            let found = track.discretionary_data.get(self.track_offset..self.track_offset + CVV_LENGTH)
+                .ok_or(RC_CVV_FAILED)?;
+            let service_code = match is_chip(request) {
+                true => ICVV_SERVICE_CODE,
+                false => track.service_code.as_str(),
+            };
+            self.verify(&track.pan, &track.expiry, service_code, found, RC_CVV_FAILED)?;
+        }
+
+        if let Some(found) = self.cvv2.as_ref().and_then(|location| location.find(request)) {
+            let pan = request_pan(request).ok_or(RC_CVV2_FAILED)?;
+            let expiry = request_expiry(request).ok_or(RC_CVV2_FAILED)?;
+            self.verify(&pan, &expiry, CVV2_SERVICE_CODE, found, RC_CVV2_FAILED)?;
+        }
+        Ok(())
+    }
+
+    fn verify(&self, pan: &str, expiry: &str, service_code: &str, found: &str, response_code: &'static str) -> Result<(), &'static str> {
+        match cvv::verify(&self.cvk, pan, expiry, service_code, found) {
+            Ok(true) => Ok(()),
+            _ => Err(response_code),
+        }
+    }
+}
+
+fn is_chip(request: &HashMap<u16, String>) -> bool {
+    request.contains_key(&FIELD_ICC_DATA)
+        || request.get(&FIELD_POS_ENTRY_MODE)
+            .and_then(|mode| mode.get(..2))
+            .is_some_and(|mode| CHIP_ENTRY_MODES.contains(&mode))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn request(fields: &[(u16, &str)]) -> HashMap<u16, String> {
+        fields.iter()
+            .map(|(k, v)| (*k, v.to_string()))
+            .collect()
+    }
+
+    fn checks() -> CvvChecks {
+        CvvChecks::new("tdes:0123456789ABCDEFFEDCBA9876543210".parse().unwrap()).unwrap()
+            .with_cvv2("48.92".parse().unwrap())
+    }
+
+    #[test]
+    fn field_locations() {
+        let fields = request(&[(48, "0103ABC9203719"), (126, "719")]);
+        assert_eq!(Some("719"), "48.92".parse::<FieldLocation>().unwrap().find(&fields));
+        assert_eq!(Some("ABC"), "48.01".parse::<FieldLocation>().unwrap().find(&fields));
+        assert_eq!(None, "48.93".parse::<FieldLocation>().unwrap().find(&fields));
+        assert_eq!(Some("719"), "126".parse::<FieldLocation>().unwrap().find(&fields));
+        assert_eq!(None, "48.92".parse::<FieldLocation>().unwrap().find(&request(&[(48, "9205719")])));
+        assert_eq!("48.92", "48.92".parse::<FieldLocation>().unwrap().to_string());
+        assert!("48.9".parse::<FieldLocation>().is_err());
+        assert!("x".parse::<FieldLocation>().is_err());
+    }
+
+    #[test]
+    fn field_location_redacted() {
+        let mut spec = Spec::new();
+        "48.92".parse::<FieldLocation>().unwrap().redact(&mut spec);
+
+        let masked = spec.mask_fields(&request(&[(48, "0103ABC9203719"), (49, "826")]));
+        assert_eq!("**************", masked[&48]);
+        assert_eq!("826", masked[&49]);
+    }
+
+    #[test]
+    fn track_cvvs() {
+        let checks = checks();
+        assert_eq!(Ok(()), checks.check(&request(&[(35, "4111111111111111=291210100000216")])));
+        assert_eq!(Err("82"), checks.check(&request(&[(35, "4111111111111111=291210100000217")])));
+        assert_eq!(Err("82"), checks.check(&request(&[(35, "4111111111111111=2912101000002")])));
+
+        let chip = |mode| request(&[(22, mode), (35, "4111111111111111=291220100000997")]);
+        assert_eq!(Ok(()), checks.check(&chip("051")));
+        assert_eq!(Ok(()), checks.check(&chip("071")));
+        assert_eq!(Err("82"), checks.check(&chip("901")));
+
+        let checks = checks.with_track_offset(0);
+        assert_eq!(Ok(()), checks.check(&request(&[(35, "4111111111111111=2912101216")])));
+    }
+
+    #[test]
+    fn cvv2s() {
+        let checks = checks();
+        assert_eq!(Ok(()), checks.check(&request(&[(2, "4111111111111111"), (14, "2912"), (48, "9203719")])));
+        assert_eq!(Err("N7"), checks.check(&request(&[(2, "4111111111111111"), (14, "2912"), (48, "9203718")])));
+        assert_eq!(Err("N7"), checks.check(&request(&[(2, "4111111111111111"), (48, "9203719")])));
+        assert_eq!(Ok(()), checks.check(&request(&[(2, "4111111111111111"), (14, "2912")])));
+    }
+
+    #[test]
+    fn invalid_cvk() {
+        assert_eq!(Err(CvvError::InvalidCvk), CvvChecks::new("aes:000102030405060708090A0B0C0D0E0F".parse().unwrap()).map(|_| ()));
+    }
+}
@@ -0,0 +1,134 @@
+use std::sync::Mutex;
+
+use zaps::{
+    crypto::{
+        dukpt::{
+            self,
+            KeyUsage,
+            Terminal,
+            AES_KSN_LENGTH,
+            TDES_KSN_LENGTH,
+        },
+        pin::{
+            self,
+            PinFormat,
+        },
+        Key,
+    },
+    iso8583::Message,
+    util::string_to_bytes,
+};
+
+use crate::responder::{
+    FIELD_PAN,
+    FIELD_PIN_BLOCK,
+};
+
+/// A private field, as the 1987 field 53 is too short for a KSN
+pub const DEFAULT_KSN_FIELD: u16 = 62;
+
+/// A KSN from a field's value, in hex for character fields or as it is for binary ones
+pub fn ksn(value: &str) -> Option<Vec<u8>> {
+    match value.chars().count() {
+        TDES_KSN_LENGTH | AES_KSN_LENGTH => string_to_bytes(value),
+        _ => hex::decode(value).ok()
+            .filter(|ksn| matches!(ksn.len(), TDES_KSN_LENGTH | AES_KSN_LENGTH)),
+    }
+}
+
+/// The PIN key for a request, derived from the base derivation key and the KSN in the field
+pub fn pin_key(bdk: &Key, ksn_value: &str) -> Option<Key> {
+    let ksn = ksn(ksn_value)?;
+    dukpt::derive(bdk, &ksn, KeyUsage::Pin).ok()
+}
+
+/// Emulates a PIN pad for messages sent to a host, encrypting each PIN under the next DUKPT key
+pub struct PinPad {
+    terminal: Mutex<Terminal>,
+    ksn_field: u16,
+    format: PinFormat,
+}
+
+impl PinPad {
+    pub fn new(terminal: Terminal, ksn_field: u16, format: PinFormat) -> Self {
+        PinPad {
+            terminal: Mutex::new(terminal),
+            ksn_field,
+            format,
+        }
+    }
+
+    /// The KSN of the last PIN encrypted
+    pub fn ksn(&self) -> Vec<u8> {
+        self.terminal.lock().unwrap().ksn().to_vec()
+    }
+
+    /// Replaces a clear PIN in field 52 with its PIN block, in hex, and puts the KSN in hex in the KSN field.
+    /// Messages without a PIN are left as they are.
+    pub fn encrypt_pin(&self, message: &mut Message) -> Result<(), String> {
+        let pin = match message.fields.get(&FIELD_PIN_BLOCK) {
+            Some(pin) => pin.clone(),
+            None => return Ok(()),
+        };
+        let pan = message.fields.get(&FIELD_PAN).cloned().unwrap_or_default();
+
+        let mut terminal = self.terminal.lock().unwrap();
+        let ksn = terminal.next_ksn()
+            .map_err(|e| e.to_string())?
+            .to_vec();
+        let key = terminal.key(KeyUsage::Pin)
+            .map_err(|e| e.to_string())?;
+        let block = pin::encrypt(&key, self.format, &pin, &pan)
+            .map_err(|e| e.to_string())?;
+
+        message.fields.insert(FIELD_PIN_BLOCK, hex::encode_upper(block));
+        message.fields.insert(self.ksn_field, hex::encode_upper(ksn));
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use zaps::util::byte_to_string;
+
+    const BDK: &str = "tdes:0123456789ABCDEFFEDCBA9876543210";
+
+    #[test]
+    fn reads_ksns() {
+        let ksn_bytes = hex::decode("FFFF9876543210E00001").unwrap();
+        assert_eq!(Some(ksn_bytes.clone()), ksn("FFFF9876543210E00001"));
+        assert_eq!(Some(ksn_bytes.clone()), ksn(&byte_to_string(&ksn_bytes)));
+        assert_eq!(Some(hex::decode("123456789012345600000001").unwrap()), ksn("123456789012345600000001"));
+        assert_eq!(None, ksn("FFFF9876543210E0000"));
+        assert_eq!(None, ksn("FFFF9876543210E000"));
+    }
+
+    #[test]
+    fn pin_pad_and_host_agree() {
+        let bdk = BDK.parse::<Key>().unwrap();
+        let terminal = Terminal::new(&bdk, &hex::decode("FFFF9876543210E00000").unwrap()).unwrap();
+        let pin_pad = PinPad::new(terminal, DEFAULT_KSN_FIELD, PinFormat::Iso0);
+
+        let mut message = Message::default();
+        message.fields.insert(FIELD_PAN, "4012345678909".to_string());
+        message.fields.insert(FIELD_PIN_BLOCK, "1234".to_string());
+        pin_pad.encrypt_pin(&mut message).unwrap();
+        assert_eq!("1B9C1845EB993A7A", message.fields[&FIELD_PIN_BLOCK]);
+        assert_eq!("FFFF9876543210E00001", message.fields[&DEFAULT_KSN_FIELD]);
+
+        let key = pin_key(&bdk, &message.fields[&DEFAULT_KSN_FIELD]).unwrap();
+        let block = hex::decode(&message.fields[&FIELD_PIN_BLOCK]).unwrap();
+        assert_eq!(Ok("1234".to_string()), pin::decrypt(&key, PinFormat::Iso0, &block, "4012345678909"));
+
+        message.fields.insert(FIELD_PIN_BLOCK, "1234".to_string());
+        pin_pad.encrypt_pin(&mut message).unwrap();
+        assert_eq!("FFFF9876543210E00002", message.fields[&DEFAULT_KSN_FIELD]);
+
+        message.fields.insert(FIELD_PIN_BLOCK, "12".to_string());
+        assert!(pin_pad.encrypt_pin(&mut message).is_err());
+        let mut no_pin = Message::default();
+        pin_pad.encrypt_pin(&mut no_pin).unwrap();
+        assert!(no_pin.fields.is_empty());
+    }
+}
@@ -0,0 +1,79 @@
+use std::collections::HashMap;
+
+use zaps::{
+    emv::{
+        cryptogram::Issuer,
+        Tlv,
+    },
+    util::{
+        byte_to_string,
+        string_to_bytes,
+    },
+};
+
+use crate::responder::FIELD_PAN;
+
+pub const FIELD_PSN: u16 = 23;
+pub const FIELD_ICC_DATA: u16 = 55;
+
+/// Declines for an ARQC which isn't the card's
+pub const RC_CRYPTOGRAM_FAILED: &str = "82";
+
+/// ICC data from field 55, in hex for character fields or as it is for binary ones, and whether it was in hex
+pub fn icc_data(value: &str) -> Option<(Tlv, bool)> {
+    let from_hex = hex::decode(value).ok()
+        .and_then(|data| Tlv::parse(&data).ok());
+    match from_hex {
+        Some(tlv) => Some((tlv, true)),
+        None => Tlv::parse(&string_to_bytes(value)?).ok()
+            .map(|tlv| (tlv, false)),
+    }
+}
+
+/// Whether the request's field 55 has the card's ARQC, `true` if it has no field 55
+pub fn verify_arqc(issuer: &Issuer, request: &HashMap<u16, String>) -> bool {
+    let icc = match request.get(&FIELD_ICC_DATA) {
+        Some(icc) => icc,
+        None => return true,
+    };
+    let (pan, psn) = pan_and_psn(request);
+    icc_data(icc)
+        .and_then(|(icc, _hex)| issuer.verify_arqc(pan, psn, &icc).ok())
+        .unwrap_or(false)
+}
+
+/// The response's field 55, with the issuer authentication data for the response code and in the same encoding as
+/// the request's, or `None` if the request has none or it couldn't be worked out
+pub fn issuer_response(issuer: &Issuer, request: &HashMap<u16, String>, response_code: &str) -> Option<String> {
+    let (icc, hex) = icc_data(request.get(&FIELD_ICC_DATA)?)?;
+    let (pan, psn) = pan_and_psn(request);
+    let response = issuer.respond(pan, psn, &icc, response_code).ok()?;
+    let response = response.to_bytes();
+    match hex {
+        true => Some(hex::encode_upper(response)),
+        false => Some(byte_to_string(&response)),
+    }
+}
+
+fn pan_and_psn(request: &HashMap<u16, String>) -> (&str, Option<&str>) {
+    let pan = request.get(&FIELD_PAN).map(|pan| &pan[..]).unwrap_or_default();
+    (pan, request.get(&FIELD_PSN).map(|psn| &psn[..]))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn reads_icc_data() {
+        let data = hex::decode("820219809F360200A1").unwrap();
+        let (tlv, hex) = icc_data("820219809F360200A1").unwrap();
+        assert!(hex);
+        assert_eq!(Some(&[0x00, 0xa1][..]), tlv.get(0x9f36));
+
+        let (tlv, hex) = icc_data(&byte_to_string(&data)).unwrap();
+        assert!(!hex);
+        assert_eq!(Some(&[0x19, 0x80][..]), tlv.get(0x82));
+        assert_eq!(None, icc_data("9F36"));
+    }
+}
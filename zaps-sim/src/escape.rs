@@ -0,0 +1,31 @@
+/// Reverses [`str::escape_default`]
+pub(crate) fn unescape(s: &str) -> Option<String> {
+    let mut unescaped = String::with_capacity(s.len());
+    let mut chars = s.chars();
+
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            unescaped.push(c);
+            continue;
+        }
+        let c = match chars.next()? {
+            't' => '\t',
+            'r' => '\r',
+            'n' => '\n',
+            'u' => {
+                if chars.next()? != '{' {
+                    return None;
+                }
+                let code = chars.by_ref()
+                    .take_while(|c| *c != '}')
+                    .collect::<String>();
+                char::from_u32(u32::from_str_radix(&code, 16).ok()?)?
+            },
+            c @ ('\\' | '\'' | '"') => c,
+            _ => return None,
+        };
+        unescaped.push(c);
+    }
+
+    Some(unescaped)
+}
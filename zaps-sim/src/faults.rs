@@ -0,0 +1,542 @@
+use std::collections::HashMap;
+use std::error;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::net::SocketAddr;
+use std::path::Path;
+use std::str::FromStr;
+use std::time::Duration;
+
+use rand::Rng;
+use zaps::{
+    iso8583::{
+        Iso8583Engine,
+        MTI_FIELD,
+        spec::{
+            DataType,
+            FieldType,
+        },
+    },
+    util::string_to_bytes,
+};
+
+#[derive(Debug, PartialEq)]
+pub enum FaultParseError {
+    InvalidFormat(String),
+    UnknownSetting(String),
+    InvalidValue(String),
+    UnknownProfile(String),
+}
+
+impl fmt::Display for FaultParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::InvalidFormat(s) => write!(f, "invalid format: {}", s),
+            Self::UnknownSetting(setting) => write!(f, "unknown setting: {}", setting),
+            Self::InvalidValue(value) => write!(f, "invalid value: {}", value),
+            Self::UnknownProfile(name) => write!(f, "unknown profile: {}", name),
+        }
+    }
+}
+
+impl error::Error for FaultParseError {}
+
+/// The ways a simulator response can be made to misbehave.
+///
+/// Percentages are the chance of each fault happening to a given response, and are decided independently.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct FaultProfile {
+    /// Added to every response, picked at random from the range (inclusive) when it isn't a single value
+    pub delay: Option<(Duration, Duration)>,
+    pub drop: u8,
+    pub duplicate: u8,
+    /// Only the first half of the frame is sent
+    pub truncate: u8,
+    /// The length indicator of the first variable length field is overstated by one
+    pub bad_length: u8,
+    /// The bit for the secondary bitmap is flipped
+    pub bad_bitmap: u8,
+    /// Reset the connection once the profile has been applied to this many messages on it
+    pub reset: Option<u64>,
+    /// Write responses a byte at a time with this interval between bytes
+    pub drip: Option<Duration>,
+}
+
+/// The faults decided on for a single response
+#[derive(Debug, PartialEq)]
+pub struct FaultPlan {
+    pub delay: Duration,
+    pub drop: bool,
+    pub copies: usize,
+    pub truncate: bool,
+    pub bad_length: bool,
+    pub bad_bitmap: bool,
+    pub drip: Option<Duration>,
+}
+
+impl Default for FaultPlan {
+    fn default() -> Self {
+        FaultPlan {
+            delay: Duration::ZERO,
+            drop: false,
+            copies: 1,
+            truncate: false,
+            bad_length: false,
+            bad_bitmap: false,
+            drip: None,
+        }
+    }
+}
+
+impl FaultProfile {
+    pub fn plan<R: Rng>(&self, rng: &mut R) -> FaultPlan {
+        let mut chance = |percent: u8| rng.gen_range(0..100) < percent;
+        let drop = chance(self.drop);
+        let copies = if chance(self.duplicate) { 2 } else { 1 };
+        let truncate = chance(self.truncate);
+        let bad_length = chance(self.bad_length);
+        let bad_bitmap = chance(self.bad_bitmap);
+        let delay = match self.delay {
+            Some((min, max)) if min < max => rng.gen_range(min..=max),
+            Some((min, _)) => min,
+            None => Duration::ZERO,
+        };
+
+        FaultPlan {
+            delay,
+            drop,
+            copies,
+            truncate,
+            bad_length,
+            bad_bitmap,
+            drip: self.drip,
+        }
+    }
+}
+
+/// Profiles are written as space separated settings, any of which may be left out:
+/// `delay=<ms>[-<ms>] drop=<%> duplicate=<%> truncate=<%> bad_length=<%> bad_bitmap=<%> reset=<messages> drip=<ms>`
+impl fmt::Display for FaultProfile {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut settings = vec![];
+        match self.delay {
+            Some((min, max)) if min == max => settings.push(format!("delay={}", min.as_millis())),
+            Some((min, max)) => settings.push(format!("delay={}-{}", min.as_millis(), max.as_millis())),
+            None => (),
+        }
+        let percentages = [
+            ("drop", self.drop),
+            ("duplicate", self.duplicate),
+            ("truncate", self.truncate),
+            ("bad_length", self.bad_length),
+            ("bad_bitmap", self.bad_bitmap),
+        ];
+        for (name, percent) in percentages {
+            if percent > 0 {
+                settings.push(format!("{}={}", name, percent));
+            }
+        }
+        if let Some(reset) = self.reset {
+            settings.push(format!("reset={}", reset));
+        }
+        if let Some(drip) = self.drip {
+            settings.push(format!("drip={}", drip.as_millis()));
+        }
+        write!(f, "{}", settings.join(" "))
+    }
+}
+
+impl FromStr for FaultProfile {
+    type Err = FaultParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut profile = FaultProfile::default();
+
+        for setting in s.split_whitespace() {
+            let (name, value) = setting.split_once('=')
+                .ok_or_else(|| FaultParseError::InvalidFormat(setting.to_string()))?;
+            let invalid = || FaultParseError::InvalidValue(setting.to_string());
+            let millis = |ms: &str| ms.parse::<u64>()
+                .map(Duration::from_millis)
+                .map_err(|_e| invalid());
+            let percent = || value.parse::<u8>()
+                .ok()
+                .filter(|percent| *percent <= 100)
+                .ok_or_else(invalid);
+
+            match name {
+                "delay" => {
+                    let (min, max) = value.split_once('-')
+                        .unwrap_or((value, value));
+                    let (min, max) = (millis(min)?, millis(max)?);
+                    if min > max {
+                        return Err(invalid());
+                    }
+                    profile.delay = Some((min, max));
+                },
+                "drop" => profile.drop = percent()?,
+                "duplicate" => profile.duplicate = percent()?,
+                "truncate" => profile.truncate = percent()?,
+                "bad_length" => profile.bad_length = percent()?,
+                "bad_bitmap" => profile.bad_bitmap = percent()?,
+                "reset" => profile.reset = Some(value.parse().map_err(|_e| invalid())?),
+                "drip" => profile.drip = Some(millis(value)?),
+                _ => return Err(FaultParseError::UnknownSetting(name.to_string())),
+            }
+        }
+
+        Ok(profile)
+    }
+}
+
+/// Named fault profiles and which connections they apply to.
+///
+/// Profiles apply to connections by peer address, and to individual responses when selected by a response rule, which
+/// takes precedence.
+#[derive(Debug, Clone, Default)]
+pub struct Faults {
+    profiles: HashMap<String, FaultProfile>,
+    peers: HashMap<String, String>,
+    default: Option<String>,
+}
+
+impl Faults {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Loads profiles from a file with one entry per line, one of
+    /// - `profile <name> <settings>` to define a profile
+    /// - `connection <ip>[:<port>] <name>` to apply a profile to connections from a peer
+    /// - `default <name>` to apply a profile to all other connections
+    ///
+    /// Blank lines and lines starting `#` are ignored.
+    pub fn load<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        fs::read_to_string(path)?
+            .parse()
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+
+    pub fn add_profile(&mut self, name: &str, profile: FaultProfile) {
+        self.profiles.insert(name.to_string(), profile);
+    }
+
+    pub fn profile(&self, name: &str) -> Option<&FaultProfile> {
+        self.profiles.get(name)
+    }
+
+    pub fn profiles(&self) -> &HashMap<String, FaultProfile> {
+        &self.profiles
+    }
+
+    /// Profile names by peer address
+    pub fn peers(&self) -> &HashMap<String, String> {
+        &self.peers
+    }
+
+    pub fn default_profile(&self) -> Option<&str> {
+        self.default.as_deref()
+    }
+
+    pub fn assign(&mut self, peer: &str, name: &str) -> Result<(), FaultParseError> {
+        self.check_profile(name)?;
+        self.peers.insert(peer.to_string(), name.to_string());
+        Ok(())
+    }
+
+    pub fn set_default(&mut self, name: Option<&str>) -> Result<(), FaultParseError> {
+        if let Some(name) = name {
+            self.check_profile(name)?;
+        }
+        self.default = name.map(|name| name.to_string());
+        Ok(())
+    }
+
+    /// The name of the profile for connections from a peer, matching on the full address before the IP
+    pub fn for_peer(&self, peer: &SocketAddr) -> Option<&str> {
+        self.peers.get(&peer.to_string())
+            .or_else(|| self.peers.get(&peer.ip().to_string()))
+            .or(self.default.as_ref())
+            .map(|name| &name[..])
+    }
+
+    fn check_profile(&self, name: &str) -> Result<(), FaultParseError> {
+        match self.profiles.contains_key(name) {
+            true => Ok(()),
+            false => Err(FaultParseError::UnknownProfile(name.to_string())),
+        }
+    }
+}
+
+impl FromStr for Faults {
+    type Err = FaultParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut faults = Faults::new();
+
+        for line in s.lines().map(str::trim) {
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let invalid = || FaultParseError::InvalidFormat(line.to_string());
+            let mut words = line.splitn(3, char::is_whitespace);
+            let (kind, first, rest) = (words.next(), words.next(), words.next());
+
+            match (kind, first, rest) {
+                (Some("profile"), Some(name), settings) => faults.add_profile(name, settings.unwrap_or("").parse()?),
+                (Some("connection"), Some(peer), Some(name)) => faults.assign(peer, name.trim())?,
+                (Some("default"), Some(name), None) => faults.set_default(Some(name))?,
+                _ => return Err(invalid()),
+            }
+        }
+
+        Ok(faults)
+    }
+}
+
+/// Corrupts encoded messages, for the faults that need to know how a message is laid out.
+///
+/// Both return `false` if the message has nothing to corrupt.
+pub trait Corrupt<K> {
+    fn corrupt_bitmap(&self, fields: &HashMap<K, String>, payload: &mut [u8]) -> bool;
+    fn corrupt_length(&self, fields: &HashMap<K, String>, payload: &mut [u8]) -> bool;
+}
+
+const MTI_LEN: usize = 4;
+
+impl Corrupt<u16> for Iso8583Engine {
+    fn corrupt_bitmap(&self, fields: &HashMap<u16, String>, payload: &mut [u8]) -> bool {
+        let bitmap = fields.get(&MTI_FIELD)
+            .and_then(|mti| self.spec().get_mti_spec(mti))
+            .and_then(|mti_spec| mti_spec.get(&0));
+        let (bitmap, byte) = match (bitmap, payload.get_mut(MTI_LEN)) {
+            (Some(bitmap), Some(byte)) => (bitmap, byte),
+            _ => return false,
+        };
+
+        if bitmap.data_type == DataType::Binary {
+            *byte ^= 0x80;
+        } else {
+            match (*byte as char).to_digit(16) {
+                Some(digit) => *byte = std::char::from_digit(digit ^ 0x8, 16).unwrap().to_ascii_uppercase() as u8,
+                None => return false,
+            }
+        }
+        true
+    }
+
+    fn corrupt_length(&self, fields: &HashMap<u16, String>, payload: &mut [u8]) -> bool {
+        let mti_spec = match fields.get(&MTI_FIELD).and_then(|mti| self.spec().get_mti_spec(mti)) {
+            Some(mti_spec) => mti_spec,
+            None => return false,
+        };
+        let mut offset = MTI_LEN + mti_spec.get(&0).map_or(0, |bitmap| bitmap.size);
+
+        // field 1 is laid out as the secondary bitmap, when there are fields for it to map, rather than as a value
+        let sec_bitmap = mti_spec.get(&1).filter(|field| field.ftype.is_bitmap());
+        if let Some(sec_bitmap) = sec_bitmap {
+            if fields.keys().any(|field_num| (65..=128).contains(field_num)) {
+                offset += sec_bitmap.size;
+            }
+        }
+
+        let mut field_nums = fields.keys()
+            .filter(|field_num| **field_num != 0 && **field_num != MTI_FIELD)
+            .filter(|field_num| **field_num != 1 || sec_bitmap.is_none())
+            .collect::<Vec<_>>();
+        field_nums.sort_unstable();
+
+        for field_num in field_nums {
+            let field = match mti_spec.get(field_num) {
+                Some(field) => field,
+                None => return false,
+            };
+            let value_len = match field.data_type {
+                DataType::Binary => match string_to_bytes(&fields[field_num]) {
+                    Some(bytes) => bytes.len(),
+                    None => return false,
+                },
+                _ => fields[field_num].len(),
+            };
+            let size_len = match field.ftype.var_size_len() {
+                Some(size_len) => size_len,
+                // fixed fields take up their whole size, whether their value does or is padded out to it
+                None if field.ftype == FieldType::Fixed => {
+                    offset += field.size;
+                    continue;
+                },
+                None => return false,
+            };
+
+            let indicator = match offset.checked_add(size_len).and_then(|end| payload.get_mut(offset..end)) {
+                Some(indicator) => indicator,
+                None => return false,
+            };
+            let max = 10usize.pow(size_len as u32) - 1;
+            let wrong = match value_len.checked_add(1).filter(|wrong| *wrong <= max).or_else(|| value_len.checked_sub(1)) {
+                Some(wrong) => wrong,
+                None => return false,
+            };
+            let wrong = format!("{:0width$}", wrong, width = size_len);
+            indicator.copy_from_slice(&self.spec().charset().encode(wrong.as_bytes()));
+            return true;
+        }
+
+        false
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use rand::{rngs::StdRng, SeedableRng};
+    use zaps::{
+        core::Unparser,
+        iso8583::spec::{Field, Spec},
+    };
+
+    macro_rules! test_profile_parse {
+        ($(
+            $name:ident: $input:literal => $expected:expr;
+        )*) => {
+            $(
+                #[test]
+                fn $name() {
+                    assert_eq!($expected, $input.parse::<FaultProfile>());
+                }
+            )*
+        };
+    }
+
+    test_profile_parse!(
+        empty: "" => Ok(FaultProfile::default());
+        fixed_delay: "delay=500" => Ok(FaultProfile { delay: Some((Duration::from_millis(500), Duration::from_millis(500))), ..Default::default() });
+        random_delay: "delay=100-200" => Ok(FaultProfile { delay: Some((Duration::from_millis(100), Duration::from_millis(200))), ..Default::default() });
+        percentages: "drop=10 duplicate=20 truncate=30 bad_length=40 bad_bitmap=50" => Ok(FaultProfile { drop: 10, duplicate: 20, truncate: 30, bad_length: 40, bad_bitmap: 50, ..Default::default() });
+        reset_and_drip: "reset=5 drip=20" => Ok(FaultProfile { reset: Some(5), drip: Some(Duration::from_millis(20)), ..Default::default() });
+        percentage_too_high: "drop=101" => Err(FaultParseError::InvalidValue("drop=101".to_string()));
+        delay_backwards: "delay=200-100" => Err(FaultParseError::InvalidValue("delay=200-100".to_string()));
+        unknown_setting: "explode=1" => Err(FaultParseError::UnknownSetting("explode".to_string()));
+        no_value: "drop" => Err(FaultParseError::InvalidFormat("drop".to_string()));
+    );
+
+    #[test]
+    fn profile_round_trip() {
+        let profile = "delay=100-200 drop=10 duplicate=20 truncate=30 bad_length=40 bad_bitmap=50 reset=5 drip=20";
+        assert_eq!(profile, profile.parse::<FaultProfile>().unwrap().to_string());
+    }
+
+    #[test]
+    fn plan() {
+        let mut rng = StdRng::seed_from_u64(0);
+        let always = "delay=100-200 drop=100 duplicate=100 truncate=100 bad_length=100 bad_bitmap=100 drip=5".parse::<FaultProfile>().unwrap();
+        for _ in 0..20 {
+            let plan = always.plan(&mut rng);
+            assert!(plan.delay >= Duration::from_millis(100) && plan.delay <= Duration::from_millis(200));
+            assert!(plan.drop && plan.truncate && plan.bad_length && plan.bad_bitmap);
+            assert_eq!(2, plan.copies);
+            assert_eq!(Some(Duration::from_millis(5)), plan.drip);
+        }
+
+        let never = FaultProfile::default().plan(&mut rng);
+        assert_eq!(FaultPlan::default(), never);
+    }
+
+    #[test]
+    fn faults_for_peer() {
+        let faults = "
+            # slow everything down, and break the test client
+            profile slow delay=1000
+            profile broken drop=100
+            connection 127.0.0.1:5000 broken
+            connection 10.0.0.1 broken
+            default slow
+        ".parse::<Faults>().unwrap();
+
+        assert_eq!(Some("broken"), faults.for_peer(&"127.0.0.1:5000".parse().unwrap()));
+        assert_eq!(Some("broken"), faults.for_peer(&"10.0.0.1:1234".parse().unwrap()));
+        assert_eq!(Some("slow"), faults.for_peer(&"127.0.0.1:5001".parse().unwrap()));
+        assert_eq!(Some(&FaultProfile { drop: 100, ..Default::default() }), faults.profile("broken"));
+    }
+
+    #[test]
+    fn faults_unknown_profile() {
+        let result = "profile slow delay=1000\ndefault fast".parse::<Faults>();
+        assert_eq!(Some(FaultParseError::UnknownProfile("fast".to_string())), result.err());
+    }
+
+    fn engine(bitmap: &str) -> Iso8583Engine {
+        let parse = |fields: &[(u16, &str)]| fields.iter()
+            .map(|(num, field)| (*num, field.parse::<Field>().unwrap()))
+            .collect();
+        let mut spec = Spec::new();
+        spec.add_mti_spec("0210".to_string(), parse(&[(0, bitmap), (2, "LLVar(n)"), (11, "Fixed(6:n)"), (41, "Fixed(8:an,pad)"), (55, "LLLVar(bin)")]));
+        spec.add_mti_spec("0810".to_string(), parse(&[(0, bitmap), (1, "Bitmap(64)"), (11, "Fixed(6:n)"), (70, "Fixed(3:n)"), (100, "LLVar(n)")]));
+        Iso8583Engine::new(spec)
+    }
+
+    fn encode(engine: &Iso8583Engine, fields: &[(u16, &str)]) -> (HashMap<u16, String>, Vec<u8>) {
+        let fields = fields.iter()
+            .map(|(k, v)| (*k, v.to_string()))
+            .collect();
+        let mut payload = vec![];
+        engine.unparse(&fields, &mut payload).unwrap();
+        (fields, payload)
+    }
+
+    #[test]
+    fn corrupt_ascii_bitmap() {
+        let engine = engine("AsciiBitmap(64)");
+        let (fields, mut payload) = encode(&engine, &[(MTI_FIELD, "0210"), (2, "4111"), (11, "000001")]);
+        assert!(engine.corrupt_bitmap(&fields, &mut payload));
+        assert_eq!(&b"0210C020"[..], &payload[..8]);
+    }
+
+    #[test]
+    fn corrupt_binary_bitmap() {
+        let engine = engine("Bitmap(64)");
+        let (fields, mut payload) = encode(&engine, &[(MTI_FIELD, "0210"), (2, "4111")]);
+        assert!(engine.corrupt_bitmap(&fields, &mut payload));
+        assert_eq!(0xc0, payload[4]);
+    }
+
+    #[test]
+    fn corrupt_length() {
+        let engine = engine("AsciiBitmap(64)");
+        let (fields, mut payload) = encode(&engine, &[(MTI_FIELD, "0210"), (2, "4111"), (11, "000001")]);
+        assert!(engine.corrupt_length(&fields, &mut payload));
+        assert_eq!(&b"054111000001"[..], &payload[20..]);
+
+        let (fields, mut payload) = encode(&engine, &[(MTI_FIELD, "0210"), (11, "000001")]);
+        assert!(!engine.corrupt_length(&fields, &mut payload));
+    }
+
+    #[test]
+    fn corrupt_length_binary() {
+        let engine = engine("AsciiBitmap(64)");
+        let (fields, mut payload) = encode(&engine, &[(MTI_FIELD, "0210"), (11, "000001"), (55, "\u{9f}\u{ff}")]);
+        assert!(engine.corrupt_length(&fields, &mut payload));
+        assert_eq!(&b"000001003\x9f\xff"[..], &payload[20..]);
+    }
+
+    #[test]
+    fn corrupt_length_padded() {
+        let engine = engine("AsciiBitmap(64)");
+        let (fields, mut payload) = encode(&engine, &[(MTI_FIELD, "0210"), (11, "000001"), (41, "TERM1"), (55, "\u{1}")]);
+        assert!(engine.corrupt_length(&fields, &mut payload));
+        assert_eq!(&b"000001TERM1   002\x01"[..], &payload[20..]);
+    }
+
+    #[test]
+    fn corrupt_length_secondary_bitmap() {
+        // the responder echoes field 1 back, with the secondary bitmap as its value
+        let engine = engine("Bitmap(64)");
+        let (fields, mut payload) = encode(&engine, &[(MTI_FIELD, "0810"), (1, "\u{4}\0\0\0\0\0\0\0"), (11, "000001"), (70, "301")]);
+        assert!(!engine.corrupt_length(&fields, &mut payload));
+
+        let (fields, mut payload) = encode(&engine, &[(MTI_FIELD, "0810"), (1, ""), (11, "000001"), (70, "301"), (100, "1234")]);
+        assert!(engine.corrupt_length(&fields, &mut payload));
+        assert_eq!(&b"00000130105"[..], &payload[20..31]);
+    }
+}
@@ -0,0 +1,159 @@
+use std::convert::TryFrom;
+use std::fmt;
+use std::io;
+use std::str::FromStr;
+
+use tokio::io::{AsyncRead, AsyncReadExt};
+
+/// The most a length header can say a frame holds
+pub const MAX_FRAME_LENGTH: usize = u16::MAX as usize;
+
+/// How frames are delimited on the wire
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum Framing {
+    /// Each frame follows its length as 2 bytes big endian, so binary fields can hold any byte
+    Length,
+    /// Each frame ends with a newline, to type messages e.g. with `nc`. Binary values with a 0x0A byte in them split
+    /// the frame, so this is only for specs without binary fields.
+    #[default]
+    Newline,
+}
+
+impl Framing {
+    /// The frame with its framing, ready to write
+    pub fn encode(&self, frame: &[u8]) -> io::Result<Vec<u8>> {
+        match self {
+            Self::Length => {
+                let length = u16::try_from(frame.len())
+                    .map_err(|_e| io::Error::new(io::ErrorKind::InvalidInput, format!("frame of {} bytes is too long for its length", frame.len())))?;
+                let mut out = length.to_be_bytes().to_vec();
+                out.extend_from_slice(frame);
+                Ok(out)
+            },
+            Self::Newline => {
+                let mut out = frame.to_vec();
+                out.push(b'\n');
+                Ok(out)
+            },
+        }
+    }
+
+    /// Takes the first whole frame off the front of the bytes read, without its framing
+    fn split(&self, buffer: &mut Vec<u8>) -> Option<Vec<u8>> {
+        match self {
+            Self::Length => {
+                let length = u16::from_be_bytes([*buffer.first()?, *buffer.get(1)?]) as usize;
+                let frame = buffer.get(2..2 + length)?.to_vec();
+                buffer.drain(..2 + length);
+                Some(frame)
+            },
+            Self::Newline => {
+                let end = buffer.iter().position(|b| *b == b'\n')?;
+                let mut frame = buffer.drain(..=end).collect::<Vec<_>>();
+                frame.pop();
+                Some(frame)
+            },
+        }
+    }
+}
+
+impl fmt::Display for Framing {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Length => write!(f, "length"),
+            Self::Newline => write!(f, "newline"),
+        }
+    }
+}
+
+impl FromStr for Framing {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "length" => Ok(Self::Length),
+            "newline" => Ok(Self::Newline),
+            _ => Err(format!("invalid framing: {}", s)),
+        }
+    }
+}
+
+/// Reads frames from a stream, keeping the bytes of any frame only partly read between calls
+pub struct FrameReader<R> {
+    reader: R,
+    framing: Framing,
+    buffer: Vec<u8>,
+}
+
+impl<R> FrameReader<R>
+where
+    R: AsyncRead + Unpin,
+{
+    pub fn new(reader: R, framing: Framing) -> Self {
+        FrameReader {
+            reader,
+            framing,
+            buffer: vec![],
+        }
+    }
+
+    /// The next frame, without its framing, or `None` once the stream ends. Nothing is lost if the future is dropped
+    /// before it completes, so this can be raced against other futures in `select!`.
+    pub async fn next(&mut self) -> io::Result<Option<Vec<u8>>> {
+        loop {
+            if let Some(frame) = self.framing.split(&mut self.buffer) {
+                return Ok(Some(frame));
+            }
+            let mut chunk = [0; 4096];
+            let read = self.reader.read(&mut chunk).await?;
+            if read == 0 {
+                // a last line without its newline is still a frame, but a frame shorter than its length isn't
+                if self.framing == Framing::Newline && !self.buffer.is_empty() {
+                    return Ok(Some(std::mem::take(&mut self.buffer)));
+                }
+                return Ok(None);
+            }
+            self.buffer.extend_from_slice(&chunk[..read]);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    async fn frames(framing: Framing, input: &[u8]) -> Vec<Vec<u8>> {
+        let mut reader = FrameReader::new(input, framing);
+        let mut frames = vec![];
+        while let Some(frame) = reader.next().await.unwrap() {
+            frames.push(frame);
+        }
+        frames
+    }
+
+    #[tokio::test]
+    async fn length() {
+        let mut input = Framing::Length.encode(b"iso8583:\x0a\x00\xff").unwrap();
+        input.extend(Framing::Length.encode(b"").unwrap());
+        input.extend(Framing::Length.encode(b"ping").unwrap());
+        assert_eq!(b"\x00\x0biso8583:\x0a\x00\xff", &input[..13]);
+
+        assert_eq!(vec![b"iso8583:\x0a\x00\xff".to_vec(), vec![], b"ping".to_vec()], frames(Framing::Length, &input).await);
+        assert!(frames(Framing::Length, b"\x00\x05abc").await.is_empty());
+        assert!(Framing::Length.encode(&[0; MAX_FRAME_LENGTH + 1]).is_err());
+    }
+
+    #[tokio::test]
+    async fn newline() {
+        assert_eq!(b"ping\n".to_vec(), Framing::Newline.encode(b"ping").unwrap());
+        assert_eq!(vec![b"ping".to_vec(), b"".to_vec(), b"pong".to_vec()], frames(Framing::Newline, b"ping\n\npong").await);
+    }
+
+    #[test]
+    fn from_str() {
+        assert_eq!(Ok(Framing::Length), "length".parse());
+        assert_eq!(Ok(Framing::Newline), "newline".parse());
+        assert!("crlf".parse::<Framing>().is_err());
+        assert_eq!(Framing::Newline, Framing::default());
+    }
+}
@@ -0,0 +1,339 @@
+use std::fmt;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
+
+use axum::{
+    extract::{ConnectInfo, State},
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    routing::post,
+    Json,
+    Router,
+};
+use serde::Serialize;
+use tokio::{
+    io::AsyncWriteExt,
+    net::{TcpListener, TcpStream},
+    time,
+};
+use tracing::{error, info};
+use zaps::{
+    core::Unparser,
+    iso8583::{
+        BinaryEncoding,
+        Iso8583Engine,
+        Message,
+    },
+};
+
+use crate::{
+    dukpt::PinPad,
+    framing::{
+        FrameReader,
+        Framing,
+    },
+    ISO8583_PREFIX,
+    parse_frame,
+    responder::Responder,
+    Simulator,
+};
+
+/// How long to wait for a response from the target before giving up
+const RESPONSE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// An error for the API caller, returned as `{"error": "..."}`
+#[derive(Debug, PartialEq)]
+pub struct GatewayError(StatusCode, String);
+
+impl GatewayError {
+    fn bad_request<E: fmt::Display>(e: E) -> Self {
+        GatewayError(StatusCode::BAD_REQUEST, e.to_string())
+    }
+
+    fn bad_gateway<E: fmt::Display>(e: E) -> Self {
+        GatewayError(StatusCode::BAD_GATEWAY, e.to_string())
+    }
+
+    fn no_response() -> Self {
+        GatewayError(StatusCode::GATEWAY_TIMEOUT, "no response".to_string())
+    }
+}
+
+#[derive(Serialize)]
+struct ErrorBody {
+    error: String,
+}
+
+impl IntoResponse for GatewayError {
+    fn into_response(self) -> Response {
+        let GatewayError(status, error) = self;
+        (status, Json(ErrorBody{ error })).into_response()
+    }
+}
+
+/// Turns JSON messages into ISO8583 frames and back, for services which don't speak ISO8583.
+///
+/// Requests are built with the simulator's engine then handled by the simulator as though they came on a connection
+/// from the API caller, see [`Simulator::exchange`], or, with a target, sent to another host over TCP in the
+/// simulator's framing, `iso8583:` then the message in a frame. Each request to a target gets its own connection.
+pub struct Gateway<R> {
+    sim: Arc<Simulator<Iso8583Engine, R>>,
+    target: Option<String>,
+    pin_pad: Option<Arc<PinPad>>,
+}
+
+impl<R> Clone for Gateway<R> {
+    fn clone(&self) -> Self {
+        Gateway {
+            sim: self.sim.clone(),
+            target: self.target.clone(),
+            pin_pad: self.pin_pad.clone(),
+        }
+    }
+}
+
+impl<R> Gateway<R>
+where
+    R: Responder<u16>,
+{
+    pub fn new(sim: Arc<Simulator<Iso8583Engine, R>>, target: Option<String>) -> Self {
+        Gateway {
+            sim,
+            target,
+            pin_pad: None,
+        }
+    }
+
+    /// Emulates a terminal's PIN pad, so field 52 of messages is the clear PIN, to encrypt under DUKPT
+    pub fn with_pin_pad(mut self, pin_pad: PinPad) -> Self {
+        self.pin_pad = Some(Arc::new(pin_pad));
+        self
+    }
+
+    /// Sends the message for the API caller at `peer` and returns the response, with binary values in hex both ways
+    pub async fn exchange(&self, peer: SocketAddr, mut message: Message) -> Result<Message, GatewayError> {
+        if let Some(pin_pad) = &self.pin_pad {
+            pin_pad.encrypt_pin(&mut message)
+                .map_err(GatewayError::bad_request)?;
+        }
+        let engine = self.sim.engine();
+        let tokens = message.to_tokens(engine.spec(), BinaryEncoding::Hex)
+            .map_err(GatewayError::bad_request)?;
+        let mut frame = ISO8583_PREFIX.as_bytes().to_vec();
+        engine.unparse(&tokens, &mut frame)
+            .map_err(GatewayError::bad_request)?;
+
+        let response = match &self.target {
+            Some(target) => forward(target, self.sim.framing(), &frame).await?,
+            None => self.sim.exchange(peer, &frame).await
+                .ok_or_else(GatewayError::no_response)?,
+        };
+        let response = match parse_frame(engine, &response) {
+            Some(Ok(response)) => response,
+            Some(Err(e)) => return Err(GatewayError::bad_gateway(format!("unable to parse response: {}", e))),
+            None => return Err(GatewayError::bad_gateway("response isn't an ISO8583 frame")),
+        };
+        Ok(Message::from_tokens(engine.spec(), &response, BinaryEncoding::Hex))
+    }
+}
+
+/// Sends the frame to the target and waits for the frame it responds with
+async fn forward(target: &str, framing: Framing, frame: &[u8]) -> Result<Vec<u8>, GatewayError> {
+    let exchange = async {
+        let stream = TcpStream::connect(target).await?;
+        let (reader, mut writer) = stream.into_split();
+        writer.write_all(&framing.encode(frame)?).await?;
+        FrameReader::new(reader, framing).next().await
+    };
+
+    match time::timeout(RESPONSE_TIMEOUT, exchange).await {
+        Ok(Ok(None)) => Err(GatewayError::no_response()),
+        Ok(Ok(Some(response))) => Ok(response),
+        Ok(Err(e)) => Err(GatewayError::bad_gateway(format!("unable to exchange with {}: {}", target, e))),
+        Err(_elapsed) => Err(GatewayError::no_response()),
+    }
+}
+
+/// Serves the gateway until the listener fails. `POST /messages` takes a message as a map of field number to value
+/// with the MTI under `mti`, as logged in JSON, and returns the response the same way.
+pub async fn serve<R>(addr: &str, gateway: Gateway<R>)
+where
+    R: 'static + Responder<u16> + Send + Sync,
+{
+    let listener = TcpListener::bind(addr)
+        .await
+        .unwrap_or_else(|e| panic!("Unable to listen on {}: {}", addr, e));
+
+    info!("Gateway listening on {}", addr);
+
+    let app = Router::new()
+        .route("/messages", post(exchange_message))
+        .with_state(gateway);
+
+    if let Err(e) = axum::serve(listener, app.into_make_service_with_connect_info::<SocketAddr>()).await {
+        error!("Gateway failed: {}", e);
+    }
+}
+
+async fn exchange_message<R>(State(gateway): State<Gateway<R>>, ConnectInfo(peer): ConnectInfo<SocketAddr>, Json(message): Json<Message>) -> Result<Json<Message>, GatewayError>
+where
+    R: Responder<u16>,
+{
+    gateway.exchange(peer, message)
+        .await
+        .map(Json)
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::HashMap;
+    use super::*;
+    use crate::faults::Faults;
+    use crate::responder::Action;
+    use zaps::iso8583::{
+        spec::Spec,
+        MTI_FIELD,
+    };
+
+    /// Approves everything, or drops amounts of 0
+    struct Approve;
+
+    impl Responder<u16> for Approve {
+        fn respond(&self, request: &HashMap<u16, String>) -> Action<u16> {
+            if request.get(&4).map(|amount| amount.trim_start_matches('0').is_empty()) == Some(true) {
+                return Action::Drop;
+            }
+            let mut response = request.clone();
+            response.insert(MTI_FIELD, "0210".to_string());
+            response.insert(39, "00".to_string());
+            Action::Respond(response)
+        }
+    }
+
+    fn simulator() -> Simulator<Iso8583Engine, Approve> {
+        let spec = "
+            field 0 AsciiBitmap(64)
+            field 2 LLVar(n..19)
+            field 4 Fixed(12:n)
+            field 39 Fixed(2:an)
+            field 64 Fixed(4:bin)
+            mti 0200 0210
+        ".parse::<Spec>().unwrap();
+        Simulator::new(Iso8583Engine::new(spec), Approve)
+    }
+
+    fn gateway(target: Option<String>) -> Gateway<Approve> {
+        Gateway::new(Arc::new(simulator()), target)
+    }
+
+    fn peer() -> SocketAddr {
+        "127.0.0.1:5000".parse().unwrap()
+    }
+
+    fn message(json: &str) -> Message {
+        serde_json::from_str(json).unwrap()
+    }
+
+    #[tokio::test]
+    async fn local() {
+        let response = gateway(None)
+            .exchange(peer(), message(r#"{"mti": "0200", "2": "4111111111111111", "4": "000000001000", "64": "4D414331"}"#))
+            .await
+            .unwrap();
+
+        assert_eq!(
+            message(r#"{"mti": "0210", "2": "4111111111111111", "4": "000000001000", "39": "00", "64": "4D414331"}"#),
+            response,
+        );
+    }
+
+    #[tokio::test]
+    async fn local_drop() {
+        let response = gateway(None)
+            .exchange(peer(), message(r#"{"mti": "0200", "4": "000000000000"}"#))
+            .await;
+
+        assert_eq!(Err(GatewayError(StatusCode::GATEWAY_TIMEOUT, "no response".to_string())), response);
+    }
+
+    #[tokio::test]
+    async fn local_counted() {
+        let gateway = gateway(None);
+        gateway.exchange(peer(), message(r#"{"mti": "0200", "4": "000000001000"}"#)).await.unwrap();
+
+        let rendered = gateway.sim.metrics().render();
+        assert!(rendered.contains(r#"zaps_messages_total{direction="in",mti="0200"} 1"#));
+        assert!(rendered.contains(r#"zaps_messages_total{direction="out",mti="0210"} 1"#));
+        assert!(rendered.contains(r#"zaps_response_codes_total{code="00",mti="0210"} 1"#));
+    }
+
+    #[tokio::test]
+    async fn local_faults() {
+        let faults = "
+            profile broken drop=100
+            profile slow delay=50
+            connection 127.0.0.1:5000 broken
+            default slow
+        ".parse::<Faults>().unwrap();
+        let gateway = Gateway::new(Arc::new(simulator().with_faults(faults)), None);
+        let request = || message(r#"{"mti": "0200", "4": "000000001000"}"#);
+
+        let response = gateway.exchange(peer(), request()).await;
+        assert_eq!(Err(GatewayError(StatusCode::GATEWAY_TIMEOUT, "no response".to_string())), response);
+
+        let start = time::Instant::now();
+        let response = gateway.exchange("127.0.0.1:5001".parse().unwrap(), request()).await.unwrap();
+        assert_eq!(message(r#"{"mti": "0210", "4": "000000001000", "39": "00"}"#), response);
+        assert!(start.elapsed() >= Duration::from_millis(50));
+    }
+
+    #[tokio::test]
+    async fn invalid_message() {
+        let response = gateway(None)
+            .exchange(peer(), message(r#"{"mti": "0200", "4": "1000"}"#))
+            .await;
+
+        assert_eq!(
+            Err(GatewayError(StatusCode::BAD_REQUEST, "field 4 has length 4 but must be at most 12".to_string())),
+            response,
+        );
+    }
+
+    #[tokio::test]
+    async fn forwarded() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let target = listener.local_addr().unwrap().to_string();
+        // only field 39 is set, the 39th bit of the bitmap
+        let bitmap = format!("{:016X}", 1u64 << (64 - 39));
+        let response = format!("iso8583:0210{}51", bitmap);
+        let host = tokio::spawn(async move {
+            let (stream, _addr) = listener.accept().await.unwrap();
+            let (reader, mut writer) = stream.into_split();
+            let request = FrameReader::new(reader, Framing::Newline).next().await.unwrap().unwrap();
+            writer.write_all(&Framing::Newline.encode(response.as_bytes()).unwrap()).await.unwrap();
+            request
+        });
+
+        let response = gateway(Some(target))
+            .exchange(peer(), message(r#"{"mti": "0200", "39": "05"}"#))
+            .await
+            .unwrap();
+
+        assert_eq!(format!("iso8583:0200{}05", bitmap).into_bytes(), host.await.unwrap());
+        assert_eq!(message(r#"{"mti": "0210", "39": "51"}"#), response);
+    }
+
+    #[tokio::test]
+    async fn forward_unreachable() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let target = listener.local_addr().unwrap().to_string();
+        drop(listener);
+
+        let response = gateway(Some(target))
+            .exchange(peer(), message(r#"{"mti": "0200", "39": "05"}"#))
+            .await;
+
+        assert_eq!(StatusCode::BAD_GATEWAY, response.unwrap_err().0);
+    }
+}
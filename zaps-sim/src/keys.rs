@@ -0,0 +1,346 @@
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::sync::Mutex;
+
+use tracing::{info, warn};
+use zaps::{
+    crypto::{
+        hsm::{
+            Hsm,
+            HsmError,
+            KeyType,
+        },
+        pin::{
+            self,
+            PinFormat,
+        },
+        CryptoError,
+        Key,
+    },
+    iso8583::{
+        Iso8583Engine,
+        MTI_FIELD,
+    },
+    util::{
+        byte_to_string,
+        string_to_bytes,
+    },
+};
+
+use crate::field;
+use crate::responder::{
+    response_mti,
+    FIELD_PAN,
+    FIELD_PIN_BLOCK,
+    FIELD_RESPONSE_CODE,
+    RC_APPROVED,
+};
+
+/// The new working key, encrypted under the ZMK in hex followed by its check value
+pub const FIELD_KEY: u16 = 48;
+pub const FIELD_NETWORK_CODE: u16 = 70;
+
+pub const NETWORK_PIN_KEY_CHANGE: &str = "161";
+pub const NETWORK_MAC_KEY_CHANGE: &str = "162";
+
+pub const RC_INVALID_TRANSACTION: &str = "12";
+pub const RC_FORMAT_ERROR: &str = "30";
+
+/// The length of a key check value in hex
+const CHECK_VALUE_LENGTH: usize = 6;
+
+/// Engines which can MAC a connection's messages under its own key
+pub trait SessionEngine: Sized {
+    fn with_session_mac_key(&self, key: Key) -> Result<Self, CryptoError>;
+}
+
+impl SessionEngine for Iso8583Engine {
+    fn with_session_mac_key(&self, key: Key) -> Result<Self, CryptoError> {
+        self.clone().with_mac_key(key)
+    }
+}
+
+/// The working keys a connection has changed to
+#[derive(Debug, Clone, Default)]
+struct SessionKeys {
+    pin: Option<Key>,
+    mac: Option<Key>,
+}
+
+/// The outcome of a key change request
+#[derive(Debug)]
+pub struct KeyChange<K> {
+    pub response: HashMap<K, String>,
+    /// The connection's new MAC key, to MAC its messages under once the response is sent
+    pub mac_key: Option<Key>,
+}
+
+/// Exchanges working keys under a ZMK held in an [`Hsm`], for each connection.
+///
+/// A network management request (0800) with field 70 of 161 changes the connection's PIN key and 162 its MAC key.
+/// Given the new key in field 48, as the key under the ZMK in hex followed by its check value, the key is taken on
+/// and the response has no key. Otherwise the simulator makes a new key and sends it back in field 48.
+///
+/// PIN blocks on a connection with its own PIN key are translated to the base PIN key before they're checked.
+pub struct KeyExchange {
+    hsm: Hsm,
+    zmk: String,
+    pin_key: Option<(Key, PinFormat)>,
+    mac_key: Option<Key>,
+    sessions: Mutex<HashMap<u64, SessionKeys>>,
+}
+
+impl KeyExchange {
+    /// Exchanges keys under the named ZMK
+    pub fn new(hsm: Hsm, zmk: &str) -> Result<Self, HsmError> {
+        hsm.key(zmk, KeyType::Zmk)?;
+        Ok(KeyExchange {
+            hsm,
+            zmk: zmk.to_string(),
+            pin_key: None,
+            mac_key: None,
+            sessions: Mutex::new(HashMap::new()),
+        })
+    }
+
+    /// The PIN key and format connections start with, which PIN blocks are checked under
+    pub fn with_pin_key(mut self, key: Key, format: PinFormat) -> Self {
+        self.pin_key = Some((key, format));
+        self
+    }
+
+    /// The MAC key connections start with. New MAC keys are made like it.
+    pub fn with_mac_key(mut self, key: Key) -> Self {
+        self.mac_key = Some(key);
+        self
+    }
+
+    pub fn hsm(&self) -> &Hsm {
+        &self.hsm
+    }
+
+    /// Handles a key change request for the connection, `None` if the request isn't one
+    pub fn key_change<K>(&self, connection: u64, request: &HashMap<K, String>) -> Option<KeyChange<K>>
+    where
+        K: From<u16> + Hash + Eq + Clone,
+    {
+        if field(request, MTI_FIELD) != Some("0800") {
+            return None;
+        }
+        let base = match field(request, FIELD_NETWORK_CODE)? {
+            NETWORK_PIN_KEY_CHANGE => self.pin_key.as_ref().map(|(key, _format)| key),
+            NETWORK_MAC_KEY_CHANGE => self.mac_key.as_ref(),
+            _ => return None,
+        };
+        let pin = field(request, FIELD_NETWORK_CODE) == Some(NETWORK_PIN_KEY_CHANGE);
+
+        let mut response = request.clone();
+        response.remove(&K::from(0));
+        response.remove(&K::from(FIELD_KEY));
+        response.insert(K::from(MTI_FIELD), response_mti("0800").unwrap());
+        let declined = |mut response: HashMap<K, String>, response_code: &str| {
+            response.insert(K::from(FIELD_RESPONSE_CODE), response_code.to_string());
+            Some(KeyChange { response, mac_key: None })
+        };
+
+        let base = match base {
+            Some(base) => base,
+            None => {
+                warn!(connection, "Key change for a key the simulator doesn't use");
+                return declined(response, RC_INVALID_TRANSACTION);
+            },
+        };
+        let key = match field(request, FIELD_KEY) {
+            Some(value) => match self.receive_key(base, value) {
+                Ok(key) => key,
+                Err(e) => {
+                    warn!(connection, error = %e, "Unable to take on key");
+                    return declined(response, RC_FORMAT_ERROR);
+                },
+            },
+            None => match self.send_key(base) {
+                Ok((key, value)) => {
+                    response.insert(K::from(FIELD_KEY), value);
+                    key
+                },
+                Err(e) => {
+                    warn!(connection, error = %e, "Unable to make key");
+                    return declined(response, RC_INVALID_TRANSACTION);
+                },
+            },
+        };
+        response.insert(K::from(FIELD_RESPONSE_CODE), RC_APPROVED.to_string());
+
+        info!(connection, check_value = %key.check_value(), "Changed {} key", if pin { "PIN" } else { "MAC" });
+        let mut sessions = self.sessions.lock().unwrap();
+        let session = sessions.entry(connection).or_default();
+        let mac_key = if pin {
+            session.pin = Some(key);
+            None
+        } else {
+            session.mac = Some(key.clone());
+            Some(key)
+        };
+        Some(KeyChange { response, mac_key })
+    }
+
+    /// Re-encrypts the request's PIN block from the connection's PIN key to the base PIN key, if the connection has
+    /// changed its key. Blocks which don't decrypt are left as they are, to be declined.
+    pub fn translate_pin<K>(&self, connection: u64, request: &mut HashMap<K, String>)
+    where
+        K: From<u16> + Hash + Eq,
+    {
+        let (to_key, format) = match &self.pin_key {
+            Some(pin_key) => pin_key,
+            None => return,
+        };
+        let sessions = self.sessions.lock().unwrap();
+        let from_key = match sessions.get(&connection).and_then(|session| session.pin.as_ref()) {
+            Some(from_key) => from_key,
+            None => return,
+        };
+        let pan = field(request, FIELD_PAN).unwrap_or_default();
+        let translated = field(request, FIELD_PIN_BLOCK)
+            .and_then(string_to_bytes)
+            .and_then(|block| pin::translate(&block, pan, from_key, *format, to_key, *format).ok());
+        if let Some(block) = translated {
+            request.insert(K::from(FIELD_PIN_BLOCK), byte_to_string(&block));
+        }
+    }
+
+    /// The connection's MAC key, if it has changed it
+    pub fn session_mac_key(&self, connection: u64) -> Option<Key> {
+        self.sessions.lock().unwrap()
+            .get(&connection)
+            .and_then(|session| session.mac.clone())
+    }
+
+    /// Forgets the connection's working keys
+    pub fn end_session(&self, connection: u64) {
+        self.sessions.lock().unwrap().remove(&connection);
+    }
+
+    /// A new key like the base key, and it as sent under the ZMK in hex followed by its check value
+    fn send_key(&self, base: &Key) -> Result<(Key, String), HsmError> {
+        let key = Key::generate(base.algorithm(), base.bytes().len())?;
+        let encrypted = self.hsm.wrap(&self.zmk, &key)?;
+        let value = format!("{}{}", hex::encode_upper(encrypted), key.check_value());
+        Ok((key, value))
+    }
+
+    /// A key received as the key under the ZMK in hex followed by its check value, of the same algorithm as the base
+    fn receive_key(&self, base: &Key, value: &str) -> Result<Key, HsmError> {
+        let invalid = || HsmError::Crypto(CryptoError::InvalidKey("expected the key in hex then its check value".to_string()));
+        if value.len() <= CHECK_VALUE_LENGTH || !value.is_char_boundary(value.len() - CHECK_VALUE_LENGTH) {
+            return Err(invalid());
+        }
+        let (encrypted, check_value) = value.split_at(value.len() - CHECK_VALUE_LENGTH);
+        let encrypted = hex::decode(encrypted)
+            .map_err(|_e| invalid())?;
+        self.hsm.unwrap(&self.zmk, base.algorithm(), &encrypted, Some(check_value))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const ZMK: &str = "tdes:1111111111111111EEEEEEEEEEEEEEEE";
+    const PAN: &str = "4111111111111111";
+
+    fn key(key: &str) -> Key {
+        key.parse().unwrap()
+    }
+
+    fn exchange() -> KeyExchange {
+        let mut hsm = Hsm::new(key("tdes:0123456789ABCDEFFEDCBA9876543210"));
+        hsm.insert("zmk", KeyType::Zmk, &key(ZMK)).unwrap();
+        KeyExchange::new(hsm, "zmk").unwrap()
+            .with_pin_key(key("tdes:0123456789ABCDEFFEDCBA9876543210"), PinFormat::Iso0)
+            .with_mac_key(key("tdes:FEDCBA98765432100123456789ABCDEF"))
+    }
+
+    fn request(network_code: &str, extra: &[(u16, &str)]) -> HashMap<u16, String> {
+        [(MTI_FIELD, "0800"), (0, "bitmap"), (11, "000001"), (FIELD_NETWORK_CODE, network_code)]
+            .iter()
+            .chain(extra)
+            .map(|(k, v)| (*k, v.to_string()))
+            .collect()
+    }
+
+    /// The key the simulator sent back, decrypted under the ZMK
+    fn sent_key(response: &HashMap<u16, String>) -> Key {
+        let (encrypted, check_value) = response[&FIELD_KEY].split_at(response[&FIELD_KEY].len() - 6);
+        let key = Key::new(zaps::crypto::Algorithm::Tdes, &key(ZMK).decrypt(&hex::decode(encrypted).unwrap()).unwrap()).unwrap();
+        assert_eq!(check_value, key.check_value());
+        key
+    }
+
+    #[test]
+    fn sends_new_pin_key() {
+        let exchange = exchange();
+        let change = exchange.key_change(1, &request("161", &[])).unwrap();
+        assert_eq!("0810", change.response[&MTI_FIELD]);
+        assert_eq!("00", change.response[&39]);
+        assert!(change.mac_key.is_none());
+        let zpk = sent_key(&change.response);
+
+        // blocks under the new key are checked under the base key, on this connection only
+        let block = byte_to_string(&pin::encrypt(&zpk, PinFormat::Iso0, "1234", PAN).unwrap());
+        let mut request = [(FIELD_PAN, PAN), (FIELD_PIN_BLOCK, &block[..])].iter()
+            .map(|(k, v)| (*k, v.to_string()))
+            .collect::<HashMap<_, _>>();
+        let mut other = request.clone();
+        exchange.translate_pin(1, &mut request);
+        exchange.translate_pin(2, &mut other);
+
+        let base = key("tdes:0123456789ABCDEFFEDCBA9876543210");
+        let translated = string_to_bytes(&request[&FIELD_PIN_BLOCK]).unwrap();
+        assert_eq!(Ok("1234".to_string()), pin::decrypt(&base, PinFormat::Iso0, &translated, PAN));
+        assert_eq!(block, other[&FIELD_PIN_BLOCK]);
+
+        exchange.end_session(1);
+        let mut request = request.clone();
+        request.insert(FIELD_PIN_BLOCK, block.clone());
+        exchange.translate_pin(1, &mut request);
+        assert_eq!(block, request[&FIELD_PIN_BLOCK]);
+    }
+
+    #[test]
+    fn takes_on_mac_key() {
+        let exchange = exchange();
+        let tak = key("tdes:0123012301230123456745674567456F");
+        let value = format!("{}{}", hex::encode_upper(key(ZMK).encrypt(tak.bytes()).unwrap()), tak.check_value());
+
+        let change = exchange.key_change(3, &request("162", &[(FIELD_KEY, &value)])).unwrap();
+        assert_eq!("00", change.response[&39]);
+        assert!(!change.response.contains_key(&FIELD_KEY));
+        assert_eq!(Some(tak.clone()), change.mac_key);
+        assert_eq!(Some(tak), exchange.session_mac_key(3));
+        assert_eq!(None, exchange.session_mac_key(1));
+    }
+
+    #[test]
+    fn rejects_changes() {
+        let exchange = exchange();
+        let bad_check_value = format!("{}000000", hex::encode_upper(key(ZMK).encrypt(&[0x01; 16]).unwrap()));
+        let change = exchange.key_change(1, &request("162", &[(FIELD_KEY, &bad_check_value)])).unwrap();
+        assert_eq!(RC_FORMAT_ERROR, change.response[&39]);
+        assert_eq!(None, exchange.session_mac_key(1));
+
+        let change = exchange.key_change(1, &request("161", &[(FIELD_KEY, "XYZ")])).unwrap();
+        assert_eq!(RC_FORMAT_ERROR, change.response[&39]);
+
+        let mut hsm = Hsm::new(key("tdes:0123456789ABCDEFFEDCBA9876543210"));
+        hsm.insert("zmk", KeyType::Zmk, &key(ZMK)).unwrap();
+        let without_mac = KeyExchange::new(hsm, "zmk").unwrap();
+        let change = without_mac.key_change(1, &request("162", &[])).unwrap();
+        assert_eq!(RC_INVALID_TRANSACTION, change.response[&39]);
+
+        assert!(exchange.key_change(1, &request("301", &[])).is_none());
+        let mut echo = request("161", &[]);
+        echo.insert(MTI_FIELD, "0810".to_string());
+        assert!(exchange.key_change(1, &echo).is_none());
+        assert!(matches!(KeyExchange::new(Hsm::new(key(ZMK)), "zmk"), Err(HsmError::UnknownKey(_))));
+    }
+}
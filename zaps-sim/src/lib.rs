@@ -1,80 +1,866 @@
 // Shameful theft from Lily Mara/Code Tech as a baseline - https://www.youtube.com/watch?v=Iapc-qGTEBQ
-use std::sync::Arc;
+use std::collections::HashMap;
+use std::error;
+use std::fmt;
+use std::hash::Hash;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex, RwLock};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{
+    Duration,
+    Instant,
+    SystemTime,
+    UNIX_EPOCH,
+};
+
+use serde::Serialize;
+use tracing::{info, warn};
 
 use tokio::{
-    io::{AsyncBufReadExt, BufReader, AsyncWriteExt},
+    io::{AsyncWrite, AsyncWriteExt},
     net::TcpListener,
-    sync::broadcast,
+    sync::{broadcast, mpsc},
+    time,
 };
 
 use zaps::{
-    core::Parser,
+    core::{
+        Parser,
+        Unparser,
+    },
+};
+
+pub mod admin;
+pub mod capture;
+pub mod cards;
+pub mod cvv;
+pub mod dukpt;
+pub mod emv;
+mod escape;
+pub mod faults;
+pub mod framing;
+pub mod gateway;
+pub mod keys;
+pub mod logging;
+pub mod metrics;
+pub mod proxy;
+pub mod replay;
+pub mod responder;
+pub mod rules;
+pub mod script;
+pub mod store;
+
+use capture::{
+    Capture,
+    Direction,
+};
+use faults::{
+    Corrupt,
+    FaultPlan,
+    Faults,
+};
+use framing::{
+    FrameReader,
+    Framing,
+};
+use keys::{
+    KeyChange,
+    KeyExchange,
+    SessionEngine,
+};
+use logging::{
+    MaskFields,
+    MessageLog,
+};
+use metrics::{
+    ErrorKind,
+    Metrics,
 };
+use responder::{
+    Action,
+    Responder,
+    FIELD_RESPONSE_CODE,
+};
+
+const ISO8583_PREFIX: &str = "iso8583:";
+/// Separates the peer a broadcast echo came from and what it sent, e.g. `127.0.0.1:5000 => MTI 0200 ...`
+const ECHO_SEPARATOR: &str = " => ";
+
+/// Parses a frame with the engine if it is prefixed as an ISO8583 message, otherwise returns `None`.
+pub fn parse_frame<K, T>(engine: &T, frame: &[u8]) -> Option<Result<HashMap<K, String>, T::Err>>
+where
+    T: Parser<K>,
+{
+    frame.strip_prefix(ISO8583_PREFIX.as_bytes())
+        .map(|payload| engine.parse(payload))
+}
+
+/// Whether a frame is another connection's message echoed to this one, which starts with the peer it came from
+pub fn is_echo(frame: &[u8]) -> bool {
+    String::from_utf8_lossy(frame)
+        .split_once(ECHO_SEPARATOR)
+        .is_some_and(|(peer, _message)| peer.parse::<SocketAddr>().is_ok())
+}
 
-pub async fn serve<K, T>(engine: T)
+/// The value of an ISO8583 field in tokens keyed by field number
+fn field<K>(tokens: &HashMap<K, String>, field: u16) -> Option<&str>
 where
-    T: 'static + Parser<K> + Send + Sync,
-    <T as Parser<K>>::Err: std::fmt::Debug,
-    K: std::fmt::Debug,
+    K: From<u16> + Hash + Eq,
 {
-    let engine = Arc::new(engine);
-    let listen_addr = "localhost:9090";
-    let listener = TcpListener::bind(listen_addr)
-        .await
-        .unwrap_or_else(|e| panic!("Unable to listen on {}: {}", listen_addr, e));
-    
-    println!("Listener established for {}", listen_addr);
-
-    let (tx, _rx) = broadcast::channel(10);
-
-    loop {
-        let (mut socket, addr) = listener.accept().await.unwrap();
-    
-        println!("Accepted connection from {}", addr);
-
-        let tx = tx.clone();
-        let mut rx = tx.subscribe();
-        let thread_engine = engine.clone();
-
-        tokio::spawn(async move {
-            let (reader, mut writer) = socket.split();
-            let mut reader = BufReader::new(reader);
-            let mut line = String::new();
-
-            loop {
-                tokio::select! {
-                    result = reader.read_line(&mut line) => {
-                        if result.unwrap() == 0 {
-                            break;
+    tokens.get(&K::from(field))
+        .map(|value| &value[..])
+}
+
+/// Orders parsed tokens by field for recording.
+pub fn sorted_fields<K>(tokens: &HashMap<K, String>) -> Vec<(String, String)>
+where
+    K: Ord + fmt::Display,
+{
+    let mut fields = tokens.iter().collect::<Vec<_>>();
+    fields.sort_by_key(|(k, _)| *k);
+    fields.into_iter()
+        .map(|(k, v)| (k.to_string(), v.clone()))
+        .collect()
+}
+
+/// When a request was received, to measure the latency of its response
+struct Received {
+    at: Instant,
+    mti: Option<String>,
+}
+
+/// A frame to write to a connection along with its parsed fields for capture
+struct Outbound {
+    raw: Vec<u8>,
+    fields: Vec<(String, String)>,
+    /// Not set for raw frames
+    log: Option<MessageLog>,
+    /// How many times to send the frame
+    copies: usize,
+    /// Interval between bytes when the frame should be written slowly
+    drip: Option<Duration>,
+    /// The request this is a response to, if any
+    request: Option<Received>,
+}
+
+impl Outbound {
+    fn new(raw: Vec<u8>, fields: Vec<(String, String)>, log: Option<MessageLog>) -> Self {
+        Outbound {
+            raw,
+            fields,
+            log,
+            copies: 1,
+            drip: None,
+            request: None,
+        }
+    }
+}
+
+/// What to send back to the sender of a frame
+enum Reply {
+    Now(Outbound),
+    After(Duration, Outbound),
+    Disconnect,
+}
+
+/// The outcome of handling an inbound frame
+struct Handled {
+    /// The line to echo to other connections
+    echo: String,
+    reply: Option<Reply>,
+    /// Whether to reset the connection once any immediate reply is sent
+    reset: bool,
+}
+
+/// Instructions to a connection from outside its own read loop e.g. delayed replies and the admin API
+enum Command {
+    Send(Box<Outbound>),
+    Close,
+}
+
+#[derive(Default)]
+struct Stats {
+    received: AtomicU64,
+    invalid: AtomicU64,
+    responded: AtomicU64,
+    dropped: AtomicU64,
+    injected: AtomicU64,
+}
+
+/// A snapshot of a connection and its stats
+#[derive(Debug, Clone, Serialize)]
+pub struct ConnectionInfo {
+    pub id: u64,
+    pub peer: String,
+    /// Seconds since the unix epoch
+    pub connected_at: u64,
+    pub fault_profile: Option<String>,
+    /// ISO8583 frames received
+    pub received: u64,
+    /// ISO8583 frames which couldn't be parsed
+    pub invalid: u64,
+    pub responded: u64,
+    /// Requests which weren't responded to, whether by choice or by fault
+    pub dropped: u64,
+    /// Unsolicited messages sent
+    pub injected: u64,
+}
+
+struct Connection {
+    id: u64,
+    peer: SocketAddr,
+    connected_at: SystemTime,
+    /// The fault profile applied to every response, unless a rule picks another
+    profile: Mutex<Option<String>>,
+    /// How many messages each fault profile has been applied to, for resets
+    applied: Mutex<HashMap<String, u64>>,
+    stats: Stats,
+    commands: mpsc::UnboundedSender<Command>,
+}
+
+impl Connection {
+    fn info(&self) -> ConnectionInfo {
+        let stat = |stat: &AtomicU64| stat.load(Ordering::Relaxed);
+        ConnectionInfo {
+            id: self.id,
+            peer: self.peer.to_string(),
+            connected_at: self.connected_at
+                .duration_since(UNIX_EPOCH)
+                .map(|since| since.as_secs())
+                .unwrap_or_default(),
+            fault_profile: self.profile.lock().unwrap().clone(),
+            received: stat(&self.stats.received),
+            invalid: stat(&self.stats.invalid),
+            responded: stat(&self.stats.responded),
+            dropped: stat(&self.stats.dropped),
+            injected: stat(&self.stats.injected),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub enum SimulatorError {
+    NoConnection(u64),
+    UnknownProfile(String),
+    InvalidMessage(String),
+}
+
+impl fmt::Display for SimulatorError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::NoConnection(id) => write!(f, "no connection {}", id),
+            Self::UnknownProfile(name) => write!(f, "unknown fault profile: {}", name),
+            Self::InvalidMessage(reason) => write!(f, "invalid message: {}", reason),
+        }
+    }
+}
+
+impl error::Error for SimulatorError {}
+
+/// The simulator and its live connections.
+///
+/// Shared between the listener and the admin API so the latter can inspect and steer connections while it runs.
+pub struct Simulator<T, R> {
+    engine: T,
+    responder: R,
+    framing: Framing,
+    capture: Option<Capture>,
+    faults: RwLock<Faults>,
+    keys: Option<KeyExchange>,
+    /// Engines for connections which have changed their MAC key
+    session_engines: Mutex<HashMap<u64, Arc<T>>>,
+    connections: Mutex<HashMap<u64, Arc<Connection>>>,
+    connection_ids: AtomicU64,
+    metrics: Arc<Metrics>,
+}
+
+impl<T, R> Simulator<T, R> {
+    pub fn new(engine: T, responder: R) -> Self {
+        Simulator {
+            engine,
+            responder,
+            framing: Framing::default(),
+            capture: None,
+            faults: RwLock::new(Faults::new()),
+            keys: None,
+            session_engines: Mutex::new(HashMap::new()),
+            connections: Mutex::new(HashMap::new()),
+            connection_ids: AtomicU64::new(1),
+            metrics: Arc::new(Metrics::new()),
+        }
+    }
+
+    /// How frames are delimited, by a newline unless set
+    pub fn with_framing(mut self, framing: Framing) -> Self {
+        self.framing = framing;
+        self
+    }
+
+    pub fn framing(&self) -> Framing {
+        self.framing
+    }
+
+    pub fn with_capture(mut self, capture: Capture) -> Self {
+        self.capture = Some(capture);
+        self
+    }
+
+    pub fn with_faults(mut self, faults: Faults) -> Self {
+        self.faults = RwLock::new(faults);
+        self
+    }
+
+    /// Handles key change requests and the working keys they set up for each connection
+    pub fn with_key_exchange(mut self, keys: KeyExchange) -> Self {
+        self.keys = Some(keys);
+        self
+    }
+
+    pub fn key_exchange(&self) -> Option<&KeyExchange> {
+        self.keys.as_ref()
+    }
+
+    pub fn engine(&self) -> &T {
+        &self.engine
+    }
+
+    pub fn responder(&self) -> &R {
+        &self.responder
+    }
+
+    pub fn metrics(&self) -> &Arc<Metrics> {
+        &self.metrics
+    }
+
+    /// Fault profiles and the peers they apply to. Changes apply to new connections and to rules from then on.
+    pub fn faults(&self) -> &RwLock<Faults> {
+        &self.faults
+    }
+
+    pub fn connections(&self) -> Vec<ConnectionInfo> {
+        let mut connections = self.connections.lock().unwrap()
+            .values()
+            .map(|connection| connection.info())
+            .collect::<Vec<_>>();
+        connections.sort_by_key(|connection| connection.id);
+        connections
+    }
+
+    pub fn connection(&self, id: u64) -> Option<ConnectionInfo> {
+        self.get(id)
+            .ok()
+            .map(|connection| connection.info())
+    }
+
+    /// Changes the fault profile of a live connection, or removes it if `None`.
+    pub fn set_fault_profile(&self, id: u64, profile: Option<&str>) -> Result<(), SimulatorError> {
+        let connection = self.get(id)?;
+        if let Some(profile) = profile {
+            if self.faults.read().unwrap().profile(profile).is_none() {
+                return Err(SimulatorError::UnknownProfile(profile.to_string()));
+            }
+        }
+        *connection.profile.lock().unwrap() = profile.map(|profile| profile.to_string());
+        Ok(())
+    }
+
+    pub fn close(&self, id: u64) -> Result<(), SimulatorError> {
+        self.command(id, Command::Close)
+    }
+
+    /// Sends an unsolicited message, e.g. a network management request, to a connection.
+    pub fn inject<K>(&self, id: u64, message: &HashMap<K, String>) -> Result<(), SimulatorError>
+    where
+        T: Unparser<K> + MaskFields<K>,
+        <T as Unparser<K>>::Err: fmt::Display,
+        K: Ord + fmt::Display + From<u16> + Hash,
+    {
+        let mut raw = ISO8583_PREFIX.as_bytes().to_vec();
+        self.engine.unparse(message, &mut raw)
+            .map_err(|e| SimulatorError::InvalidMessage(e.to_string()))?;
+        let log = MessageLog::new(&self.engine, message);
+        self.queue(id, Outbound::new(raw, sorted_fields(message), Some(log)))
+    }
+
+    /// Sends an unsolicited frame as is, whether or not it is valid.
+    pub fn inject_raw(&self, id: u64, raw: Vec<u8>) -> Result<(), SimulatorError> {
+        self.queue(id, Outbound::new(raw, vec![], None))
+    }
+
+    fn queue(&self, id: u64, out: Outbound) -> Result<(), SimulatorError> {
+        self.command(id, Command::Send(Box::new(out)))?;
+        self.metrics.queued();
+        self.get(id)?.stats.injected.fetch_add(1, Ordering::Relaxed);
+        Ok(())
+    }
+
+    fn get(&self, id: u64) -> Result<Arc<Connection>, SimulatorError> {
+        self.connections.lock().unwrap()
+            .get(&id)
+            .cloned()
+            .ok_or(SimulatorError::NoConnection(id))
+    }
+
+    fn command(&self, id: u64, command: Command) -> Result<(), SimulatorError> {
+        self.get(id)?
+            .commands
+            .send(command)
+            .map_err(|_e| SimulatorError::NoConnection(id))
+    }
+
+    /// Handles an inbound frame, deciding the reply (if any) for the sender and which faults to apply to it.
+    fn process_frame<K>(&self, connection: &Connection, line: &[u8]) -> Handled
+    where
+        T: Parser<K> + Unparser<K> + Corrupt<K> + MaskFields<K> + SessionEngine,
+        <T as Parser<K>>::Err: fmt::Debug + ErrorKind,
+        <T as Unparser<K>>::Err: fmt::Debug,
+        R: Responder<K>,
+        K: fmt::Debug + fmt::Display + Ord + From<u16> + Hash + Clone,
+    {
+        let session_engine = self.session_engines.lock().unwrap().get(&connection.id).cloned();
+        let engine = session_engine.as_deref().unwrap_or(&self.engine);
+        let received = Instant::now();
+        let parsed = parse_frame(engine, line);
+        if let Some(capture) = &self.capture {
+            let fields = match &parsed {
+                Some(Ok(tokens)) => sorted_fields(tokens),
+                _ => vec![],
+            };
+            capture.record(connection.id, Direction::Inbound, line, fields);
+        }
+
+        let stats = &connection.stats;
+        let mut tokens = match parsed {
+            None => return Handled { echo: String::from_utf8_lossy(line).into_owned(), reply: None, reset: false },
+            Some(Err(e)) => {
+                warn!(connection = connection.id, direction = %Direction::Inbound, error = ?e, "Unable to parse message");
+                stats.invalid.fetch_add(1, Ordering::Relaxed);
+                self.metrics.parse_error(&e);
+                return Handled { echo: format!("{:?}", e), reply: None, reset: false };
+            },
+            Some(Ok(tokens)) => tokens,
+        };
+        stats.received.fetch_add(1, Ordering::Relaxed);
+        let request_log = MessageLog::new(engine, &tokens);
+        request_log.log(connection.id, Direction::Inbound);
+        let request_mti = request_log.mti().map(|mti| mti.to_string());
+        self.metrics.message_in(request_mti.as_deref());
+        let echo = engine.dump_fields(&tokens);
+
+        let action = match self.keys.as_ref().and_then(|keys| keys.key_change(connection.id, &tokens)) {
+            Some(KeyChange{ response, mac_key }) => {
+                // the response goes under the old key, and the connection's messages under the new key after it
+                if let Some(key) = mac_key {
+                    match self.engine.with_session_mac_key(key) {
+                        Ok(engine) => {
+                            self.session_engines.lock().unwrap().insert(connection.id, Arc::new(engine));
+                        },
+                        Err(e) => warn!(connection = connection.id, error = %e, "Unable to MAC under the new key"),
+                    }
+                }
+                Action::Respond(response)
+            },
+            None => {
+                if let Some(keys) = &self.keys {
+                    keys.translate_pin(connection.id, &mut tokens);
+                }
+                self.responder.respond(&tokens)
+            },
+        };
+
+        let profile_name = match &action {
+            Action::Fault(name, _) => Some(name.clone()),
+            _ => connection.profile.lock().unwrap().clone(),
+        };
+        let (plan, reset) = {
+            let faults = self.faults.read().unwrap();
+            let profile = profile_name.as_ref().and_then(|name| {
+                let profile = faults.profile(name);
+                if profile.is_none() {
+                    warn!(connection = connection.id, profile = %name, "Unknown fault profile");
+                }
+                profile.map(|profile| (name, profile))
+            });
+            match profile {
+                Some((name, profile)) => {
+                    let mut applied = connection.applied.lock().unwrap();
+                    let applied = applied.entry(name.clone()).or_default();
+                    *applied += 1;
+                    let reset = profile.reset.is_some_and(|reset| *applied >= reset);
+                    (profile.plan(&mut rand::thread_rng()), reset)
+                },
+                None => (FaultPlan::default(), false),
+            }
+        };
+
+        let dropped = |reset| {
+            stats.dropped.fetch_add(1, Ordering::Relaxed);
+            Handled { echo: echo.clone(), reply: None, reset }
+        };
+        let (delay, response) = match action {
+            Action::Respond(response) | Action::Fault(_, response) => (Duration::ZERO, response),
+            Action::Delay(delay, response) => (delay, response),
+            Action::Drop => return dropped(reset),
+            Action::Disconnect => return Handled { echo, reply: Some(Reply::Disconnect), reset },
+        };
+        if plan.drop {
+            return dropped(reset);
+        }
+
+        let mut raw = ISO8583_PREFIX.as_bytes().to_vec();
+        if let Err(e) = engine.unparse(&response, &mut raw) {
+            warn!(connection = connection.id, error = ?e, "Unable to build response");
+            return dropped(reset);
+        }
+        let payload = &mut raw[ISO8583_PREFIX.len()..];
+        if plan.bad_bitmap {
+            engine.corrupt_bitmap(&response, payload);
+        }
+        if plan.bad_length {
+            engine.corrupt_length(&response, payload);
+        }
+        if plan.truncate {
+            raw.truncate(raw.len() / 2);
+        }
+        stats.responded.fetch_add(1, Ordering::Relaxed);
+        let log = MessageLog::new(engine, &response);
+        if let Some(code) = field(&response, FIELD_RESPONSE_CODE) {
+            self.metrics.response_code(log.mti(), code);
+        }
+
+        let out = Outbound {
+            raw,
+            fields: sorted_fields(&response),
+            log: Some(log),
+            copies: plan.copies,
+            drip: plan.drip,
+            request: Some(Received {
+                at: received,
+                mti: request_mti,
+            }),
+        };
+        let delay = delay + plan.delay;
+        let reply = match delay.is_zero() {
+            true => Reply::Now(out),
+            false => Reply::After(delay, out),
+        };
+
+        Handled { echo, reply: Some(reply), reset }
+    }
+
+    /// Handles a frame from outside any connection e.g. the gateway's, as though it came on a connection of its own
+    /// from `peer`: it's captured, logged and counted, answered by the responder with the key exchange and the faults
+    /// for the peer applied, but not echoed to the connections. Returns the response, once any delay is up, or `None`
+    /// if there isn't one.
+    pub async fn exchange<K>(&self, peer: SocketAddr, frame: &[u8]) -> Option<Vec<u8>>
+    where
+        T: Parser<K> + Unparser<K> + Corrupt<K> + MaskFields<K> + SessionEngine,
+        <T as Parser<K>>::Err: fmt::Debug + ErrorKind,
+        <T as Unparser<K>>::Err: fmt::Debug,
+        R: Responder<K>,
+        K: fmt::Debug + fmt::Display + Ord + From<u16> + Hash + Clone,
+    {
+        let (commands, _command_rx) = mpsc::unbounded_channel();
+        let connection = Connection {
+            id: self.connection_ids.fetch_add(1, Ordering::Relaxed),
+            peer,
+            connected_at: SystemTime::now(),
+            profile: Mutex::new(self.faults.read().unwrap().for_peer(&peer).map(|name| name.to_string())),
+            applied: Mutex::new(HashMap::new()),
+            stats: Stats::default(),
+            commands,
+        };
+
+        let Handled{ reply, .. } = self.process_frame(&connection, frame);
+        let out = match reply {
+            Some(Reply::Now(out)) => Some(out),
+            Some(Reply::After(delay, out)) => {
+                time::sleep(delay).await;
+                Some(out)
+            },
+            Some(Reply::Disconnect) | None => None,
+        };
+
+        // written out as to a connection, so faults such as duplicates and truncation apply the same
+        let mut written = vec![];
+        if let Some(out) = out {
+            let capture = self.capture.as_ref();
+            if let Err(e) = write_outbound(&mut written, self.framing, capture, &self.metrics, connection.id, out).await {
+                warn!(connection = connection.id, error = %e, "Unable to write response");
+            }
+        }
+        self.session_engines.lock().unwrap().remove(&connection.id);
+        if let Some(keys) = &self.keys {
+            keys.end_session(connection.id);
+        }
+
+        FrameReader::new(&written[..], self.framing)
+            .next()
+            .await
+            .ok()
+            .flatten()
+    }
+
+    pub async fn serve<K>(self: Arc<Self>)
+    where
+        T: 'static + Parser<K> + Unparser<K> + Corrupt<K> + MaskFields<K> + SessionEngine + Send + Sync,
+        <T as Parser<K>>::Err: fmt::Debug + ErrorKind,
+        <T as Unparser<K>>::Err: fmt::Debug,
+        R: 'static + Responder<K> + Send + Sync,
+        K: fmt::Debug + fmt::Display + Ord + From<u16> + Hash + Clone,
+    {
+        let listen_addr = "localhost:9090";
+        let listener = TcpListener::bind(listen_addr)
+            .await
+            .unwrap_or_else(|e| panic!("Unable to listen on {}: {}", listen_addr, e));
+
+        info!("Listener established for {}", listen_addr);
+
+        self.accept(listener).await;
+    }
+
+    async fn accept<K>(self: Arc<Self>, listener: TcpListener)
+    where
+        T: 'static + Parser<K> + Unparser<K> + Corrupt<K> + MaskFields<K> + SessionEngine + Send + Sync,
+        <T as Parser<K>>::Err: fmt::Debug + ErrorKind,
+        <T as Unparser<K>>::Err: fmt::Debug,
+        R: 'static + Responder<K> + Send + Sync,
+        K: fmt::Debug + fmt::Display + Ord + From<u16> + Hash + Clone,
+    {
+        let (tx, _rx) = broadcast::channel(10);
+
+        loop {
+            let (mut socket, addr) = listener.accept().await.unwrap();
+            let (commands, mut command_rx) = mpsc::unbounded_channel();
+            let connection = Arc::new(Connection {
+                id: self.connection_ids.fetch_add(1, Ordering::Relaxed),
+                peer: addr,
+                connected_at: SystemTime::now(),
+                profile: Mutex::new(self.faults.read().unwrap().for_peer(&addr).map(|name| name.to_string())),
+                applied: Mutex::new(HashMap::new()),
+                stats: Stats::default(),
+                commands,
+            });
+            self.connections.lock().unwrap().insert(connection.id, connection.clone());
+            self.metrics.connection_opened();
+
+            info!(connection = connection.id, peer = %addr, "Accepted connection");
+
+            let tx = tx.clone();
+            let mut rx = tx.subscribe();
+            let sim = self.clone();
+
+            tokio::spawn(async move {
+                let capture = sim.capture.as_ref();
+                let metrics = &*sim.metrics;
+                let framing = sim.framing;
+                let (reader, mut writer) = socket.split();
+                // frames are read as bytes as binary fields needn't be UTF-8
+                let mut reader = FrameReader::new(reader, framing);
+
+                loop {
+                    tokio::select! {
+                        result = reader.next() => {
+                            let line = match result {
+                                Ok(Some(line)) => line,
+                                Ok(None) | Err(_) => break,
+                            };
+                            let Handled{ echo, reply, reset } = sim.process_frame(&connection, &line);
+                            let written = match reply {
+                                Some(Reply::Now(out)) => write_outbound(&mut writer, framing, capture, metrics, connection.id, out).await,
+                                Some(Reply::After(delay, out)) => {
+                                    let commands = connection.commands.clone();
+                                    let metrics = sim.metrics.clone();
+                                    metrics.queued();
+                                    tokio::spawn(async move {
+                                        time::sleep(delay).await;
+                                        if commands.send(Command::Send(Box::new(out))).is_err() {
+                                            metrics.dequeued();
+                                        }
+                                    });
+                                    Ok(())
+                                },
+                                Some(Reply::Disconnect) => break,
+                                None => Ok(()),
+                            };
+                            if written.is_err() {
+                                break;
+                            }
+                            tx.send((echo, addr)).unwrap();
+                            if reset {
+                                info!(connection = connection.id, "Resetting connection");
+                                let _ = writer.as_ref().set_zero_linger();
+                                break;
+                            }
                         }
-                        line = line.trim_end_matches('\n').into();
-                        if line.starts_with("iso8583:") {
-                            line = line.trim_start_matches("iso8583:").into();
-                            line = match thread_engine.parse(line[..].as_bytes()) {
-                                Ok(tokens) => format!("{:?}", tokens),
-                                Err(e) => format!("{:?}", e),
+                        Some(command) = command_rx.recv() => {
+                            let out = match command {
+                                Command::Send(out) => {
+                                    metrics.dequeued();
+                                    *out
+                                },
+                                Command::Close => break,
                             };
+                            if write_outbound(&mut writer, framing, capture, metrics, connection.id, out).await.is_err() {
+                                break;
+                            }
                         }
-                        tx.send((line.clone() + "\n", addr)).unwrap();
-                    }
-                    result = rx.recv() => {
-                        let (msg, recv_addr)  = result.unwrap();
-                        if recv_addr != addr {
-                            writer.write(recv_addr.to_string().as_bytes()).await.unwrap();
-                            writer.write(" => ".as_bytes()).await.unwrap();
-                            writer.write_all(msg.as_bytes()).await.unwrap();
+                        result = rx.recv() => {
+                            let (msg, recv_addr)  = result.unwrap();
+                            if recv_addr != addr {
+                                let out = format!("{}{}{}", recv_addr, ECHO_SEPARATOR, msg);
+                                let written = match framing.encode(out.as_bytes()) {
+                                    Ok(framed) => writer.write_all(&framed).await,
+                                    Err(e) => Err(e),
+                                };
+                                if written.is_err() {
+                                    break;
+                                }
+                                if let Some(capture) = capture {
+                                    capture.record(connection.id, Direction::Outbound, out.as_bytes(), vec![]);
+                                }
+                            }
                         }
                     }
                 }
-            }
-        });
+
+                sim.connections.lock().unwrap().remove(&connection.id);
+                sim.session_engines.lock().unwrap().remove(&connection.id);
+                if let Some(keys) = &sim.keys {
+                    keys.end_session(connection.id);
+                }
+                command_rx.close();
+                while let Ok(command) = command_rx.try_recv() {
+                    if let Command::Send(_) = command {
+                        metrics.dequeued();
+                    }
+                }
+                metrics.connection_closed();
+                info!(connection = connection.id, "Closed connection");
+            });
+        }
     }
-    // let res = tokio::spawn(async move {
-    //     for _ in 0..10 {
-    //         println!("hello world");
-    //         std::thread::sleep(std::time::Duration::from_millis(1000));
-    //     }
-    // }).await;
+}
 
-    // println!("{:?}", res);
+async fn write_outbound<W>(writer: &mut W, framing: Framing, capture: Option<&Capture>, metrics: &Metrics, connection: u64, out: Outbound) -> std::io::Result<()>
+where
+    W: AsyncWrite + Unpin,
+{
+    let Outbound{ raw, fields, log, copies, drip, request } = out;
+    let framed = framing.encode(&raw)?;
+
+    for _ in 0..copies {
+        if let Some(capture) = capture {
+            capture.record(connection, Direction::Outbound, &raw, fields.clone());
+        }
+        match drip {
+            Some(interval) => {
+                for byte in &framed {
+                    writer.write_all(&[*byte]).await?;
+                    time::sleep(interval).await;
+                }
+            },
+            None => writer.write_all(&framed).await?,
+        }
+        if let Some(log) = &log {
+            log.log(connection, Direction::Outbound);
+        }
+        metrics.message_out(log.as_ref().and_then(|log| log.mti()));
+    }
+    if let Some(Received{ at, mti }) = request {
+        metrics.latency(mti.as_deref(), at.elapsed());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use tokio::net::TcpStream;
+    use zaps::iso8583::{
+        spec::Spec,
+        Iso8583Engine,
+        MTI_FIELD,
+    };
+
+    /// Approves everything, echoing the request's fields
+    struct Approve;
+
+    impl Responder<u16> for Approve {
+        fn respond(&self, request: &HashMap<u16, String>) -> Action<u16> {
+            let mut response = request.clone();
+            response.insert(MTI_FIELD, "0210".to_string());
+            response.insert(FIELD_RESPONSE_CODE, "00".to_string());
+            Action::Respond(response)
+        }
+    }
+
+    fn engine() -> Iso8583Engine {
+        let spec = "
+            field 0 AsciiBitmap(64)
+            field 11 Fixed(6:n)
+            field 39 Fixed(2:an)
+            field 52 Fixed(8:bin)
+            mti 0200 0210
+        ".parse::<Spec>().unwrap();
+        Iso8583Engine::new(spec)
+    }
+
+    #[tokio::test]
+    async fn binary_frames() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(Arc::new(Simulator::new(engine(), Approve).with_framing(Framing::Length)).accept::<u16>(listener));
+
+        // a PIN block with newlines in it, which would split a newline delimited frame
+        let pin_block = "\u{12}\n\u{34}\n\u{56}\u{78}\u{9a}\n";
+        let request = [(MTI_FIELD, "0200"), (11, "000001"), (52, pin_block)]
+            .iter()
+            .map(|(k, v)| (*k, v.to_string()))
+            .collect::<HashMap<_, _>>();
+        let mut frame = ISO8583_PREFIX.as_bytes().to_vec();
+        engine().unparse(&request, &mut frame).unwrap();
+
+        let (reader, mut writer) = TcpStream::connect(addr).await.unwrap().into_split();
+        writer.write_all(&Framing::Length.encode(&frame).unwrap()).await.unwrap();
+        let response = FrameReader::new(reader, Framing::Length).next().await.unwrap().unwrap();
+        let response = parse_frame(&engine(), &response).unwrap().unwrap();
+
+        assert_eq!("00", response[&FIELD_RESPONSE_CODE]);
+        assert_eq!(pin_block, response[&52]);
+    }
+
+    #[tokio::test]
+    async fn binary_macs() {
+        let spec = "
+            field 0 AsciiBitmap(64)
+            field 11 Fixed(6:n)
+            field 39 Fixed(2:an)
+            field 64 Fixed(8:bin)
+            mti 0200 0210
+            mac retail
+        ".parse::<Spec>().unwrap();
+        let engine = Iso8583Engine::new(spec).with_mac_key("tdes:0123456789ABCDEFFEDCBA9876543210".parse().unwrap()).unwrap();
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(Arc::new(Simulator::new(engine.clone(), Approve).with_framing(Framing::Length)).accept::<u16>(listener));
+
+        // the first request whose MAC has a newline in it
+        let frame = (0..1000)
+            .map(|stan| {
+                let request = HashMap::from([(MTI_FIELD, "0200".to_string()), (11, format!("{:06}", stan))]);
+                let mut frame = ISO8583_PREFIX.as_bytes().to_vec();
+                engine.unparse(&request, &mut frame).unwrap();
+                frame
+            })
+            .find(|frame| frame[frame.len() - 8..].contains(&b'\n'))
+            .unwrap();
+
+        let (reader, mut writer) = TcpStream::connect(addr).await.unwrap().into_split();
+        writer.write_all(&Framing::Length.encode(&frame).unwrap()).await.unwrap();
+        let response = FrameReader::new(reader, Framing::Length).next().await.unwrap().unwrap();
+        let response = parse_frame(&engine, &response).unwrap().unwrap();
+
+        assert_eq!("00", response[&FIELD_RESPONSE_CODE]);
+    }
 }
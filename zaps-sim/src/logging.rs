@@ -0,0 +1,200 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::hash::Hash;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use tracing::info;
+use tracing_subscriber::EnvFilter;
+use zaps::iso8583::{
+    BinaryEncoding,
+    Iso8583Engine,
+    Message,
+    MTI_FIELD,
+};
+
+use crate::capture::Direction;
+
+/// Masks sensitive fields before messages are logged or echoed, for engines which know which fields are sensitive.
+pub trait MaskFields<K> {
+    fn mask_fields(&self, tokens: &HashMap<K, String>) -> HashMap<K, String>;
+
+    /// The masked message laid out field by field
+    fn dump_fields(&self, tokens: &HashMap<K, String>) -> String;
+
+    /// The masked message as a JSON object of field number to value, with the MTI under `mti`
+    fn json_fields(&self, tokens: &HashMap<K, String>) -> String;
+}
+
+impl MaskFields<u16> for Iso8583Engine {
+    fn mask_fields(&self, tokens: &HashMap<u16, String>) -> HashMap<u16, String> {
+        self.spec().mask_fields(tokens)
+    }
+
+    fn dump_fields(&self, tokens: &HashMap<u16, String>) -> String {
+        self.dump(tokens).to_string()
+    }
+
+    fn json_fields(&self, tokens: &HashMap<u16, String>) -> String {
+        let mut message = Message::from_tokens(self.spec(), tokens, BinaryEncoding::Hex);
+        message.mask(self.spec());
+        serde_json::to_string(&message).unwrap_or_default()
+    }
+}
+
+/// Whether messages are logged as JSON too, as they are with [`LogFormat::Json`]
+static JSON_MESSAGES: AtomicBool = AtomicBool::new(false);
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum LogFormat {
+    /// Human readable, with every message dumped field by field
+    Dump,
+    /// One JSON object per line
+    Json,
+}
+
+impl fmt::Display for LogFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Dump => write!(f, "dump"),
+            Self::Json => write!(f, "json"),
+        }
+    }
+}
+
+impl FromStr for LogFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "dump" => Ok(Self::Dump),
+            "json" => Ok(Self::Json),
+            _ => Err(format!("invalid log format: {}", s)),
+        }
+    }
+}
+
+/// Logs to stdout at info and above, or as set by `RUST_LOG`.
+pub fn init(format: LogFormat) {
+    let filter = EnvFilter::try_from_default_env()
+        .unwrap_or_else(|_e| EnvFilter::new("info"));
+    let builder = tracing_subscriber::fmt()
+        .with_env_filter(filter)
+        .with_target(false);
+
+    match format {
+        LogFormat::Dump => builder.init(),
+        LogFormat::Json => {
+            JSON_MESSAGES.store(true, Ordering::Relaxed);
+            builder.json().init()
+        },
+    }
+}
+
+/// What is logged about a message, with sensitive fields already masked
+pub struct MessageLog {
+    mti: Option<String>,
+    /// Present fields, excluding the MTI and bitmap
+    fields: Vec<String>,
+    dump: String,
+    /// Only with [`LogFormat::Json`]
+    json: Option<String>,
+}
+
+impl MessageLog {
+    pub fn new<K, T>(engine: &T, tokens: &HashMap<K, String>) -> Self
+    where
+        T: MaskFields<K>,
+        K: Ord + fmt::Display + From<u16> + Hash,
+    {
+        let mti_key = K::from(MTI_FIELD);
+        let bitmap_key = K::from(0);
+        let mut fields = tokens.keys()
+            .filter(|k| **k != mti_key && **k != bitmap_key)
+            .collect::<Vec<_>>();
+        fields.sort();
+
+        MessageLog {
+            mti: tokens.get(&mti_key).cloned(),
+            fields: fields.into_iter().map(|k| k.to_string()).collect(),
+            dump: engine.dump_fields(tokens),
+            json: match JSON_MESSAGES.load(Ordering::Relaxed) {
+                true => Some(engine.json_fields(tokens)),
+                false => None,
+            },
+        }
+    }
+
+    pub fn mti(&self) -> Option<&str> {
+        self.mti.as_deref()
+    }
+
+    pub fn log(&self, connection: u64, direction: Direction) {
+        info!(
+            connection,
+            direction = %direction,
+            mti = self.mti.as_deref().unwrap_or_default(),
+            fields = %self.fields.join(","),
+            iso8583 = self.json.as_deref(),
+            "{}", self.dump,
+        );
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use zaps::iso8583::spec::{
+        Field,
+        Spec,
+    };
+
+    #[test]
+    fn masks_and_dumps() {
+        let mut spec = Spec::new();
+        let fields = [(0, "AsciiBitmap(64)"), (2, "LLVar(n)"), (4, "Fixed(12:n)"), (52, "Fixed(16:h)")]
+            .iter()
+            .map(|(k, v)| (*k, v.parse::<Field>().unwrap()))
+            .collect();
+        spec.add_mti_spec("0200".to_string(), fields);
+        let engine = Iso8583Engine::new(spec);
+        let tokens = [(MTI_FIELD, "0200"), (0, "0101000000000000000000000000000000000000000000000000100000000000"), (2, "4111111111111111"), (4, "000000001000"), (52, "1234567890ABCDEF")]
+            .iter()
+            .map(|(k, v)| (*k, v.to_string()))
+            .collect();
+
+        let log = MessageLog::new(&engine, &tokens);
+        assert_eq!(Some("0200".to_string()), log.mti);
+        assert_eq!(vec!["2", "4", "52"], log.fields);
+        assert!(log.dump.contains("  002 LLVar(n..99)    16 411111******1111\n"));
+        assert!(log.dump.contains("  052 Fixed(16:h)     16 ****************"));
+        assert!(!log.dump.contains("1234567890ABCDEF"));
+    }
+
+    #[test]
+    fn json_fields() {
+        let mut spec = Spec::new();
+        let fields = [(0, "AsciiBitmap(64)"), (2, "LLVar(n)"), (4, "Fixed(12:n)"), (64, "Fixed(4:bin)")]
+            .iter()
+            .map(|(k, v)| (*k, v.parse::<Field>().unwrap()))
+            .collect();
+        spec.add_mti_spec("0200".to_string(), fields);
+        let engine = Iso8583Engine::new(spec);
+        let tokens = [(MTI_FIELD, "0200"), (0, "0101000000000000000000000000000000000000000000000000000000000001"), (2, "4111111111111111"), (4, "000000001000"), (64, "MAC1")]
+            .iter()
+            .map(|(k, v)| (*k, v.to_string()))
+            .collect();
+
+        assert_eq!(
+            r#"{"mti":"0200","2":"411111******1111","4":"000000001000","64":"4D414331"}"#,
+            engine.json_fields(&tokens),
+        );
+    }
+
+    #[test]
+    fn log_format() {
+        assert_eq!(Ok(LogFormat::Json), "json".parse());
+        assert_eq!(Ok(LogFormat::Dump), "dump".parse());
+        assert!("xml".parse::<LogFormat>().is_err());
+    }
+}
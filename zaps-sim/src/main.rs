@@ -1,23 +1,528 @@
 // Shamefule theft from Lily Mara/Code Tech as a baseline - https://www.youtube.com/watch?v=Iapc-qGTEBQ
+use std::path::PathBuf;
+use std::process;
+use std::sync::Arc;
+
+use clap::{Parser, Subcommand};
 use zaps::{
-    iso8583_spec_build,
+    card::{
+        is_valid_expiry,
+        BinTable,
+        FixedClock,
+    },
+    crypto::{
+        dukpt::Terminal,
+        hsm::{
+            Hsm,
+            KeyType,
+        },
+        pin::PinFormat,
+        Algorithm,
+        Key,
+    },
+    emv::cryptogram::{
+        ArpcMethod,
+        Issuer,
+    },
     iso8583::{
         Iso8583Engine,
+        spec::{
+            Dictionary,
+            MacSettings,
+            Spec,
+        },
     },
 };
 use zaps_sim::{
-    serve,
+    admin,
+    capture::{
+        read_capture,
+        Capture,
+    },
+    cards::{
+        CardChecks,
+        CardDatabase,
+    },
+    cvv::{
+        CvvChecks,
+        FieldLocation,
+        DEFAULT_TRACK_CVV_OFFSET,
+    },
+    dukpt::{
+        PinPad,
+        DEFAULT_KSN_FIELD,
+    },
+    faults::Faults,
+    framing::Framing,
+    gateway::{
+        self,
+        Gateway,
+    },
+    keys::KeyExchange,
+    logging::{
+        self,
+        LogFormat,
+    },
+    metrics,
+    proxy::{
+        load_rules,
+        Proxy,
+    },
+    replay::replay,
+    responder::{
+        AutoResponder,
+        Responder,
+    },
+    rules::{
+        RuleResponder,
+        RuleSet,
+    },
+    script::ScriptResponder,
+    store::TransactionStore,
+    Simulator,
 };
 
+const MTIS: &[&str] = &[
+    "0100", "0101", "0110", "0120", "0121", "0130",
+    "0200", "0201", "0210", "0220", "0221", "0230",
+    "0400", "0401", "0410", "0420", "0421", "0430",
+    "0620", "0630",
+    "0800", "0810",
+];
+
+/// The 1987 data elements, with the bitmaps in ASCII hex
+fn spec() -> Spec {
+    let mut spec = Spec::standard(Dictionary::Iso1987, MTIS);
+    spec.set_field(0, "AsciiBitmap(64)".parse().unwrap());
+    spec.set_field(1, "AsciiBitmap(64)".parse().unwrap());
+    spec
+}
+
+#[derive(Parser)]
+#[command(about = "ZAPS payments simulator")]
+struct Args {
+    /// Serve the admin API on this address e.g. localhost:9091
+    #[arg(long)]
+    admin: Option<String>,
+
+    /// Generate ARPCs for `--imk` by method `1`, with the response code, or `2`, with a card status update
+    #[arg(long, default_value_t = ArpcMethod::Method1)]
+    arpc_method: ArpcMethod,
+
+    /// Record all inbound and outbound frames to this file
+    #[arg(long)]
+    capture: Option<PathBuf>,
+
+    /// Derive PIN keys by DUKPT from this base derivation key and the KSN in the KSN field, to check PIN blocks
+    /// against the cards' PINs and for `--terminal-ksn`
+    #[arg(long)]
+    bdk: Option<Key>,
+
+    /// Only accept cards in the BIN ranges in this file, one `low,high,name` or `bin,name` per line. Implies
+    /// `--check-cards`.
+    #[arg(long)]
+    bins: Option<PathBuf>,
+
+    /// Decline cards whose PAN, from field 2 or the track data, fails the Luhn check with 14, and those whose expiry,
+    /// from field 14 or the track data, has passed with 54
+    #[arg(long)]
+    check_cards: bool,
+
+    /// Check CVVs in track 2 data, as iCVVs for chip transactions, and CVV2s in `--cvv2-field` with this test CVK pair
+    /// e.g. tdes:0123456789ABCDEFFEDCBA9876543210
+    #[arg(long)]
+    cvk: Option<Key>,
+
+    /// Where the CVV is in the track 2 discretionary data
+    #[arg(long, default_value_t = DEFAULT_TRACK_CVV_OFFSET)]
+    cvv_offset: usize,
+
+    /// The field CVV2s are in, or a subelement of it made of `<2 digit tag><2 digit length><value>` e.g. 48.92
+    #[arg(long, requires = "cvk")]
+    cvv2_field: Option<FieldLocation>,
+
+    /// Authorise against the cards in this file, one `pan,expiry,status,balance,currency,daily_limit[,pin]` per line
+    #[arg(long)]
+    cards: Option<PathBuf>,
+
+    /// Load fault profiles, and the connections they apply to, from this file
+    #[arg(long)]
+    faults: Option<PathBuf>,
+
+    /// Delimit frames with a `newline` for typing text messages e.g. with nc, or a 2 byte big endian `length` header
+    /// for binary fields
+    #[arg(long, default_value_t = Framing::Newline)]
+    framing: Framing,
+
+    /// Serve the JSON gateway on this address e.g. localhost:9092
+    #[arg(long)]
+    gateway: Option<String>,
+
+    /// Forward gateway messages to this host e.g. localhost:9090, rather than responding to them
+    #[arg(long, requires = "gateway")]
+    gateway_target: Option<String>,
+
+    /// Log messages as a human readable `dump` or as `json`, with sensitive fields masked
+    #[arg(long, default_value_t = LogFormat::Dump)]
+    log_format: LogFormat,
+
+    /// The field DUKPT KSNs are in, in hex unless it's binary
+    #[arg(long, default_value_t = DEFAULT_KSN_FIELD)]
+    ksn_field: u16,
+
+    /// Hold keys, encrypted under the master key, in this file and exchange working keys under its ZMK. Its `zpk`
+    /// and `tak` keys are the PIN and MAC keys unless they're given.
+    #[arg(long, requires = "master_key")]
+    hsm: Option<PathBuf>,
+
+    /// Verify ARQCs in field 55 and answer them with ARPCs, deriving the cards' keys from this test issuer master
+    /// key e.g. tdes:0123456789ABCDEFFEDCBA9876543210
+    #[arg(long)]
+    imk: Option<Key>,
+
+    /// MAC messages in field 64 or 128 with these settings, `alg1`, `alg3` (or `retail`) or `cmac`, optionally
+    /// followed by `pad2` and `fields <number>...`
+    #[arg(long, default_value = "retail")]
+    mac: MacSettings,
+
+    /// Check inbound messages' MACs and MAC responses with this test key e.g. tdes:0123456789ABCDEFFEDCBA9876543210
+    #[arg(long)]
+    mac_key: Option<Key>,
+
+    /// The key the HSM's keys are encrypted under
+    #[arg(long)]
+    master_key: Option<Key>,
+
+    /// Serve Prometheus metrics on this address e.g. localhost:9100
+    #[arg(long)]
+    metrics: Option<String>,
+
+    /// Check PIN blocks against the cards' PINs, decrypting them with this test key e.g. tdes:0123456789ABCDEFFEDCBA9876543210
+    #[arg(long, requires = "cards")]
+    pin_key: Option<Key>,
+
+    /// The PIN block format, `iso0`, `iso1`, `iso3` or `iso4` (with an AES key)
+    #[arg(long, default_value_t = PinFormat::Iso0)]
+    pin_format: PinFormat,
+
+    /// Decide responses with this Rhai script, reloaded whenever it changes
+    #[arg(long)]
+    script: Option<PathBuf>,
+
+    /// Persist the transaction store to this file so it survives restarts
+    #[arg(long)]
+    store: Option<PathBuf>,
+
+    /// Emulate a terminal for gateway messages sent to the target, starting from this KSN in hex and encrypting the
+    /// clear PIN in field 52 under the next DUKPT key for each message
+    #[arg(long, requires_all = ["bdk", "gateway_target"])]
+    terminal_ksn: Option<String>,
+
+    /// Check card expiries as if this were the current month, YYMM, rather than the system clock's
+    #[arg(long)]
+    today: Option<String>,
+
+    /// The name of the HSM's key to exchange working keys under
+    #[arg(long, default_value = "zmk")]
+    zmk: String,
+
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Resend a capture against a target and compare the responses with those recorded
+    Replay {
+        capture: PathBuf,
+        /// host:port to replay against
+        target: String,
+        /// Send frames as fast as possible rather than with the original timing
+        #[arg(long)]
+        fast: bool,
+    },
+    /// Sit between a client and a host, logging the messages both ways and rewriting them by rule
+    Proxy {
+        /// Address to accept clients on e.g. localhost:9080
+        listen: String,
+        /// host:port to forward to
+        target: String,
+        /// Decode the client's messages with this spec file rather than the simulator's
+        #[arg(long)]
+        client_spec: Option<PathBuf>,
+        /// Decode the host's messages with this spec file rather than the simulator's
+        #[arg(long)]
+        host_spec: Option<PathBuf>,
+        /// Rewrite messages by the rules in this JSON file
+        #[arg(long)]
+        rules: Option<PathBuf>,
+    },
+    /// Manage the keys in an HSM file
+    Keys {
+        file: PathBuf,
+        /// The key the file's keys are encrypted under
+        #[arg(long)]
+        master_key: Key,
+        #[command(subcommand)]
+        command: KeysCommand,
+    },
+}
+
+#[derive(Subcommand)]
+enum KeysCommand {
+    /// List the keys with their types, algorithms and check values
+    List,
+    /// Make a random key of type `zmk`, `zpk` or `tak`
+    Generate {
+        name: String,
+        key_type: KeyType,
+        #[arg(long, default_value = "tdes")]
+        algorithm: Algorithm,
+        /// The key length in bytes
+        #[arg(long, default_value_t = 16)]
+        length: usize,
+    },
+    /// Add a clear key e.g. a ZMK agreed with the other party
+    Insert {
+        name: String,
+        key_type: KeyType,
+        /// The key e.g. tdes:0123456789ABCDEFFEDCBA9876543210
+        key: Key,
+    },
+    /// Print a key encrypted under a ZMK, and its check value
+    Export {
+        name: String,
+        zmk: String,
+    },
+    /// Add a key received encrypted under a ZMK
+    Import {
+        name: String,
+        key_type: KeyType,
+        zmk: String,
+        /// The encrypted key e.g. tdes:<hex>
+        key: String,
+        /// Check the key against this check value
+        #[arg(long)]
+        check_value: Option<String>,
+    },
+}
+
+/// Carries out a `keys` command, printing the check value of any key added
+fn manage_keys(file: PathBuf, master_key: Key, command: KeysCommand) {
+    let mut hsm = Hsm::open(&file, master_key)
+        .unwrap_or_else(|e| panic!("Unable to open HSM {}: {}", file.display(), e));
+    let check_value = match command {
+        KeysCommand::List => {
+            for name in hsm.names() {
+                let key = hsm.get(name).unwrap();
+                println!("{} {} {} {}", name, key.key_type, key.algorithm, key.check_value);
+            }
+            return;
+        },
+        KeysCommand::Generate{ name, key_type, algorithm, length } => hsm.generate(&name, key_type, algorithm, length),
+        KeysCommand::Insert{ name, key_type, key } => hsm.insert(&name, key_type, &key),
+        KeysCommand::Export{ name, zmk } => {
+            let (encrypted, check_value) = hsm.export(&name, &zmk)
+                .unwrap_or_else(|e| panic!("Unable to export {}: {}", name, e));
+            println!("{} {}", hex::encode_upper(encrypted), check_value);
+            return;
+        },
+        KeysCommand::Import{ name, key_type, zmk, key, check_value } => {
+            let (algorithm, encrypted) = key.split_once(':')
+                .and_then(|(algorithm, encrypted)| Some((algorithm.parse::<Algorithm>().ok()?, hex::decode(encrypted).ok()?)))
+                .unwrap_or_else(|| panic!("Expected the key as <algorithm>:<hex>, got {}", key));
+            hsm.import(&name, key_type, &zmk, algorithm, &encrypted, check_value.as_deref())
+        },
+    };
+    let check_value = check_value
+        .unwrap_or_else(|e| panic!("Unable to add key: {}", e));
+    println!("{}", check_value);
+}
+
+/// The spec in the file, or the simulator's own
+fn load_spec(path: Option<PathBuf>) -> Spec {
+    match path {
+        Some(path) => Spec::load(&path)
+            .unwrap_or_else(|e| panic!("Unable to load spec {}: {}", path.display(), e)),
+        None => spec(),
+    }
+}
+
 #[tokio::main]
 async fn main() {
-    let spec = iso8583_spec_build!(
-        "0200":
-            0: AsciiBitmap, 8;
-            1: LLLVar, Alpha;
-            8: Fixed, 15, Alphanum;
-    );
-    // try sending "iso8583:020081003ABC0123456789abcde" or similar
-    let engine = Iso8583Engine::new(spec);
-    serve(engine).await;
+    let args = Args::parse();
+    logging::init(args.log_format);
+
+    let hsm = args.hsm.as_ref().map(|path| {
+        let master_key = args.master_key.clone().unwrap();
+        Hsm::open(path, master_key)
+            .unwrap_or_else(|e| panic!("Unable to open HSM {}: {}", path.display(), e))
+    });
+    let hsm_key = |name, key_type| hsm.as_ref()
+        .filter(|hsm| hsm.get(name).is_some())
+        .map(|hsm| hsm.key(name, key_type).unwrap_or_else(|e| panic!("Unable to use HSM key {}: {}", name, e)));
+    let pin_key = args.pin_key.clone().or_else(|| hsm_key("zpk", KeyType::Zpk));
+    let mac_key = args.mac_key.clone().or_else(|| hsm_key("tak", KeyType::Tak));
+
+    // try sending "iso8583:020070280000008000001641111111111111110000000000000010000000011019TERMID01" or similar
+    let mut spec = spec();
+    if let Some(location) = &args.cvv2_field {
+        location.redact(&mut spec);
+    }
+    let engine = match mac_key.clone() {
+        Some(key) => {
+            spec.set_mac(Some(args.mac));
+            Iso8583Engine::new(spec).with_mac_key(key)
+                .unwrap_or_else(|e| panic!("Unable to use MAC key: {}", e))
+        },
+        None => Iso8583Engine::new(spec),
+    };
+
+    match args.command {
+        Some(Command::Replay{ capture, target, fast }) => {
+            let records = read_capture(&capture)
+                .await
+                .unwrap_or_else(|e| panic!("Unable to read capture {}: {}", capture.display(), e));
+            let differences = replay(&engine, &records, &target, args.framing, fast)
+                .await
+                .unwrap_or_else(|e| panic!("Unable to replay against {}: {}", target, e));
+
+            for difference in &differences {
+                println!("{}", difference);
+            }
+            println!("Replayed {} records with {} differences", records.len(), differences.len());
+            if !differences.is_empty() {
+                process::exit(1);
+            }
+        },
+        Some(Command::Keys{ file, master_key, command }) => manage_keys(file, master_key, command),
+        Some(Command::Proxy{ listen, target, client_spec, host_spec, rules }) => {
+            let client = Iso8583Engine::new(load_spec(client_spec));
+            let host = Iso8583Engine::new(load_spec(host_spec));
+            let rules = match rules {
+                Some(path) => load_rules(&path)
+                    .unwrap_or_else(|e| panic!("Unable to load rules {}: {}", path.display(), e)),
+                None => vec![],
+            };
+            let proxy = Proxy::new(client, host, &target)
+                .with_rules(rules)
+                .with_framing(args.framing);
+            Arc::new(proxy).serve(&listen).await;
+        },
+        None => {
+            let store = match args.store {
+                Some(path) => TransactionStore::open(&path)
+                    .unwrap_or_else(|e| panic!("Unable to open transaction store {}: {}", path.display(), e)),
+                None => TransactionStore::new(),
+            };
+            let mut auto_responder = AutoResponder::new(store);
+            if let Some(path) = args.cards {
+                let cards = CardDatabase::load(&path)
+                    .unwrap_or_else(|e| panic!("Unable to load cards {}: {}", path.display(), e));
+                auto_responder = auto_responder.with_cards(cards);
+            }
+            if let Some(key) = &pin_key {
+                if key.algorithm() != args.pin_format.algorithm() {
+                    panic!("{} PIN blocks need a {} key", args.pin_format, args.pin_format.algorithm());
+                }
+                auto_responder = auto_responder.with_pin_key(key.clone(), args.pin_format);
+            }
+            if let (Some(bdk), None) = (&args.bdk, &pin_key) {
+                if bdk.algorithm() != args.pin_format.algorithm() {
+                    panic!("{} PIN blocks need a {} key", args.pin_format, args.pin_format.algorithm());
+                }
+                auto_responder = auto_responder.with_dukpt(bdk.clone(), args.ksn_field, args.pin_format);
+            }
+            if args.check_cards || args.bins.is_some() {
+                let mut checks = CardChecks::new();
+                if let Some(path) = args.bins {
+                    let bins = BinTable::load(&path)
+                        .unwrap_or_else(|e| panic!("Unable to load BIN table {}: {}", path.display(), e));
+                    checks = checks.with_bins(bins);
+                }
+                auto_responder = auto_responder.with_card_checks(checks);
+            }
+            if let Some(cvk) = args.cvk {
+                let mut checks = CvvChecks::new(cvk)
+                    .unwrap_or_else(|e| panic!("Unable to use CVK: {}", e))
+                    .with_track_offset(args.cvv_offset);
+                if let Some(location) = args.cvv2_field {
+                    checks = checks.with_cvv2(location);
+                }
+                auto_responder = auto_responder.with_cvv_checks(checks);
+            }
+            if let Some(today) = args.today {
+                if !is_valid_expiry(&today) {
+                    panic!("Today must be YYMM: {}", today);
+                }
+                auto_responder = auto_responder.with_clock(FixedClock(today));
+            }
+            if let Some(imk) = args.imk {
+                let issuer = Issuer::new(imk)
+                    .unwrap_or_else(|e| panic!("Unable to use issuer master key: {}", e))
+                    .with_arpc_method(args.arpc_method);
+                auto_responder = auto_responder.with_issuer(issuer);
+            }
+            let responder: Box<dyn Responder<u16> + Send + Sync> = match args.script {
+                Some(path) => Box::new(ScriptResponder::new(&path, auto_responder)
+                    .unwrap_or_else(|e| panic!("Unable to load script {}: {}", path.display(), e))),
+                None => Box::new(auto_responder),
+            };
+            let rules = Arc::new(RuleSet::new());
+            let responder = RuleResponder::new(rules.clone(), responder);
+
+            let mut sim = Simulator::new(engine, responder).with_framing(args.framing);
+            if let Some(path) = args.capture {
+                let capture = Capture::create(&path)
+                    .await
+                    .unwrap_or_else(|e| panic!("Unable to open capture {}: {}", path.display(), e));
+                sim = sim.with_capture(capture);
+            }
+            if let Some(path) = args.faults {
+                let faults = Faults::load(&path)
+                    .unwrap_or_else(|e| panic!("Unable to load faults {}: {}", path.display(), e));
+                sim = sim.with_faults(faults);
+            }
+            if let Some(hsm) = hsm {
+                let zmk = args.zmk;
+                let mut keys = KeyExchange::new(hsm, &zmk)
+                    .unwrap_or_else(|e| panic!("Unable to exchange keys under {}: {}", zmk, e));
+                if let Some(key) = pin_key {
+                    keys = keys.with_pin_key(key, args.pin_format);
+                }
+                if let Some(key) = mac_key {
+                    keys = keys.with_mac_key(key);
+                }
+                sim = sim.with_key_exchange(keys);
+            }
+
+            let sim = Arc::new(sim);
+            if let Some(addr) = args.admin {
+                let sim = sim.clone();
+                tokio::spawn(async move {
+                    admin::serve(&addr, sim, rules).await;
+                });
+            }
+            if let Some(addr) = args.gateway {
+                let mut gateway = Gateway::new(sim.clone(), args.gateway_target);
+                if let (Some(ksn), Some(bdk)) = (&args.terminal_ksn, &args.bdk) {
+                    let ksn = hex::decode(ksn)
+                        .unwrap_or_else(|_e| panic!("Terminal KSN {} isn't hex", ksn));
+                    let terminal = Terminal::new(bdk, &ksn)
+                        .unwrap_or_else(|e| panic!("Unable to load the terminal: {}", e));
+                    gateway = gateway.with_pin_pad(PinPad::new(terminal, args.ksn_field, args.pin_format));
+                }
+                tokio::spawn(async move {
+                    gateway::serve(&addr, gateway).await;
+                });
+            }
+            if let Some(addr) = args.metrics {
+                let metrics = sim.metrics().clone();
+                tokio::spawn(async move {
+                    metrics::serve(&addr, metrics).await;
+                });
+            }
+            sim.serve().await;
+        },
+    }
 }
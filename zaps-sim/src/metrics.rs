@@ -0,0 +1,200 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use axum::{
+    extract::State,
+    http::header,
+    response::IntoResponse,
+    routing::get,
+    Router,
+};
+use prometheus::{
+    Encoder,
+    HistogramOpts,
+    HistogramVec,
+    IntCounterVec,
+    IntGauge,
+    Opts,
+    Registry,
+    TextEncoder,
+};
+use tokio::net::TcpListener;
+use tracing::{error, info};
+use zaps::iso8583::Iso8583ParseError;
+
+/// Label for messages with no MTI, e.g. frames which aren't ISO8583
+const NO_MTI: &str = "none";
+
+/// Parse errors which can be counted by kind, without details that would make every error its own label
+pub trait ErrorKind {
+    fn kind(&self) -> &'static str;
+}
+
+impl ErrorKind for Iso8583ParseError {
+    fn kind(&self) -> &'static str {
+        Iso8583ParseError::kind(self)
+    }
+}
+
+/// The simulator's Prometheus metrics
+pub struct Metrics {
+    registry: Registry,
+    messages: IntCounterVec,
+    response_codes: IntCounterVec,
+    parse_errors: IntCounterVec,
+    connections: IntGauge,
+    latency: HistogramVec,
+    queue_depth: IntGauge,
+}
+
+impl Metrics {
+    pub fn new() -> Self {
+        let messages = IntCounterVec::new(
+            Opts::new("zaps_messages_total", "ISO8583 messages by direction and MTI"),
+            &["direction", "mti"],
+        ).unwrap();
+        let response_codes = IntCounterVec::new(
+            Opts::new("zaps_response_codes_total", "Responses sent by MTI and response code (39)"),
+            &["mti", "code"],
+        ).unwrap();
+        let parse_errors = IntCounterVec::new(
+            Opts::new("zaps_parse_errors_total", "Inbound frames which couldn't be parsed, by error"),
+            &["error"],
+        ).unwrap();
+        let connections = IntGauge::new("zaps_active_connections", "Currently open connections").unwrap();
+        let latency = HistogramVec::new(
+            HistogramOpts::new("zaps_response_latency_seconds", "Time from receiving a request to writing its response, by request MTI"),
+            &["mti"],
+        ).unwrap();
+        let queue_depth = IntGauge::new("zaps_queue_depth", "Delayed responses and unsolicited messages waiting to be written").unwrap();
+
+        let registry = Registry::new();
+        registry.register(Box::new(messages.clone())).unwrap();
+        registry.register(Box::new(response_codes.clone())).unwrap();
+        registry.register(Box::new(parse_errors.clone())).unwrap();
+        registry.register(Box::new(connections.clone())).unwrap();
+        registry.register(Box::new(latency.clone())).unwrap();
+        registry.register(Box::new(queue_depth.clone())).unwrap();
+
+        Metrics {
+            registry,
+            messages,
+            response_codes,
+            parse_errors,
+            connections,
+            latency,
+            queue_depth,
+        }
+    }
+
+    pub fn message_in(&self, mti: Option<&str>) {
+        self.messages.with_label_values(&["in", mti.unwrap_or(NO_MTI)]).inc();
+    }
+
+    pub fn message_out(&self, mti: Option<&str>) {
+        self.messages.with_label_values(&["out", mti.unwrap_or(NO_MTI)]).inc();
+    }
+
+    pub fn response_code(&self, mti: Option<&str>, code: &str) {
+        self.response_codes.with_label_values(&[mti.unwrap_or(NO_MTI), code]).inc();
+    }
+
+    /// Counts a parse error by its kind e.g. `Overflow` for `Iso8583ParseError::Overflow{..}`
+    pub fn parse_error<E: ErrorKind>(&self, error: &E) {
+        self.parse_errors.with_label_values(&[error.kind()]).inc();
+    }
+
+    pub fn connection_opened(&self) {
+        self.connections.inc();
+    }
+
+    pub fn connection_closed(&self) {
+        self.connections.dec();
+    }
+
+    pub fn latency(&self, mti: Option<&str>, latency: Duration) {
+        self.latency.with_label_values(&[mti.unwrap_or(NO_MTI)]).observe(latency.as_secs_f64());
+    }
+
+    pub fn queued(&self) {
+        self.queue_depth.inc();
+    }
+
+    pub fn dequeued(&self) {
+        self.queue_depth.dec();
+    }
+
+    /// All metrics in the Prometheus text format
+    pub fn render(&self) -> String {
+        let mut out = vec![];
+        TextEncoder::new()
+            .encode(&self.registry.gather(), &mut out)
+            .unwrap();
+        String::from_utf8(out).unwrap()
+    }
+}
+
+impl Default for Metrics {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Serves the metrics on `/metrics` until the listener fails.
+pub async fn serve(addr: &str, metrics: Arc<Metrics>) {
+    let listener = TcpListener::bind(addr)
+        .await
+        .unwrap_or_else(|e| panic!("Unable to listen on {}: {}", addr, e));
+
+    info!("Metrics listening on {}", addr);
+
+    let app = Router::new()
+        .route("/metrics", get(render))
+        .with_state(metrics);
+
+    if let Err(e) = axum::serve(listener, app).await {
+        error!("Metrics failed: {}", e);
+    }
+}
+
+async fn render(State(metrics): State<Arc<Metrics>>) -> impl IntoResponse {
+    ([(header::CONTENT_TYPE, prometheus::TEXT_FORMAT)], metrics.render())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use zaps::util::DecodeBitmapError;
+
+    #[test]
+    fn parse_errors_by_kind() {
+        let metrics = Metrics::new();
+        metrics.parse_error(&Iso8583ParseError::Overflow{ from: 0, count: 1, max: 0 });
+        metrics.parse_error(&Iso8583ParseError::NoMtiDefinition);
+        metrics.parse_error(&Iso8583ParseError::BadBitmap(DecodeBitmapError::BitmapTooShort{ actual: 1, expected: 8 }));
+        metrics.parse_error(&Iso8583ParseError::Mac("no MAC settings".to_string()));
+
+        let rendered = metrics.render();
+        assert!(rendered.contains(r#"zaps_parse_errors_total{error="Overflow"} 1"#));
+        assert!(rendered.contains(r#"zaps_parse_errors_total{error="NoMtiDefinition"} 1"#));
+        assert!(rendered.contains(r#"zaps_parse_errors_total{error="BadBitmap"} 1"#));
+        assert!(rendered.contains(r#"zaps_parse_errors_total{error="Mac"} 1"#));
+    }
+
+    #[test]
+    fn renders_messages_and_codes() {
+        let metrics = Metrics::new();
+        metrics.message_in(Some("0200"));
+        metrics.message_out(Some("0210"));
+        metrics.message_out(None);
+        metrics.response_code(Some("0210"), "00");
+        metrics.latency(Some("0200"), Duration::from_millis(3));
+
+        let rendered = metrics.render();
+        assert!(rendered.contains(r#"zaps_messages_total{direction="in",mti="0200"} 1"#));
+        assert!(rendered.contains(r#"zaps_messages_total{direction="out",mti="0210"} 1"#));
+        assert!(rendered.contains(r#"zaps_messages_total{direction="out",mti="none"} 1"#));
+        assert!(rendered.contains(r#"zaps_response_codes_total{code="00",mti="0210"} 1"#));
+        assert!(rendered.contains(r#"zaps_response_latency_seconds_count{mti="0200"} 1"#));
+    }
+}
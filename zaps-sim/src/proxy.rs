@@ -0,0 +1,453 @@
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+use tokio::{
+    io::AsyncWriteExt,
+    net::{
+        tcp::{OwnedReadHalf, OwnedWriteHalf},
+        TcpListener,
+        TcpStream,
+    },
+    sync::mpsc,
+    time,
+};
+use tracing::{info, warn};
+use zaps::{
+    core::Unparser,
+    iso8583::{
+        Iso8583Engine,
+        MTI_FIELD,
+    },
+};
+
+use crate::{
+    capture::Direction,
+    framing::{
+        FrameReader,
+        Framing,
+    },
+    logging::MessageLog,
+    parse_frame,
+    responder::{
+        response_mti,
+        FIELD_RESPONSE_CODE,
+    },
+    ISO8583_PREFIX,
+};
+
+/// The response code for declines when a rule doesn't give one: do not honour
+const RC_DECLINED: &str = "05";
+
+/// Which end of the proxy a frame came from
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Side {
+    Client,
+    Host,
+}
+
+impl Side {
+    /// Frames from the client are logged as inbound, as they would be by the host
+    fn direction(&self) -> Direction {
+        match self {
+            Self::Client => Direction::Inbound,
+            Self::Host => Direction::Outbound,
+        }
+    }
+}
+
+/// What a rewrite rule does with a frame once any fields are set
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "action", rename_all = "lowercase")]
+pub enum RewriteAction {
+    #[default]
+    Forward,
+    Delay { ms: u64 },
+    /// Answers a request from the client with the code, `05` if not given, rather than forwarding it. Anything else,
+    /// e.g. a response from the host, is forwarded with the code.
+    Decline { code: Option<String> },
+    Drop,
+}
+
+/// Changes frames passing through the proxy which match a side, MTI and field values, e.g. in JSON
+/// `{"from": "host", "mti": "0210", "set": {"39": "51"}, "action": "delay", "ms": 500}`
+///
+/// Only the action is required, one of `forward`, `delay` (with `ms`), `decline` (with an optional `code`) or `drop`.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RewriteRule {
+    /// Either side if not given
+    pub from: Option<Side>,
+    /// Any MTI if not given
+    pub mti: Option<String>,
+    pub fields: HashMap<u16, String>,
+    /// Fields to set before the frame is forwarded
+    pub set: HashMap<u16, String>,
+    #[serde(flatten)]
+    pub action: RewriteAction,
+}
+
+impl RewriteRule {
+    pub fn matches(&self, from: Side, message: &HashMap<u16, String>) -> bool {
+        let mti_matches = match &self.mti {
+            Some(mti) => message.get(&MTI_FIELD) == Some(mti),
+            None => true,
+        };
+        self.from.is_none_or(|side| side == from)
+            && mti_matches
+            && self.fields.iter().all(|(field, value)| message.get(field) == Some(value))
+    }
+}
+
+/// Reads rewrite rules from a JSON array of them
+pub fn load_rules<P: AsRef<Path>>(path: P) -> io::Result<Vec<RewriteRule>> {
+    serde_json::from_str(&fs::read_to_string(path)?)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
+
+/// Where a frame goes once it has been through the rules
+#[derive(Debug, PartialEq)]
+enum Route {
+    /// On to the other side, after the delay
+    Forward(Duration, Vec<u8>),
+    /// Back to the side it came from
+    Reply(Vec<u8>),
+    Drop,
+}
+
+/// Sits between a client and a host, forwarding frames both ways and logging the messages in them.
+///
+/// Each side is decoded with its own engine, so the two can use different specs. Frames go through unchanged unless
+/// a rule rewrites them, in which case they're built again with the engine of the side they came from. Frames which
+/// aren't ISO8583 messages, or don't parse, are forwarded as they are.
+pub struct Proxy {
+    client: Iso8583Engine,
+    host: Iso8583Engine,
+    target: String,
+    rules: Vec<RewriteRule>,
+    framing: Framing,
+    connection_ids: AtomicU64,
+}
+
+impl Proxy {
+    pub fn new(client: Iso8583Engine, host: Iso8583Engine, target: &str) -> Self {
+        Proxy {
+            client,
+            host,
+            target: target.to_string(),
+            rules: vec![],
+            framing: Framing::default(),
+            connection_ids: AtomicU64::new(1),
+        }
+    }
+
+    /// The first matching rule applies to each frame
+    pub fn with_rules(mut self, rules: Vec<RewriteRule>) -> Self {
+        self.rules = rules;
+        self
+    }
+
+    /// How frames are delimited on both sides, by a newline unless set
+    pub fn with_framing(mut self, framing: Framing) -> Self {
+        self.framing = framing;
+        self
+    }
+
+    fn engine(&self, side: Side) -> &Iso8583Engine {
+        match side {
+            Side::Client => &self.client,
+            Side::Host => &self.host,
+        }
+    }
+
+    fn route(&self, connection: u64, from: Side, frame: Vec<u8>) -> Route {
+        let engine = self.engine(from);
+        let mut message = match parse_frame(engine, &frame) {
+            Some(Ok(message)) => message,
+            Some(Err(e)) => {
+                warn!(connection, direction = %from.direction(), error = ?e, "Unable to parse message");
+                return Route::Forward(Duration::ZERO, frame);
+            },
+            None => return Route::Forward(Duration::ZERO, frame),
+        };
+        MessageLog::new(engine, &message).log(connection, from.direction());
+
+        let rule = match self.rules.iter().position(|rule| rule.matches(from, &message)) {
+            Some(index) => {
+                info!(connection, rule = index, "Rewriting message");
+                &self.rules[index]
+            },
+            None => return Route::Forward(Duration::ZERO, frame),
+        };
+        message.extend(rule.set.clone());
+
+        let (delay, reply) = match &rule.action {
+            RewriteAction::Forward => (Duration::ZERO, false),
+            RewriteAction::Delay{ ms } => (Duration::from_millis(*ms), false),
+            RewriteAction::Decline{ code } => {
+                let code = code.as_deref().unwrap_or(RC_DECLINED).to_string();
+                message.insert(FIELD_RESPONSE_CODE, code);
+                let response = message.get(&MTI_FIELD)
+                    .filter(|_mti| from == Side::Client)
+                    .and_then(|mti| response_mti(mti));
+                match response {
+                    Some(mti) => {
+                        message.insert(MTI_FIELD, mti);
+                        (Duration::ZERO, true)
+                    },
+                    None => (Duration::ZERO, false),
+                }
+            },
+            RewriteAction::Drop => return Route::Drop,
+        };
+
+        let mut raw = ISO8583_PREFIX.as_bytes().to_vec();
+        if let Err(e) = engine.unparse(&message, &mut raw) {
+            warn!(connection, error = ?e, "Unable to build rewritten message");
+            return Route::Drop;
+        }
+        if reply {
+            MessageLog::new(engine, &message).log(connection, Direction::Outbound);
+            Route::Reply(raw)
+        } else {
+            Route::Forward(delay, raw)
+        }
+    }
+
+    /// Accepts clients on the address until the listener fails, connecting each to the target
+    pub async fn serve(self: Arc<Self>, addr: &str) {
+        let listener = TcpListener::bind(addr)
+            .await
+            .unwrap_or_else(|e| panic!("Unable to listen on {}: {}", addr, e));
+
+        info!("Proxying {} to {}", addr, self.target);
+
+        self.accept(listener).await;
+    }
+
+    async fn accept(self: Arc<Self>, listener: TcpListener) {
+        loop {
+            let (client, peer) = match listener.accept().await {
+                Ok(accepted) => accepted,
+                Err(e) => {
+                    warn!(error = %e, "Unable to accept connection");
+                    return;
+                },
+            };
+            let id = self.connection_ids.fetch_add(1, Ordering::Relaxed);
+            let host = match TcpStream::connect(&self.target).await {
+                Ok(host) => host,
+                Err(e) => {
+                    warn!(connection = id, target = %self.target, error = %e, "Unable to connect to host");
+                    continue;
+                },
+            };
+            info!(connection = id, peer = %peer, target = %self.target, "Accepted connection");
+
+            let proxy = self.clone();
+            tokio::spawn(async move {
+                let (client_reader, client_writer) = client.into_split();
+                let (host_reader, host_writer) = host.into_split();
+                let (to_client, client_rx) = mpsc::unbounded_channel();
+                let (to_host, host_rx) = mpsc::unbounded_channel();
+                tokio::spawn(write_frames(client_writer, proxy.framing, client_rx));
+                tokio::spawn(write_frames(host_writer, proxy.framing, host_rx));
+
+                // either side closing closes the other, once the frames already on their way are written
+                tokio::select! {
+                    _ = proxy.pump(id, Side::Client, client_reader, to_host.clone(), to_client.clone()) => {},
+                    _ = proxy.pump(id, Side::Host, host_reader, to_client, to_host) => {},
+                }
+                info!(connection = id, "Closed connection");
+            });
+        }
+    }
+
+    /// Routes frames from one side until it closes
+    async fn pump(&self, connection: u64, from: Side, reader: OwnedReadHalf, onward: mpsc::UnboundedSender<Vec<u8>>, back: mpsc::UnboundedSender<Vec<u8>>) {
+        let mut reader = FrameReader::new(reader, self.framing);
+        loop {
+            let frame = match reader.next().await {
+                Ok(Some(frame)) => frame,
+                Ok(None) | Err(_) => return,
+            };
+
+            match self.route(connection, from, frame) {
+                Route::Forward(delay, frame) if delay.is_zero() => {
+                    let _ = onward.send(frame);
+                },
+                Route::Forward(delay, frame) => {
+                    let onward = onward.clone();
+                    tokio::spawn(async move {
+                        time::sleep(delay).await;
+                        let _ = onward.send(frame);
+                    });
+                },
+                Route::Reply(frame) => {
+                    let _ = back.send(frame);
+                },
+                Route::Drop => {},
+            }
+        }
+    }
+}
+
+async fn write_frames(mut writer: OwnedWriteHalf, framing: Framing, mut frames: mpsc::UnboundedReceiver<Vec<u8>>) {
+    while let Some(frame) = frames.recv().await {
+        let framed = match framing.encode(&frame) {
+            Ok(framed) => framed,
+            Err(e) => {
+                warn!(error = %e, "Unable to frame message");
+                continue;
+            },
+        };
+        if writer.write_all(&framed).await.is_err() {
+            return;
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use zaps::iso8583::spec::Spec;
+
+    fn engine() -> Iso8583Engine {
+        let spec = "
+            field 0 AsciiBitmap(64)
+            field 4 Fixed(12:n)
+            field 11 Fixed(6:n)
+            field 39 Fixed(2:an)
+            field 52 Fixed(8:bin)
+            mti 0200 0210
+        ".parse::<Spec>().unwrap();
+        Iso8583Engine::new(spec)
+    }
+
+    fn proxy(rules: &str) -> Proxy {
+        Proxy::new(engine(), engine(), "localhost:0")
+            .with_rules(serde_json::from_str(rules).unwrap())
+    }
+
+    fn frame(mti: &str, fields: &[(u16, &str)]) -> Vec<u8> {
+        let mut message = fields.iter()
+            .map(|(k, v)| (*k, v.to_string()))
+            .collect::<HashMap<_, _>>();
+        message.insert(MTI_FIELD, mti.to_string());
+        let mut raw = ISO8583_PREFIX.as_bytes().to_vec();
+        engine().unparse(&message, &mut raw).unwrap();
+        raw
+    }
+
+    #[test]
+    fn rule_from_json() {
+        let rule = serde_json::from_str::<RewriteRule>(r#"{"from": "host", "mti": "0210", "set": {"39": "51"}, "action": "delay", "ms": 500}"#).unwrap();
+        assert_eq!(RewriteRule {
+            from: Some(Side::Host),
+            mti: Some("0210".to_string()),
+            fields: HashMap::new(),
+            set: HashMap::from([(39, "51".to_string())]),
+            action: RewriteAction::Delay{ ms: 500 },
+        }, rule);
+
+        let decline = serde_json::from_str::<RewriteRule>(r#"{"action": "decline"}"#).unwrap();
+        assert_eq!(RewriteAction::Decline{ code: None }, decline.action);
+    }
+
+    #[test]
+    fn unchanged() {
+        let proxy = proxy(r#"[{"from": "host", "action": "drop"}]"#);
+        let request = frame("0200", &[(4, "000000001000"), (11, "000001")]);
+
+        assert_eq!(Route::Forward(Duration::ZERO, request.clone()), proxy.route(1, Side::Client, request));
+        assert_eq!(Route::Forward(Duration::ZERO, b"ping".to_vec()), proxy.route(1, Side::Client, b"ping".to_vec()));
+        assert_eq!(Route::Forward(Duration::ZERO, b"iso8583:x".to_vec()), proxy.route(1, Side::Client, b"iso8583:x".to_vec()));
+    }
+
+    #[test]
+    fn set_field() {
+        let proxy = proxy(r#"[{"from": "host", "fields": {"39": "00"}, "set": {"39": "51"}, "action": "delay", "ms": 10}]"#);
+        let response = frame("0210", &[(11, "000001"), (39, "00")]);
+
+        assert_eq!(
+            Route::Forward(Duration::from_millis(10), frame("0210", &[(11, "000001"), (39, "51")])),
+            proxy.route(1, Side::Host, response),
+        );
+    }
+
+    #[test]
+    fn decline_request() {
+        let proxy = proxy(r#"[{"mti": "0200", "action": "decline"}]"#);
+        let request = frame("0200", &[(4, "000000001000"), (11, "000001")]);
+
+        assert_eq!(
+            Route::Reply(frame("0210", &[(4, "000000001000"), (11, "000001"), (39, "05")])),
+            proxy.route(1, Side::Client, request),
+        );
+    }
+
+    #[test]
+    fn decline_response() {
+        let proxy = proxy(r#"[{"action": "decline", "code": "51"}]"#);
+        let response = frame("0210", &[(11, "000001"), (39, "00")]);
+
+        assert_eq!(
+            Route::Forward(Duration::ZERO, frame("0210", &[(11, "000001"), (39, "51")])),
+            proxy.route(1, Side::Host, response),
+        );
+    }
+
+    #[test]
+    fn drop() {
+        let proxy = proxy(r#"[{"from": "client", "fields": {"4": "000000000000"}, "action": "drop"}]"#);
+
+        assert_eq!(Route::Drop, proxy.route(1, Side::Client, frame("0200", &[(4, "000000000000")])));
+        assert!(matches!(proxy.route(1, Side::Client, frame("0200", &[(4, "000000000100")])), Route::Forward(..)));
+    }
+
+    #[tokio::test]
+    async fn proxies() {
+        let host = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let target = host.local_addr().unwrap().to_string();
+        tokio::spawn(async move {
+            let (stream, _addr) = host.accept().await.unwrap();
+            let (reader, mut writer) = stream.into_split();
+            let mut reader = FrameReader::new(reader, Framing::Length);
+            while let Some(request) = reader.next().await.unwrap() {
+                let response = match &request[..] {
+                    b"ping" => b"pong".to_vec(),
+                    _ => {
+                        let request = parse_frame(&engine(), &request).unwrap().unwrap();
+                        frame("0210", &[(11, "000001"), (39, "00"), (52, &request[&52])])
+                    },
+                };
+                writer.write_all(&Framing::Length.encode(&response).unwrap()).await.unwrap();
+            }
+        });
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let proxy = Proxy::new(engine(), engine(), &target)
+            .with_framing(Framing::Length)
+            .with_rules(serde_json::from_str(r#"[{"from": "host", "set": {"39": "91"}, "action": "forward"}]"#).unwrap());
+        tokio::spawn(Arc::new(proxy).accept(listener));
+
+        let (reader, mut writer) = TcpStream::connect(addr).await.unwrap().into_split();
+        let mut reader = FrameReader::new(reader, Framing::Length);
+        writer.write_all(&Framing::Length.encode(b"ping").unwrap()).await.unwrap();
+        assert_eq!(Some(b"pong".to_vec()), reader.next().await.unwrap());
+
+        // a PIN block with a newline in it stays in the one frame
+        let pin_block = "\u{12}\n\u{34}\n\u{56}\u{78}\u{9a}\n";
+        let request = frame("0200", &[(4, "000000001000"), (11, "000001"), (52, pin_block)]);
+        writer.write_all(&Framing::Length.encode(&request).unwrap()).await.unwrap();
+        let response = reader.next().await.unwrap().unwrap();
+        assert_eq!(frame("0210", &[(11, "000001"), (39, "91"), (52, pin_block)]), response);
+    }
+}
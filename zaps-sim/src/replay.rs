@@ -0,0 +1,383 @@
+use std::collections::{
+    hash_map::Entry,
+    BTreeSet,
+    HashMap,
+};
+use std::fmt;
+use std::hash::Hash;
+use std::io;
+use std::time::Duration;
+
+use tokio::{
+    io::AsyncWriteExt,
+    net::{
+        TcpStream,
+        tcp::{OwnedReadHalf, OwnedWriteHalf},
+    },
+    time::{self, Instant},
+};
+
+use zaps::{
+    core::Parser,
+    iso8583::MTI_FIELD,
+};
+
+use crate::{
+    capture::{CaptureRecord, Direction},
+    field,
+    is_echo,
+    framing::{
+        FrameReader,
+        Framing,
+    },
+    parse_frame,
+    store::{
+        FIELD_RRN,
+        FIELD_STAN,
+    },
+};
+
+/// How long to wait for each recorded response before treating it as missing
+const RESPONSE_TIMEOUT: Duration = Duration::from_secs(5);
+
+#[derive(Debug, PartialEq)]
+pub enum DifferenceKind {
+    /// A field present in either frame differs
+    Field{
+        field: String,
+        expected: Option<String>,
+        actual: Option<String>,
+    },
+    /// The frames differ but could not be compared field by field
+    Raw{
+        expected: Vec<u8>,
+        actual: Vec<u8>,
+    },
+    /// No response was received in time
+    Missing{
+        expected: Vec<u8>,
+    },
+}
+
+#[derive(Debug, PartialEq)]
+pub struct Difference {
+    pub connection: u64,
+    /// Index of the expected record in the capture
+    pub record: usize,
+    pub kind: DifferenceKind,
+}
+
+impl fmt::Display for Difference {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "connection {} record {}: ", self.connection, self.record)?;
+        match &self.kind {
+            DifferenceKind::Field{ field, expected, actual } => write!(f, "field {} expected {:?} but was {:?}", field, expected, actual),
+            DifferenceKind::Raw{ expected, actual } => write!(f, "expected {:?} but was {:?}", String::from_utf8_lossy(expected), String::from_utf8_lossy(actual)),
+            DifferenceKind::Missing{ expected } => write!(f, "no response, expected {:?}", String::from_utf8_lossy(expected)),
+        }
+    }
+}
+
+/// What a response is matched to its recorded frame by
+#[derive(Debug, PartialEq)]
+enum FrameId {
+    /// An ISO8583 message by its MTI and STAN
+    Stan(Option<String>, String),
+    /// An ISO8583 message without a STAN by its MTI and RRN
+    Rrn(Option<String>, String),
+    /// Anything else by the whole frame
+    Raw(Vec<u8>),
+}
+
+impl FrameId {
+    fn of<K, T>(engine: &T, frame: &[u8]) -> Self
+    where
+        T: Parser<K>,
+        K: From<u16> + Hash + Eq,
+    {
+        let tokens = match parse_frame(engine, frame) {
+            Some(Ok(tokens)) => tokens,
+            _ => return FrameId::Raw(frame.to_vec()),
+        };
+        let mti = field(&tokens, MTI_FIELD).map(|mti| mti.to_string());
+        match (field(&tokens, FIELD_STAN), field(&tokens, FIELD_RRN)) {
+            (Some(stan), _) => FrameId::Stan(mti, stan.to_string()),
+            (None, Some(rrn)) => FrameId::Rrn(mti, rrn.to_string()),
+            (None, None) => FrameId::Raw(frame.to_vec()),
+        }
+    }
+}
+
+struct ReplayConnection {
+    reader: FrameReader<OwnedReadHalf>,
+    writer: OwnedWriteHalf,
+    /// Frames read while waiting for another response e.g. messages broadcast to every connection, in case a later
+    /// recorded frame is one of them
+    unmatched: Vec<Vec<u8>>,
+}
+
+impl ReplayConnection {
+    /// The frame identified by `id`, from those already read or else the next read in time, or `None` if none is
+    async fn response<K, T>(&mut self, engine: &T, id: &FrameId) -> io::Result<Option<Vec<u8>>>
+    where
+        T: Parser<K>,
+        K: From<u16> + Hash + Eq,
+    {
+        if let Some(index) = self.unmatched.iter().position(|frame| FrameId::of(engine, frame) == *id) {
+            return Ok(Some(self.unmatched.remove(index)));
+        }
+
+        let deadline = Instant::now() + RESPONSE_TIMEOUT;
+        loop {
+            match time::timeout_at(deadline, self.reader.next()).await {
+                Ok(Ok(Some(frame))) if FrameId::of(engine, &frame) == *id => return Ok(Some(frame)),
+                Ok(Ok(Some(frame))) => self.unmatched.push(frame),
+                Ok(Err(e)) => return Err(e),
+                Ok(Ok(None)) | Err(_) => return Ok(None),
+            }
+        }
+    }
+}
+
+/// Resends the inbound frames of a capture to `target`, one connection per recorded connection, and
+/// compares what comes back against the recorded outbound frames.
+///
+/// Responses are matched to recorded frames by MTI and STAN, or RRN if there's no STAN, and frames that aren't ISO8583
+/// messages by their bytes, so other frames sent to the connection in between, such as broadcasts, are passed over.
+/// Recorded broadcast echoes aren't expected, as they name the peer they came from.
+///
+/// When `fast` is set frames are sent as soon as the previous step completes rather than at their
+/// recorded offsets.
+pub async fn replay<K, T>(engine: &T, records: &[CaptureRecord], target: &str, framing: Framing, fast: bool) -> io::Result<Vec<Difference>>
+where
+    T: Parser<K>,
+    K: From<u16> + Eq + Hash + Ord + fmt::Display,
+{
+    let mut connections: HashMap<u64, ReplayConnection> = HashMap::new();
+    let mut differences = vec![];
+    let start = Instant::now();
+    let first_timestamp = records.first()
+        .map(|r| r.timestamp)
+        .unwrap_or(0);
+
+    // connect everything up front so no connection misses frames sent before it is first used
+    for record in records {
+        if let Entry::Vacant(entry) = connections.entry(record.connection) {
+            let (reader, writer) = TcpStream::connect(target).await?.into_split();
+            entry.insert(ReplayConnection{
+                reader: FrameReader::new(reader, framing),
+                writer,
+                unmatched: vec![],
+            });
+        }
+    }
+
+    for (index, record) in records.iter().enumerate() {
+        let connection = connections.get_mut(&record.connection)
+            .expect("replay connection established");
+
+        match record.direction {
+            Direction::Inbound => {
+                if !fast {
+                    let offset = Duration::from_micros(record.timestamp.saturating_sub(first_timestamp));
+                    time::sleep_until(start + offset).await;
+                }
+                connection.writer.write_all(&framing.encode(&record.raw)?).await?;
+            },
+            // echoes name the peer they came from, which is a different one on replay
+            Direction::Outbound if is_echo(&record.raw) => {},
+            Direction::Outbound => {
+                let id = FrameId::of(engine, &record.raw);
+                match connection.response(engine, &id).await? {
+                    Some(actual) => {
+                        differences.extend(compare(engine, &record.raw, &actual)
+                            .into_iter()
+                            .map(|kind| Difference{
+                                connection: record.connection,
+                                record: index,
+                                kind,
+                            }));
+                    },
+                    None => differences.push(Difference{
+                        connection: record.connection,
+                        record: index,
+                        kind: DifferenceKind::Missing{
+                            expected: record.raw.clone(),
+                        },
+                    }),
+                }
+            },
+        }
+    }
+
+    Ok(differences)
+}
+
+/// Compares two frames field by field when both parse, otherwise byte for byte
+pub fn compare<K, T>(engine: &T, expected: &[u8], actual: &[u8]) -> Vec<DifferenceKind>
+where
+    T: Parser<K>,
+    K: Eq + Hash + Ord + fmt::Display,
+{
+    match (parse_frame(engine, expected), parse_frame(engine, actual)) {
+        (Some(Ok(expected)), Some(Ok(actual))) => {
+            let fields = expected.keys()
+                .chain(actual.keys())
+                .collect::<BTreeSet<_>>();
+            fields.into_iter()
+                .filter(|field| expected.get(field) != actual.get(field))
+                .map(|field| DifferenceKind::Field{
+                    field: field.to_string(),
+                    expected: expected.get(field).cloned(),
+                    actual: actual.get(field).cloned(),
+                })
+                .collect()
+        },
+        _ if expected != actual => vec![DifferenceKind::Raw{
+            expected: expected.to_vec(),
+            actual: actual.to_vec(),
+        }],
+        _ => vec![],
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use tokio::net::TcpListener;
+    use zaps::{
+        core::Unparser,
+        iso8583::Iso8583Engine,
+        iso8583_spec_build,
+    };
+
+    fn engine() -> Iso8583Engine {
+        Iso8583Engine::new(iso8583_spec_build!(
+            "0200":
+                0: AsciiBitmap, 8;
+                1: LLLVar, Alpha;
+                8: Fixed, 15, Alphanum;
+        ))
+    }
+
+    #[test]
+    fn compare_same() {
+        let frame = b"iso8583:020081003ABC0123456789abcde";
+        assert!(compare(&engine(), frame, frame).is_empty());
+    }
+
+    #[test]
+    fn compare_fields() {
+        let differences = compare(
+            &engine(),
+            b"iso8583:020081003ABC0123456789abcde",
+            b"iso8583:020080003XYZ",
+        );
+        assert_eq!(vec![
+            DifferenceKind::Field{
+                field: "0".to_string(),
+                expected: Some("10000001".to_string()),
+                actual: Some("10000000".to_string()),
+            },
+            DifferenceKind::Field{
+                field: "1".to_string(),
+                expected: Some("ABC".to_string()),
+                actual: Some("XYZ".to_string()),
+            },
+            DifferenceKind::Field{
+                field: "8".to_string(),
+                expected: Some("0123456789abcde".to_string()),
+                actual: None,
+            },
+        ], differences);
+    }
+
+    #[test]
+    fn compare_raw() {
+        let differences = compare(&engine(), b"hello", b"world");
+        assert_eq!(vec![
+            DifferenceKind::Raw{
+                expected: b"hello".to_vec(),
+                actual: b"world".to_vec(),
+            },
+        ], differences);
+    }
+
+    fn frame(engine: &Iso8583Engine, mti: &str, stan: &str) -> Vec<u8> {
+        let fields = [(MTI_FIELD, mti), (11, stan)].iter()
+            .map(|(k, v)| (*k, v.to_string()))
+            .collect();
+        let mut frame = b"iso8583:".to_vec();
+        engine.unparse(&fields, &mut frame).unwrap();
+        frame
+    }
+
+    fn record(direction: Direction, raw: &[u8]) -> CaptureRecord {
+        CaptureRecord::new(1, direction, raw, vec![])
+    }
+
+    #[tokio::test]
+    async fn replay_skips_other_frames() {
+        let engine = Iso8583Engine::new(iso8583_spec_build!(
+            "0200":
+                0: AsciiBitmap, 64;
+                11: Fixed, 6, Numeric;
+            "0210":
+                0: AsciiBitmap, 64;
+                11: Fixed, 6, Numeric;
+        ));
+        let request = frame(&engine, "0200", "000001");
+        let response = frame(&engine, "0210", "000001");
+        // another connection's response and a broadcast, both sent on ahead of the response to the request
+        let others = [frame(&engine, "0210", "000002"), b"hello".to_vec()];
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let target = listener.local_addr().unwrap().to_string();
+        let (sent_others, sent_response) = (others.clone(), response.clone());
+        tokio::spawn(async move {
+            let (stream, _addr) = listener.accept().await.unwrap();
+            let (reader, mut writer) = stream.into_split();
+            FrameReader::new(reader, Framing::Length).next().await.unwrap().unwrap();
+            for frame in sent_others.iter().chain([&sent_response]) {
+                writer.write_all(&Framing::Length.encode(frame).unwrap()).await.unwrap();
+            }
+        });
+
+        let records = [
+            record(Direction::Inbound, &request),
+            record(Direction::Outbound, &response),
+            record(Direction::Outbound, &others[1]),
+        ];
+        let differences = replay(&engine, &records, &target, Framing::Length, true).await.unwrap();
+        assert_eq!(Vec::<Difference>::new(), differences);
+    }
+
+    #[tokio::test]
+    async fn replay_skips_recorded_echoes() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let target = listener.local_addr().unwrap().to_string();
+        tokio::spawn(async move {
+            let (stream, _addr) = listener.accept().await.unwrap();
+            let (reader, mut writer) = stream.into_split();
+            let mut reader = FrameReader::new(reader, Framing::Newline);
+            while let Some(frame) = reader.next().await.unwrap() {
+                writer.write_all(&Framing::Newline.encode(&frame).unwrap()).await.unwrap();
+            }
+        });
+
+        // another client's message echoed to this one when it was recorded, which it won't be on replay
+        let echo = b"127.0.0.1:50312 => hello".to_vec();
+        assert!(is_echo(&echo));
+        assert!(!is_echo(b"ping => pong"));
+        let records = [
+            record(Direction::Outbound, &echo),
+            record(Direction::Inbound, b"ping"),
+            record(Direction::Outbound, b"ping"),
+        ];
+        let differences = time::timeout(Duration::from_secs(1), replay(&engine(), &records, &target, Framing::Newline, true))
+            .await
+            .expect("echoes aren't waited for")
+            .unwrap();
+        assert_eq!(Vec::<Difference>::new(), differences);
+    }
+}
@@ -0,0 +1,849 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use tracing::error;
+use zaps::{
+    card::{
+        Clock,
+        SystemClock,
+    },
+    crypto::{
+        pin::PinFormat,
+        Key,
+    },
+    emv::cryptogram::Issuer,
+    iso8583::MTI_FIELD,
+};
+
+use crate::cards::{
+    request_pan,
+    CardChecks,
+    CardDatabase,
+    RC_INCORRECT_PIN,
+};
+use crate::cvv::CvvChecks;
+use crate::dukpt;
+use crate::emv::{
+    self,
+    FIELD_ICC_DATA,
+    RC_CRYPTOGRAM_FAILED,
+};
+use crate::store::{
+    FIELD_RRN,
+    Transaction,
+    TransactionKey,
+    TransactionState,
+    TransactionStore,
+};
+
+pub const FIELD_PAN: u16 = 2;
+pub const FIELD_AMOUNT: u16 = 4;
+pub const FIELD_CURRENCY: u16 = 49;
+pub const FIELD_AUTH_CODE: u16 = 38;
+pub const FIELD_RESPONSE_CODE: u16 = 39;
+pub const FIELD_PIN_BLOCK: u16 = 52;
+
+/// The request fields echoed in responses, identifying the transaction but leaving out card data such as the expiry,
+/// track data, PIN block, CVV2 and ICC data
+pub const ECHOED_FIELDS: &[u16] = &[
+    2, 3, 4, 5, 6, 7, 9, 10, 11, 12, 13, 15, 18, 19, 22, 23, 24, 25, 32, 33, 37, 41, 42, 49, 50, 51, 70, 90, 95, 100,
+];
+
+pub const RC_APPROVED: &str = "00";
+pub const RC_NO_ORIGINAL: &str = "25";
+pub const RC_DUPLICATE: &str = "94";
+
+/// What the simulator should do on receiving a request
+#[derive(Debug, Clone)]
+pub enum Action<K> {
+    Respond(HashMap<K, String>),
+    /// Respond after a delay, without holding up other messages on the connection
+    Delay(Duration, HashMap<K, String>),
+    /// Respond with the named fault profile in place of the connection's
+    Fault(String, HashMap<K, String>),
+    /// Don't respond at all
+    Drop,
+    Disconnect,
+}
+
+/// Decides what to do with a tokenised request.
+pub trait Responder<K> {
+    fn respond(&self, request: &HashMap<K, String>) -> Action<K>;
+}
+
+impl<K, R> Responder<K> for Box<R>
+where
+    R: Responder<K> + ?Sized,
+{
+    fn respond(&self, request: &HashMap<K, String>) -> Action<K> {
+        (**self).respond(request)
+    }
+}
+
+/// The key PIN blocks are encrypted under
+#[derive(Debug, Clone)]
+pub enum PinKey {
+    Static(Key),
+    /// A key for each request derived by DUKPT from the base derivation key and the KSN in the field
+    Dukpt{
+        bdk: Key,
+        ksn_field: u16,
+    },
+}
+
+impl PinKey {
+    /// The key for the request, `None` if it has no KSN to derive one from
+    fn key(&self, request: &HashMap<u16, String>) -> Option<Key> {
+        match self {
+            PinKey::Static(key) => Some(key.clone()),
+            PinKey::Dukpt{ bdk, ksn_field } => request.get(ksn_field)
+                .and_then(|ksn| dukpt::pin_key(bdk, ksn)),
+        }
+    }
+}
+
+/// Approves authorisation and financial requests and remembers them so that:
+/// - reversals (x400/x420) void their original, or are declined with 25 when there is none and with 94 when it is
+///   already reversed
+/// - advices and completions (x120/x220) complete their pre-authorisation, or are declined with 94 when it is already
+///   completed. Advices (x120) without one are of authorisations made elsewhere e.g. in stand-in, so are approved and
+///   remembered like an authorisation, but completions (x220) are declined with 25.
+/// - duplicate transmissions, repeat or otherwise, get the original response back
+///
+/// When given a card database, requests are also authorised against it, with declines for unknown, lost, stolen
+/// and expired cards, currencies other than the card's, insufficient funds and exceeded daily limits. Balances are
+/// debited on approval, credited back on reversal and adjusted on completion, with each transaction keeping what it
+/// still has debited so the card is never credited more than that. With a PIN key too, PIN blocks in field 52 are
+/// decrypted and checked against the card's PIN, declining with 55 if it's incorrect or, with DUKPT, there's no KSN to
+/// derive the key from.
+///
+/// With card checks, cards are also declined with 14 or 54 by their PAN and expiry before anything else. Expiries are
+/// checked against the clock, the system's by default.
+///
+/// With a CVK, CVVs in the track data and CVV2s are checked, declining with 82 and N7 if they aren't the card's.
+///
+/// With an issuer, the ARQCs of chip requests are verified, declining with 82 if they aren't the card's, and field 55
+/// of their responses is replaced by the issuer authentication data with the ARPC.
+///
+/// Responses echo the request's fields in [`ECHOED_FIELDS`], so the response MTIs must define those the requests have.
+pub struct AutoResponder {
+    store: Mutex<TransactionStore>,
+    cards: Option<Mutex<CardDatabase>>,
+    pin_key: Option<(PinKey, PinFormat)>,
+    issuer: Option<Issuer>,
+    card_checks: Option<CardChecks>,
+    cvv_checks: Option<CvvChecks>,
+    clock: Box<dyn Clock + Send + Sync>,
+}
+
+impl AutoResponder {
+    pub fn new(store: TransactionStore) -> Self {
+        AutoResponder {
+            store: Mutex::new(store),
+            cards: None,
+            pin_key: None,
+            issuer: None,
+            card_checks: None,
+            cvv_checks: None,
+            clock: Box::new(SystemClock),
+        }
+    }
+
+    pub fn with_cards(mut self, cards: CardDatabase) -> Self {
+        self.cards = Some(Mutex::new(cards));
+        self
+    }
+
+    /// The key and format PIN blocks are encrypted with, for checking PINs against the cards
+    pub fn with_pin_key(mut self, key: Key, format: PinFormat) -> Self {
+        self.pin_key = Some((PinKey::Static(key), format));
+        self
+    }
+
+    /// Derive the key PIN blocks are encrypted under from the base derivation key and the KSN in the field
+    pub fn with_dukpt(mut self, bdk: Key, ksn_field: u16, format: PinFormat) -> Self {
+        self.pin_key = Some((PinKey::Dukpt{ bdk, ksn_field }, format));
+        self
+    }
+
+    /// Verify ARQCs in field 55 and answer them with ARPCs, as this issuer
+    pub fn with_issuer(mut self, issuer: Issuer) -> Self {
+        self.issuer = Some(issuer);
+        self
+    }
+
+    pub fn with_card_checks(mut self, checks: CardChecks) -> Self {
+        self.card_checks = Some(checks);
+        self
+    }
+
+    pub fn with_cvv_checks(mut self, checks: CvvChecks) -> Self {
+        self.cvv_checks = Some(checks);
+        self
+    }
+
+    /// The clock card expiries are checked against
+    pub fn with_clock<C: Clock + Send + Sync + 'static>(mut self, clock: C) -> Self {
+        self.clock = Box::new(clock);
+        self
+    }
+
+    pub fn store(&self) -> &Mutex<TransactionStore> {
+        &self.store
+    }
+
+    pub fn cards(&self) -> Option<&Mutex<CardDatabase>> {
+        self.cards.as_ref()
+    }
+
+    /// The response for a request, or `None` if it shouldn't have one e.g. because it is itself a response
+    pub fn response(&self, request: &HashMap<u16, String>) -> Option<HashMap<u16, String>> {
+        let mti = request.get(&MTI_FIELD)?;
+        let mti = non_repeat_mti(mti)?;
+        let response_mti = response_mti(&mti)?;
+
+        let key = TransactionKey::from_fields(&mti, request);
+        let mut store = self.store.lock().unwrap();
+
+        // the PAN isn't stored with the response, so it's echoed from the duplicate
+        if let Some(original) = key.as_ref().and_then(|key| store.get(key)) {
+            let mut response = original.response.clone();
+            response.extend(request.get_key_value(&FIELD_PAN).map(|(field, pan)| (*field, pan.clone())));
+            return Some(response);
+        }
+
+        let mut response = echoed(request);
+        response.insert(MTI_FIELD, response_mti);
+
+        let (state, response_code, debited) = self.decide(&mut store, &mti, key.as_ref(), request, &mut response);
+        response.insert(FIELD_RESPONSE_CODE, response_code.to_string());
+
+        let original = matches!(mti.as_bytes()[1..3], [b'1' | b'2', b'0']);
+        let chip_authorisation = original && request.contains_key(&FIELD_ICC_DATA);
+        if let (Some(issuer), true) = (&self.issuer, chip_authorisation) {
+            // a cryptogram which isn't the card's can't be answered, but declines for anything else still are
+            let icc = match emv::verify_arqc(issuer, request) {
+                true => emv::issuer_response(issuer, request, response_code),
+                false => None,
+            };
+            match icc {
+                Some(icc) => response.insert(FIELD_ICC_DATA, icc),
+                None => response.remove(&FIELD_ICC_DATA),
+            };
+        }
+
+        if let Some(key) = key {
+            // only what's needed to match and answer the transaction is kept, with the PAN only to credit the card
+            let mut stored_request = echoed(request);
+            stored_request.remove(&FIELD_PAN);
+            if let (Some(_), Some(pan)) = (&self.cards, request_pan(request)) {
+                stored_request.insert(FIELD_PAN, pan);
+            }
+            let mut stored_response = response.clone();
+            stored_response.remove(&FIELD_PAN);
+            let transaction = Transaction {
+                key,
+                state,
+                debited,
+                request: stored_request,
+                response: stored_response,
+            };
+            if let Err(e) = store.insert(transaction) {
+                error!("Unable to update transaction store: {}", e);
+            }
+        }
+
+        Some(response)
+    }
+
+    fn decide(
+        &self,
+        store: &mut TransactionStore,
+        mti: &str,
+        key: Option<&TransactionKey>,
+        request: &HashMap<u16, String>,
+        response: &mut HashMap<u16, String>,
+    ) -> (TransactionState, &'static str, i64) {
+        let rrn = request.get(&FIELD_RRN).map(|rrn| &rrn[..]);
+        let date = key.map(|key| &key.date[..]).unwrap_or_default();
+        let (class, function) = (mti.as_bytes()[1], mti.as_bytes()[2]);
+
+        match (class, function, key) {
+            (b'1' | b'2', b'0', _) => {
+                let sequence = store.len() as u64 + 1;
+                response.entry(FIELD_RRN)
+                    .or_insert_with(|| format!("{:012}", sequence));
+                if let Some(checks) = &self.card_checks {
+                    if let Err(response_code) = checks.check(request, self.clock.as_ref()) {
+                        return (TransactionState::Declined, response_code, 0);
+                    }
+                }
+                if let Some(checks) = &self.cvv_checks {
+                    if let Err(response_code) = checks.check(request) {
+                        return (TransactionState::Declined, response_code, 0);
+                    }
+                }
+                if let Some(issuer) = &self.issuer {
+                    if !emv::verify_arqc(issuer, request) {
+                        return (TransactionState::Declined, RC_CRYPTOGRAM_FAILED, 0);
+                    }
+                }
+                let mut debited = 0;
+                if let Some(cards) = &self.cards {
+                    let pan = request_pan(request).unwrap_or_default();
+                    let pan = &pan[..];
+                    let mut cards = cards.lock().unwrap();
+                    if let Some(currency) = request.get(&FIELD_CURRENCY) {
+                        if let Err(response_code) = cards.verify_currency(pan, currency) {
+                            return (TransactionState::Declined, response_code, 0);
+                        }
+                    }
+                    if let (Some((pin_key, format)), Some(pin_block)) = (&self.pin_key, request.get(&FIELD_PIN_BLOCK)) {
+                        let verified = pin_key.key(request)
+                            .ok_or(RC_INCORRECT_PIN)
+                            .and_then(|key| cards.verify_pin(pan, pin_block, &key, *format));
+                        if let Err(response_code) = verified {
+                            return (TransactionState::Declined, response_code, 0);
+                        }
+                    }
+                    let debit = cards.debit(pan, amount(request), date, &self.clock.yymm());
+                    if let Err(response_code) = debit {
+                        return (TransactionState::Declined, response_code, 0);
+                    }
+                    debited = amount(request);
+                }
+                response.entry(FIELD_AUTH_CODE)
+                    .or_insert_with(|| format!("{:06}", sequence % 1_000_000));
+                (TransactionState::Approved, RC_APPROVED, debited)
+            },
+            (b'1' | b'2', b'2', Some(key)) => {
+                let pre_auth = mti_with_class(mti, b'1', b'0');
+                let original = store.find_original(rrn, key, &[&pre_auth]);
+                match original.map(|original| original.state) {
+                    Some(TransactionState::Completed) => return (TransactionState::Declined, RC_DUPLICATE, 0),
+                    None if class == b'1' => {
+                        // the authorisation was already made elsewhere so the card is debited without any checks
+                        let sequence = store.len() as u64 + 1;
+                        response.entry(FIELD_RRN)
+                            .or_insert_with(|| format!("{:012}", sequence));
+                        let mut debited = 0;
+                        if let (Some(cards), Some(pan)) = (&self.cards, request_pan(request)) {
+                            cards.lock().unwrap().credit(&pan, -amount(request), date);
+                            debited = amount(request);
+                        }
+                        return (TransactionState::Approved, RC_APPROVED, debited);
+                    },
+                    _ => {},
+                }
+                let original = original.filter(|original| original.state == TransactionState::Approved);
+                let mut debited = 0;
+                if let (Some(cards), Some(original)) = (&self.cards, original) {
+                    // the pre-authorisation already holds what it debited so only the difference is debited
+                    if let Some(pan) = request_pan(&original.request) {
+                        cards.lock().unwrap().credit(&pan, original.debited - amount(request), &original.key.date);
+                    }
+                    debited = amount(request);
+                }
+                let original = original.map(|original| original.key.clone());
+                Self::update_original(store, original, TransactionState::Completed, debited)
+            },
+            (b'4', b'0' | b'2', Some(key)) => {
+                let originals = [mti_with_class(mti, b'1', b'0'), mti_with_class(mti, b'2', b'0')];
+                let original = store.find_original(rrn, key, &[&originals[0], &originals[1]])
+                    .filter(|original| original.state != TransactionState::Declined);
+                // repeats of the same reversal get its response back as duplicates, but others have nothing to void
+                if original.is_some_and(|original| original.state == TransactionState::Reversed) {
+                    return (TransactionState::Declined, RC_DUPLICATE, 0);
+                }
+                // only what the original still has debited is credited back, after any completion of it
+                if let (Some(cards), Some(original)) = (&self.cards, original) {
+                    if let Some(pan) = request_pan(&original.request) {
+                        cards.lock().unwrap().credit(&pan, original.debited, &original.key.date);
+                    }
+                }
+                let original = original.map(|original| original.key.clone());
+                Self::update_original(store, original, TransactionState::Reversed, 0)
+            },
+            (_, b'2', None) | (b'4', _, None) => (TransactionState::Declined, RC_NO_ORIGINAL, 0),
+            _ => (TransactionState::Approved, RC_APPROVED, 0),
+        }
+    }
+
+    fn update_original(
+        store: &mut TransactionStore,
+        original: Option<TransactionKey>,
+        state: TransactionState,
+        debited: i64,
+    ) -> (TransactionState, &'static str, i64) {
+        match original {
+            Some(original) => {
+                let updated = store.update(&original, |original| {
+                    original.state = state;
+                    original.debited = debited;
+                });
+                if let Err(e) = updated {
+                    error!("Unable to update transaction store: {}", e);
+                }
+                // what's debited is the original's, not the reversal's or completion's
+                (TransactionState::Approved, RC_APPROVED, 0)
+            },
+            None => (TransactionState::Declined, RC_NO_ORIGINAL, 0),
+        }
+    }
+}
+
+/// The fields of a request in [`ECHOED_FIELDS`]
+fn echoed(request: &HashMap<u16, String>) -> HashMap<u16, String> {
+    request.iter()
+        .filter(|(field, _value)| ECHOED_FIELDS.contains(field))
+        .map(|(field, value)| (*field, value.clone()))
+        .collect()
+}
+
+/// The transaction amount in minor units, zero if missing or invalid
+fn amount(fields: &HashMap<u16, String>) -> i64 {
+    fields.get(&FIELD_AMOUNT)
+        .and_then(|amount| amount.parse::<i64>().ok())
+        .unwrap_or(0)
+}
+
+impl Responder<u16> for AutoResponder {
+    fn respond(&self, request: &HashMap<u16, String>) -> Action<u16> {
+        match self.response(request) {
+            Some(response) => Action::Respond(response),
+            None => Action::Drop,
+        }
+    }
+}
+
+/// Validates the MTI and clears the repeat flag from the message origin e.g. 0201 => 0200, 0421 => 0420
+pub fn non_repeat_mti(mti: &str) -> Option<String> {
+    if mti.len() != 4 || !mti.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    let mut mti = mti.as_bytes().to_vec();
+    if mti[3] % 2 == 1 {
+        mti[3] -= 1;
+    }
+    String::from_utf8(mti).ok()
+}
+
+/// The response MTI for a request or advice e.g. 0200 => 0210, 0421 => 0430, or `None` if it is already a response
+pub fn response_mti(mti: &str) -> Option<String> {
+    let mut mti = non_repeat_mti(mti)?.into_bytes();
+    if mti[2] % 2 == 1 {
+        return None;
+    }
+    mti[2] += 1;
+    String::from_utf8(mti).ok()
+}
+
+fn mti_with_class(mti: &str, class: u8, function: u8) -> String {
+    let mut mti = mti.as_bytes().to_vec();
+    mti[1] = class;
+    mti[2] = function;
+    String::from_utf8_lossy(&mti).into_owned()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use zaps::card::FixedClock;
+    use zaps::crypto::{
+        dukpt::{
+            self,
+            KeyUsage,
+        },
+        pin,
+    };
+    use crate::cards::{
+        Card,
+        CardStatus,
+    };
+
+    fn request(mti: &str, stan: &str, extra: &[(u16, &str)]) -> HashMap<u16, String> {
+        [(MTI_FIELD, mti), (0, "bitmap"), (11, stan), (13, "1019"), (41, "TERMID01")]
+            .iter()
+            .chain(extra)
+            .map(|(k, v)| (*k, v.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn mti_conversions() {
+        assert_eq!(Some("0200".to_string()), non_repeat_mti("0201"));
+        assert_eq!(Some("0420".to_string()), non_repeat_mti("0420"));
+        assert_eq!(None, non_repeat_mti("02x0"));
+        assert_eq!(Some("0210".to_string()), response_mti("0200"));
+        assert_eq!(Some("0230".to_string()), response_mti("0221"));
+        assert_eq!(Some("0810".to_string()), response_mti("0800"));
+        assert_eq!(None, response_mti("0210"));
+    }
+
+    #[test]
+    fn approves_and_generates_ids() {
+        let responder = AutoResponder::new(TransactionStore::new());
+        let response = responder.response(&request("0200", "000001", &[])).unwrap();
+
+        assert_eq!("0210", response[&MTI_FIELD]);
+        assert_eq!("00", response[&39]);
+        assert_eq!("000001", response[&38]);
+        assert_eq!("000000000001", response[&37]);
+        assert_eq!("TERMID01", response[&41]);
+        assert!(!response.contains_key(&0));
+    }
+
+    #[test]
+    fn card_data_not_echoed() {
+        let responder = AutoResponder::new(TransactionStore::new());
+        let card_data = [
+            (14, "2912"),
+            (35, "4111111111111111=29121010000000"),
+            (45, "B4111111111111111^TEST/CARD^2912101"),
+            (48, "9203123"),
+            (52, "0123456789ABCDEF"),
+            (55, "9F2608481BF1B0B0F4325C"),
+        ];
+        let mut fields = vec![(2, "4111111111111111"), (4, "000000001000")];
+        fields.extend_from_slice(&card_data);
+        let response = responder.response(&request("0200", "000001", &fields)).unwrap();
+
+        assert_eq!(("4111111111111111", "000000001000"), (&response[&2][..], &response[&4][..]));
+        assert!(card_data.iter().all(|(field, _value)| !response.contains_key(field)));
+    }
+
+    #[test]
+    fn card_data_not_stored() {
+        let stored = |responder: &AutoResponder, fields: &[(u16, &str)]| {
+            let response = responder.response(&request("0200", "000001", fields)).unwrap();
+            assert_eq!(response, responder.response(&request("0201", "000001", fields)).unwrap());
+            let key = TransactionKey::from_fields("0200", &request("0200", "000001", &[])).unwrap();
+            let store = responder.store().lock().unwrap();
+            let transaction = store.get(&key).unwrap();
+            (transaction.request.clone(), transaction.response.clone())
+        };
+        let keyed = [(11, "000001"), (13, "1019"), (41, "TERMID01")]
+            .iter()
+            .map(|(field, value)| (*field, value.to_string()));
+
+        let plain = AutoResponder::new(TransactionStore::new());
+        let (request, response) = stored(&plain, &[(2, "4111111111111111"), (52, "0123456789ABCDEF")]);
+        assert_eq!(keyed.clone().collect::<HashMap<_, _>>(), request);
+        assert!(!response.contains_key(&2));
+
+        let track = "4111111111111111=99121010000000";
+        let (request, response) = stored(&card_responder(), &[(35, track), (4, "000000001000")]);
+        let mut expected = keyed.collect::<HashMap<_, _>>();
+        expected.extend([(2, "4111111111111111".to_string()), (4, "000000001000".to_string())]);
+        assert_eq!(expected, request);
+        assert!(!response.contains_key(&2));
+    }
+
+    #[test]
+    fn duplicate_gets_original_response() {
+        let responder = AutoResponder::new(TransactionStore::new());
+        let original = responder.response(&request("0200", "000001", &[(4, "000000001000")])).unwrap();
+        let repeat = responder.response(&request("0201", "000001", &[(4, "000000009999")])).unwrap();
+
+        assert_eq!(original, repeat);
+        assert_eq!(1, responder.store().lock().unwrap().len());
+    }
+
+    #[test]
+    fn reversal_voids_original() {
+        let responder = AutoResponder::new(TransactionStore::new());
+        responder.response(&request("0200", "000001", &[]));
+
+        let response = responder.response(&request("0400", "000001", &[])).unwrap();
+        assert_eq!("0410", response[&MTI_FIELD]);
+        assert_eq!("00", response[&39]);
+
+        let store = responder.store().lock().unwrap();
+        let original = TransactionKey::from_fields("0200", &request("0200", "000001", &[])).unwrap();
+        assert_eq!(TransactionState::Reversed, store.get(&original).unwrap().state);
+    }
+
+    #[test]
+    fn reversal_of_reversed() {
+        let responder = card_responder();
+        let pan = "4111111111111111";
+        let original = responder.response(&request("0200", "000001", &[(2, pan), (4, "000000001000")])).unwrap();
+
+        let reversal = responder.response(&request("0400", "000002", &[(2, pan), (37, &original[&37])])).unwrap();
+        assert_eq!("00", reversal[&39]);
+        assert_eq!(reversal, responder.response(&request("0401", "000002", &[(2, pan), (37, &original[&37])])).unwrap());
+
+        let response = responder.response(&request("0420", "000003", &[(2, pan), (37, &original[&37])])).unwrap();
+        assert_eq!("94", response[&39]);
+        assert_eq!(10_000, balance(&responder, pan));
+    }
+
+    #[test]
+    fn reversal_by_rrn() {
+        let responder = AutoResponder::new(TransactionStore::new());
+        let original = responder.response(&request("0100", "000001", &[])).unwrap();
+
+        let response = responder.response(&request("0420", "000002", &[(37, &original[&37])])).unwrap();
+        assert_eq!("0430", response[&MTI_FIELD]);
+        assert_eq!("00", response[&39]);
+    }
+
+    #[test]
+    fn reversal_without_original() {
+        let responder = AutoResponder::new(TransactionStore::new());
+        let response = responder.response(&request("0400", "000001", &[])).unwrap();
+        assert_eq!("25", response[&39]);
+    }
+
+    #[test]
+    fn completion_matches_pre_auth() {
+        let responder = AutoResponder::new(TransactionStore::new());
+        let pre_auth = responder.response(&request("0100", "000001", &[])).unwrap();
+
+        let response = responder.response(&request("0220", "000002", &[(37, &pre_auth[&37])])).unwrap();
+        assert_eq!("0230", response[&MTI_FIELD]);
+        assert_eq!("00", response[&39]);
+
+        let store = responder.store().lock().unwrap();
+        assert_eq!(TransactionState::Completed, store.get_by_rrn(&pre_auth[&37]).unwrap().state);
+    }
+
+    #[test]
+    fn completion_without_pre_auth() {
+        let responder = AutoResponder::new(TransactionStore::new());
+        let response = responder.response(&request("0220", "000001", &[])).unwrap();
+        assert_eq!("25", response[&39]);
+    }
+
+    #[test]
+    fn completion_already_completed() {
+        let responder = AutoResponder::new(TransactionStore::new());
+        let pre_auth = responder.response(&request("0100", "000001", &[])).unwrap();
+        responder.response(&request("0220", "000002", &[(37, &pre_auth[&37])]));
+
+        let response = responder.response(&request("0220", "000003", &[(37, &pre_auth[&37])])).unwrap();
+        assert_eq!("94", response[&39]);
+    }
+
+    #[test]
+    fn advice_without_pre_auth() {
+        let responder = AutoResponder::new(TransactionStore::new());
+        let advice = request("0120", "000001", &[]);
+        let response = responder.response(&advice).unwrap();
+        assert_eq!("0130", response[&MTI_FIELD]);
+        assert_eq!("00", response[&39]);
+
+        let key = TransactionKey::from_fields("0120", &advice).unwrap();
+        assert_eq!(TransactionState::Approved, responder.store().lock().unwrap().get(&key).unwrap().state);
+    }
+
+    fn card_responder() -> AutoResponder {
+        let mut cards = CardDatabase::new();
+        cards.add(Card::new("4111111111111111", "9912", CardStatus::Active, 10_000, "826", 8_000));
+        cards.add(Card::new("4000000000000002", "9912", CardStatus::Lost, 10_000, "826", 8_000));
+        cards.add(Card::new("4000000000000010", "0001", CardStatus::Active, 10_000, "826", 8_000));
+        AutoResponder::new(TransactionStore::new()).with_cards(cards)
+    }
+
+    fn balance(responder: &AutoResponder, pan: &str) -> i64 {
+        responder.cards().unwrap().lock().unwrap().get(pan).unwrap().balance
+    }
+
+    #[test]
+    fn card_declines() {
+        let responder = card_responder();
+        let decline = |stan, pan, amount| {
+            let response = responder.response(&request("0200", stan, &[(2, pan), (4, amount)])).unwrap();
+            assert!(!response.contains_key(&38));
+            response[&39].clone()
+        };
+
+        assert_eq!("14", decline("000001", "4000000000000028", "000000000100"));
+        assert_eq!("41", decline("000002", "4000000000000002", "000000000100"));
+        assert_eq!("54", decline("000003", "4000000000000010", "000000000100"));
+        assert_eq!("51", decline("000004", "4111111111111111", "000000010001"));
+        assert_eq!("61", decline("000005", "4111111111111111", "000000008001"));
+        assert_eq!(10_000, balance(&responder, "4111111111111111"));
+    }
+
+    #[test]
+    fn card_balance_updates() {
+        let responder = card_responder();
+        let pan = "4111111111111111";
+
+        let response = responder.response(&request("0200", "000001", &[(2, pan), (4, "000000001000")])).unwrap();
+        assert_eq!("00", response[&39]);
+        assert_eq!(9_000, balance(&responder, pan));
+
+        responder.response(&request("0400", "000001", &[(2, pan), (4, "000000001000")]));
+        assert_eq!(10_000, balance(&responder, pan));
+
+        let pre_auth = responder.response(&request("0100", "000002", &[(2, pan), (4, "000000002000")])).unwrap();
+        assert_eq!(8_000, balance(&responder, pan));
+
+        responder.response(&request("0220", "000003", &[(2, pan), (4, "000000001500"), (37, &pre_auth[&37])]));
+        assert_eq!(8_500, balance(&responder, pan));
+    }
+
+    #[test]
+    fn card_advice_without_pre_auth() {
+        let responder = card_responder();
+        let pan = "4111111111111111";
+
+        // advices are of authorisations already made, so aren't declined for funds
+        let advice = request("0120", "000001", &[(2, pan), (4, "000000012000")]);
+        let response = responder.response(&advice).unwrap();
+        assert_eq!("00", response[&39]);
+        assert_eq!(-2_000, balance(&responder, pan));
+
+        let key = TransactionKey::from_fields("0120", &advice).unwrap();
+        assert_eq!(12_000, responder.store().lock().unwrap().get(&key).unwrap().debited);
+    }
+
+    #[test]
+    fn card_reversal_after_completion() {
+        let responder = card_responder();
+        let pan = "4111111111111111";
+
+        let pre_auth = responder.response(&request("0100", "000001", &[(2, pan), (4, "000000002000")])).unwrap();
+        responder.response(&request("0220", "000002", &[(2, pan), (4, "000000001500"), (37, &pre_auth[&37])]));
+        assert_eq!(8_500, balance(&responder, pan));
+
+        // only the completed amount is still debited, not the pre-authorised one
+        let reversal = request("0420", "000003", &[(2, pan), (4, "000000002000"), (37, &pre_auth[&37])]);
+        let response = responder.response(&reversal).unwrap();
+        assert_eq!("00", response[&39]);
+        assert_eq!(10_000, balance(&responder, pan));
+    }
+
+    #[test]
+    fn card_currency() {
+        let responder = card_responder();
+        let pay = |stan, currency| {
+            let fields = [(2, "4111111111111111"), (4, "000000001000"), (49, currency)];
+            responder.response(&request("0200", stan, &fields)).unwrap()[&39].clone()
+        };
+
+        assert_eq!("57", pay("000001", "840"));
+        assert_eq!(10_000, balance(&responder, "4111111111111111"));
+        assert_eq!("00", pay("000002", "826"));
+        assert_eq!(9_000, balance(&responder, "4111111111111111"));
+    }
+
+    #[test]
+    fn card_pin_checks() {
+        let key = "tdes:0123456789ABCDEFFEDCBA9876543210".parse::<Key>().unwrap();
+        let mut cards = CardDatabase::new();
+        cards.add(Card::new("4111111111111111", "9912", CardStatus::Active, 10_000, "826", 8_000).with_pin("1234"));
+        let responder = AutoResponder::new(TransactionStore::new())
+            .with_cards(cards)
+            .with_pin_key(key.clone(), PinFormat::Iso0);
+        let pay = |stan, pin| {
+            let block = pin::encrypt(&key, PinFormat::Iso0, pin, "4111111111111111").unwrap();
+            let block = block.iter().map(|b| *b as char).collect::<String>();
+            let fields = [(2, "4111111111111111"), (4, "000000001000"), (52, &block[..])];
+            responder.response(&request("0200", stan, &fields)).unwrap()[&39].clone()
+        };
+
+        assert_eq!("55", pay("000001", "4321"));
+        assert_eq!(10_000, balance(&responder, "4111111111111111"));
+        assert_eq!("00", pay("000002", "1234"));
+        assert_eq!(9_000, balance(&responder, "4111111111111111"));
+    }
+
+    #[test]
+    fn dukpt_pin_checks() {
+        let bdk = "tdes:0123456789ABCDEFFEDCBA9876543210".parse::<Key>().unwrap();
+        let mut cards = CardDatabase::new();
+        cards.add(Card::new("4111111111111111", "9912", CardStatus::Active, 10_000, "826", 8_000).with_pin("1234"));
+        let responder = AutoResponder::new(TransactionStore::new())
+            .with_cards(cards)
+            .with_dukpt(bdk.clone(), 62, PinFormat::Iso0);
+        let pay = |stan, pin, ksn: Option<&str>| {
+            let key = dukpt::derive(&bdk, &hex::decode("FFFF9876543210E00003").unwrap(), KeyUsage::Pin).unwrap();
+            let block = pin::encrypt(&key, PinFormat::Iso0, pin, "4111111111111111").unwrap();
+            let block = block.iter().map(|b| *b as char).collect::<String>();
+            let mut fields = vec![(2, "4111111111111111"), (4, "000000001000"), (52, &block[..])];
+            fields.extend(ksn.map(|ksn| (62, ksn)));
+            responder.response(&request("0200", stan, &fields)).unwrap()[&39].clone()
+        };
+
+        assert_eq!("00", pay("000001", "1234", Some("FFFF9876543210E00003")));
+        assert_eq!("55", pay("000002", "1234", Some("FFFF9876543210E00004")));
+        assert_eq!("55", pay("000003", "1234", None));
+        assert_eq!(9_000, balance(&responder, "4111111111111111"));
+    }
+
+    #[test]
+    fn card_checks() {
+        let responder = AutoResponder::new(TransactionStore::new())
+            .with_card_checks(CardChecks::new())
+            .with_clock(FixedClock("2610".to_string()));
+        let pay = |stan, fields: &[(u16, &str)]| responder.response(&request("0200", stan, fields)).unwrap()[&39].clone();
+
+        assert_eq!("00", pay("000001", &[(2, "4111111111111111"), (14, "2610")]));
+        assert_eq!("14", pay("000002", &[(2, "4111111111111112"), (14, "2610")]));
+        assert_eq!("54", pay("000003", &[(35, "4111111111111111=2609101")]));
+        assert_eq!("00", pay("000004", &[(35, "4111111111111111=2912101")]));
+    }
+
+    #[test]
+    fn cvv_checks() {
+        let checks = CvvChecks::new("tdes:0123456789ABCDEFFEDCBA9876543210".parse().unwrap()).unwrap()
+            .with_cvv2("48.92".parse().unwrap());
+        let responder = AutoResponder::new(TransactionStore::new()).with_cvv_checks(checks);
+        let pay = |stan, fields: &[(u16, &str)]| responder.response(&request("0200", stan, fields)).unwrap()[&39].clone();
+
+        assert_eq!("00", pay("000001", &[(35, "4111111111111111=291210100000216")]));
+        assert_eq!("82", pay("000002", &[(35, "4111111111111111=291210100000217")]));
+        assert_eq!("00", pay("000003", &[(2, "4111111111111111"), (14, "2912"), (48, "9203719")]));
+        assert_eq!("N7", pay("000004", &[(2, "4111111111111111"), (14, "2912"), (48, "9203718")]));
+    }
+
+    #[test]
+    fn chip_cryptograms() {
+        let issuer = Issuer::new("tdes:0123456789ABCDEFFEDCBA9876543210".parse().unwrap()).unwrap();
+        let responder = AutoResponder::new(TransactionStore::new()).with_issuer(issuer);
+        let icc = "9F02060000000010009F03060000000000009F1A020826950500000000005F2A0208269A032410199C01009F3704123456788202\
+            19809F360200019F2608481BF1B0B0F4325C";
+        let pay = |stan, icc: &str| {
+            let fields = [(2, "4761739001010010"), (4, "000000001000"), (23, "001"), (55, icc)];
+            responder.response(&request("0200", stan, &fields)).unwrap()
+        };
+
+        let response = pay("000001", icc);
+        assert_eq!("00", response[&39]);
+        assert_eq!("910A7EA3F9CEFB3729AB3030", response[&55]);
+
+        let response = pay("000002", &icc.replace("9F2608481B", "9F2608481C"));
+        assert_eq!("82", response[&39]);
+        assert!(!response.contains_key(&55));
+
+        let response = responder.response(&request("0200", "000003", &[(2, "4761739001010010")])).unwrap();
+        assert_eq!("00", response[&39]);
+    }
+
+    #[test]
+    fn chip_cvv_declined() {
+        let key = "tdes:0123456789ABCDEFFEDCBA9876543210";
+        let responder = AutoResponder::new(TransactionStore::new())
+            .with_issuer(Issuer::new(key.parse().unwrap()).unwrap())
+            .with_cvv_checks(CvvChecks::new(key.parse().unwrap()).unwrap());
+        let icc = "9F02060000000010009F03060000000000009F1A020826950500000000005F2A0208269A032410199C01009F3704123456788202\
+            19809F360200019F2608481BF1B0B0F4325C";
+        let track = "4761739001010010=291220100000000";
+        let fields = [(2, "4761739001010010"), (4, "000000001000"), (23, "001"), (35, track), (55, icc)];
+
+        // the iCVV declines it but the cryptogram is the card's, so it's still answered
+        let response = responder.response(&request("0200", "000001", &fields)).unwrap();
+        assert_eq!("82", response[&39]);
+        assert_eq!("910A461C54C38D235E483832", response[&55]);
+    }
+
+    #[test]
+    fn responses_not_answered() {
+        let responder = AutoResponder::new(TransactionStore::new());
+        assert_eq!(None, responder.response(&request("0210", "000001", &[])));
+    }
+}
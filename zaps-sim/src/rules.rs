@@ -0,0 +1,202 @@
+use std::collections::HashMap;
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+use zaps::iso8583::MTI_FIELD;
+
+use crate::responder::{
+    Action,
+    Responder,
+};
+
+/// What a rule does with the response it would otherwise have got, mirroring [`Action`].
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "action", rename_all = "lowercase")]
+pub enum RuleAction {
+    #[default]
+    Respond,
+    Delay { ms: u64 },
+    Fault { profile: String },
+    Drop,
+    Disconnect,
+}
+
+/// Overrides the response to requests matching an MTI and field values, e.g. in JSON
+/// `{"mti": "0200", "fields": {"4": "000000000100"}, "set": {"39": "51"}, "action": "delay", "ms": 500}`
+///
+/// Only the action is required, one of `respond`, `delay` (with `ms`), `fault` (with `profile`), `drop` or
+/// `disconnect`.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Rule {
+    /// Any MTI if not given
+    pub mti: Option<String>,
+    pub fields: HashMap<u16, String>,
+    /// Fields to set in the response
+    pub set: HashMap<u16, String>,
+    #[serde(flatten)]
+    pub action: RuleAction,
+}
+
+impl Rule {
+    pub fn matches(&self, request: &HashMap<u16, String>) -> bool {
+        let mti_matches = match &self.mti {
+            Some(mti) => request.get(&MTI_FIELD) == Some(mti),
+            None => true,
+        };
+        mti_matches && self.fields.iter().all(|(field, value)| request.get(field) == Some(value))
+    }
+
+    /// Applies the rule to the action that would have been taken without it.
+    ///
+    /// Rules which respond can only change a response, so if there isn't one the original action is kept.
+    pub fn apply(&self, mut action: Action<u16>) -> Action<u16> {
+        if let Action::Respond(response) | Action::Delay(_, response) | Action::Fault(_, response) = &mut action {
+            response.extend(self.set.clone());
+        }
+
+        match (&self.action, action) {
+            (RuleAction::Drop, _) => Action::Drop,
+            (RuleAction::Disconnect, _) => Action::Disconnect,
+            (RuleAction::Delay{ ms }, Action::Respond(response) | Action::Delay(_, response) | Action::Fault(_, response)) => {
+                Action::Delay(Duration::from_millis(*ms), response)
+            },
+            (RuleAction::Fault{ profile }, Action::Respond(response) | Action::Delay(_, response) | Action::Fault(_, response)) => {
+                Action::Fault(profile.clone(), response)
+            },
+            (_, action) => action,
+        }
+    }
+}
+
+/// An ordered list of rules which can be changed while the simulator runs
+#[derive(Debug, Default)]
+pub struct RuleSet {
+    rules: RwLock<Vec<Rule>>,
+}
+
+impl RuleSet {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn rules(&self) -> Vec<Rule> {
+        self.rules.read().unwrap().clone()
+    }
+
+    pub fn set(&self, rules: Vec<Rule>) {
+        *self.rules.write().unwrap() = rules;
+    }
+
+    pub fn push(&self, rule: Rule) {
+        self.rules.write().unwrap().push(rule);
+    }
+
+    /// The first rule matching a request
+    pub fn find(&self, request: &HashMap<u16, String>) -> Option<Rule> {
+        self.rules.read().unwrap()
+            .iter()
+            .find(|rule| rule.matches(request))
+            .cloned()
+    }
+}
+
+/// Applies the first matching rule, if any, to the response from another responder.
+pub struct RuleResponder<R> {
+    rules: Arc<RuleSet>,
+    inner: R,
+}
+
+impl<R> RuleResponder<R> {
+    pub fn new(rules: Arc<RuleSet>, inner: R) -> Self {
+        RuleResponder {
+            rules,
+            inner,
+        }
+    }
+
+    pub fn rules(&self) -> &Arc<RuleSet> {
+        &self.rules
+    }
+}
+
+impl<R> Responder<u16> for RuleResponder<R>
+where
+    R: Responder<u16>,
+{
+    fn respond(&self, request: &HashMap<u16, String>) -> Action<u16> {
+        let action = self.inner.respond(request);
+        match self.rules.find(request) {
+            Some(rule) => rule.apply(action),
+            None => action,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        responder::AutoResponder,
+        store::TransactionStore,
+    };
+
+    fn request(mti: &str, amount: &str) -> HashMap<u16, String> {
+        [(MTI_FIELD, mti), (4, amount), (11, "000001"), (41, "TERMID01")]
+            .iter()
+            .map(|(k, v)| (*k, v.to_string()))
+            .collect()
+    }
+
+    fn responder(rules: &str) -> RuleResponder<AutoResponder> {
+        let ruleset = RuleSet::new();
+        ruleset.set(serde_json::from_str(rules).unwrap());
+        RuleResponder::new(Arc::new(ruleset), AutoResponder::new(TransactionStore::new()))
+    }
+
+    #[test]
+    fn rule_from_json() {
+        let rule = serde_json::from_str::<Rule>(r#"{"mti": "0200", "fields": {"4": "000000000100"}, "set": {"39": "51"}, "action": "delay", "ms": 500}"#).unwrap();
+        assert_eq!(Rule {
+            mti: Some("0200".to_string()),
+            fields: HashMap::from([(4, "000000000100".to_string())]),
+            set: HashMap::from([(39, "51".to_string())]),
+            action: RuleAction::Delay{ ms: 500 },
+        }, rule);
+
+        let drop_all = Rule {
+            action: RuleAction::Drop,
+            ..Default::default()
+        };
+        assert_eq!(drop_all, serde_json::from_str::<Rule>(r#"{"action": "drop"}"#).unwrap());
+    }
+
+    #[test]
+    fn first_matching_rule_applies() {
+        let responder = responder(r#"[
+            {"mti": "0200", "fields": {"4": "000000000100"}, "set": {"39": "51"}, "action": "respond"},
+            {"mti": "0200", "action": "fault", "profile": "slow"},
+            {"action": "drop"}
+        ]"#);
+
+        match responder.respond(&request("0200", "000000000100")) {
+            Action::Respond(response) => assert_eq!("51", response[&39]),
+            other => panic!("expected response but was {:?}", other),
+        }
+        match responder.respond(&request("0200", "000000000200")) {
+            Action::Fault(profile, response) => {
+                assert_eq!("slow", profile);
+                assert_eq!("00", response[&39]);
+            },
+            other => panic!("expected fault but was {:?}", other),
+        }
+        assert!(matches!(responder.respond(&request("0100", "000000000100")), Action::Drop));
+    }
+
+    #[test]
+    fn no_response_to_change() {
+        let responder = responder(r#"[{"set": {"39": "05"}, "action": "delay", "ms": 100}]"#);
+        assert!(matches!(responder.respond(&request("0210", "000000000100")), Action::Drop));
+    }
+}
@@ -0,0 +1,433 @@
+use std::collections::HashMap;
+use std::error;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, RwLock, Weak};
+use std::thread;
+use std::time::{Duration, SystemTime};
+
+use rhai::{
+    CallFnOptions,
+    Dynamic,
+    Engine,
+    ImmutableString,
+    Map,
+    Scope,
+    AST,
+};
+use tracing::{info, warn};
+use zaps::iso8583::MTI_FIELD;
+
+use crate::responder::{
+    response_mti,
+    Action,
+    AutoResponder,
+    Responder,
+};
+
+/// The function every script must define, taking the request and returning what to do with it
+const ENTRYPOINT: &str = "respond";
+const MTI_KEY: &str = "mti";
+/// How often the script file is checked for changes
+const RELOAD_INTERVAL: Duration = Duration::from_secs(1);
+
+#[derive(Debug)]
+pub enum ScriptError {
+    Io(io::Error),
+    Parse(String),
+}
+
+impl fmt::Display for ScriptError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Io(e) => write!(f, "unable to read script: {}", e),
+            Self::Parse(e) => write!(f, "unable to compile script: {}", e),
+        }
+    }
+}
+
+impl error::Error for ScriptError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            Self::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for ScriptError {
+    fn from(err: io::Error) -> Self {
+        Self::Io(err)
+    }
+}
+
+struct Script {
+    ast: AST,
+    modified: Option<SystemTime>,
+}
+
+/// Responds using a [Rhai](https://rhai.rs) script.
+///
+/// The script must define `fn respond(request)` which is passed the request as a map of field number to value, with
+/// the MTI under `mti`, and returns one of
+/// - a map in the same form, which is sent as the response
+/// - `delay(ms, response)` to send the response after a delay
+/// - `fault(profile, response)` to send the response with a fault profile in place of the connection's
+/// - `drop()` or `()` to not respond
+/// - `disconnect()` to close the connection
+///
+/// Scripts also have
+/// - `response_mti(mti)` e.g. `"0210"` for `"0200"`
+/// - `response_to(request)`, a copy of the request with the response MTI
+/// - `auto_respond(request)`, the response the auto-responder would have sent, or `()`
+/// - `msg.field(n)`, `msg.set_field(n, value)` and `msg.remove_field(n)`
+/// - `state_get(key)` and `state_set(key, value)` for state shared by all connections
+///
+/// The script is reloaded within a second of the file changing, by a thread of its own so that requests never wait on
+/// the file system. If the new version fails to compile the error is reported and the previous version kept.
+pub struct ScriptResponder {
+    loaded: Arc<LoadedScript>,
+}
+
+struct LoadedScript {
+    path: PathBuf,
+    engine: Engine,
+    script: RwLock<Script>,
+}
+
+impl ScriptResponder {
+    pub fn new<P: Into<PathBuf>>(path: P, fallback: AutoResponder) -> Result<Self, ScriptError> {
+        let path = path.into();
+        let engine = engine(Arc::new(fallback));
+        let script = load(&engine, &path)?;
+
+        let loaded = Arc::new(LoadedScript {
+            path,
+            engine,
+            script: RwLock::new(script),
+        });
+        let watched = Arc::downgrade(&loaded);
+        thread::spawn(move || watch(watched));
+
+        Ok(ScriptResponder {
+            loaded,
+        })
+    }
+}
+
+/// Reloads the script whenever it changes, until the responder using it is dropped
+fn watch(loaded: Weak<LoadedScript>) {
+    loop {
+        thread::sleep(RELOAD_INTERVAL);
+        match loaded.upgrade() {
+            Some(loaded) => loaded.reload_if_modified(),
+            None => return,
+        }
+    }
+}
+
+impl LoadedScript {
+    fn reload_if_modified(&self) {
+        let modified = modified(&self.path);
+        if modified == self.script.read().unwrap().modified {
+            return;
+        }
+
+        let mut script = self.script.write().unwrap();
+        match load(&self.engine, &self.path) {
+            Ok(reloaded) => {
+                info!("Reloaded script {}", self.path.display());
+                *script = reloaded;
+            },
+            Err(e) => {
+                warn!("Keeping previous script, {}", e);
+                script.modified = modified;
+            },
+        }
+    }
+}
+
+impl Responder<u16> for ScriptResponder {
+    fn respond(&self, request: &HashMap<u16, String>) -> Action<u16> {
+        let LoadedScript{ engine, script, .. } = &*self.loaded;
+        let script = script.read().unwrap();
+        let options = CallFnOptions::new().eval_ast(false);
+        let result = engine.call_fn_with_options::<Dynamic>(options, &mut Scope::new(), &script.ast, ENTRYPOINT, (to_map(request),));
+
+        match result {
+            Ok(result) => to_action(result).unwrap_or_else(|e| {
+                warn!("Invalid result from script: {}", e);
+                Action::Drop
+            }),
+            Err(e) => {
+                warn!("Script failed: {}", e);
+                Action::Drop
+            },
+        }
+    }
+}
+
+fn modified(path: &Path) -> Option<SystemTime> {
+    fs::metadata(path)
+        .and_then(|metadata| metadata.modified())
+        .ok()
+}
+
+fn load(engine: &Engine, path: &Path) -> Result<Script, ScriptError> {
+    let modified = modified(path);
+    let source = fs::read_to_string(path)?;
+    let ast = engine.compile(source)
+        .map_err(|e| ScriptError::Parse(e.to_string()))?;
+
+    if !ast.iter_functions().any(|f| f.name == ENTRYPOINT && f.params.len() == 1) {
+        return Err(ScriptError::Parse(format!("no {}(request) function defined", ENTRYPOINT)));
+    }
+
+    Ok(Script {
+        ast,
+        modified,
+    })
+}
+
+fn engine(fallback: Arc<AutoResponder>) -> Engine {
+    let mut engine = Engine::new();
+    let state = Arc::new(Mutex::new(HashMap::<String, Dynamic>::new()));
+
+    engine.register_type_with_name::<Action<u16>>("Action");
+    engine.register_fn("delay", |ms: i64, response: Map| -> Result<Action<u16>, Box<rhai::EvalAltResult>> {
+        let delay = Duration::from_millis(ms.max(0) as u64);
+        Ok(Action::Delay(delay, from_map(&response)?))
+    });
+    engine.register_fn("fault", |profile: &str, response: Map| -> Result<Action<u16>, Box<rhai::EvalAltResult>> {
+        Ok(Action::Fault(profile.to_string(), from_map(&response)?))
+    });
+    engine.register_fn("drop", || Action::<u16>::Drop);
+    engine.register_fn("disconnect", || Action::<u16>::Disconnect);
+
+    engine.register_fn("response_mti", |mti: &str| -> Dynamic {
+        response_mti(mti).map(Dynamic::from).unwrap_or(Dynamic::UNIT)
+    });
+    engine.register_fn("response_to", |request: Map| -> Map {
+        let mut response = request;
+        response.remove("0");
+        let mti = response.get(MTI_KEY)
+            .and_then(|mti| response_mti(&mti.to_string()));
+        if let Some(mti) = mti {
+            response.insert(MTI_KEY.into(), mti.into());
+        }
+        response
+    });
+    engine.register_fn("auto_respond", move |request: Map| -> Result<Dynamic, Box<rhai::EvalAltResult>> {
+        Ok(fallback.response(&from_map(&request)?)
+            .map(|response| Dynamic::from_map(to_map(&response)))
+            .unwrap_or(Dynamic::UNIT))
+    });
+
+    engine.register_fn("field", |msg: &mut Map, field: i64| -> Dynamic {
+        msg.get(field.to_string().as_str())
+            .cloned()
+            .unwrap_or(Dynamic::UNIT)
+    });
+    engine.register_fn("set_field", |msg: &mut Map, field: i64, value: Dynamic| {
+        msg.insert(field.to_string().into(), value.to_string().into());
+    });
+    engine.register_fn("remove_field", |msg: &mut Map, field: i64| {
+        msg.remove(field.to_string().as_str());
+    });
+
+    let get_state = state.clone();
+    engine.register_fn("state_get", move |key: ImmutableString| -> Dynamic {
+        get_state.lock().unwrap()
+            .get(key.as_str())
+            .cloned()
+            .unwrap_or(Dynamic::UNIT)
+    });
+    engine.register_fn("state_set", move |key: ImmutableString, value: Dynamic| {
+        state.lock().unwrap().insert(key.to_string(), value);
+    });
+
+    engine
+}
+
+fn to_map(fields: &HashMap<u16, String>) -> Map {
+    fields.iter()
+        .filter(|(field, _)| **field != 0)
+        .map(|(field, value)| {
+            let key = match *field {
+                MTI_FIELD => MTI_KEY.to_string(),
+                field => field.to_string(),
+            };
+            (key.into(), value.clone().into())
+        })
+        .collect()
+}
+
+fn from_map(map: &Map) -> Result<HashMap<u16, String>, Box<rhai::EvalAltResult>> {
+    map.iter()
+        .filter(|(_, value)| !value.is_unit())
+        .map(|(key, value)| {
+            let field = match key.as_str() {
+                MTI_KEY => MTI_FIELD,
+                key => key.parse::<u16>()
+                    .map_err(|_e| format!("invalid field {}", key))?,
+            };
+            Ok((field, value.to_string()))
+        })
+        .collect()
+}
+
+fn to_action(result: Dynamic) -> Result<Action<u16>, Box<rhai::EvalAltResult>> {
+    if result.is_unit() {
+        Ok(Action::Drop)
+    } else if result.is_map() {
+        Ok(Action::Respond(from_map(&result.cast::<Map>())?))
+    } else if result.is::<Action<u16>>() {
+        Ok(result.cast::<Action<u16>>())
+    } else {
+        Err(format!("expected a map or action but was {}", result.type_name()).into())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::store::TransactionStore;
+
+    fn responder(script: &str) -> (ScriptResponder, PathBuf) {
+        let path = std::env::temp_dir().join(format!("zaps-script-{}-{:?}.rhai", std::process::id(), std::thread::current().id()));
+        fs::write(&path, script).unwrap();
+        (ScriptResponder::new(&path, AutoResponder::new(TransactionStore::new())).unwrap(), path)
+    }
+
+    fn request(mti: &str, amount: &str) -> HashMap<u16, String> {
+        [(MTI_FIELD, mti), (0, "bitmap"), (4, amount), (11, "000001"), (41, "TERMID01")]
+            .iter()
+            .map(|(k, v)| (*k, v.to_string()))
+            .collect()
+    }
+
+    fn response(action: Action<u16>) -> HashMap<u16, String> {
+        match action {
+            Action::Respond(response) => response,
+            other => panic!("expected response but was {:?}", other),
+        }
+    }
+
+    #[test]
+    fn responds_with_map() {
+        let (responder, path) = responder(r#"
+            fn respond(request) {
+                let response = response_to(request);
+                response.set_field(39, if request.field(4) == "000000000100" { "51" } else { "00" });
+                response
+            }
+        "#);
+
+        let declined = response(responder.respond(&request("0200", "000000000100")));
+        assert_eq!("0210", declined[&MTI_FIELD]);
+        assert_eq!("51", declined[&39]);
+        assert_eq!("000001", declined[&11]);
+        assert!(!declined.contains_key(&0));
+
+        let approved = response(responder.respond(&request("0200", "000000000200")));
+        assert_eq!("00", approved[&39]);
+
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn actions() {
+        let (responder, path) = responder(r#"
+            fn respond(request) {
+                switch request.field(4) {
+                    "1" => delay(250, auto_respond(request)),
+                    "2" => drop(),
+                    "3" => disconnect(),
+                    "5" => fault("flaky", response_to(request)),
+                    _ => (),
+                }
+            }
+        "#);
+
+        match responder.respond(&request("0200", "1")) {
+            Action::Delay(delay, response) => {
+                assert_eq!(Duration::from_millis(250), delay);
+                assert_eq!("00", response[&39]);
+            },
+            other => panic!("expected delay but was {:?}", other),
+        }
+        assert!(matches!(responder.respond(&request("0200", "2")), Action::Drop));
+        assert!(matches!(responder.respond(&request("0200", "3")), Action::Disconnect));
+        assert!(matches!(responder.respond(&request("0200", "4")), Action::Drop));
+        assert!(matches!(responder.respond(&request("0200", "5")), Action::Fault(profile, _) if profile == "flaky"));
+
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn shared_state() {
+        let (responder, path) = responder(r#"
+            fn respond(request) {
+                let count = state_get("count") ?? 0;
+                state_set("count", count + 1);
+                let response = response_to(request);
+                response.set_field(38, count);
+                response
+            }
+        "#);
+
+        assert_eq!("0", response(responder.respond(&request("0200", "1")))[&38]);
+        assert_eq!("1", response(responder.respond(&request("0200", "1")))[&38]);
+
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn hot_reload() {
+        let (responder, path) = responder(r#"fn respond(request) { disconnect() }"#);
+        assert!(matches!(responder.respond(&request("0200", "1")), Action::Disconnect));
+
+        // make sure the modification time moves on even on coarse grained file systems
+        let later = SystemTime::now() + Duration::from_secs(5);
+        fs::write(&path, "fn respond(request) { drop() }").unwrap();
+        fs::File::options().write(true).open(&path).unwrap().set_modified(later).unwrap();
+        responder.loaded.reload_if_modified();
+        assert!(matches!(responder.respond(&request("0200", "1")), Action::Drop));
+
+        // broken scripts are ignored
+        fs::write(&path, "fn respond(request) {").unwrap();
+        fs::File::options().write(true).open(&path).unwrap().set_modified(later + Duration::from_secs(5)).unwrap();
+        responder.loaded.reload_if_modified();
+        assert!(matches!(responder.respond(&request("0200", "1")), Action::Drop));
+
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn watches_for_changes() {
+        let (responder, path) = responder(r#"fn respond(request) { disconnect() }"#);
+
+        let later = SystemTime::now() + Duration::from_secs(5);
+        fs::write(&path, "fn respond(request) { drop() }").unwrap();
+        fs::File::options().write(true).open(&path).unwrap().set_modified(later).unwrap();
+        let reloaded = (0..50).any(|_| {
+            thread::sleep(RELOAD_INTERVAL / 10);
+            matches!(responder.respond(&request("0200", "1")), Action::Drop)
+        });
+        assert!(reloaded);
+
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn missing_entrypoint() {
+        let path = std::env::temp_dir().join(format!("zaps-script-{}-missing.rhai", std::process::id()));
+        fs::write(&path, "fn other(request) { () }").unwrap();
+        let result = ScriptResponder::new(&path, AutoResponder::new(TransactionStore::new()));
+        fs::remove_file(path).unwrap();
+
+        assert!(matches!(result, Err(ScriptError::Parse(_))));
+    }
+}
@@ -0,0 +1,391 @@
+use std::collections::HashMap;
+use std::error;
+use std::fmt;
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufRead, BufReader, BufWriter, Write};
+use std::path::Path;
+use std::str::FromStr;
+
+use crate::escape::unescape;
+
+pub const FIELD_STAN: u16 = 11;
+pub const FIELD_TRANSMISSION_DATE: u16 = 7;
+pub const FIELD_LOCAL_DATE: u16 = 13;
+pub const FIELD_RRN: u16 = 37;
+pub const FIELD_TERMINAL: u16 = 41;
+
+/// Identifies a message by its (non-repeat) MTI, STAN, terminal and date.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct TransactionKey {
+    pub mti: String,
+    pub stan: String,
+    pub terminal: String,
+    pub date: String,
+}
+
+impl TransactionKey {
+    /// Builds the key for a message, `None` if it has no STAN or terminal to key on.
+    ///
+    /// The local transaction date is preferred, falling back to the date part of the transmission date time.
+    pub fn from_fields(mti: &str, fields: &HashMap<u16, String>) -> Option<Self> {
+        let stan = fields.get(&FIELD_STAN)?;
+        let terminal = fields.get(&FIELD_TERMINAL)?;
+        let date = fields.get(&FIELD_LOCAL_DATE)
+            .map(|date| date.to_string())
+            .or_else(|| fields.get(&FIELD_TRANSMISSION_DATE).map(|dt| dt.chars().take(4).collect()))
+            .unwrap_or_default();
+
+        Some(TransactionKey {
+            mti: mti.to_string(),
+            stan: stan.clone(),
+            terminal: terminal.clone(),
+            date,
+        })
+    }
+
+    /// The same transaction under a different MTI
+    pub fn with_mti(&self, mti: &str) -> Self {
+        TransactionKey {
+            mti: mti.to_string(),
+            ..self.clone()
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TransactionState {
+    Approved,
+    Declined,
+    Reversed,
+    Completed,
+}
+
+impl fmt::Display for TransactionState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(self, f)
+    }
+}
+
+impl FromStr for TransactionState {
+    type Err = StoreParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "Approved" => Ok(Self::Approved),
+            "Declined" => Ok(Self::Declined),
+            "Reversed" => Ok(Self::Reversed),
+            "Completed" => Ok(Self::Completed),
+            _ => Err(StoreParseError::InvalidState(s.to_string())),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub enum StoreParseError {
+    MissingColumn(&'static str),
+    InvalidState(String),
+    InvalidField(String),
+}
+
+impl fmt::Display for StoreParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::MissingColumn(column) => write!(f, "missing column {}", column),
+            Self::InvalidState(state) => write!(f, "invalid state: {}", state),
+            Self::InvalidField(field) => write!(f, "invalid field: {}", field),
+        }
+    }
+}
+
+impl error::Error for StoreParseError {}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Transaction {
+    pub key: TransactionKey,
+    pub state: TransactionState,
+    /// The amount still debited from the card for this transaction, after any completion or reversal of it
+    pub debited: i64,
+    /// The request's fields to match the transaction by, without card data and with the PAN only to credit the card
+    pub request: HashMap<u16, String>,
+    /// The response to send back to duplicates, without the PAN which they carry themselves
+    pub response: HashMap<u16, String>,
+}
+
+/// Journal lines are tab separated columns:
+/// `<mti> <stan> <terminal> <date> <state> [d=<debited>] [q<field>=<value>...] [r<field>=<value>...]`
+/// where `d` is the amount still debited, left out when there is none, `q` fields are from the request and `r` fields
+/// from the response.
+impl fmt::Display for Transaction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let TransactionKey{ mti, stan, terminal, date } = &self.key;
+        write!(f, "{}\t{}\t{}\t{}\t{}", mti, stan, terminal, date, self.state)?;
+        if self.debited != 0 {
+            write!(f, "\td={}", self.debited)?;
+        }
+        for (prefix, fields) in [('q', &self.request), ('r', &self.response)] {
+            let mut fields = fields.iter().collect::<Vec<_>>();
+            fields.sort_unstable();
+            for (field, value) in fields {
+                write!(f, "\t{}{}={}", prefix, field, value.escape_default())?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for Transaction {
+    type Err = StoreParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut columns = s.split('\t');
+        let mut next = |name| columns.next()
+            .map(|c| c.to_string())
+            .ok_or(StoreParseError::MissingColumn(name));
+
+        let key = TransactionKey {
+            mti: next("mti")?,
+            stan: next("stan")?,
+            terminal: next("terminal")?,
+            date: next("date")?,
+        };
+        let state = next("state")?.parse::<TransactionState>()?;
+
+        let mut debited = 0;
+        let mut request = HashMap::new();
+        let mut response = HashMap::new();
+        for column in columns {
+            let invalid = || StoreParseError::InvalidField(column.to_string());
+            let (field, value) = column.split_once('=')
+                .ok_or_else(invalid)?;
+            if field == "d" {
+                debited = value.parse::<i64>()
+                    .map_err(|_e| invalid())?;
+                continue;
+            }
+            let fields = match field.chars().next() {
+                Some('q') => &mut request,
+                Some('r') => &mut response,
+                _ => return Err(invalid()),
+            };
+            let field = field[1..].parse::<u16>()
+                .map_err(|_e| invalid())?;
+            let value = unescape(value)
+                .ok_or_else(invalid)?;
+            fields.insert(field, value);
+        }
+
+        Ok(Transaction {
+            key,
+            state,
+            debited,
+            request,
+            response,
+        })
+    }
+}
+
+/// In memory record of every keyed message the simulator has responded to.
+///
+/// When opened from a file every change is appended to it as a journal line, and the journal is replayed on open.
+#[derive(Default)]
+pub struct TransactionStore {
+    transactions: HashMap<TransactionKey, Transaction>,
+    rrns: HashMap<String, TransactionKey>,
+    journal: Option<BufWriter<File>>,
+}
+
+impl TransactionStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn open<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let mut store = Self::new();
+
+        if path.as_ref().exists() {
+            for line in BufReader::new(File::open(&path)?).lines() {
+                let line = line?;
+                if line.is_empty() {
+                    continue;
+                }
+                let transaction = line.parse::<Transaction>()
+                    .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+                store.index(transaction);
+            }
+        }
+
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)?;
+        store.journal = Some(BufWriter::new(file));
+
+        Ok(store)
+    }
+
+    pub fn get(&self, key: &TransactionKey) -> Option<&Transaction> {
+        self.transactions.get(key)
+    }
+
+    pub fn get_by_rrn(&self, rrn: &str) -> Option<&Transaction> {
+        self.rrns.get(rrn)
+            .and_then(|key| self.transactions.get(key))
+    }
+
+    pub fn len(&self) -> usize {
+        self.transactions.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.transactions.is_empty()
+    }
+
+    /// Finds the original of a reversal or completion, by RRN if given and otherwise by trying the key under each of
+    /// the candidate MTIs.
+    pub fn find_original(&self, rrn: Option<&str>, key: &TransactionKey, mtis: &[&str]) -> Option<&Transaction> {
+        let by_rrn = rrn
+            .and_then(|rrn| self.get_by_rrn(rrn))
+            .filter(|txn| mtis.contains(&&txn.key.mti[..]));
+
+        by_rrn.or_else(|| mtis.iter()
+            .find_map(|mti| self.transactions.get(&key.with_mti(mti))))
+    }
+
+    pub fn insert(&mut self, transaction: Transaction) -> io::Result<()> {
+        self.journal(&transaction.to_string())?;
+        self.index(transaction);
+        Ok(())
+    }
+
+    pub fn set_state(&mut self, key: &TransactionKey, state: TransactionState) -> io::Result<()> {
+        self.update(key, |transaction| transaction.state = state)
+    }
+
+    /// Changes a transaction already in the store e.g. its state or what it still has debited
+    pub fn update<F: FnOnce(&mut Transaction)>(&mut self, key: &TransactionKey, update: F) -> io::Result<()> {
+        let updated = match self.transactions.get_mut(key) {
+            Some(transaction) => {
+                update(transaction);
+                transaction.to_string()
+            },
+            None => return Ok(()),
+        };
+        self.journal(&updated)
+    }
+
+    fn journal(&mut self, line: &str) -> io::Result<()> {
+        if let Some(journal) = &mut self.journal {
+            writeln!(journal, "{}", line)?;
+            journal.flush()?;
+        }
+        Ok(())
+    }
+
+    fn index(&mut self, transaction: Transaction) {
+        // only originals are looked up by RRN, reversals and completions carry the same one
+        if is_original(&transaction.key.mti) {
+            let rrn = transaction.response.get(&FIELD_RRN)
+                .or_else(|| transaction.request.get(&FIELD_RRN));
+            if let Some(rrn) = rrn {
+                self.rrns.insert(rrn.clone(), transaction.key.clone());
+            }
+        }
+        self.transactions.insert(transaction.key.clone(), transaction);
+    }
+}
+
+/// Authorisation or financial requests i.e. x100 or x200
+fn is_original(mti: &str) -> bool {
+    matches!(mti.as_bytes(), [_, b'1' | b'2', b'0', _])
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use zaps::iso8583::MTI_FIELD;
+
+    fn fields(fields: &[(u16, &str)]) -> HashMap<u16, String> {
+        fields.iter()
+            .map(|(k, v)| (*k, v.to_string()))
+            .collect()
+    }
+
+    fn transaction(mti: &str, stan: &str, rrn: &str) -> Transaction {
+        let request = fields(&[(MTI_FIELD, mti), (11, stan), (13, "1019"), (41, "TERMID01")]);
+        let mut response = request.clone();
+        response.insert(37, rrn.to_string());
+        response.insert(39, "00".to_string());
+        Transaction {
+            key: TransactionKey::from_fields(mti, &request).unwrap(),
+            state: TransactionState::Approved,
+            debited: 0,
+            request,
+            response,
+        }
+    }
+
+    #[test]
+    fn key_falls_back_to_transmission_date() {
+        let key = TransactionKey::from_fields("0200", &fields(&[(7, "1019123456"), (11, "000001"), (41, "TERMID01")])).unwrap();
+        assert_eq!("1019", key.date);
+    }
+
+    #[test]
+    fn key_requires_stan_and_terminal() {
+        assert_eq!(None, TransactionKey::from_fields("0200", &fields(&[(11, "000001")])));
+        assert_eq!(None, TransactionKey::from_fields("0200", &fields(&[(41, "TERMID01")])));
+    }
+
+    #[test]
+    fn transaction_round_trip() {
+        let mut txn = transaction("0200", "000001", "000000000001");
+        txn.request.insert(43, "TAB\tNEWLINE\n".to_string());
+        txn.debited = 1_000;
+
+        let line = txn.to_string();
+        assert!(!line.contains('\n'));
+        assert_eq!(txn, line.parse::<Transaction>().unwrap());
+    }
+
+    #[test]
+    fn find_original() {
+        let mut store = TransactionStore::new();
+        store.insert(transaction("0100", "000001", "000000000001")).unwrap();
+        store.insert(transaction("0200", "000002", "000000000002")).unwrap();
+        let reversal_key = TransactionKey::from_fields("0400", &fields(&[(11, "000002"), (13, "1019"), (41, "TERMID01")])).unwrap();
+
+        let by_rrn = store.find_original(Some("000000000001"), &reversal_key, &["0100", "0200"]).unwrap();
+        assert_eq!("000001", by_rrn.key.stan);
+
+        let by_key = store.find_original(None, &reversal_key, &["0100", "0200"]).unwrap();
+        assert_eq!("000002", by_key.key.stan);
+
+        let rrn_wrong_mti = store.find_original(Some("000000000001"), &reversal_key, &["0200"]).unwrap();
+        assert_eq!("000002", rrn_wrong_mti.key.stan);
+
+        assert_eq!(None, store.find_original(None, &reversal_key, &["0100"]));
+    }
+
+    #[test]
+    fn journal_replayed_on_open() {
+        let path = std::env::temp_dir().join(format!("zaps-store-{}.journal", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+
+        let txn = transaction("0200", "000001", "000000000001");
+        {
+            let mut store = TransactionStore::open(&path).unwrap();
+            store.insert(txn.clone()).unwrap();
+            store.update(&txn.key, |txn| {
+                txn.state = TransactionState::Reversed;
+                txn.debited = 0;
+            }).unwrap();
+        }
+
+        let store = TransactionStore::open(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(1, store.len());
+        assert_eq!(TransactionState::Reversed, store.get(&txn.key).unwrap().state);
+        assert_eq!(txn.key, store.get_by_rrn("000000000001").unwrap().key);
+    }
+}
@@ -0,0 +1,179 @@
+use std::error;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::str::FromStr;
+
+const MAX_BIN_LENGTH: usize = 12;
+
+#[derive(Debug, PartialEq)]
+pub enum BinParseError {
+    MissingColumn(&'static str),
+    /// Not digits, or the low and high ends aren't the same length
+    InvalidRange(String),
+}
+
+impl fmt::Display for BinParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::MissingColumn(column) => write!(f, "missing column {}", column),
+            Self::InvalidRange(range) => write!(f, "invalid BIN range: {}", range),
+        }
+    }
+}
+
+impl error::Error for BinParseError {}
+
+/// A range of bank (issuer) identification numbers, the leading digits of PANs. The ends are inclusive and the same
+/// length, usually 6 or 8 digits.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BinRange {
+    pub low: String,
+    pub high: String,
+    /// Who the cards belong to e.g. a scheme or issuer
+    pub name: String,
+}
+
+impl BinRange {
+    pub fn new(low: &str, high: &str, name: &str) -> Self {
+        BinRange {
+            low: low.to_string(),
+            high: high.to_string(),
+            name: name.to_string(),
+        }
+    }
+
+    pub fn contains(&self, pan: &str) -> bool {
+        match pan.get(..self.low.len()) {
+            Some(bin) => self.low.as_str() <= bin && bin <= self.high.as_str(),
+            None => false,
+        }
+    }
+
+    /// How many BINs the range covers, at the precision of the longest ranges, to prefer the narrowest
+    fn width(&self) -> u128 {
+        let bins = self.high.parse::<u128>().unwrap_or_default() - self.low.parse::<u128>().unwrap_or_default() + 1;
+        bins * 10u128.pow((MAX_BIN_LENGTH - self.low.len()) as u32)
+    }
+}
+
+/// Parses a line of the BIN table: `low,high,name`, or `bin,name` for a single BIN
+impl FromStr for BinRange {
+    type Err = BinParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let columns = s.split(',').map(|c| c.trim()).collect::<Vec<_>>();
+        let (low, high, name) = match columns[..] {
+            [bin, name] => (bin, bin, name),
+            [low, high, name] => (low, high, name),
+            [""] | [_] => return Err(BinParseError::MissingColumn("name")),
+            _ => return Err(BinParseError::InvalidRange(s.to_string())),
+        };
+        let is_bin = |bin: &str| !bin.is_empty() && bin.len() <= MAX_BIN_LENGTH && bin.bytes().all(|b| b.is_ascii_digit());
+        if !is_bin(low) || !is_bin(high) || low.len() != high.len() || low > high {
+            return Err(BinParseError::InvalidRange(format!("{}-{}", low, high)));
+        }
+        if name.is_empty() {
+            return Err(BinParseError::MissingColumn("name"));
+        }
+        Ok(BinRange::new(low, high, name))
+    }
+}
+
+/// BIN ranges to look PANs up in. When ranges overlap the narrowest containing the PAN wins.
+#[derive(Debug, Clone, Default)]
+pub struct BinTable {
+    ranges: Vec<BinRange>,
+}
+
+impl BinTable {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Loads a BIN table file, one range per line with blank lines and lines starting `#` ignored.
+    pub fn load<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let mut table = Self::new();
+
+        for (num, line) in fs::read_to_string(path)?.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let range = line.parse::<BinRange>()
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, format!("line {}: {}", num + 1, e)))?;
+            table.add(range);
+        }
+
+        Ok(table)
+    }
+
+    pub fn add(&mut self, range: BinRange) {
+        self.ranges.push(range);
+    }
+
+    pub fn lookup(&self, pan: &str) -> Option<&BinRange> {
+        self.ranges.iter()
+            .filter(|range| range.contains(pan))
+            .min_by_key(|range| range.width())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn table() -> BinTable {
+        let mut table = BinTable::new();
+        for line in ["400000,499999,visa", "476173,476173,visa debit", "47617390,47617399,test issuer", "510000,559999,mastercard"] {
+            table.add(line.parse().unwrap());
+        }
+        table
+    }
+
+    macro_rules! lookup_tests {
+        ($($name:ident: $pan:literal => $expected:expr,)*) => {
+            $(
+                #[test]
+                fn $name() {
+                    assert_eq!($expected, table().lookup($pan).map(|range| &range.name[..]));
+                }
+            )*
+        };
+    }
+
+    lookup_tests!(
+        lookup_range: "4111111111111111" => Some("visa"),
+        lookup_narrower: "4761731111111111" => Some("visa debit"),
+        lookup_longest_bin: "4761739001010010" => Some("test issuer"),
+        lookup_high_end: "5599999999999999" => Some("mastercard"),
+        lookup_missing: "6011000990139424" => None,
+        lookup_short_pan: "4761" => None,
+    );
+
+    macro_rules! parse_error_tests {
+        ($($name:ident: $str:expr => $expect_err:expr,)*) => {
+            $(
+                #[test]
+                fn $name() {
+                    assert_eq!(Err($expect_err), $str.parse::<BinRange>());
+                }
+            )*
+        };
+    }
+
+    parse_error_tests!(
+        parse_no_name: "411111" => BinParseError::MissingColumn("name"),
+        parse_empty_name: "411111," => BinParseError::MissingColumn("name"),
+        parse_lengths: "4,49,visa" => BinParseError::InvalidRange("4-49".to_string()),
+        parse_backwards: "49,40,visa" => BinParseError::InvalidRange("49-40".to_string()),
+        parse_not_digits: "4A,4B,visa" => BinParseError::InvalidRange("4A-4B".to_string()),
+        parse_junk: "40,49,visa,debit" => BinParseError::InvalidRange("40,49,visa,debit".to_string()),
+    );
+
+    #[test]
+    fn parse_single_bin() {
+        assert_eq!(BinRange::new("411111", "411111", "test"), " 411111 , test".parse().unwrap());
+    }
+}
@@ -0,0 +1,83 @@
+use std::time::{
+    SystemTime,
+    UNIX_EPOCH,
+};
+
+/// Where the current month comes from for expiry checks, so tests and simulations can choose the date
+pub trait Clock {
+    /// The current year and month as YYMM
+    fn yymm(&self) -> String;
+}
+
+/// The system clock's UTC year and month
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn yymm(&self) -> String {
+        let days = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs() / 86_400)
+            .unwrap_or(0) as i64;
+
+        // civil from days, see http://howardhinnant.github.io/date_algorithms.html
+        let z = days + 719_468;
+        let era = z.div_euclid(146_097);
+        let doe = z.rem_euclid(146_097);
+        let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+        let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+        let mp = (5 * doy + 2) / 153;
+        let month = if mp < 10 { mp + 3 } else { mp - 9 };
+        let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+
+        format!("{:02}{:02}", year % 100, month)
+    }
+}
+
+/// Always the same YYMM
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FixedClock(pub String);
+
+impl Clock for FixedClock {
+    fn yymm(&self) -> String {
+        self.0.clone()
+    }
+}
+
+/// Whether the expiry is a YYMM date
+pub fn is_valid_expiry(expiry: &str) -> bool {
+    expiry.len() == 4
+        && expiry.bytes().all(|b| b.is_ascii_digit())
+        && matches!(&expiry[2..], "01" | "02" | "03" | "04" | "05" | "06" | "07" | "08" | "09" | "10" | "11" | "12")
+}
+
+/// Whether a YYMM expiry is before the clock's month, as cards expire at the end of theirs. Invalid expiries have
+/// always expired.
+pub fn is_expired(expiry: &str, clock: &dyn Clock) -> bool {
+    !is_valid_expiry(expiry) || expiry < &clock.yymm()[..]
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn expiries() {
+        let clock = FixedClock("2410".to_string());
+        assert!(!is_expired("2410", &clock));
+        assert!(!is_expired("2501", &clock));
+        assert!(is_expired("2409", &clock));
+        assert!(is_expired("2413", &clock));
+        assert!(is_expired("24", &clock));
+
+        assert!(is_valid_expiry("9912"));
+        assert!(!is_valid_expiry("9900"));
+        assert!(!is_valid_expiry("99-1"));
+    }
+
+    #[test]
+    fn system_clock() {
+        assert!(is_valid_expiry(&SystemClock.yymm()));
+        assert!(!is_expired("9912", &SystemClock));
+    }
+}
@@ -0,0 +1,67 @@
+/// Whether the number's last digit is its Luhn (mod 10) check digit. Numbers with anything but digits aren't.
+pub fn luhn_valid(number: &str) -> bool {
+    let mut digits = number.chars();
+    match digits.next_back() {
+        Some(check_digit) => luhn_check_digit(digits.as_str()) == Some(check_digit),
+        None => false,
+    }
+}
+
+/// The Luhn check digit for the number, or `None` if it has anything but digits
+pub fn luhn_check_digit(number: &str) -> Option<char> {
+    let mut sum = 0;
+    // the check digit will be in the units so doubling starts with the last digit here
+    for (i, c) in number.chars().rev().enumerate() {
+        let mut digit = c.to_digit(10)?;
+        if i % 2 == 0 {
+            digit *= 2;
+            if digit > 9 {
+                digit -= 9;
+            }
+        }
+        sum += digit;
+    }
+    std::char::from_digit((10 - sum % 10) % 10, 10)
+}
+
+/// The number with its Luhn check digit appended e.g. to make test PANs
+pub fn with_check_digit(number: &str) -> Option<String> {
+    luhn_check_digit(number)
+        .map(|digit| format!("{}{}", number, digit))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    macro_rules! luhn_tests {
+        ($($name:ident: $number:literal => $valid:expr,)*) => {
+            $(
+                #[test]
+                fn $name() {
+                    assert_eq!($valid, luhn_valid($number));
+                }
+            )*
+        };
+    }
+
+    luhn_tests!(
+        luhn_visa: "4111111111111111" => true,
+        luhn_mastercard: "5454545454545454" => true,
+        luhn_amex: "378282246310005" => true,
+        luhn_19_digits: "6011000990139424123" => false,
+        luhn_wrong_digit: "4111111111111112" => false,
+        luhn_transposed: "4111111111111161" => false,
+        luhn_zero: "0" => true,
+        luhn_empty: "" => false,
+        luhn_not_digits: "411111111111111A" => false,
+    );
+
+    #[test]
+    fn check_digits() {
+        assert_eq!(Some('1'), luhn_check_digit("411111111111111"));
+        assert_eq!(Some('5'), luhn_check_digit("37828224631000"));
+        assert_eq!(Some("4761739001010010".to_string()), with_check_digit("476173900101001"));
+        assert_eq!(None, luhn_check_digit("4111 1111"));
+    }
+}
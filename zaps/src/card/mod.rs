@@ -0,0 +1,35 @@
+mod bin;
+pub use bin::{
+    BinParseError,
+    BinRange,
+    BinTable,
+};
+mod expiry;
+pub use expiry::{
+    is_expired,
+    is_valid_expiry,
+    Clock,
+    FixedClock,
+    SystemClock,
+};
+mod luhn;
+pub use luhn::{
+    luhn_check_digit,
+    luhn_valid,
+    with_check_digit,
+};
+mod track;
+pub use track::{
+    ServiceCode,
+    Track1,
+    Track2,
+    TrackError,
+};
+
+pub const MIN_PAN_LENGTH: usize = 12;
+pub const MAX_PAN_LENGTH: usize = 19;
+
+/// Whether the PAN is 12 to 19 digits with a valid Luhn check digit
+pub fn is_valid_pan(pan: &str) -> bool {
+    (MIN_PAN_LENGTH..=MAX_PAN_LENGTH).contains(&pan.len()) && luhn_valid(pan)
+}
@@ -0,0 +1,277 @@
+use std::error;
+use std::fmt;
+use std::str::FromStr;
+
+use crate::card::{
+    MAX_PAN_LENGTH,
+    MIN_PAN_LENGTH,
+};
+
+const TRACK_1_START: char = '%';
+const TRACK_2_START: char = ';';
+const TRACK_END: char = '?';
+const TRACK_1_FORMAT_CODE: char = 'B';
+const TRACK_1_SEPARATOR: char = '^';
+const TRACK_2_SEPARATOR: char = '=';
+/// Track 2 equivalent data in binary encodings uses the hex digit D for the separator
+const TRACK_2_HEX_SEPARATOR: char = 'D';
+const MAX_NAME_LENGTH: usize = 26;
+
+#[derive(Debug, PartialEq)]
+pub enum TrackError {
+    InvalidPan(String),
+    /// Track 1 isn't format B, the only one for cards
+    InvalidFormatCode(String),
+    InvalidName(String),
+    MissingSeparator,
+    InvalidExpiry(String),
+    InvalidServiceCode(String),
+}
+
+impl fmt::Display for TrackError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::InvalidPan(pan) => write!(f, "invalid PAN: {}", pan),
+            Self::InvalidFormatCode(code) => write!(f, "invalid track 1 format code: {}", code),
+            Self::InvalidName(name) => write!(f, "invalid cardholder name: {}", name),
+            Self::MissingSeparator => write!(f, "missing field separator"),
+            Self::InvalidExpiry(expiry) => write!(f, "invalid expiry, must be YYMM: {}", expiry),
+            Self::InvalidServiceCode(code) => write!(f, "invalid service code, must be 3 digits: {}", code),
+        }
+    }
+}
+
+impl error::Error for TrackError {}
+
+/// The 3 digit service code on the magnetic stripe, saying where and how the card can be used
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ServiceCode(String);
+
+impl ServiceCode {
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+
+    fn digit(&self, i: usize) -> u8 {
+        self.0.as_bytes()[i]
+    }
+
+    /// Whether the card can be used internationally, rather than only in its own country
+    pub fn international(&self) -> bool {
+        matches!(self.digit(0), b'1' | b'2')
+    }
+
+    /// Whether the card has a chip, which terminals should use rather than the stripe
+    pub fn chip(&self) -> bool {
+        matches!(self.digit(0), b'2' | b'6')
+    }
+
+    /// Whether transactions must be authorised online by the issuer
+    pub fn online_only(&self) -> bool {
+        self.digit(1) == b'2'
+    }
+
+    /// Whether a PIN is required
+    pub fn pin_required(&self) -> bool {
+        matches!(self.digit(2), b'0' | b'3' | b'5')
+    }
+}
+
+impl fmt::Display for ServiceCode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl FromStr for ServiceCode {
+    type Err = TrackError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.len() != 3 || !s.bytes().all(|b| b.is_ascii_digit()) {
+            return Err(TrackError::InvalidServiceCode(s.to_string()));
+        }
+        Ok(ServiceCode(s.to_string()))
+    }
+}
+
+/// Track 2 data, or the track 2 equivalent data in field 35: `PAN=YYMM<service code><discretionary data>`. The start
+/// and end sentinels are optional and `D` may be the separator.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Track2 {
+    pub pan: String,
+    /// YYMM
+    pub expiry: String,
+    pub service_code: ServiceCode,
+    pub discretionary_data: String,
+}
+
+impl fmt::Display for Track2 {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}{}{}{}{}", self.pan, TRACK_2_SEPARATOR, self.expiry, self.service_code, self.discretionary_data)
+    }
+}
+
+impl FromStr for Track2 {
+    type Err = TrackError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = strip_sentinels(s, TRACK_2_START);
+        let (pan, rest) = s.split_once([TRACK_2_SEPARATOR, TRACK_2_HEX_SEPARATOR])
+            .ok_or(TrackError::MissingSeparator)?;
+        // a trailing hex F pads binary encodings to whole bytes
+        let rest = rest.strip_suffix('F').unwrap_or(rest);
+        let (expiry, service_code, discretionary_data) = expiry_and_service_code(rest)?;
+        Ok(Track2 {
+            pan: check_pan(pan)?,
+            expiry,
+            service_code,
+            discretionary_data,
+        })
+    }
+}
+
+/// Track 1 data, as in field 45: `B<PAN>^<name>^YYMM<service code><discretionary data>`. The start and end sentinels
+/// are optional.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Track1 {
+    pub pan: String,
+    /// The cardholder's name, `SURNAME/FIRST NAME` by convention
+    pub name: String,
+    /// YYMM
+    pub expiry: String,
+    pub service_code: ServiceCode,
+    pub discretionary_data: String,
+}
+
+impl fmt::Display for Track1 {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f, "{}{}{}{}{}{}{}{}",
+            TRACK_1_FORMAT_CODE, self.pan, TRACK_1_SEPARATOR, self.name, TRACK_1_SEPARATOR, self.expiry,
+            self.service_code, self.discretionary_data,
+        )
+    }
+}
+
+impl FromStr for Track1 {
+    type Err = TrackError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = strip_sentinels(s, TRACK_1_START);
+        let s = s.strip_prefix(TRACK_1_FORMAT_CODE)
+            .ok_or_else(|| TrackError::InvalidFormatCode(s.chars().take(1).collect()))?;
+        let mut parts = s.splitn(3, TRACK_1_SEPARATOR);
+        let pan = parts.next().unwrap_or_default();
+        let (name, rest) = parts.next().zip(parts.next())
+            .ok_or(TrackError::MissingSeparator)?;
+        if name.len() < 2 || name.len() > MAX_NAME_LENGTH {
+            return Err(TrackError::InvalidName(name.to_string()));
+        }
+        let (expiry, service_code, discretionary_data) = expiry_and_service_code(rest)?;
+        Ok(Track1 {
+            pan: check_pan(pan)?,
+            name: name.to_string(),
+            expiry,
+            service_code,
+            discretionary_data,
+        })
+    }
+}
+
+fn strip_sentinels(s: &str, start: char) -> &str {
+    let s = s.trim();
+    let s = s.strip_prefix(start).unwrap_or(s);
+    // anything after the end sentinel is the LRC
+    s.split(TRACK_END).next().unwrap_or_default()
+}
+
+fn check_pan(pan: &str) -> Result<String, TrackError> {
+    if pan.len() < MIN_PAN_LENGTH || pan.len() > MAX_PAN_LENGTH || !pan.bytes().all(|b| b.is_ascii_digit()) {
+        return Err(TrackError::InvalidPan(pan.to_string()));
+    }
+    Ok(pan.to_string())
+}
+
+fn expiry_and_service_code(s: &str) -> Result<(String, ServiceCode, String), TrackError> {
+    let expiry = s.get(..4)
+        .filter(|expiry| expiry.bytes().all(|b| b.is_ascii_digit()))
+        .ok_or_else(|| TrackError::InvalidExpiry(s.chars().take(4).collect()))?;
+    let service_code = s.get(4..7)
+        .ok_or_else(|| TrackError::InvalidServiceCode(s[4..].to_string()))?
+        .parse::<ServiceCode>()?;
+    Ok((expiry.to_string(), service_code, s[7..].to_string()))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    macro_rules! track_2_tests {
+        ($($name:ident: $track:literal => $pan:literal, $expiry:literal, $service_code:literal, $discretionary:literal,)*) => {
+            $(
+                #[test]
+                fn $name() {
+                    let track = $track.parse::<Track2>().unwrap();
+                    assert_eq!($pan, track.pan);
+                    assert_eq!($expiry, track.expiry);
+                    assert_eq!($service_code, track.service_code.as_str());
+                    assert_eq!($discretionary, track.discretionary_data);
+                }
+            )*
+        };
+    }
+
+    track_2_tests!(
+        track_2_equivalent: "4761739001010010=22122011143804400000" => "4761739001010010", "2212", "201", "1143804400000",
+        track_2_sentinels: ";4111111111111111=2912101123?5" => "4111111111111111", "2912", "101", "123",
+        track_2_hex_separator: "4111111111111111D2912101123F" => "4111111111111111", "2912", "101", "123",
+        track_2_no_discretionary_data: "4111111111111111=2912101" => "4111111111111111", "2912", "101", "",
+    );
+
+    #[test]
+    fn track_1() {
+        let track = "%B4111111111111111^SMITH/JOHN^2912101000000000123?".parse::<Track1>().unwrap();
+        assert_eq!("4111111111111111", track.pan);
+        assert_eq!("SMITH/JOHN", track.name);
+        assert_eq!("2912", track.expiry);
+        assert_eq!("101", track.service_code.as_str());
+        assert_eq!("000000000123", track.discretionary_data);
+        assert_eq!("B4111111111111111^SMITH/JOHN^2912101000000000123", track.to_string());
+    }
+
+    #[test]
+    fn service_codes() {
+        let code = "201".parse::<ServiceCode>().unwrap();
+        assert!(code.international() && code.chip() && !code.online_only() && !code.pin_required());
+        let code = "620".parse::<ServiceCode>().unwrap();
+        assert!(!code.international() && code.chip() && code.online_only() && code.pin_required());
+    }
+
+    macro_rules! track_error_tests {
+        ($($name:ident: $track:ty, $str:literal => $expect_err:expr,)*) => {
+            $(
+                #[test]
+                fn $name() {
+                    assert_eq!(Err($expect_err), $str.parse::<$track>());
+                }
+            )*
+        };
+    }
+
+    track_error_tests!(
+        track_2_no_separator: Track2, "41111111111111112912101" => TrackError::MissingSeparator,
+        track_2_short_pan: Track2, "41111111111=2912101" => TrackError::InvalidPan("41111111111".to_string()),
+        track_2_pan_not_digits: Track2, "411111111111111X=2912101" => TrackError::InvalidPan("411111111111111X".to_string()),
+        track_2_bad_expiry: Track2, "4111111111111111=29AB101" => TrackError::InvalidExpiry("29AB".to_string()),
+        track_2_short_service_code: Track2, "4111111111111111=291210" => TrackError::InvalidServiceCode("10".to_string()),
+        track_1_format: Track1, "A4111111111111111^SMITH/JOHN^2912101" => TrackError::InvalidFormatCode("A".to_string()),
+        track_1_no_name: Track1, "B4111111111111111^2912101" => TrackError::MissingSeparator,
+        track_1_long_name: Track1, "B4111111111111111^ABCDEFGHIJKLMNOPQRSTUVWXYZA^2912101" => TrackError::InvalidName("ABCDEFGHIJKLMNOPQRSTUVWXYZA".to_string()),
+    );
+
+    #[test]
+    fn round_trip() {
+        let track = "4111111111111111=2912101123".parse::<Track2>().unwrap();
+        assert_eq!("4111111111111111=2912101123", track.to_string());
+    }
+}
@@ -3,5 +3,5 @@ use std::collections::HashMap;
 pub trait Unparser<K> {
     type Err;
 
-    fn unparse(&self, fields: HashMap<K, String>, out:&mut [u8]) -> Result<(), Self::Err>;
-}
\ No newline at end of file
+    fn unparse(&self, fields: &HashMap<K, String>, out: &mut Vec<u8>) -> Result<(), Self::Err>;
+}
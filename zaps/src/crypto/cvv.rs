@@ -0,0 +1,133 @@
+use std::error;
+use std::fmt;
+use crate::{
+    card::{
+        is_valid_expiry,
+        MAX_PAN_LENGTH,
+    },
+    crypto::{
+        Algorithm,
+        CryptoError,
+        Key,
+    },
+};
+
+/// The service code CVV2s and CVC2s, printed on the card, are generated with
+pub const CVV2_SERVICE_CODE: &str = "000";
+/// The service code iCVVs, in chip cards' track 2 equivalent data, are generated with
+pub const ICVV_SERVICE_CODE: &str = "999";
+pub const CVV_LENGTH: usize = 3;
+
+/// The PAN, expiry and service code are padded with zeros to this many digits
+const CVV_DATA_DIGITS: usize = 32;
+
+#[derive(Debug, PartialEq)]
+pub enum CvvError {
+    /// CVKs are a pair of DES keys, so double length TDES keys
+    InvalidCvk,
+    InvalidPan(String),
+    InvalidExpiry(String),
+    InvalidServiceCode(String),
+    Crypto(CryptoError),
+}
+
+impl fmt::Display for CvvError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::InvalidCvk => write!(f, "CVKs must be double length TDES keys"),
+            Self::InvalidPan(pan) => write!(f, "invalid PAN: {}", pan),
+            Self::InvalidExpiry(expiry) => write!(f, "invalid expiry, must be YYMM: {}", expiry),
+            Self::InvalidServiceCode(code) => write!(f, "invalid service code, must be 3 digits: {}", code),
+            Self::Crypto(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl error::Error for CvvError {}
+
+impl From<CryptoError> for CvvError {
+    fn from(e: CryptoError) -> Self {
+        CvvError::Crypto(e)
+    }
+}
+
+/// The card verification value (Visa CVV, Mastercard CVC) for the card, under the CVK pair. The expiry is YYMM as on
+/// the stripe, and the service code is the card's for the stripe, [`CVV2_SERVICE_CODE`] or [`ICVV_SERVICE_CODE`].
+pub fn generate(cvk: &Key, pan: &str, expiry: &str, service_code: &str) -> Result<String, CvvError> {
+    if cvk.algorithm() != Algorithm::Tdes || cvk.bytes().len() != 16 {
+        return Err(CvvError::InvalidCvk);
+    }
+    if pan.is_empty() || pan.len() > MAX_PAN_LENGTH || !pan.bytes().all(|b| b.is_ascii_digit()) {
+        return Err(CvvError::InvalidPan(pan.to_string()));
+    }
+    if !is_valid_expiry(expiry) {
+        return Err(CvvError::InvalidExpiry(expiry.to_string()));
+    }
+    if service_code.len() != 3 || !service_code.bytes().all(|b| b.is_ascii_digit()) {
+        return Err(CvvError::InvalidServiceCode(service_code.to_string()));
+    }
+
+    let data = format!("{:0<width$}", format!("{}{}{}", pan, expiry, service_code), width = CVV_DATA_DIGITS);
+    let data = hex::decode(data).expect("CVV data is digits");
+    let (block_a, block_b) = data.split_at(8);
+
+    // the first block under key A alone, then chained into the second under both
+    let key_a = Key::new(Algorithm::Tdes, &cvk.bytes()[..8])?;
+    let chained = key_a.encrypt(block_a)?
+        .iter()
+        .zip(block_b)
+        .map(|(a, b)| a ^ b)
+        .collect::<Vec<_>>();
+    let result = hex::encode_upper(cvk.encrypt(&chained)?);
+
+    // decimalise: the digits in order, then the letters as digits
+    let digits = result.chars().filter(|c| c.is_ascii_digit());
+    let letters = result.chars()
+        .filter(|c| !c.is_ascii_digit())
+        .map(|c| (b'0' + (c as u8 - b'A')) as char);
+    Ok(digits.chain(letters).take(CVV_LENGTH).collect())
+}
+
+/// Whether the CVV is the card's
+pub fn verify(cvk: &Key, pan: &str, expiry: &str, service_code: &str, cvv: &str) -> Result<bool, CvvError> {
+    Ok(generate(cvk, pan, expiry, service_code)? == cvv)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const CVK: &str = "tdes:0123456789ABCDEFFEDCBA9876543210";
+
+    macro_rules! cvv_tests {
+        ($($name:ident: $pan:literal, $expiry:literal, $service_code:expr => $cvv:literal,)*) => {
+            $(
+                #[test]
+                fn $name() {
+                    let cvk = CVK.parse::<Key>().unwrap();
+                    assert_eq!($cvv, generate(&cvk, $pan, $expiry, $service_code).unwrap());
+                    assert_eq!(Ok(true), verify(&cvk, $pan, $expiry, $service_code, $cvv));
+                }
+            )*
+        };
+    }
+
+    cvv_tests!(
+        cvv_visa_example: "4123456789012345", "8701", "101" => "561",
+        cvv_stripe: "4111111111111111", "2912", "101" => "216",
+        cvv_chip_stripe: "4111111111111111", "2912", "201" => "010",
+        cvv2: "4111111111111111", "2912", CVV2_SERVICE_CODE => "719",
+        icvv: "4111111111111111", "2912", ICVV_SERVICE_CODE => "997",
+        cvc: "5454545454545454", "2912", "101" => "058",
+    );
+
+    #[test]
+    fn errors() {
+        let cvk = CVK.parse::<Key>().unwrap();
+        assert_eq!(Ok(false), verify(&cvk, "4111111111111111", "2912", "101", "217"));
+        assert_eq!(Err(CvvError::InvalidCvk), generate(&"tdes:0123456789ABCDEF".parse().unwrap(), "4111111111111111", "2912", "101"));
+        assert_eq!(Err(CvvError::InvalidPan("4111x".to_string())), generate(&cvk, "4111x", "2912", "101"));
+        assert_eq!(Err(CvvError::InvalidExpiry("2913".to_string())), generate(&cvk, "4111111111111111", "2913", "101"));
+        assert_eq!(Err(CvvError::InvalidServiceCode("10".to_string())), generate(&cvk, "4111111111111111", "2912", "10"));
+    }
+}
@@ -0,0 +1,435 @@
+use std::error;
+use std::fmt;
+use std::str::FromStr;
+use crate::crypto::{
+    Algorithm,
+    CryptoError,
+    Key,
+};
+
+/// The length in bytes of a TDES key serial number, a 59 bit initial KSN and a 21 bit counter
+pub const TDES_KSN_LENGTH: usize = 10;
+/// The length in bytes of an AES key serial number, an 8 byte initial key ID and a 32 bit counter
+pub const AES_KSN_LENGTH: usize = 12;
+
+/// Counters with more bits set than this are skipped
+const TDES_MAX_ONE_BITS: u32 = 10;
+const AES_MAX_ONE_BITS: u32 = 16;
+const TDES_COUNTER_MASK: u32 = 0x1f_ffff;
+
+/// AES key usage indicators for the keys derived on the way to working keys
+const AES_INITIAL_KEY: u16 = 0x8001;
+const AES_DERIVATION_KEY: u16 = 0x8000;
+
+/// XORed with a key for the second half of the non-reversible key generation, and the right half of the IPEK
+const KEY_MASK: [u8; 16] = [0xc0, 0xc0, 0xc0, 0xc0, 0, 0, 0, 0, 0xc0, 0xc0, 0xc0, 0xc0, 0, 0, 0, 0];
+
+/// What a working key derived by DUKPT is for
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KeyUsage {
+    Pin,
+    /// MACs on requests from the terminal
+    MacRequest,
+    /// MACs on responses to the terminal
+    MacResponse,
+    /// Data encrypted by the terminal
+    DataRequest,
+    /// Data encrypted for the terminal
+    DataResponse,
+}
+
+impl KeyUsage {
+    /// The TDES variant XORed with the future key
+    fn variant(&self) -> [u8; 8] {
+        let byte = match self {
+            KeyUsage::Pin => 7,
+            KeyUsage::MacRequest => 6,
+            KeyUsage::MacResponse => 4,
+            KeyUsage::DataRequest => 5,
+            KeyUsage::DataResponse => 3,
+        };
+        let mut variant = [0; 8];
+        variant[byte] = 0xff;
+        variant
+    }
+
+    /// The AES key usage indicator
+    fn indicator(&self) -> u16 {
+        match self {
+            KeyUsage::Pin => 0x1000,
+            KeyUsage::MacRequest => 0x2000,
+            KeyUsage::MacResponse => 0x2001,
+            KeyUsage::DataRequest => 0x3000,
+            KeyUsage::DataResponse => 0x3001,
+        }
+    }
+}
+
+impl fmt::Display for KeyUsage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            KeyUsage::Pin => write!(f, "pin"),
+            KeyUsage::MacRequest => write!(f, "mac-request"),
+            KeyUsage::MacResponse => write!(f, "mac-response"),
+            KeyUsage::DataRequest => write!(f, "data-request"),
+            KeyUsage::DataResponse => write!(f, "data-response"),
+        }
+    }
+}
+
+impl FromStr for KeyUsage {
+    type Err = DukptError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match &s.to_ascii_lowercase()[..] {
+            "pin" => Ok(KeyUsage::Pin),
+            "mac-request" | "mac" => Ok(KeyUsage::MacRequest),
+            "mac-response" => Ok(KeyUsage::MacResponse),
+            "data-request" | "data" => Ok(KeyUsage::DataRequest),
+            "data-response" => Ok(KeyUsage::DataResponse),
+            _ => Err(DukptError::InvalidKeyUsage(s.to_string())),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub enum DukptError {
+    InvalidKeyUsage(String),
+    /// The KSN isn't the length for the key's algorithm
+    InvalidKsn{
+        algorithm: Algorithm,
+        length: usize,
+    },
+    /// TDES DUKPT is only defined for double length keys
+    InvalidBdk,
+    /// The terminal has used every counter value
+    CounterExhausted,
+    Crypto(CryptoError),
+}
+
+impl fmt::Display for DukptError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::InvalidKeyUsage(usage) => write!(f, "invalid key usage: {}", usage),
+            Self::InvalidKsn{ algorithm, length } => write!(f, "invalid {} KSN length: {} bytes", algorithm, length),
+            Self::InvalidBdk => write!(f, "TDES DUKPT needs a double length key"),
+            Self::CounterExhausted => write!(f, "the KSN counter is exhausted"),
+            Self::Crypto(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl error::Error for DukptError {}
+
+impl From<CryptoError> for DukptError {
+    fn from(e: CryptoError) -> Self {
+        DukptError::Crypto(e)
+    }
+}
+
+/// The length of a KSN for keys of the algorithm
+pub fn ksn_length(algorithm: Algorithm) -> usize {
+    match algorithm {
+        Algorithm::Tdes => TDES_KSN_LENGTH,
+        Algorithm::Aes => AES_KSN_LENGTH,
+    }
+}
+
+/// The initial key loaded into the terminal, derived from the base derivation key and the KSN. The KSN's counter is
+/// ignored.
+pub fn initial_key(bdk: &Key, ksn: &[u8]) -> Result<Key, DukptError> {
+    check_ksn(bdk, ksn)?;
+    match bdk.algorithm() {
+        Algorithm::Tdes => {
+            let mut ksn = ksn[..8].to_vec();
+            ksn[7] &= 0xe0;
+            let left = bdk.encrypt(&ksn)?;
+            let right = Key::new(Algorithm::Tdes, &xor(bdk.bytes(), &KEY_MASK))?.encrypt(&ksn)?;
+            Ok(Key::new(Algorithm::Tdes, &[left, right].concat())?)
+        },
+        Algorithm::Aes => {
+            let data = aes_derivation_data(AES_INITIAL_KEY, bdk.bytes().len(), &ksn[..8]);
+            aes_derive(bdk, &data)
+        },
+    }
+}
+
+/// The working key for the transaction with the KSN, as worked out by the host from the base derivation key
+pub fn derive(bdk: &Key, ksn: &[u8], usage: KeyUsage) -> Result<Key, DukptError> {
+    derive_from_initial(&initial_key(bdk, ksn)?, ksn, usage)
+}
+
+/// The working key for the transaction with the KSN, as worked out by the terminal from its initial key
+pub fn derive_from_initial(initial_key: &Key, ksn: &[u8], usage: KeyUsage) -> Result<Key, DukptError> {
+    check_ksn(initial_key, ksn)?;
+    match initial_key.algorithm() {
+        Algorithm::Tdes => {
+            let future_key = tdes_future_key(initial_key, ksn)?;
+            let key = Key::new(Algorithm::Tdes, &xor(future_key.bytes(), &usage.variant().repeat(2)))?;
+            match usage {
+                // data keys go one step further so they can't be turned back into the others
+                KeyUsage::DataRequest | KeyUsage::DataResponse => Ok(Key::new(Algorithm::Tdes, &key.encrypt(key.bytes())?)?),
+                _ => Ok(key),
+            }
+        },
+        Algorithm::Aes => {
+            let length = initial_key.bytes().len();
+            let (id, counter) = ksn.split_at(8);
+            let counter = u32::from_be_bytes([counter[0], counter[1], counter[2], counter[3]]);
+
+            let mut key = initial_key.clone();
+            let mut working_counter = 0;
+            for bit in (0..32).rev() {
+                let mask = 1 << bit;
+                if counter & mask != 0 {
+                    working_counter |= mask;
+                    key = aes_derive(&key, &aes_working_data(AES_DERIVATION_KEY, length, id, working_counter))?;
+                }
+            }
+            aes_derive(&key, &aes_working_data(usage.indicator(), length, id, counter))
+        },
+    }
+}
+
+/// A PIN entry device's DUKPT state. Each transaction takes the next KSN, skipping counters with too many bits set
+/// as the standards do, and derives its keys from the initial key rather than holding future keys.
+#[derive(Debug, Clone)]
+pub struct Terminal {
+    initial_key: Key,
+    ksn: Vec<u8>,
+}
+
+impl Terminal {
+    /// A terminal loaded with the initial key for the KSN, whose counter is the last one used
+    pub fn new(bdk: &Key, ksn: &[u8]) -> Result<Self, DukptError> {
+        Ok(Terminal {
+            initial_key: initial_key(bdk, ksn)?,
+            ksn: ksn.to_vec(),
+        })
+    }
+
+    /// The KSN of the last transaction
+    pub fn ksn(&self) -> &[u8] {
+        &self.ksn
+    }
+
+    pub fn counter(&self) -> u32 {
+        counter(&self.ksn)
+    }
+
+    /// Moves on to the next transaction, returning its KSN
+    pub fn next_ksn(&mut self) -> Result<&[u8], DukptError> {
+        let (max, max_one_bits) = match self.initial_key.algorithm() {
+            Algorithm::Tdes => (TDES_COUNTER_MASK, TDES_MAX_ONE_BITS),
+            Algorithm::Aes => (u32::MAX, AES_MAX_ONE_BITS),
+        };
+        let mut counter = self.counter();
+        loop {
+            counter = match counter.checked_add(1) {
+                Some(counter) if counter <= max => counter,
+                _ => return Err(DukptError::CounterExhausted),
+            };
+            if counter.count_ones() <= max_one_bits {
+                break;
+            }
+        }
+        set_counter(&mut self.ksn, counter);
+        Ok(&self.ksn)
+    }
+
+    /// The working key for the current transaction
+    pub fn key(&self, usage: KeyUsage) -> Result<Key, DukptError> {
+        derive_from_initial(&self.initial_key, &self.ksn, usage)
+    }
+}
+
+fn check_ksn(key: &Key, ksn: &[u8]) -> Result<(), DukptError> {
+    if key.algorithm() == Algorithm::Tdes && key.bytes().len() != 16 {
+        return Err(DukptError::InvalidBdk);
+    }
+    if ksn.len() != ksn_length(key.algorithm()) {
+        return Err(DukptError::InvalidKsn{ algorithm: key.algorithm(), length: ksn.len() });
+    }
+    Ok(())
+}
+
+fn counter(ksn: &[u8]) -> u32 {
+    let bytes = &ksn[ksn.len() - 4..];
+    let counter = u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]);
+    match ksn.len() {
+        TDES_KSN_LENGTH => counter & TDES_COUNTER_MASK,
+        _ => counter,
+    }
+}
+
+fn set_counter(ksn: &mut [u8], counter: u32) {
+    let start = ksn.len() - 4;
+    let mut bytes = u32::from_be_bytes([ksn[start], ksn[start + 1], ksn[start + 2], ksn[start + 3]]);
+    if ksn.len() == TDES_KSN_LENGTH {
+        bytes = bytes & !TDES_COUNTER_MASK | counter;
+    } else {
+        bytes = counter;
+    }
+    ksn[start..].copy_from_slice(&bytes.to_be_bytes());
+}
+
+/// Runs the non-reversible key generation for each bit set in the counter, from the highest
+fn tdes_future_key(initial_key: &Key, ksn: &[u8]) -> Result<Key, DukptError> {
+    let counter = counter(ksn);
+    let mut register = u64::from_be_bytes([ksn[2], ksn[3], ksn[4], ksn[5], ksn[6], ksn[7], ksn[8], ksn[9]]);
+    register &= !u64::from(TDES_COUNTER_MASK);
+
+    let mut key = initial_key.bytes().to_vec();
+    for bit in (0..21).rev() {
+        if counter & (1 << bit) != 0 {
+            register |= 1 << bit;
+            key = non_reversible_key(&key, &register.to_be_bytes())?;
+        }
+    }
+    Ok(Key::new(Algorithm::Tdes, &key)?)
+}
+
+fn non_reversible_key(key: &[u8], register: &[u8]) -> Result<Vec<u8>, CryptoError> {
+    let half = |key: &[u8]| {
+        let (left, right) = key.split_at(8);
+        let encrypted = Key::new(Algorithm::Tdes, left)?.encrypt(&xor(right, register))?;
+        Ok::<_, CryptoError>(xor(&encrypted, right))
+    };
+    let right = half(key)?;
+    let left = half(&xor(key, &KEY_MASK))?;
+    Ok([left, right].concat())
+}
+
+/// Derivation data for an AES key of the length in bytes, of the same length as the key it's derived from
+fn aes_derivation_data(usage: u16, length: usize, id: &[u8]) -> Vec<u8> {
+    let (algorithm, bits): (u16, u16) = match length {
+        16 => (2, 128),
+        24 => (3, 192),
+        _ => (4, 256),
+    };
+    let mut data = vec![1, 1];
+    data.extend(usage.to_be_bytes());
+    data.extend(algorithm.to_be_bytes());
+    data.extend(bits.to_be_bytes());
+    data.extend(id);
+    data
+}
+
+fn aes_working_data(usage: u16, length: usize, id: &[u8], counter: u32) -> Vec<u8> {
+    aes_derivation_data(usage, length, &[&id[4..], &counter.to_be_bytes()[..]].concat())
+}
+
+/// Encrypts the derivation data a block at a time, counting the blocks in its second byte, for as many as the key
+/// needs
+fn aes_derive(key: &Key, data: &[u8]) -> Result<Key, DukptError> {
+    let length = key.bytes().len();
+    let mut data = data.to_vec();
+    let mut derived = vec![];
+    let mut block = 1;
+    while derived.len() < length {
+        data[1] = block;
+        derived.extend(key.encrypt(&data)?);
+        block += 1;
+    }
+    derived.truncate(length);
+    Ok(Key::new(Algorithm::Aes, &derived)?)
+}
+
+fn xor(a: &[u8], b: &[u8]) -> Vec<u8> {
+    a.iter()
+        .zip(b)
+        .map(|(a, b)| a ^ b)
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::crypto::pin::{
+        self,
+        PinFormat,
+    };
+
+    const TDES_BDK: &str = "tdes:0123456789ABCDEFFEDCBA9876543210";
+    const AES_BDK: &str = "aes:FEDCBA9876543210F1F1F1F1F1F1F1F1";
+
+    macro_rules! derive_tests {
+        ($($name:ident: $bdk:expr, $ksn:literal, $usage:expr => $key:literal,)*) => {
+            $(
+                #[test]
+                fn $name() {
+                    let bdk = $bdk.parse::<Key>().unwrap();
+                    let ksn = hex::decode($ksn).unwrap();
+                    assert_eq!($key, hex::encode_upper(derive(&bdk, &ksn, $usage).unwrap().bytes()));
+                }
+            )*
+        };
+    }
+
+    derive_tests!(
+        tdes_pin: TDES_BDK, "FFFF9876543210E00001", KeyUsage::Pin => "042666B49184CF5C68DE9628D0397B36",
+        tdes_mac_request: TDES_BDK, "FFFF9876543210E00001", KeyUsage::MacRequest => "042666B4918430A368DE9628D03984C9",
+        tdes_mac_response: TDES_BDK, "FFFF9876543210E00001", KeyUsage::MacResponse => "042666B46E84CFA368DE96282F397BC9",
+        tdes_data_request: TDES_BDK, "FFFF9876543210E00001", KeyUsage::DataRequest => "448D3F076D8304036A55A3D7E0055A78",
+        tdes_data_response: TDES_BDK, "FFFF9876543210E00001", KeyUsage::DataResponse => "AD7BFC8B06AD3A08A560B4105CF8D9E5",
+        tdes_pin_2: TDES_BDK, "FFFF9876543210E00002", KeyUsage::Pin => "C46551CEF9FD244FAA9AD834130D3B38",
+        tdes_pin_12: TDES_BDK, "FFFF9876543210E00012", KeyUsage::Pin => "9CF640F279C2AE1915F725EEEAC2CB50",
+        aes_pin: AES_BDK, "123456789012345600000001", KeyUsage::Pin => "AF8CB133A78F8DC2D1359F18527593FB",
+        aes_mac_request: AES_BDK, "123456789012345600000001", KeyUsage::MacRequest => "A2DC23DE6FDE0824A2BC321E08E4B8B7",
+        aes_mac_response: AES_BDK, "123456789012345600000001", KeyUsage::MacResponse => "DBB463945B286C07CD3AD82EE96FD9C9",
+        aes_data_request: AES_BDK, "123456789012345600000001", KeyUsage::DataRequest => "A35C412EFD41FDB98B69797C02DCD08F",
+        aes_pin_2: AES_BDK, "123456789012345600000002", KeyUsage::Pin => "D30BDC73EC9714B000BEC66BDB7B6D09",
+    );
+
+    #[test]
+    fn initial_keys() {
+        let bdk = TDES_BDK.parse::<Key>().unwrap();
+        let ipek = initial_key(&bdk, &hex::decode("FFFF9876543210E00008").unwrap()).unwrap();
+        assert_eq!("6AC292FAA1315B4D858AB3A3D7D5933A", hex::encode_upper(ipek.bytes()));
+
+        let bdk = AES_BDK.parse::<Key>().unwrap();
+        let initial = initial_key(&bdk, &hex::decode("123456789012345600000000").unwrap()).unwrap();
+        assert_eq!("1273671EA26AC29AFA4D1084127652A1", hex::encode_upper(initial.bytes()));
+    }
+
+    #[test]
+    fn terminal_pin_block() {
+        let bdk = TDES_BDK.parse::<Key>().unwrap();
+        let mut terminal = Terminal::new(&bdk, &hex::decode("FFFF9876543210E00000").unwrap()).unwrap();
+        assert_eq!("FFFF9876543210E00001", hex::encode_upper(terminal.next_ksn().unwrap()));
+
+        let key = terminal.key(KeyUsage::Pin).unwrap();
+        let block = pin::encrypt(&key, PinFormat::Iso0, "1234", "4012345678909").unwrap();
+        assert_eq!("1B9C1845EB993A7A", hex::encode_upper(&block));
+        assert_eq!(key, derive(&bdk, terminal.ksn(), KeyUsage::Pin).unwrap());
+    }
+
+    #[test]
+    fn terminal_skips_counters() {
+        let bdk = TDES_BDK.parse::<Key>().unwrap();
+        // 0x0003FE has 9 bits set, 0x0003FF 10 and 0x000400 1
+        let mut terminal = Terminal::new(&bdk, &hex::decode("FFFF9876543210E003FE").unwrap()).unwrap();
+        assert_eq!("FFFF9876543210E003FF", hex::encode_upper(terminal.next_ksn().unwrap()));
+        let mut terminal = Terminal::new(&bdk, &hex::decode("FFFF9876543210E007FE").unwrap()).unwrap();
+        assert_eq!("FFFF9876543210E00800", hex::encode_upper(terminal.next_ksn().unwrap()));
+        assert_eq!(0x800, terminal.counter());
+
+        let mut terminal = Terminal::new(&bdk, &hex::decode("FFFF9876543210FFFFFF").unwrap()).unwrap();
+        assert_eq!(Err(DukptError::CounterExhausted), terminal.next_ksn());
+
+        let bdk = AES_BDK.parse::<Key>().unwrap();
+        let mut terminal = Terminal::new(&bdk, &hex::decode("12345678901234560000FFFE").unwrap()).unwrap();
+        assert_eq!("12345678901234560000FFFF", hex::encode_upper(terminal.next_ksn().unwrap()));
+        assert_eq!("123456789012345600010000", hex::encode_upper(terminal.next_ksn().unwrap()));
+    }
+
+    #[test]
+    fn errors() {
+        let bdk = TDES_BDK.parse::<Key>().unwrap();
+        assert_eq!(Err(DukptError::InvalidKsn{ algorithm: Algorithm::Tdes, length: 12 }), derive(&bdk, &[0; 12], KeyUsage::Pin));
+        let single = "tdes:0123456789ABCDEF".parse::<Key>().unwrap();
+        assert_eq!(Err(DukptError::InvalidBdk), initial_key(&single, &[0; 10]));
+        assert_eq!(Ok(KeyUsage::MacRequest), "mac".parse());
+        assert!(matches!("kek".parse::<KeyUsage>(), Err(DukptError::InvalidKeyUsage(_))));
+    }
+}
@@ -0,0 +1,346 @@
+use std::collections::HashMap;
+use std::error;
+use std::fmt;
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufRead, BufReader, BufWriter, Write};
+use std::path::Path;
+use std::str::FromStr;
+use crate::crypto::{
+    Algorithm,
+    CryptoError,
+    Key,
+};
+
+/// What a key is used for, which limits what it can be used with
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KeyType {
+    /// Zone master key, a key encryption key shared with another party to exchange working keys under
+    Zmk,
+    /// Zone PIN key, for PIN blocks
+    Zpk,
+    /// Terminal authentication key, for MACs
+    Tak,
+}
+
+impl fmt::Display for KeyType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            KeyType::Zmk => write!(f, "zmk"),
+            KeyType::Zpk => write!(f, "zpk"),
+            KeyType::Tak => write!(f, "tak"),
+        }
+    }
+}
+
+impl FromStr for KeyType {
+    type Err = HsmError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match &s.to_ascii_lowercase()[..] {
+            "zmk" => Ok(KeyType::Zmk),
+            "zpk" => Ok(KeyType::Zpk),
+            "tak" => Ok(KeyType::Tak),
+            _ => Err(HsmError::InvalidKeyType(s.to_string())),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum HsmError {
+    UnknownKey(String),
+    InvalidKeyType(String),
+    WrongKeyType{
+        name: String,
+        expected: KeyType,
+        actual: KeyType,
+    },
+    /// A key's check value isn't the one given, or recorded, for it e.g. it was decrypted under the wrong key
+    CheckValueMismatch{
+        name: String,
+        expected: String,
+        actual: String,
+    },
+    /// A line of a key file couldn't be read
+    InvalidLine(String),
+    Crypto(CryptoError),
+    Io(io::Error),
+}
+
+impl fmt::Display for HsmError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::UnknownKey(name) => write!(f, "unknown key: {}", name),
+            Self::InvalidKeyType(key_type) => write!(f, "invalid key type: {}", key_type),
+            Self::WrongKeyType{ name, expected, actual } => write!(f, "key {} is a {} not a {}", name, actual, expected),
+            Self::CheckValueMismatch{ name, expected, actual } => {
+                write!(f, "key {} has check value {} rather than {}", name, actual, expected)
+            },
+            Self::InvalidLine(line) => write!(f, "invalid key line: {}", line),
+            Self::Crypto(e) => write!(f, "{}", e),
+            Self::Io(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl error::Error for HsmError {}
+
+impl From<CryptoError> for HsmError {
+    fn from(e: CryptoError) -> Self {
+        HsmError::Crypto(e)
+    }
+}
+
+impl From<io::Error> for HsmError {
+    fn from(e: io::Error) -> Self {
+        HsmError::Io(e)
+    }
+}
+
+/// A key as it's held, encrypted under the master key
+#[derive(Debug, Clone, PartialEq)]
+pub struct StoredKey {
+    pub key_type: KeyType,
+    pub algorithm: Algorithm,
+    pub encrypted: Vec<u8>,
+    pub check_value: String,
+}
+
+/// A software stand-in for an HSM, holding named keys encrypted under a master key.
+///
+/// Working keys are exchanged with other parties encrypted under a shared [`KeyType::Zmk`]. Keys are only decrypted
+/// for use in process, so this is for test keys only.
+///
+/// When opened from a file every change is appended to it as a line of
+/// `<name> <type> <algorithm> <key under the master key in hex> <check value>`, and the file is replayed on open with
+/// later lines replacing earlier ones of the same name.
+pub struct Hsm {
+    master: Key,
+    keys: HashMap<String, StoredKey>,
+    journal: Option<BufWriter<File>>,
+}
+
+impl Hsm {
+    pub fn new(master: Key) -> Self {
+        Hsm {
+            master,
+            keys: HashMap::new(),
+            journal: None,
+        }
+    }
+
+    /// Loads the keys in the file, which is created if it doesn't exist, checking each decrypts to its check value
+    /// under the master key
+    pub fn open<P: AsRef<Path>>(path: P, master: Key) -> Result<Self, HsmError> {
+        let mut hsm = Self::new(master);
+
+        if path.as_ref().exists() {
+            for line in BufReader::new(File::open(&path)?).lines() {
+                let line = line?;
+                let line = line.trim();
+                if line.is_empty() || line.starts_with('#') {
+                    continue;
+                }
+                let (name, stored) = parse_line(line)?;
+                hsm.decrypt(&name, &stored)?;
+                hsm.keys.insert(name, stored);
+            }
+        }
+
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)?;
+        hsm.journal = Some(BufWriter::new(file));
+
+        Ok(hsm)
+    }
+
+    /// The names of the keys held, in order
+    pub fn names(&self) -> Vec<&str> {
+        let mut names = self.keys.keys()
+            .map(|name| &name[..])
+            .collect::<Vec<_>>();
+        names.sort_unstable();
+        names
+    }
+
+    pub fn get(&self, name: &str) -> Option<&StoredKey> {
+        self.keys.get(name)
+    }
+
+    /// The clear key, checking it's of the type
+    pub fn key(&self, name: &str, key_type: KeyType) -> Result<Key, HsmError> {
+        let stored = self.keys.get(name)
+            .ok_or_else(|| HsmError::UnknownKey(name.to_string()))?;
+        if stored.key_type != key_type {
+            return Err(HsmError::WrongKeyType{ name: name.to_string(), expected: key_type, actual: stored.key_type });
+        }
+        self.decrypt(name, stored)
+    }
+
+    /// Adds or replaces a clear key, returning its check value
+    pub fn insert(&mut self, name: &str, key_type: KeyType, key: &Key) -> Result<String, HsmError> {
+        let stored = StoredKey {
+            key_type,
+            algorithm: key.algorithm(),
+            encrypted: self.master.encrypt(key.bytes())?,
+            check_value: key.check_value(),
+        };
+        let check_value = stored.check_value.clone();
+        self.journal(&format_line(name, &stored))?;
+        self.keys.insert(name.to_string(), stored);
+        Ok(check_value)
+    }
+
+    /// Adds or replaces a random key of the length in bytes, returning its check value
+    pub fn generate(&mut self, name: &str, key_type: KeyType, algorithm: Algorithm, length: usize) -> Result<String, HsmError> {
+        self.insert(name, key_type, &Key::generate(algorithm, length)?)
+    }
+
+    /// Encrypts a key under the named ZMK for sending to another party
+    pub fn wrap(&self, zmk: &str, key: &Key) -> Result<Vec<u8>, HsmError> {
+        Ok(self.key(zmk, KeyType::Zmk)?.encrypt(key.bytes())?)
+    }
+
+    /// Decrypts a key received under the named ZMK, checking it against its check value if given
+    pub fn unwrap(&self, zmk: &str, algorithm: Algorithm, encrypted: &[u8], check_value: Option<&str>) -> Result<Key, HsmError> {
+        let key = Key::new(algorithm, &self.key(zmk, KeyType::Zmk)?.decrypt(encrypted)?)?;
+        if let Some(expected) = check_value {
+            if !key.check_value().eq_ignore_ascii_case(expected) {
+                return Err(HsmError::CheckValueMismatch{ name: zmk.to_string(), expected: expected.to_string(), actual: key.check_value() });
+            }
+        }
+        Ok(key)
+    }
+
+    /// The named working key encrypted under the named ZMK, and its check value
+    pub fn export(&self, name: &str, zmk: &str) -> Result<(Vec<u8>, String), HsmError> {
+        let stored = self.keys.get(name)
+            .ok_or_else(|| HsmError::UnknownKey(name.to_string()))?;
+        let key = self.decrypt(name, stored)?;
+        Ok((self.wrap(zmk, &key)?, stored.check_value.clone()))
+    }
+
+    /// Adds or replaces a working key received under the named ZMK, returning its check value
+    pub fn import(&mut self, name: &str, key_type: KeyType, zmk: &str, algorithm: Algorithm, encrypted: &[u8], check_value: Option<&str>) -> Result<String, HsmError> {
+        let key = self.unwrap(zmk, algorithm, encrypted, check_value)?;
+        self.insert(name, key_type, &key)
+    }
+
+    fn decrypt(&self, name: &str, stored: &StoredKey) -> Result<Key, HsmError> {
+        let key = Key::new(stored.algorithm, &self.master.decrypt(&stored.encrypted)?)?;
+        if key.check_value() != stored.check_value {
+            return Err(HsmError::CheckValueMismatch{ name: name.to_string(), expected: stored.check_value.clone(), actual: key.check_value() });
+        }
+        Ok(key)
+    }
+
+    fn journal(&mut self, line: &str) -> io::Result<()> {
+        if let Some(journal) = &mut self.journal {
+            writeln!(journal, "{}", line)?;
+            journal.flush()?;
+        }
+        Ok(())
+    }
+}
+
+fn format_line(name: &str, stored: &StoredKey) -> String {
+    format!("{} {} {} {} {}", name, stored.key_type, stored.algorithm, hex::encode_upper(&stored.encrypted), stored.check_value)
+}
+
+fn parse_line(line: &str) -> Result<(String, StoredKey), HsmError> {
+    let invalid = || HsmError::InvalidLine(line.to_string());
+    let columns = line.split_whitespace().collect::<Vec<_>>();
+    let (name, key_type, algorithm, encrypted, check_value) = match columns[..] {
+        [name, key_type, algorithm, encrypted, check_value] => (name, key_type, algorithm, encrypted, check_value),
+        _ => return Err(invalid()),
+    };
+
+    let stored = StoredKey {
+        key_type: key_type.parse()?,
+        algorithm: algorithm.parse()?,
+        encrypted: hex::decode(encrypted).map_err(|_e| invalid())?,
+        check_value: check_value.to_ascii_uppercase(),
+    };
+    Ok((name.to_string(), stored))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::env;
+    use std::fs;
+    use std::process;
+
+    fn master() -> Key {
+        "tdes:0123456789ABCDEFFEDCBA9876543210".parse().unwrap()
+    }
+
+    fn zmk() -> Key {
+        "tdes:1111111111111111EEEEEEEEEEEEEEEE".parse().unwrap()
+    }
+
+    #[test]
+    fn insert_and_get() {
+        let mut hsm = Hsm::new(master());
+        assert_eq!("08D7B4", hsm.insert("lmk", KeyType::Zmk, &master()).unwrap());
+        assert_eq!(master(), hsm.key("lmk", KeyType::Zmk).unwrap());
+        assert_ne!(master().bytes(), &hsm.get("lmk").unwrap().encrypted[..]);
+
+        assert!(matches!(hsm.key("lmk", KeyType::Zpk), Err(HsmError::WrongKeyType{ expected: KeyType::Zpk, actual: KeyType::Zmk, .. })));
+        assert!(matches!(hsm.key("zpk", KeyType::Zpk), Err(HsmError::UnknownKey(_))));
+    }
+
+    #[test]
+    fn exchange() {
+        let mut ours = Hsm::new(master());
+        let mut theirs = Hsm::new("tdes:FEDCBA98765432100123456789ABCDEF".parse().unwrap());
+        ours.insert("zmk", KeyType::Zmk, &zmk()).unwrap();
+        theirs.insert("shared", KeyType::Zmk, &zmk()).unwrap();
+
+        let check_value = ours.generate("zpk", KeyType::Zpk, Algorithm::Tdes, 16).unwrap();
+        let (encrypted, exported_check_value) = ours.export("zpk", "zmk").unwrap();
+        assert_eq!(check_value, exported_check_value);
+
+        theirs.import("zpk", KeyType::Zpk, "shared", Algorithm::Tdes, &encrypted, Some(&check_value)).unwrap();
+        assert_eq!(ours.key("zpk", KeyType::Zpk).unwrap(), theirs.key("zpk", KeyType::Zpk).unwrap());
+
+        assert!(matches!(
+            theirs.import("zpk", KeyType::Zpk, "shared", Algorithm::Tdes, &encrypted, Some("000000")),
+            Err(HsmError::CheckValueMismatch{ .. }),
+        ));
+        assert!(matches!(ours.export("zpk", "zpk"), Err(HsmError::WrongKeyType{ .. })));
+    }
+
+    #[test]
+    fn open_replays_file() {
+        let path = env::temp_dir().join(format!("zaps-hsm-{}.keys", process::id()));
+        let _ = fs::remove_file(&path);
+
+        {
+            let mut hsm = Hsm::open(&path, master()).unwrap();
+            hsm.insert("zmk", KeyType::Zmk, &zmk()).unwrap();
+            hsm.generate("tak", KeyType::Tak, Algorithm::Tdes, 16).unwrap();
+            hsm.generate("tak", KeyType::Tak, Algorithm::Aes, 16).unwrap();
+        }
+        let contents = fs::read_to_string(&path).unwrap();
+        assert!(!contents.contains("1111111111111111EEEEEEEEEEEEEEEE"));
+
+        let hsm = Hsm::open(&path, master()).unwrap();
+        assert_eq!(vec!["tak", "zmk"], hsm.names());
+        assert_eq!(Algorithm::Aes, hsm.key("tak", KeyType::Tak).unwrap().algorithm());
+        assert_eq!(zmk(), hsm.key("zmk", KeyType::Zmk).unwrap());
+
+        let wrong_master = Hsm::open(&path, "tdes:FEDCBA98765432100123456789ABCDEF".parse().unwrap());
+        assert!(matches!(wrong_master, Err(HsmError::CheckValueMismatch{ .. })));
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn invalid_lines() {
+        assert!(matches!(parse_line("zmk zmk tdes 0123"), Err(HsmError::InvalidLine(_))));
+        assert!(matches!(parse_line("zmk zmk tdes 01X3 08D7B4"), Err(HsmError::InvalidLine(_))));
+        assert!(matches!(parse_line("zmk kek tdes 0123 08D7B4"), Err(HsmError::InvalidKeyType(_))));
+        assert!(matches!(parse_line("zmk zmk rsa 0123 08D7B4"), Err(HsmError::Crypto(_))));
+    }
+}
@@ -0,0 +1,253 @@
+use std::fmt;
+use std::str::FromStr;
+use aes::{
+    Aes128,
+    Aes192,
+    Aes256,
+};
+use des::{
+    cipher::{
+        generic_array::GenericArray,
+        BlockDecrypt,
+        BlockEncrypt,
+        KeyInit,
+    },
+    Des,
+    TdesEde2,
+    TdesEde3,
+};
+use rand::Rng;
+use crate::crypto::CryptoError;
+
+/// The bytes of a key check value
+const CHECK_VALUE_LENGTH: usize = 3;
+
+/// The block cipher a [`Key`] is for
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Algorithm {
+    /// Triple DES with single (plain DES), double or triple length keys
+    Tdes,
+    Aes,
+}
+
+impl Algorithm {
+    /// The block size in bytes
+    pub fn block_size(&self) -> usize {
+        match self {
+            Algorithm::Tdes => 8,
+            Algorithm::Aes => 16,
+        }
+    }
+
+    fn valid_length(&self, length: usize) -> bool {
+        match self {
+            Algorithm::Tdes => matches!(length, 8 | 16 | 24),
+            Algorithm::Aes => matches!(length, 16 | 24 | 32),
+        }
+    }
+}
+
+impl fmt::Display for Algorithm {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Algorithm::Tdes => write!(f, "tdes"),
+            Algorithm::Aes => write!(f, "aes"),
+        }
+    }
+}
+
+impl FromStr for Algorithm {
+    type Err = CryptoError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match &s.to_ascii_lowercase()[..] {
+            "tdes" | "3des" | "des" => Ok(Algorithm::Tdes),
+            "aes" => Ok(Algorithm::Aes),
+            _ => Err(CryptoError::InvalidKey(format!("unknown algorithm {}", s))),
+        }
+    }
+}
+
+/// A clear symmetric key. These are for testing so are held in memory as they are, but aren't printed by `Debug`.
+#[derive(Clone, PartialEq, Eq)]
+pub struct Key {
+    algorithm: Algorithm,
+    bytes: Vec<u8>,
+}
+
+impl Key {
+    pub fn new(algorithm: Algorithm, bytes: &[u8]) -> Result<Self, CryptoError> {
+        if !algorithm.valid_length(bytes.len()) {
+            return Err(CryptoError::InvalidKeyLength{ algorithm, length: bytes.len() });
+        }
+        Ok(Key {
+            algorithm,
+            bytes: bytes.to_vec(),
+        })
+    }
+
+    /// A random key of the length in bytes, with odd parity for TDES
+    pub fn generate(algorithm: Algorithm, length: usize) -> Result<Self, CryptoError> {
+        Self::generate_with(algorithm, length, &mut rand::thread_rng())
+    }
+
+    /// [`Key::generate`] with the bytes from `rng`
+    pub fn generate_with<R: Rng + ?Sized>(algorithm: Algorithm, length: usize, rng: &mut R) -> Result<Self, CryptoError> {
+        let mut bytes = vec![0u8; length];
+        rng.fill(&mut bytes[..]);
+        if algorithm == Algorithm::Tdes {
+            set_odd_parity(&mut bytes);
+        }
+        Key::new(algorithm, &bytes)
+    }
+
+    pub fn from_hex(algorithm: Algorithm, hex: &str) -> Result<Self, CryptoError> {
+        let bytes = hex::decode(hex)
+            .map_err(|_e| CryptoError::InvalidKey("key isn't hex".to_string()))?;
+        Key::new(algorithm, &bytes)
+    }
+
+    pub fn algorithm(&self) -> Algorithm {
+        self.algorithm
+    }
+
+    pub fn bytes(&self) -> &[u8] {
+        &self.bytes
+    }
+
+    pub fn block_size(&self) -> usize {
+        self.algorithm.block_size()
+    }
+
+    /// The key check value (KCV), the leading 3 bytes of a block of zeros encrypted under the key, in hex
+    pub fn check_value(&self) -> String {
+        let zeros = vec![0; self.block_size()];
+        let encrypted = self.encrypt(&zeros)
+            .expect("a block is a whole number of blocks");
+        hex::encode_upper(&encrypted[..CHECK_VALUE_LENGTH])
+    }
+
+    /// Encrypts data a block at a time (ECB), so it must be a whole number of blocks
+    pub fn encrypt(&self, data: &[u8]) -> Result<Vec<u8>, CryptoError> {
+        self.ecb(data, true)
+    }
+
+    /// Decrypts data a block at a time (ECB), so it must be a whole number of blocks
+    pub fn decrypt(&self, data: &[u8]) -> Result<Vec<u8>, CryptoError> {
+        self.ecb(data, false)
+    }
+
+    fn ecb(&self, data: &[u8], encrypt: bool) -> Result<Vec<u8>, CryptoError> {
+        let block_size = self.block_size();
+        if !data.len().is_multiple_of(block_size) {
+            return Err(CryptoError::InvalidDataLength{ length: data.len(), block_size });
+        }
+
+        let mut data = data.to_vec();
+        // the key length was checked when the key was made
+        match (self.algorithm, self.bytes.len()) {
+            (Algorithm::Tdes, 8) => ecb(Des::new_from_slice(&self.bytes).unwrap(), &mut data, encrypt),
+            (Algorithm::Tdes, 16) => ecb(TdesEde2::new_from_slice(&self.bytes).unwrap(), &mut data, encrypt),
+            (Algorithm::Tdes, _) => ecb(TdesEde3::new_from_slice(&self.bytes).unwrap(), &mut data, encrypt),
+            (Algorithm::Aes, 16) => ecb(Aes128::new_from_slice(&self.bytes).unwrap(), &mut data, encrypt),
+            (Algorithm::Aes, 24) => ecb(Aes192::new_from_slice(&self.bytes).unwrap(), &mut data, encrypt),
+            (Algorithm::Aes, _) => ecb(Aes256::new_from_slice(&self.bytes).unwrap(), &mut data, encrypt),
+        }
+        Ok(data)
+    }
+}
+
+/// Sets the low bit of each byte so it has an odd number of bits set, as DES keys conventionally do
+pub(crate) fn set_odd_parity(bytes: &mut [u8]) {
+    for byte in bytes {
+        if byte.count_ones().is_multiple_of(2) {
+            *byte ^= 1;
+        }
+    }
+}
+
+fn ecb<C: BlockEncrypt + BlockDecrypt>(cipher: C, data: &mut [u8], encrypt: bool) {
+    for block in data.chunks_exact_mut(C::block_size()) {
+        let block = GenericArray::from_mut_slice(block);
+        if encrypt {
+            cipher.encrypt_block(block);
+        } else {
+            cipher.decrypt_block(block);
+        }
+    }
+}
+
+impl fmt::Debug for Key {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Key({}, {} bytes)", self.algorithm, self.bytes.len())
+    }
+}
+
+/// Parses a key as its algorithm then the key in hex, e.g. `tdes:0123456789ABCDEFFEDCBA9876543210`
+impl FromStr for Key {
+    type Err = CryptoError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (algorithm, hex) = s.split_once(':')
+            .ok_or_else(|| CryptoError::InvalidKey("expected <algorithm>:<hex>".to_string()))?;
+        Key::from_hex(algorithm.parse()?, hex)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    macro_rules! cipher_tests {
+        ($($name:ident: $key:literal, $clear:literal => $encrypted:literal,)*) => {
+            $(
+                #[test]
+                fn $name() {
+                    let key = $key.parse::<Key>().unwrap();
+                    let clear = hex::decode($clear).unwrap();
+                    let encrypted = key.encrypt(&clear).unwrap();
+                    assert_eq!($encrypted, hex::encode_upper(&encrypted));
+                    assert_eq!(clear, key.decrypt(&encrypted).unwrap());
+                }
+            )*
+        };
+    }
+
+    cipher_tests!(
+        des: "tdes:133457799BBCDFF1", "0123456789ABCDEF" => "85E813540F0AB405",
+        tdes_double: "tdes:0123456789ABCDEFFEDCBA9876543210", "0123456789ABCDEF" => "1A4D672DCA6CB335",
+        tdes_two_blocks: "tdes:0123456789ABCDEFFEDCBA9876543210", "0123456789ABCDEF0123456789ABCDEF" => "1A4D672DCA6CB3351A4D672DCA6CB335",
+        aes_128: "aes:000102030405060708090A0B0C0D0E0F", "00112233445566778899AABBCCDDEEFF" => "69C4E0D86A7B0430D8CDB78070B4C55A",
+        aes_256: "aes:000102030405060708090A0B0C0D0E0F101112131415161718191A1B1C1D1E1F", "00112233445566778899AABBCCDDEEFF" => "8EA2B7CA516745BFEAFC49904B496089",
+    );
+
+    #[test]
+    fn errors() {
+        assert_eq!(
+            Err(CryptoError::InvalidKeyLength{ algorithm: Algorithm::Aes, length: 8 }),
+            "aes:0123456789ABCDEF".parse::<Key>(),
+        );
+        assert!(matches!("0123456789ABCDEF".parse::<Key>(), Err(CryptoError::InvalidKey(_))));
+        assert!(matches!("rsa:0123456789ABCDEF".parse::<Key>(), Err(CryptoError::InvalidKey(_))));
+        assert!(matches!("tdes:0123456789ABCDEG".parse::<Key>(), Err(CryptoError::InvalidKey(_))));
+
+        let key = "tdes:0123456789ABCDEF".parse::<Key>().unwrap();
+        assert_eq!(Err(CryptoError::InvalidDataLength{ length: 4, block_size: 8 }), key.encrypt(b"1234"));
+        assert_eq!("Key(tdes, 8 bytes)", format!("{:?}", key));
+    }
+
+    #[test]
+    fn check_values() {
+        assert_eq!("08D7B4", "tdes:0123456789ABCDEFFEDCBA9876543210".parse::<Key>().unwrap().check_value());
+        assert_eq!("C6A13B", "aes:000102030405060708090A0B0C0D0E0F".parse::<Key>().unwrap().check_value());
+    }
+
+    #[test]
+    fn generate() {
+        let key = Key::generate(Algorithm::Tdes, 16).unwrap();
+        assert_eq!(16, key.bytes().len());
+        assert!(key.bytes().iter().all(|b| b.count_ones() % 2 == 1));
+        assert_eq!(32, Key::generate(Algorithm::Aes, 32).unwrap().bytes().len());
+        assert_eq!(Err(CryptoError::InvalidKeyLength{ algorithm: Algorithm::Aes, length: 8 }), Key::generate(Algorithm::Aes, 8));
+    }
+}
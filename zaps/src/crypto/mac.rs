@@ -0,0 +1,153 @@
+use crate::{
+    crypto::{
+        Algorithm,
+        CryptoError,
+        Key,
+    },
+    iso8583::spec::{
+        MacAlgorithm,
+        MacPadding,
+    },
+};
+
+/// Checks the key can be used with the algorithm, as algorithm 3 needs a double length TDES key
+pub fn check_key(key: &Key, algorithm: MacAlgorithm) -> Result<(), CryptoError> {
+    if algorithm == MacAlgorithm::Alg3 && (key.algorithm() != Algorithm::Tdes || key.bytes().len() != 16) {
+        return Err(CryptoError::InvalidKey("algorithm 3 needs a double length TDES key".to_string()));
+    }
+    Ok(())
+}
+
+/// The full MAC of the data, a block long. Links usually send only its leading bytes.
+pub fn generate(key: &Key, algorithm: MacAlgorithm, padding: MacPadding, data: &[u8]) -> Result<Vec<u8>, CryptoError> {
+    check_key(key, algorithm)?;
+
+    match algorithm {
+        MacAlgorithm::Alg1 => cbc_mac(key, &pad(data, key.block_size(), padding)),
+        MacAlgorithm::Alg3 => {
+            let (left, right) = key.bytes().split_at(8);
+            let (left, right) = (Key::new(Algorithm::Tdes, left)?, Key::new(Algorithm::Tdes, right)?);
+            let mac = cbc_mac(&left, &pad(data, 8, padding))?;
+            left.encrypt(&right.decrypt(&mac)?)
+        },
+        MacAlgorithm::Cmac => cmac(key, data),
+    }
+}
+
+/// Whether the MAC, or its leading bytes, is that of the data
+pub fn verify(key: &Key, algorithm: MacAlgorithm, padding: MacPadding, data: &[u8], mac: &[u8]) -> Result<bool, CryptoError> {
+    let expected = generate(key, algorithm, padding, data)?;
+    Ok(!mac.is_empty() && expected.starts_with(mac))
+}
+
+fn pad(data: &[u8], block_size: usize, padding: MacPadding) -> Vec<u8> {
+    let mut data = data.to_vec();
+    if padding == MacPadding::Method2 {
+        data.push(0x80);
+    }
+    // method 1 pads empty data to a block
+    let blocks = data.len().div_ceil(block_size).max(1);
+    data.resize(blocks * block_size, 0);
+    data
+}
+
+/// The last block of the data encrypted in CBC mode with a zero IV
+fn cbc_mac(key: &Key, data: &[u8]) -> Result<Vec<u8>, CryptoError> {
+    let mut mac = vec![0; key.block_size()];
+    for block in data.chunks(key.block_size()) {
+        mac = key.encrypt(&xor(&mac, block))?;
+    }
+    Ok(mac)
+}
+
+fn cmac(key: &Key, data: &[u8]) -> Result<Vec<u8>, CryptoError> {
+    let block_size = key.block_size();
+    let k1 = double(&key.encrypt(&vec![0; block_size])?);
+    let k2 = double(&k1);
+
+    let complete = !data.is_empty() && data.len().is_multiple_of(block_size);
+    let mut data = data.to_vec();
+    let subkey = if complete {
+        k1
+    } else {
+        data.push(0x80);
+        data.resize(data.len().div_ceil(block_size) * block_size, 0);
+        k2
+    };
+    let last = data.len() - block_size;
+    let masked = xor(&data[last..], &subkey);
+    data[last..].copy_from_slice(&masked);
+
+    cbc_mac(key, &data)
+}
+
+/// Multiplies by x in the field CMAC subkeys are made in
+fn double(block: &[u8]) -> Vec<u8> {
+    let polynomial = if block.len() == 16 { 0x87 } else { 0x1b };
+    let mut doubled = block.iter()
+        .zip(block.iter().skip(1).chain([0].iter()))
+        .map(|(b, next)| b << 1 | next >> 7)
+        .collect::<Vec<_>>();
+    if block[0] & 0x80 != 0 {
+        *doubled.last_mut().unwrap() ^= polynomial;
+    }
+    doubled
+}
+
+fn xor(a: &[u8], b: &[u8]) -> Vec<u8> {
+    a.iter()
+        .zip(b)
+        .map(|(a, b)| a ^ b)
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    macro_rules! mac_tests {
+        ($($name:ident: $key:expr, $algorithm:expr, $padding:expr, $data:expr => $mac:literal,)*) => {
+            $(
+                #[test]
+                fn $name() {
+                    let key = $key.parse::<Key>().unwrap();
+                    let data = hex::decode($data).unwrap();
+                    let mac = generate(&key, $algorithm, $padding, &data).unwrap();
+                    assert_eq!($mac, hex::encode_upper(&mac));
+                    assert_eq!(Ok(true), verify(&key, $algorithm, $padding, &data, &mac[..4]));
+                }
+            )*
+        };
+    }
+
+    const TDES_KEY: &str = "tdes:0123456789ABCDEFFEDCBA9876543210";
+    /// "Now is the time for all ", the ANSI X9.19 example
+    const DATA: &str = "4E6F77206973207468652074696D6520666F7220616C6C20";
+
+    mac_tests!(
+        alg1_tdes: TDES_KEY, MacAlgorithm::Alg1, MacPadding::Method1, DATA => "93462A6DB9B4A4D1",
+        alg1_pad2: TDES_KEY, MacAlgorithm::Alg1, MacPadding::Method2, DATA => "805036D50BB76107",
+        alg3: TDES_KEY, MacAlgorithm::Alg3, MacPadding::Method1, DATA => "A1C72E74EA3FA9B6",
+        alg3_partial_block: TDES_KEY, MacAlgorithm::Alg3, MacPadding::Method1, "4E6F77206973207468" => "D5C14B5308FE5EF0",
+        cmac_empty: "aes:2B7E151628AED2A6ABF7158809CF4F3C", MacAlgorithm::Cmac, MacPadding::Method1, "" => "BB1D6929E95937287FA37D129B756746",
+        cmac_block: "aes:2B7E151628AED2A6ABF7158809CF4F3C", MacAlgorithm::Cmac, MacPadding::Method1, "6BC1BEE22E409F96E93D7E117393172A" => "070A16B46B4D4144F79BDD9DD04A287C",
+        cmac_partial: "aes:2B7E151628AED2A6ABF7158809CF4F3C", MacAlgorithm::Cmac, MacPadding::Method1, "6BC1BEE22E409F96E93D7E117393172AAE2D8A571E03AC9C9EB76FAC45AF8E5130C81C46A35CE411" => "DFA66747DE9AE63030CA32611497C827",
+    );
+
+    #[test]
+    fn mismatch() {
+        let key = TDES_KEY.parse::<Key>().unwrap();
+        let mac = generate(&key, MacAlgorithm::Alg3, MacPadding::Method1, b"0200").unwrap();
+        assert_eq!(Ok(false), verify(&key, MacAlgorithm::Alg3, MacPadding::Method1, b"0210", &mac));
+        assert_eq!(Ok(false), verify(&key, MacAlgorithm::Alg3, MacPadding::Method1, b"0200", &[]));
+    }
+
+    #[test]
+    fn key_checks() {
+        let aes = "aes:2B7E151628AED2A6ABF7158809CF4F3C".parse::<Key>().unwrap();
+        let single = "tdes:0123456789ABCDEF".parse::<Key>().unwrap();
+        assert!(matches!(generate(&aes, MacAlgorithm::Alg3, MacPadding::Method1, b""), Err(CryptoError::InvalidKey(_))));
+        assert!(matches!(check_key(&single, MacAlgorithm::Alg3), Err(CryptoError::InvalidKey(_))));
+        assert_eq!(Ok(()), check_key(&single, MacAlgorithm::Alg1));
+    }
+}
@@ -0,0 +1,41 @@
+use std::error;
+use std::fmt;
+
+mod key;
+pub use key::{
+    Algorithm,
+    Key,
+};
+pub(crate) use key::set_odd_parity;
+pub mod cvv;
+pub mod dukpt;
+pub mod hsm;
+pub mod mac;
+pub mod pin;
+
+#[derive(Debug, PartialEq)]
+pub enum CryptoError {
+    /// A key couldn't be read
+    InvalidKey(String),
+    InvalidKeyLength{
+        algorithm: Algorithm,
+        length: usize,
+    },
+    /// Data to encrypt or decrypt isn't a whole number of blocks
+    InvalidDataLength{
+        length: usize,
+        block_size: usize,
+    },
+}
+
+impl fmt::Display for CryptoError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::InvalidKey(reason) => write!(f, "invalid key: {}", reason),
+            Self::InvalidKeyLength{ algorithm, length } => write!(f, "invalid {} key length: {} bytes", algorithm, length),
+            Self::InvalidDataLength{ length, block_size } => write!(f, "data length {} isn't a multiple of the block size {}", length, block_size),
+        }
+    }
+}
+
+impl error::Error for CryptoError {}
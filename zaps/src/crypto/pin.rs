@@ -0,0 +1,420 @@
+use std::error;
+use std::fmt;
+use std::str::FromStr;
+use rand::Rng;
+use crate::crypto::{
+    Algorithm,
+    CryptoError,
+    Key,
+};
+
+const MIN_PIN_LENGTH: usize = 4;
+const MAX_PIN_LENGTH: usize = 12;
+/// The longest PAN format 4 can carry
+const MAX_PAN_LENGTH: usize = 19;
+
+/// An ISO 9564-1 PIN block format
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PinFormat {
+    /// The PIN padded with `F`s, XORed with 12 digits of the PAN
+    Iso0,
+    /// The PIN padded with random digits, without the PAN
+    Iso1,
+    /// The PIN padded with random `A` to `F`s, XORed with 12 digits of the PAN
+    Iso3,
+    /// A 16 byte block for AES, enciphered with the whole PAN between two encryptions
+    Iso4,
+}
+
+impl PinFormat {
+    /// The first nibble of a clear block
+    fn control(&self) -> u8 {
+        match self {
+            PinFormat::Iso0 => 0,
+            PinFormat::Iso1 => 1,
+            PinFormat::Iso3 => 3,
+            PinFormat::Iso4 => 4,
+        }
+    }
+
+    /// The algorithm of keys for blocks in this format
+    pub fn algorithm(&self) -> Algorithm {
+        match self {
+            PinFormat::Iso4 => Algorithm::Aes,
+            _ => Algorithm::Tdes,
+        }
+    }
+
+    /// The block length in bytes
+    pub fn block_size(&self) -> usize {
+        self.algorithm().block_size()
+    }
+}
+
+impl fmt::Display for PinFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "iso{}", self.control())
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub struct PinFormatParseError(pub String);
+
+impl fmt::Display for PinFormatParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "invalid PIN block format: {}", self.0)
+    }
+}
+
+impl error::Error for PinFormatParseError {}
+
+impl FromStr for PinFormat {
+    type Err = PinFormatParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let lower = s.to_ascii_lowercase();
+        let number = lower.strip_prefix("iso")
+            .map(|number| number.trim_start_matches('-'))
+            .unwrap_or(&lower);
+        match number {
+            "0" => Ok(PinFormat::Iso0),
+            "1" => Ok(PinFormat::Iso1),
+            "3" => Ok(PinFormat::Iso3),
+            "4" => Ok(PinFormat::Iso4),
+            _ => Err(PinFormatParseError(s.to_string())),
+        }
+    }
+}
+
+/// PIN block errors, which never include the PIN
+#[derive(Debug, PartialEq)]
+pub enum PinError {
+    /// The PIN isn't 4 to 12 digits
+    InvalidPin,
+    /// The PAN isn't 1 to 19 digits
+    InvalidPan,
+    /// A clear block isn't in the format, most likely as it was encrypted under another key or with another PAN
+    InvalidBlock,
+    /// The key is for the wrong algorithm for the format
+    WrongAlgorithm{
+        format: PinFormat,
+        algorithm: Algorithm,
+    },
+    Crypto(CryptoError),
+}
+
+impl fmt::Display for PinError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::InvalidPin => write!(f, "PIN must be {} to {} digits", MIN_PIN_LENGTH, MAX_PIN_LENGTH),
+            Self::InvalidPan => write!(f, "PAN must be 1 to {} digits", MAX_PAN_LENGTH),
+            Self::InvalidBlock => write!(f, "invalid PIN block, check the key, format and PAN"),
+            Self::WrongAlgorithm{ format, algorithm } => write!(f, "{} PIN blocks can't use {} keys", format, algorithm),
+            Self::Crypto(e) => e.fmt(f),
+        }
+    }
+}
+
+impl error::Error for PinError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            Self::Crypto(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<CryptoError> for PinError {
+    fn from(e: CryptoError) -> Self {
+        Self::Crypto(e)
+    }
+}
+
+/// The clear PIN block for formats 0, 1 and 3. For format 4 this is the plain text PIN field, as the PAN is only
+/// brought in by [`encrypt`].
+pub fn encode(format: PinFormat, pin: &str, pan: &str) -> Result<Vec<u8>, PinError> {
+    encode_with(format, pin, pan, &mut rand::thread_rng())
+}
+
+/// [`encode`] with the random fill from `rng`
+pub fn encode_with<R: Rng + ?Sized>(format: PinFormat, pin: &str, pan: &str, rng: &mut R) -> Result<Vec<u8>, PinError> {
+    let pin_field = pin_field(format, pin, rng)?;
+    match format {
+        PinFormat::Iso0 | PinFormat::Iso3 => Ok(xor(&pin_field, &pan_field(pan)?)),
+        PinFormat::Iso1 => Ok(pin_field),
+        PinFormat::Iso4 => pan_digits(pan).map(|_digits| pin_field),
+    }
+}
+
+/// The PIN from a clear block, the reverse of [`encode`]
+pub fn decode(format: PinFormat, block: &[u8], pan: &str) -> Result<String, PinError> {
+    if block.len() != format.block_size() {
+        return Err(PinError::InvalidBlock);
+    }
+    let pin_field = match format {
+        PinFormat::Iso0 | PinFormat::Iso3 => xor(block, &pan_field(pan)?),
+        PinFormat::Iso1 | PinFormat::Iso4 => block.to_vec(),
+    };
+
+    let nibbles = unpack(&pin_field);
+    let length = nibbles[1] as usize;
+    if nibbles[0] != format.control() || !(MIN_PIN_LENGTH..=MAX_PIN_LENGTH).contains(&length) {
+        return Err(PinError::InvalidBlock);
+    }
+    let (pin, fill) = nibbles[2..16].split_at(length);
+    let fill_valid = match format {
+        PinFormat::Iso0 => fill.iter().all(|nibble| *nibble == 0xF),
+        PinFormat::Iso1 => true,
+        PinFormat::Iso3 => fill.iter().all(|nibble| *nibble >= 0xA),
+        PinFormat::Iso4 => fill.iter().all(|nibble| *nibble == 0xA),
+    };
+    if !fill_valid || pin.iter().any(|nibble| *nibble > 9) {
+        return Err(PinError::InvalidBlock);
+    }
+
+    Ok(pin.iter().map(|nibble| (b'0' + nibble) as char).collect())
+}
+
+/// The PIN block for field 52, encrypted under the key
+pub fn encrypt(key: &Key, format: PinFormat, pin: &str, pan: &str) -> Result<Vec<u8>, PinError> {
+    encrypt_with(key, format, pin, pan, &mut rand::thread_rng())
+}
+
+/// [`encrypt`] with the random fill from `rng`
+pub fn encrypt_with<R: Rng + ?Sized>(key: &Key, format: PinFormat, pin: &str, pan: &str, rng: &mut R) -> Result<Vec<u8>, PinError> {
+    check_algorithm(key, format)?;
+    let clear = encode_with(format, pin, pan, rng)?;
+    match format {
+        PinFormat::Iso4 => {
+            let intermediate = xor(&key.encrypt(&clear)?, &pan_field_4(pan)?);
+            Ok(key.encrypt(&intermediate)?)
+        },
+        _ => Ok(key.encrypt(&clear)?),
+    }
+}
+
+/// The PIN from an encrypted block, the reverse of [`encrypt`]
+pub fn decrypt(key: &Key, format: PinFormat, block: &[u8], pan: &str) -> Result<String, PinError> {
+    check_algorithm(key, format)?;
+    if block.len() != format.block_size() {
+        return Err(PinError::InvalidBlock);
+    }
+    let clear = match format {
+        PinFormat::Iso4 => {
+            let intermediate = xor(&key.decrypt(block)?, &pan_field_4(pan)?);
+            key.decrypt(&intermediate)?
+        },
+        _ => key.decrypt(block)?,
+    };
+    decode(format, &clear, pan)
+}
+
+/// Re-encrypts a PIN block under another key and format, as a switch would between zones
+pub fn translate(block: &[u8], pan: &str, from_key: &Key, from_format: PinFormat, to_key: &Key, to_format: PinFormat) -> Result<Vec<u8>, PinError> {
+    let pin = decrypt(from_key, from_format, block, pan)?;
+    encrypt(to_key, to_format, &pin, pan)
+}
+
+fn check_algorithm(key: &Key, format: PinFormat) -> Result<(), PinError> {
+    if key.algorithm() != format.algorithm() {
+        return Err(PinError::WrongAlgorithm{ format, algorithm: key.algorithm() });
+    }
+    Ok(())
+}
+
+/// The control nibble, PIN length, PIN and fill, then for format 4 another 8 random bytes
+fn pin_field<R: Rng + ?Sized>(format: PinFormat, pin: &str, rng: &mut R) -> Result<Vec<u8>, PinError> {
+    if !(MIN_PIN_LENGTH..=MAX_PIN_LENGTH).contains(&pin.len()) || !pin.bytes().all(|b| b.is_ascii_digit()) {
+        return Err(PinError::InvalidPin);
+    }
+
+    let mut nibbles = vec![format.control(), pin.len() as u8];
+    nibbles.extend(pin.bytes().map(|b| b - b'0'));
+    while nibbles.len() < 16 {
+        nibbles.push(match format {
+            PinFormat::Iso0 => 0xF,
+            PinFormat::Iso1 => rng.gen_range(0..=0xF),
+            PinFormat::Iso3 => rng.gen_range(0xA..=0xF),
+            PinFormat::Iso4 => 0xA,
+        });
+    }
+    if format == PinFormat::Iso4 {
+        nibbles.extend((0..16).map(|_| rng.gen_range(0..=0xF)));
+    }
+
+    Ok(pack(&nibbles))
+}
+
+/// For formats 0 and 3, the rightmost 12 digits of the PAN without its check digit
+fn pan_field(pan: &str) -> Result<Vec<u8>, PinError> {
+    let digits = pan_digits(pan)?;
+    let account = &digits[..digits.len() - 1];
+    let account = &account[account.len().saturating_sub(12)..];
+
+    let mut nibbles = vec![0; 16 - account.len()];
+    nibbles.extend_from_slice(account);
+    Ok(pack(&nibbles))
+}
+
+/// For format 4, the number of PAN digits over 12 then the PAN, at least 12 digits, padded with 0s
+fn pan_field_4(pan: &str) -> Result<Vec<u8>, PinError> {
+    let digits = pan_digits(pan)?;
+
+    let mut nibbles = vec![digits.len().saturating_sub(12) as u8];
+    nibbles.resize(1 + 12usize.saturating_sub(digits.len()), 0);
+    nibbles.extend_from_slice(&digits);
+    nibbles.resize(32, 0);
+    Ok(pack(&nibbles))
+}
+
+fn pan_digits(pan: &str) -> Result<Vec<u8>, PinError> {
+    if pan.is_empty() || pan.len() > MAX_PAN_LENGTH || !pan.bytes().all(|b| b.is_ascii_digit()) {
+        return Err(PinError::InvalidPan);
+    }
+    Ok(pan.bytes().map(|b| b - b'0').collect())
+}
+
+fn pack(nibbles: &[u8]) -> Vec<u8> {
+    nibbles.chunks(2)
+        .map(|pair| pair[0] << 4 | pair[1])
+        .collect()
+}
+
+fn unpack(bytes: &[u8]) -> Vec<u8> {
+    bytes.iter()
+        .flat_map(|b| [b >> 4, b & 0xF])
+        .collect()
+}
+
+fn xor(a: &[u8], b: &[u8]) -> Vec<u8> {
+    a.iter()
+        .zip(b)
+        .map(|(a, b)| a ^ b)
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use rand::rngs::mock::StepRng;
+
+    const PAN: &str = "43219876543210987";
+    const TDES_KEY: &str = "tdes:0123456789ABCDEFFEDCBA9876543210";
+    const AES_KEY: &str = "aes:00112233445566778899AABBCCDDEEFF";
+
+    /// Fill is the lowest it can be, `0` or `A`
+    fn rng() -> StepRng {
+        StepRng::new(0, 0)
+    }
+
+    macro_rules! clear_block_tests {
+        ($($name:ident: $format:expr, $pin:literal => $block:literal,)*) => {
+            $(
+                #[test]
+                fn $name() {
+                    let block = encode_with($format, $pin, PAN, &mut rng()).unwrap();
+                    assert_eq!($block, hex::encode_upper(&block));
+                    assert_eq!(Ok($pin.to_string()), decode($format, &block, PAN));
+                }
+            )*
+        };
+    }
+
+    clear_block_tests!(
+        clear_iso0: PinFormat::Iso0, "1234" => "0412AC89ABCDEF67",
+        clear_iso0_long_pin: PinFormat::Iso0, "123456789012" => "0C12AC202CA20267",
+        clear_iso1: PinFormat::Iso1, "1234" => "1412340000000000",
+        clear_iso3: PinFormat::Iso3, "1234" => "3412ACDCFE98BA32",
+        clear_iso4: PinFormat::Iso4, "1234" => "441234AAAAAAAAAA0000000000000000",
+    );
+
+    macro_rules! encrypted_block_tests {
+        ($($name:ident: $key:expr, $format:expr, $pin:literal => $block:literal,)*) => {
+            $(
+                #[test]
+                fn $name() {
+                    let key = $key.parse::<Key>().unwrap();
+                    let block = encrypt_with(&key, $format, $pin, PAN, &mut rng()).unwrap();
+                    assert_eq!($block, hex::encode_upper(&block));
+                    assert_eq!(Ok($pin.to_string()), decrypt(&key, $format, &block, PAN));
+                }
+            )*
+        };
+    }
+
+    encrypted_block_tests!(
+        encrypted_iso0: TDES_KEY, PinFormat::Iso0, "1234" => "C967C8198151A458",
+        encrypted_iso1: TDES_KEY, PinFormat::Iso1, "1234" => "98A8893F632F24EB",
+        encrypted_iso3: TDES_KEY, PinFormat::Iso3, "1234" => "BEB9343881402359",
+        encrypted_iso4: AES_KEY, PinFormat::Iso4, "1234" => "FB91709D0B623E7F4E35DA9CEC94F760",
+    );
+
+    #[test]
+    fn random_fill() {
+        let key = TDES_KEY.parse::<Key>().unwrap();
+        for format in &[PinFormat::Iso1, PinFormat::Iso3] {
+            let block = encrypt(&key, *format, "9876", PAN).unwrap();
+            assert_eq!(Ok("9876".to_string()), decrypt(&key, *format, &block, PAN));
+        }
+    }
+
+    #[test]
+    fn short_pan() {
+        for format in &[PinFormat::Iso0, PinFormat::Iso4] {
+            let block = encode(*format, "1234", "12345").unwrap();
+            assert_eq!(Ok("1234".to_string()), decode(*format, &block, "12345"));
+        }
+        let key = AES_KEY.parse::<Key>().unwrap();
+        let block = encrypt(&key, PinFormat::Iso4, "1234", "12345").unwrap();
+        assert_eq!(Ok("1234".to_string()), decrypt(&key, PinFormat::Iso4, &block, "12345"));
+    }
+
+    #[test]
+    fn translate_key_and_format() {
+        let tdes = TDES_KEY.parse::<Key>().unwrap();
+        let other_tdes = "tdes:FEDCBA98765432100123456789ABCDEF".parse::<Key>().unwrap();
+        let aes = AES_KEY.parse::<Key>().unwrap();
+
+        let block = encrypt(&tdes, PinFormat::Iso0, "4321", PAN).unwrap();
+        let translated = translate(&block, PAN, &tdes, PinFormat::Iso0, &other_tdes, PinFormat::Iso3).unwrap();
+        assert_eq!(Ok("4321".to_string()), decrypt(&other_tdes, PinFormat::Iso3, &translated, PAN));
+
+        let translated = translate(&translated, PAN, &other_tdes, PinFormat::Iso3, &aes, PinFormat::Iso4).unwrap();
+        assert_eq!(Ok("4321".to_string()), decrypt(&aes, PinFormat::Iso4, &translated, PAN));
+    }
+
+    #[test]
+    fn errors() {
+        let tdes = TDES_KEY.parse::<Key>().unwrap();
+        let aes = AES_KEY.parse::<Key>().unwrap();
+
+        assert_eq!(Err(PinError::InvalidPin), encode(PinFormat::Iso0, "123", PAN));
+        assert_eq!(Err(PinError::InvalidPin), encode(PinFormat::Iso0, "1234567890123", PAN));
+        assert_eq!(Err(PinError::InvalidPin), encode(PinFormat::Iso0, "12a4", PAN));
+        assert_eq!(Err(PinError::InvalidPan), encode(PinFormat::Iso0, "1234", "4111-1111"));
+        assert_eq!(Err(PinError::InvalidPan), encode(PinFormat::Iso4, "1234", "41111111111111111111"));
+        assert_eq!(
+            Err(PinError::WrongAlgorithm{ format: PinFormat::Iso4, algorithm: Algorithm::Tdes }),
+            encrypt(&tdes, PinFormat::Iso4, "1234", PAN),
+        );
+        assert_eq!(
+            Err(PinError::WrongAlgorithm{ format: PinFormat::Iso0, algorithm: Algorithm::Aes }),
+            encrypt(&aes, PinFormat::Iso0, "1234", PAN),
+        );
+
+        let block = encrypt(&tdes, PinFormat::Iso0, "1234", PAN).unwrap();
+        let wrong_key = "tdes:FEDCBA98765432100123456789ABCDEF".parse::<Key>().unwrap();
+        assert_eq!(Err(PinError::InvalidBlock), decrypt(&wrong_key, PinFormat::Iso0, &block, PAN));
+        assert_eq!(Err(PinError::InvalidBlock), decrypt(&tdes, PinFormat::Iso0, &block[..4], PAN));
+        assert_eq!(Err(PinError::InvalidBlock), decrypt(&tdes, PinFormat::Iso1, &block, PAN));
+    }
+
+    #[test]
+    fn parse_format() {
+        assert_eq!(Ok(PinFormat::Iso0), "iso0".parse());
+        assert_eq!(Ok(PinFormat::Iso3), "ISO-3".parse());
+        assert_eq!(Ok(PinFormat::Iso4), "4".parse());
+        assert_eq!("iso1", PinFormat::Iso1.to_string());
+        assert!("iso2".parse::<PinFormat>().is_err());
+    }
+}
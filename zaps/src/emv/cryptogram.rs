@@ -0,0 +1,396 @@
+use std::error;
+use std::fmt;
+use std::str::FromStr;
+use crate::{
+    crypto::{
+        mac,
+        set_odd_parity,
+        Algorithm,
+        CryptoError,
+        Key,
+    },
+    emv::{
+        Tlv,
+        TAG_AIP,
+        TAG_AMOUNT_AUTHORISED,
+        TAG_AMOUNT_OTHER,
+        TAG_APPLICATION_CRYPTOGRAM,
+        TAG_ATC,
+        TAG_ISSUER_AUTHENTICATION_DATA,
+        TAG_PSN,
+        TAG_TERMINAL_COUNTRY_CODE,
+        TAG_TRANSACTION_CURRENCY_CODE,
+        TAG_TRANSACTION_DATE,
+        TAG_TRANSACTION_TYPE,
+        TAG_TVR,
+        TAG_UNPREDICTABLE_NUMBER,
+    },
+    iso8583::spec::{
+        MacAlgorithm,
+        MacPadding,
+    },
+};
+
+/// The data objects an ARQC is generated over, in order, as recommended by EMV
+pub const DEFAULT_ARQC_TAGS: [u32; 10] = [
+    TAG_AMOUNT_AUTHORISED,
+    TAG_AMOUNT_OTHER,
+    TAG_TERMINAL_COUNTRY_CODE,
+    TAG_TVR,
+    TAG_TRANSACTION_CURRENCY_CODE,
+    TAG_TRANSACTION_DATE,
+    TAG_TRANSACTION_TYPE,
+    TAG_UNPREDICTABLE_NUMBER,
+    TAG_AIP,
+    TAG_ATC,
+];
+
+const ARQC_LENGTH: usize = 8;
+const ATC_LENGTH: usize = 2;
+const UNPREDICTABLE_NUMBER_LENGTH: usize = 4;
+/// The digits of the PAN and PSN the card master key is derived from
+const MASTER_KEY_DIGITS: usize = 16;
+/// Method 2 ARPCs are the leading bytes of a MAC
+const METHOD_2_ARPC_LENGTH: usize = 4;
+/// Card status update byte 1 bit 8, proprietary authentication data follows the CSU
+const CSU_PROPRIETARY_DATA: u8 = 0x80;
+/// Card status update byte 2 bit 8, the issuer approves the transaction
+const CSU_APPROVED: u8 = 0x80;
+/// Response codes which approve a transaction
+const APPROVAL_CODES: [&str; 4] = ["00", "08", "10", "11"];
+
+/// How the ARPC is generated, as set in the card's profile
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ArpcMethod {
+    /// The ARQC XORed with the authorisation response code, encrypted under the session key
+    #[default]
+    Method1,
+    /// A MAC of the ARQC and the card status update under the session key
+    Method2,
+}
+
+impl fmt::Display for ArpcMethod {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ArpcMethod::Method1 => write!(f, "1"),
+            ArpcMethod::Method2 => write!(f, "2"),
+        }
+    }
+}
+
+impl FromStr for ArpcMethod {
+    type Err = EmvError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match &s.to_ascii_lowercase()[..] {
+            "1" | "method1" => Ok(ArpcMethod::Method1),
+            "2" | "method2" => Ok(ArpcMethod::Method2),
+            _ => Err(EmvError::InvalidArpcMethod(s.to_string())),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub enum EmvError {
+    InvalidArpcMethod(String),
+    /// Card master keys are only derived from double length TDES issuer master keys
+    InvalidImk,
+    InvalidPan(String),
+    InvalidPsn(String),
+    /// The response code for the ARPC isn't 2 characters
+    InvalidResponseCode(String),
+    /// A data object needed for the cryptogram isn't in the ICC data
+    MissingTag(u32),
+    /// A data object needed for the cryptogram is the wrong length
+    InvalidTag(u32),
+    Crypto(CryptoError),
+}
+
+impl fmt::Display for EmvError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::InvalidArpcMethod(method) => write!(f, "invalid ARPC method: {}", method),
+            Self::InvalidImk => write!(f, "issuer master keys must be double length TDES keys"),
+            Self::InvalidPan(pan) => write!(f, "invalid PAN: {}", pan),
+            Self::InvalidPsn(psn) => write!(f, "invalid PAN sequence number: {}", psn),
+            Self::InvalidResponseCode(code) => write!(f, "invalid response code: {}", code),
+            Self::MissingTag(tag) => write!(f, "ICC data has no tag {:02X}", tag),
+            Self::InvalidTag(tag) => write!(f, "ICC data tag {:02X} is the wrong length", tag),
+            Self::Crypto(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl error::Error for EmvError {}
+
+impl From<CryptoError> for EmvError {
+    fn from(e: CryptoError) -> Self {
+        EmvError::Crypto(e)
+    }
+}
+
+/// The card's application cryptogram master key, derived from the issuer master key by EMV option A. Only the
+/// rightmost two digits of the PSN are used, so a 3 digit field 23 can be given as it is.
+pub fn card_master_key(imk: &Key, pan: &str, psn: &str) -> Result<Key, EmvError> {
+    if imk.algorithm() != Algorithm::Tdes || imk.bytes().len() != 16 {
+        return Err(EmvError::InvalidImk);
+    }
+    if pan.is_empty() || !pan.bytes().all(|b| b.is_ascii_digit()) {
+        return Err(EmvError::InvalidPan(pan.to_string()));
+    }
+    if !psn.bytes().all(|b| b.is_ascii_digit()) {
+        return Err(EmvError::InvalidPsn(psn.to_string()));
+    }
+
+    let psn = format!("{:0>2}", &psn[psn.len().saturating_sub(2)..]);
+    let digits = format!("{}{}", pan, psn);
+    let digits = format!("{:0>16}", &digits[digits.len().saturating_sub(MASTER_KEY_DIGITS)..]);
+    let left = hex::decode(digits).expect("PAN and PSN are digits");
+    let right = left.iter().map(|b| b ^ 0xff).collect::<Vec<_>>();
+
+    let mut master_key = imk.encrypt(&left)?;
+    master_key.extend(imk.encrypt(&right)?);
+    set_odd_parity(&mut master_key);
+    Ok(Key::new(Algorithm::Tdes, &master_key)?)
+}
+
+/// The session key for a transaction by the EMV common session key derivation, from the card master key, the ATC
+/// and the unpredictable number
+pub fn session_key(master_key: &Key, atc: &[u8], unpredictable_number: &[u8]) -> Result<Key, EmvError> {
+    if atc.len() != ATC_LENGTH {
+        return Err(EmvError::InvalidTag(TAG_ATC));
+    }
+    if unpredictable_number.len() != UNPREDICTABLE_NUMBER_LENGTH {
+        return Err(EmvError::InvalidTag(TAG_UNPREDICTABLE_NUMBER));
+    }
+
+    let diversification = |half| [atc, &[half, 0], unpredictable_number].concat();
+    let mut session_key = master_key.encrypt(&diversification(0xf0))?;
+    session_key.extend(master_key.encrypt(&diversification(0x0f))?);
+    Ok(Key::new(Algorithm::Tdes, &session_key)?)
+}
+
+/// The application cryptogram of the data under the session key, a retail MAC with `80` padding
+pub fn application_cryptogram(session_key: &Key, data: &[u8]) -> Result<Vec<u8>, EmvError> {
+    Ok(mac::generate(session_key, MacAlgorithm::Alg3, MacPadding::Method2, data)?)
+}
+
+/// The method 1 ARPC, from the ARQC and the 2 character authorisation response code
+pub fn arpc_method1(session_key: &Key, arqc: &[u8], response_code: &str) -> Result<Vec<u8>, EmvError> {
+    let mut arc = response_code_bytes(response_code)?.to_vec();
+    arc.resize(ARQC_LENGTH, 0);
+    let block = arqc.iter()
+        .zip(arc)
+        .map(|(a, b)| a ^ b)
+        .collect::<Vec<_>>();
+    Ok(session_key.encrypt(&block)?)
+}
+
+/// The method 2 ARPC, from the ARQC, the card status update and any proprietary authentication data
+pub fn arpc_method2(session_key: &Key, arqc: &[u8], csu: &[u8; 4], proprietary_data: &[u8]) -> Result<Vec<u8>, EmvError> {
+    let data = [arqc, csu, proprietary_data].concat();
+    let mut arpc = application_cryptogram(session_key, &data)?;
+    arpc.truncate(METHOD_2_ARPC_LENGTH);
+    Ok(arpc)
+}
+
+/// A card status update which only says whether the issuer approved the transaction
+pub fn card_status_update(approved: bool, proprietary_data: bool) -> [u8; 4] {
+    let mut csu = [0; 4];
+    if proprietary_data {
+        csu[0] |= CSU_PROPRIETARY_DATA;
+    }
+    if approved {
+        csu[1] |= CSU_APPROVED;
+    }
+    csu
+}
+
+fn response_code_bytes(response_code: &str) -> Result<&[u8], EmvError> {
+    match response_code.as_bytes() {
+        arc @ [_, _] => Ok(arc),
+        _ => Err(EmvError::InvalidResponseCode(response_code.to_string())),
+    }
+}
+
+/// Verifies the ARQCs chip cards send in field 55 and answers them with ARPCs, as an issuer holding the issuer
+/// master key for application cryptograms (IMK-AC). The card's PSN comes from tag 5F34 if it sent it, else from the
+/// PSN given e.g. field 23, else is 00.
+#[derive(Debug, Clone)]
+pub struct Issuer {
+    imk: Key,
+    arqc_tags: Vec<u32>,
+    arpc_method: ArpcMethod,
+}
+
+impl Issuer {
+    pub fn new(imk: Key) -> Result<Self, EmvError> {
+        if imk.algorithm() != Algorithm::Tdes || imk.bytes().len() != 16 {
+            return Err(EmvError::InvalidImk);
+        }
+        Ok(Issuer {
+            imk,
+            arqc_tags: DEFAULT_ARQC_TAGS.to_vec(),
+            arpc_method: ArpcMethod::default(),
+        })
+    }
+
+    /// The data objects the cards' ARQCs are generated over, in order
+    pub fn with_arqc_tags(mut self, tags: Vec<u32>) -> Self {
+        self.arqc_tags = tags;
+        self
+    }
+
+    pub fn with_arpc_method(mut self, method: ArpcMethod) -> Self {
+        self.arpc_method = method;
+        self
+    }
+
+    pub fn arpc_method(&self) -> ArpcMethod {
+        self.arpc_method
+    }
+
+    /// The session key for the transaction in the ICC data
+    pub fn session_key(&self, pan: &str, psn: Option<&str>, icc: &Tlv) -> Result<Key, EmvError> {
+        let psn = match icc.get(TAG_PSN) {
+            Some(psn) => hex::encode(psn),
+            None => psn.unwrap_or_default().to_string(),
+        };
+        let master_key = card_master_key(&self.imk, pan, &psn)?;
+        session_key(&master_key, tag(icc, TAG_ATC)?, tag(icc, TAG_UNPREDICTABLE_NUMBER)?)
+    }
+
+    /// The ARQC the card should have sent, from the data objects in the ICC data
+    pub fn arqc(&self, pan: &str, psn: Option<&str>, icc: &Tlv) -> Result<Vec<u8>, EmvError> {
+        let session_key = self.session_key(pan, psn, icc)?;
+        let data = self.arqc_tags.iter()
+            .map(|t| tag(icc, *t))
+            .collect::<Result<Vec<_>, _>>()?
+            .concat();
+        application_cryptogram(&session_key, &data)
+    }
+
+    /// Whether the ARQC in the ICC data is the one the card should have sent
+    pub fn verify_arqc(&self, pan: &str, psn: Option<&str>, icc: &Tlv) -> Result<bool, EmvError> {
+        let arqc = tag(icc, TAG_APPLICATION_CRYPTOGRAM)?;
+        if arqc.len() != ARQC_LENGTH {
+            return Err(EmvError::InvalidTag(TAG_APPLICATION_CRYPTOGRAM));
+        }
+        Ok(self.arqc(pan, psn, icc)? == arqc)
+    }
+
+    /// The issuer authentication data (tag 91) for the response: the ARPC then, for method 1, the response code or,
+    /// for method 2, the card status update
+    pub fn issuer_authentication_data(&self, pan: &str, psn: Option<&str>, icc: &Tlv, response_code: &str) -> Result<Vec<u8>, EmvError> {
+        let session_key = self.session_key(pan, psn, icc)?;
+        let arqc = tag(icc, TAG_APPLICATION_CRYPTOGRAM)?;
+        match self.arpc_method {
+            ArpcMethod::Method1 => {
+                let arpc = arpc_method1(&session_key, arqc, response_code)?;
+                Ok([&arpc[..], response_code_bytes(response_code)?].concat())
+            },
+            ArpcMethod::Method2 => {
+                let csu = card_status_update(APPROVAL_CODES.contains(&response_code), false);
+                let arpc = arpc_method2(&session_key, arqc, &csu, &[])?;
+                Ok([&arpc[..], &csu[..]].concat())
+            },
+        }
+    }
+
+    /// The ICC data for the response, holding the issuer authentication data
+    pub fn respond(&self, pan: &str, psn: Option<&str>, icc: &Tlv, response_code: &str) -> Result<Tlv, EmvError> {
+        let mut response = Tlv::new();
+        response.set(TAG_ISSUER_AUTHENTICATION_DATA, &self.issuer_authentication_data(pan, psn, icc, response_code)?);
+        Ok(response)
+    }
+}
+
+fn tag(icc: &Tlv, tag: u32) -> Result<&[u8], EmvError> {
+    icc.get(tag).ok_or(EmvError::MissingTag(tag))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const IMK: &str = "tdes:0123456789ABCDEFFEDCBA9876543210";
+    const PAN: &str = "4761739001010010";
+    const ICC: &str = "9F02060000000010009F03060000000000009F1A020826950500000000005F2A0208269A032410199C01009F37041234567882021980\
+        9F360200019F2608481BF1B0B0F4325C5F340101";
+
+    fn icc() -> Tlv {
+        Tlv::parse(&hex::decode(ICC).unwrap()).unwrap()
+    }
+
+    fn issuer() -> Issuer {
+        Issuer::new(IMK.parse().unwrap()).unwrap()
+    }
+
+    #[test]
+    fn derives_keys() {
+        let imk = IMK.parse::<Key>().unwrap();
+        let master_key = card_master_key(&imk, PAN, "01").unwrap();
+        assert_eq!("2F02C8B0E9CBC7B05B5167F7A1CDE6E5", hex::encode_upper(master_key.bytes()));
+        assert_eq!(master_key, card_master_key(&imk, PAN, "001").unwrap());
+
+        let session_key = session_key(&master_key, &[0x00, 0x01], &[0x12, 0x34, 0x56, 0x78]).unwrap();
+        assert_eq!("391431D70E38717B740B8410630807B3", hex::encode_upper(session_key.bytes()));
+        assert_eq!(session_key, issuer().session_key(PAN, Some("07"), &icc()).unwrap());
+    }
+
+    #[test]
+    fn verifies_arqcs() {
+        let issuer = issuer();
+        let mut icc = icc();
+        assert_eq!(Ok(true), issuer.verify_arqc(PAN, None, &icc));
+        assert_eq!(Ok(false), issuer.verify_arqc("4761739001010028", None, &icc));
+
+        icc.set(TAG_AMOUNT_AUTHORISED, &[0, 0, 0, 0, 0x20, 0]);
+        assert_eq!(Ok(false), issuer.verify_arqc(PAN, None, &icc));
+        icc.remove(TAG_PSN);
+        assert_eq!(Ok(false), issuer.verify_arqc(PAN, None, &icc));
+
+        icc.remove(TAG_TVR);
+        assert_eq!(Err(EmvError::MissingTag(TAG_TVR)), issuer.verify_arqc(PAN, None, &icc));
+        let issuer = issuer.with_arqc_tags(vec![TAG_ATC]);
+        assert_eq!(Ok(false), issuer.verify_arqc(PAN, None, &icc));
+    }
+
+    macro_rules! arpc_tests {
+        ($($name:ident: $method:expr, $response_code:literal => $data:literal,)*) => {
+            $(
+                #[test]
+                fn $name() {
+                    let issuer = issuer().with_arpc_method($method);
+                    let response = issuer.respond(PAN, None, &icc(), $response_code).unwrap();
+                    assert_eq!($data, hex::encode_upper(response.get(TAG_ISSUER_AUTHENTICATION_DATA).unwrap()));
+                }
+            )*
+        };
+    }
+
+    arpc_tests!(
+        arpc_method1_approved: ArpcMethod::Method1, "00" => "7EA3F9CEFB3729AB3030",
+        arpc_method1_declined: ArpcMethod::Method1, "05" => "2395ED6B469B95D63035",
+        arpc_method2_approved: ArpcMethod::Method2, "00" => "5BE81C7B00800000",
+        arpc_method2_declined: ArpcMethod::Method2, "05" => "9EC1B96900000000",
+    );
+
+    #[test]
+    fn card_status_updates() {
+        assert_eq!([0x00, 0x80, 0x00, 0x00], card_status_update(true, false));
+        assert_eq!([0x80, 0x00, 0x00, 0x00], card_status_update(false, true));
+    }
+
+    #[test]
+    fn errors() {
+        assert_eq!(Err(EmvError::InvalidImk), Issuer::new("aes:000102030405060708090A0B0C0D0E0F".parse().unwrap()).map(|_| ()));
+        let imk = IMK.parse::<Key>().unwrap();
+        assert_eq!(Err(EmvError::InvalidPan("4761A".to_string())), card_master_key(&imk, "4761A", "00"));
+        assert_eq!(Err(EmvError::InvalidPsn("A".to_string())), card_master_key(&imk, PAN, "A"));
+        assert_eq!(Err(EmvError::InvalidTag(TAG_ATC)), session_key(&imk, &[1], &[1, 2, 3, 4]));
+        assert_eq!(Err(EmvError::InvalidResponseCode("000".to_string())), issuer().respond(PAN, None, &icc(), "000"));
+        assert_eq!(Err(EmvError::InvalidArpcMethod("3".to_string())), "3".parse::<ArpcMethod>());
+        assert_eq!(Ok(ArpcMethod::Method2), "method2".parse::<ArpcMethod>());
+    }
+}
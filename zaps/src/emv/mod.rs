@@ -0,0 +1,27 @@
+mod tlv;
+pub use tlv::{
+    Tlv,
+    TlvError,
+};
+#[cfg(feature = "crypto")]
+pub mod cryptogram;
+
+/// Application cryptogram, the ARQC in a request
+pub const TAG_APPLICATION_CRYPTOGRAM: u32 = 0x9f26;
+/// Application transaction counter
+pub const TAG_ATC: u32 = 0x9f36;
+pub const TAG_UNPREDICTABLE_NUMBER: u32 = 0x9f37;
+pub const TAG_AMOUNT_AUTHORISED: u32 = 0x9f02;
+pub const TAG_AMOUNT_OTHER: u32 = 0x9f03;
+pub const TAG_TERMINAL_COUNTRY_CODE: u32 = 0x9f1a;
+/// Terminal verification results
+pub const TAG_TVR: u32 = 0x95;
+pub const TAG_TRANSACTION_CURRENCY_CODE: u32 = 0x5f2a;
+pub const TAG_TRANSACTION_DATE: u32 = 0x9a;
+pub const TAG_TRANSACTION_TYPE: u32 = 0x9c;
+/// Application interchange profile
+pub const TAG_AIP: u32 = 0x82;
+/// PAN sequence number
+pub const TAG_PSN: u32 = 0x5f34;
+/// Issuer authentication data, the ARPC in a response
+pub const TAG_ISSUER_AUTHENTICATION_DATA: u32 = 0x91;
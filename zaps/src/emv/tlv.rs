@@ -0,0 +1,211 @@
+use std::error;
+use std::fmt;
+
+/// The tag's first byte has more tag bytes after it
+const MORE_TAG_BYTES: u8 = 0x1f;
+/// Each further tag byte is followed by another
+const ANOTHER_TAG_BYTE: u8 = 0x80;
+/// The length is in the following bytes, as many as the low bits say
+const LONG_LENGTH: u8 = 0x80;
+
+#[derive(Debug, PartialEq)]
+pub enum TlvError {
+    /// The data ends part way through the object at the offset
+    Truncated(usize),
+    /// A tag or length at the offset is longer than can be held
+    TooLong(usize),
+}
+
+impl fmt::Display for TlvError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Truncated(offset) => write!(f, "TLV data ends in the object at {}", offset),
+            Self::TooLong(offset) => write!(f, "TLV tag or length at {} is too long", offset),
+        }
+    }
+}
+
+impl error::Error for TlvError {}
+
+/// BER-TLV data objects, as EMV puts in field 55, in the order they came. Tags are numbers of their bytes e.g.
+/// `0x9f26`, and constructed objects are held as their encoded value.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Tlv {
+    objects: Vec<(u32, Vec<u8>)>,
+}
+
+impl Tlv {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn parse(data: &[u8]) -> Result<Self, TlvError> {
+        let mut objects = vec![];
+        let mut offset = 0;
+        while offset < data.len() {
+            // padding between objects
+            if data[offset] == 0x00 || data[offset] == 0xff {
+                offset += 1;
+                continue;
+            }
+            let start = offset;
+            let truncated = || TlvError::Truncated(start);
+
+            let mut tag = u32::from(data[offset]);
+            if data[offset] & MORE_TAG_BYTES == MORE_TAG_BYTES {
+                loop {
+                    offset += 1;
+                    let byte = *data.get(offset).ok_or_else(truncated)?;
+                    if tag > 0xff_ffff {
+                        return Err(TlvError::TooLong(start));
+                    }
+                    tag = tag << 8 | u32::from(byte);
+                    if byte & ANOTHER_TAG_BYTE == 0 {
+                        break;
+                    }
+                }
+            }
+            offset += 1;
+
+            let first = *data.get(offset).ok_or_else(truncated)?;
+            offset += 1;
+            let length = if first & LONG_LENGTH == 0 {
+                usize::from(first)
+            } else {
+                let count = usize::from(first & !LONG_LENGTH);
+                if count > 2 {
+                    return Err(TlvError::TooLong(start));
+                }
+                let bytes = data.get(offset..offset + count).ok_or_else(truncated)?;
+                offset += count;
+                bytes.iter().fold(0, |length, byte| length << 8 | usize::from(*byte))
+            };
+
+            let value = data.get(offset..offset + length).ok_or_else(truncated)?;
+            offset += length;
+            objects.push((tag, value.to_vec()));
+        }
+        Ok(Tlv { objects })
+    }
+
+    /// The value of the first object with the tag
+    pub fn get(&self, tag: u32) -> Option<&[u8]> {
+        self.objects.iter()
+            .find(|(t, _value)| *t == tag)
+            .map(|(_tag, value)| &value[..])
+    }
+
+    /// Replaces the value of the object with the tag, or adds it to the end
+    pub fn set(&mut self, tag: u32, value: &[u8]) {
+        match self.objects.iter_mut().find(|(t, _value)| *t == tag) {
+            Some((_tag, existing)) => *existing = value.to_vec(),
+            None => self.objects.push((tag, value.to_vec())),
+        }
+    }
+
+    pub fn remove(&mut self, tag: u32) -> Option<Vec<u8>> {
+        let index = self.objects.iter().position(|(t, _value)| *t == tag)?;
+        Some(self.objects.remove(index).1)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (u32, &[u8])> {
+        self.objects.iter()
+            .map(|(tag, value)| (*tag, &value[..]))
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.objects.is_empty()
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut out = vec![];
+        for (tag, value) in &self.objects {
+            let tag_bytes = tag.to_be_bytes();
+            let leading_zeros = tag_bytes.iter().take_while(|byte| **byte == 0).count().min(3);
+            out.extend(&tag_bytes[leading_zeros..]);
+
+            match value.len() {
+                length @ 0..=0x7f => out.push(length as u8),
+                length @ 0x80..=0xff => out.extend([LONG_LENGTH | 1, length as u8]),
+                length => out.extend([LONG_LENGTH | 2, (length >> 8) as u8, length as u8]),
+            }
+            out.extend(value);
+        }
+        out
+    }
+}
+
+/// Lists the objects as `<tag> <value>` in hex, one per line
+impl fmt::Display for Tlv {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (tag, value) in &self.objects {
+            writeln!(f, "{:02X} {}", tag, hex::encode_upper(value))?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    macro_rules! parse_tests {
+        ($($name:ident: $hex:literal => $objects:expr,)*) => {
+            $(
+                #[test]
+                fn $name() {
+                    let data = hex::decode($hex).unwrap();
+                    let tlv = Tlv::parse(&data).unwrap();
+                    let objects = tlv.iter()
+                        .map(|(tag, value)| (tag, hex::encode_upper(value)))
+                        .collect::<Vec<_>>();
+                    let expected: Vec<(u32, &str)> = $objects;
+                    assert_eq!(expected.into_iter().map(|(tag, value)| (tag, value.to_string())).collect::<Vec<_>>(), objects);
+                }
+            )*
+        };
+    }
+
+    parse_tests!(
+        parse_one_byte_tag: "820219809A03241019" => vec![(0x82, "1980"), (0x9a, "241019")],
+        parse_two_byte_tag: "9F2608A1B2C3D4E5F607089F360200A1" => vec![(0x9f26, "A1B2C3D4E5F60708"), (0x9f36, "00A1")],
+        parse_three_byte_tag: "DF81010101" => vec![(0xdf8101, "01")],
+        parse_empty_value: "9F0300" => vec![(0x9f03, "")],
+        parse_padding: "00820219800000" => vec![(0x82, "1980")],
+        parse_long_length: "9F1081800000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000"
+            => vec![(0x9f10, "0000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000")],
+    );
+
+    #[test]
+    fn round_trip() {
+        let mut tlv = Tlv::new();
+        tlv.set(0x9f26, &[1, 2, 3, 4, 5, 6, 7, 8]);
+        tlv.set(0x82, &[0x19, 0x80]);
+        tlv.set(0x9f10, &[0; 200]);
+        tlv.set(0x82, &[0x39, 0x00]);
+        assert_eq!(Some(&[0x39, 0x00][..]), tlv.get(0x82));
+
+        let bytes = tlv.to_bytes();
+        assert_eq!("9F260801020304050607088202390", &hex::encode_upper(&bytes)[..29]);
+        assert_eq!(tlv, Tlv::parse(&bytes).unwrap());
+
+        assert_eq!(Some(vec![0x39, 0x00]), tlv.remove(0x82));
+        assert_eq!(None, tlv.get(0x82));
+        assert!(!tlv.is_empty());
+    }
+
+    #[test]
+    fn errors() {
+        assert_eq!(Err(TlvError::Truncated(0)), Tlv::parse(&hex::decode("9F").unwrap()));
+        assert_eq!(Err(TlvError::Truncated(0)), Tlv::parse(&hex::decode("9F26").unwrap()));
+        assert_eq!(Err(TlvError::Truncated(3)), Tlv::parse(&hex::decode("820180950500").unwrap()));
+        assert_eq!(Err(TlvError::TooLong(0)), Tlv::parse(&hex::decode("9F83818181").unwrap()));
+        assert_eq!(Err(TlvError::TooLong(0)), Tlv::parse(&hex::decode("9F2683010000").unwrap()));
+    }
+
+    #[test]
+    fn display() {
+        let tlv = Tlv::parse(&hex::decode("820219809F360200A1").unwrap()).unwrap();
+        assert_eq!("82 1980\n9F36 00A1\n", tlv.to_string());
+    }
+}
@@ -0,0 +1,220 @@
+use std::collections::HashMap;
+use std::fmt;
+use crate::{
+    iso8583::{
+        engine::MTI_FIELD,
+        spec::{
+            DataType,
+            Field,
+            FieldType,
+            Spec,
+        },
+    },
+    util::string_to_bytes,
+};
+
+/// A tokenised message laid out for people, one field per line in field order with its name, definition, length and
+/// value, masked if sensitive. Binary fields also get a hex view.
+///
+/// The alternate form (`{:#}`) adds field descriptions from the spec.
+pub struct Dump<'a> {
+    spec: &'a Spec,
+    tokens: &'a HashMap<u16, String>,
+}
+
+struct Row<'a> {
+    field: u16,
+    name: &'a str,
+    description: Option<&'a str>,
+    definition: String,
+    length: usize,
+    value: String,
+    hex: Option<String>,
+}
+
+impl<'a> Dump<'a> {
+    pub fn new(spec: &'a Spec, tokens: &'a HashMap<u16, String>) -> Self {
+        Dump {
+            spec,
+            tokens,
+        }
+    }
+
+    fn rows(&self) -> Vec<Row<'a>> {
+        let mti_spec = self.tokens.get(&MTI_FIELD)
+            .and_then(|mti| self.spec.get_mti_spec(mti));
+
+        let mut fields = self.tokens.keys()
+            .filter(|field| **field != MTI_FIELD)
+            .collect::<Vec<_>>();
+        fields.sort();
+
+        fields.into_iter()
+            .map(|field| {
+                let value = &self.tokens[field];
+                let definition = mti_spec.and_then(|mti_spec| mti_spec.get(field));
+                let name = self.spec.get_field_name(*field);
+                let length = value.chars().count();
+                let (value, hex) = match (self.spec.get_mask(*field), definition) {
+                    (Some(mask), _) => (mask.apply(value), None),
+                    (None, Some(definition)) => (printable(value), hex_view(definition, value)),
+                    (None, None) => (printable(value), None),
+                };
+
+                Row {
+                    field: *field,
+                    name: name.map(|name| &name.name[..]).unwrap_or_default(),
+                    description: name.and_then(|name| name.description.as_deref()),
+                    definition: definition.map(describe).unwrap_or_else(|| "?".to_string()),
+                    length,
+                    value,
+                    hex,
+                }
+            })
+            .collect()
+    }
+}
+
+impl fmt::Display for Dump<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let rows = self.rows();
+        let name_width = rows.iter().map(|row| row.name.len()).max().unwrap_or_default();
+        let definition_width = rows.iter().map(|row| row.definition.len()).max().unwrap_or_default();
+        let length_width = rows.iter().map(|row| row.length.to_string().len()).max().unwrap_or_default();
+
+        write!(f, "MTI {}", self.tokens.get(&MTI_FIELD).map(|mti| &mti[..]).unwrap_or("none"))?;
+        for row in rows {
+            write!(f, "\n  {:03} ", row.field)?;
+            // specs without names shouldn't leave a gap for them
+            if name_width > 0 {
+                write!(f, "{:width$} ", row.name, width = name_width)?;
+            }
+            write!(
+                f,
+                "{:definition_width$} {:>length_width$} {}",
+                row.definition, row.length, row.value,
+                definition_width = definition_width,
+                length_width = length_width,
+            )?;
+            if let Some(hex) = row.hex {
+                write!(f, " [{}]", hex)?;
+            }
+            if let (true, Some(description)) = (f.alternate(), row.description) {
+                write!(f, "\n      {}", description)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The definition as written in specs e.g. `Fixed(6:n)`, with the range for variable fields e.g. `LLVar(n..99)`
+fn describe(field: &Field) -> String {
+    let data_type = match field.data_type {
+        DataType::Alpha => "a",
+        DataType::Alphanum => "an",
+        DataType::Binary => "bin",
+        DataType::Hex => "h",
+        DataType::Numeric => "n",
+        DataType::Packed => "packed",
+    };
+
+    match field.ftype {
+        FieldType::Fixed => format!("{}({}:{})", field.ftype, field.size, data_type),
+        FieldType::Bitmap | FieldType::AsciiBitmap => format!("{}({})", field.ftype, field.raw_size),
+        FieldType::LVar | FieldType::LLVar | FieldType::LLLVar => {
+            let max = match field.raw_size {
+                0 => 10usize.pow(field.ftype.var_size_len().unwrap_or_default() as u32) - 1,
+                max => max,
+            };
+            format!("{}({}..{})", field.ftype, data_type, max)
+        },
+    }
+}
+
+/// Bitmaps are held as bits so are shown in hex as they'd be packed, other binary fields byte by byte
+fn hex_view(field: &Field, value: &str) -> Option<String> {
+    match (&field.ftype, &field.data_type) {
+        (FieldType::Bitmap, _) | (FieldType::AsciiBitmap, _) => Some(
+            value.as_bytes()
+                .chunks(4)
+                .map(|nibble| {
+                    let bits = nibble.iter().fold(0, |acc, bit| (acc << 1) | (*bit == b'1') as u32);
+                    // a short final chunk is padded on the right as it would be on the wire
+                    let bits = bits << (4 - nibble.len());
+                    std::char::from_digit(bits, 16).unwrap_or_default().to_ascii_uppercase()
+                })
+                .collect()
+        ),
+        (_, DataType::Binary) => Some(hex::encode_upper(string_to_bytes(value).unwrap_or_else(|| value.as_bytes().to_vec()))),
+        _ => None,
+    }
+}
+
+/// Binary values can't be printed as they are so control characters are replaced with `.`
+fn printable(value: &str) -> String {
+    value.chars()
+        .map(|c| if c.is_control() { '.' } else { c })
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::iso8583::spec::FieldName;
+
+    macro_rules! describe_tests {
+        ($($name:ident: $field:literal => $expected:literal,)*) => {
+            $(
+                #[test]
+                fn $name() {
+                    assert_eq!($expected, describe(&$field.parse::<Field>().unwrap()));
+                }
+            )*
+        };
+    }
+
+    describe_tests!(
+        describe_fixed: "Fixed(6:n)" => "Fixed(6:n)",
+        describe_lvar: "LVar(an)" => "LVar(an..9)",
+        describe_llvar: "LLVar(n)" => "LLVar(n..99)",
+        describe_lllvar: "LLLVar(bin)" => "LLLVar(bin..999)",
+        describe_bitmap: "Bitmap(64)" => "Bitmap(64)",
+        describe_ascii_bitmap: "AsciiBitmap(64)" => "AsciiBitmap(64)",
+    );
+
+    #[test]
+    fn dump() {
+        let mut spec = Spec::new();
+        let fields = [(0, "AsciiBitmap(64)"), (2, "LLVar(n)"), (4, "Fixed(12:n)"), (64, "Fixed(4:bin)")]
+            .iter()
+            .map(|(k, v)| (*k, v.parse::<Field>().unwrap()))
+            .collect();
+        spec.add_mti_spec("0200".to_string(), fields);
+        spec.set_field_name(2, FieldName::new("Primary Account Number", Some("The card number")));
+        spec.set_field_name(4, FieldName::new("Amount", None));
+
+        let tokens = [
+            (MTI_FIELD, "0200"),
+            (0, "0101000000000000000000000000000000000000000000000000000000000001"),
+            (2, "4111111111111111"),
+            (4, "000000001000"),
+            (64, "\u{1}\u{2}AB"),
+        ]
+            .iter()
+            .map(|(k, v)| (*k, v.to_string()))
+            .collect();
+
+        let dump = Dump::new(&spec, &tokens);
+        assert_eq!(
+            concat!(
+                "MTI 0200\n",
+                "  000                        AsciiBitmap(64) 64 0101000000000000000000000000000000000000000000000000000000000001 [5000000000000001]\n",
+                "  002 Primary Account Number LLVar(n..99)    16 411111******1111\n",
+                "  004 Amount                 Fixed(12:n)     12 000000001000\n",
+                "  064                        Fixed(4:bin)     4 ..AB [01024142]",
+            ),
+            dump.to_string(),
+        );
+        assert!(format!("{:#}", dump).contains("411111******1111\n      The card number\n"));
+    }
+}
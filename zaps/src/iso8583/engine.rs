@@ -1,17 +1,51 @@
+use std::collections::HashMap;
+#[cfg(feature = "crypto")]
+use crate::crypto::Key;
 use crate::{
     iso8583::{
+        dump::Dump,
         spec::Spec,
     }
 };
 
+/// The key under which the MTI is held in tokenised messages
+pub const MTI_FIELD: u16 = 0xffff;
+
+/// Parses and unparses messages of a spec. With the `crypto` feature and a MAC key the engine also MACs messages, see
+/// [`Iso8583Engine::with_mac_key`].
+#[derive(Clone)]
 pub struct Iso8583Engine {
     pub(super) spec: Spec,
+    #[cfg(feature = "crypto")]
+    pub(super) mac_key: Option<Key>,
 }
 
 impl Iso8583Engine {
     pub fn new(spec: Spec) -> Self {
         Iso8583Engine{
             spec,
+            #[cfg(feature = "crypto")]
+            mac_key: None,
         }
     }
+
+    pub fn spec(&self) -> &Spec {
+        &self.spec
+    }
+
+    /// A tokenised message laid out field by field, see [`Dump`]
+    pub fn dump<'a>(&'a self, tokens: &'a HashMap<u16, String>) -> Dump<'a> {
+        Dump::new(&self.spec, tokens)
+    }
+    /// The lowest numbered field which the MTI makes mandatory but isn't in the message. Bitmaps are left out as
+    /// they're derived from the fields.
+    pub(super) fn missing_field(&self, mti: &str, fields: &HashMap<u16, String>) -> Option<u16> {
+        let mti_spec = self.spec.get_mti_spec(mti)?;
+        self.spec.get_mandatory_fields(mti)?
+            .iter()
+            .filter(|field| !fields.contains_key(field))
+            .filter(|field| !mti_spec.get(field).is_some_and(|definition| definition.ftype.is_bitmap()))
+            .min()
+            .copied()
+    }
 }
\ No newline at end of file
@@ -0,0 +1,267 @@
+use std::collections::HashMap;
+use crate::{
+    crypto::{
+        mac,
+        CryptoError,
+        Key,
+    },
+    iso8583::{
+        engine::{
+            Iso8583Engine,
+            MTI_FIELD,
+        },
+        parse::Iso8583ParseError,
+        spec::{
+            DataType,
+            Field,
+            FieldType,
+            MacInput,
+            MacSettings,
+        },
+        unparse::{
+            Iso8583UnparseError,
+            untokenise_field,
+        },
+    },
+    util::byte_to_string,
+};
+
+impl Iso8583Engine {
+    /// MACs messages as they're unparsed and checks the MACs of those parsed, as the spec's [`MacSettings`] say
+    pub fn with_mac_key(mut self, key: Key) -> Result<Self, CryptoError> {
+        let settings = self.spec.mac()
+            .ok_or_else(|| CryptoError::InvalidKey("the spec doesn't MAC messages".to_string()))?;
+        mac::check_key(&key, settings.algorithm)?;
+        self.mac_key = Some(key);
+        Ok(self)
+    }
+
+    pub fn mac_key(&self) -> Option<&Key> {
+        self.mac_key.as_ref()
+    }
+
+    /// Unparses the fields with the MAC worked out and put in the MAC field, if the MTI defines it
+    pub(super) fn unparse_with_mac(&self, key: &Key, fields: &HashMap<u16, String>, out: &mut Vec<u8>) -> Result<(), Iso8583UnparseError> {
+        let mti_spec = fields.get(&MTI_FIELD)
+            .and_then(|mti| self.spec.get_mti_spec(mti));
+        let mac_field = MacSettings::field(fields.keys().any(|field| (65..=128).contains(field)));
+        let definition = match mti_spec.and_then(|mti_spec| mti_spec.get(&mac_field)) {
+            Some(definition) => definition,
+            None => return self.unparse_fields(fields, out),
+        };
+
+        // the MAC covers the bitmap so it needs a place in the message before it can be worked out
+        let placeholder = mac_token(definition, &[0; 16])
+            .ok_or(Iso8583UnparseError::InvalidFieldDefinition)?;
+        let mut fields = fields.clone();
+        fields.insert(mac_field, placeholder);
+        let start = out.len();
+        self.unparse_fields(&fields, out)?;
+
+        let mac_start = out.len() - definition.size;
+        let data = match &self.mac_settings()?.input {
+            MacInput::Message => out[start..mac_start].to_vec(),
+            MacInput::Fields(numbers) => self.mac_fields(&fields, numbers)?,
+        };
+        let token = mac_token(definition, &self.generate_mac(key, &data)?)
+            .ok_or(Iso8583UnparseError::InvalidFieldDefinition)?;
+        out.truncate(mac_start);
+        untokenise_field(out, definition, mac_field, &token, &self.spec.charset())
+    }
+
+    /// Checks the MAC of a parsed message, if it has one. `payload` is the message up to the end of the MAC field.
+    pub(super) fn verify_mac(&self, key: &Key, tokens: &HashMap<u16, String>, payload: &[u8]) -> Result<(), Iso8583ParseError> {
+        let mti_spec = tokens.get(&MTI_FIELD)
+            .and_then(|mti| self.spec.get_mti_spec(mti));
+        let mac_field = MacSettings::field(tokens.keys().any(|field| (65..=128).contains(field)));
+        let (definition, received) = match (mti_spec.and_then(|mti_spec| mti_spec.get(&mac_field)), tokens.get(&mac_field)) {
+            (Some(definition), Some(received)) => (definition, received),
+            _ => return Ok(()),
+        };
+
+        let settings = self.mac_settings()
+            .map_err(|e| Iso8583ParseError::Mac(e.to_string()))?;
+        let data = match &settings.input {
+            MacInput::Message => payload[..payload.len() - definition.size].to_vec(),
+            MacInput::Fields(numbers) => self.mac_fields(tokens, numbers)
+                .map_err(|_e| Iso8583ParseError::InvalidFieldDefinition)?,
+        };
+        let mac = self.generate_mac(key, &data)
+            .map_err(|e| Iso8583ParseError::Mac(e.to_string()))?;
+        let expected = mac_token(definition, &mac)
+            .ok_or(Iso8583ParseError::InvalidFieldDefinition)?;
+        let matches = match definition.data_type {
+            DataType::Binary => expected == *received,
+            _ => expected.eq_ignore_ascii_case(received),
+        };
+        if !matches {
+            return Err(Iso8583ParseError::InvalidMac(mac_field));
+        }
+        Ok(())
+    }
+
+    /// The spec's MAC settings, which were checked against the key when it was set but may have been taken out since
+    fn mac_settings(&self) -> Result<&MacSettings, Iso8583UnparseError> {
+        self.spec.mac()
+            .ok_or_else(|| Iso8583UnparseError::Mac("the spec doesn't MAC messages".to_string()))
+    }
+
+    fn generate_mac(&self, key: &Key, data: &[u8]) -> Result<Vec<u8>, Iso8583UnparseError> {
+        let settings = self.mac_settings()?;
+        mac::generate(key, settings.algorithm, settings.padding, data)
+            .map_err(|e| Iso8583UnparseError::Mac(e.to_string()))
+    }
+
+    /// The serialized fields a MAC is worked out over, leaving out those not in the message
+    fn mac_fields(&self, fields: &HashMap<u16, String>, numbers: &[u16]) -> Result<Vec<u8>, Iso8583UnparseError> {
+        let mti_spec = fields.get(&MTI_FIELD)
+            .and_then(|mti| self.spec.get_mti_spec(mti))
+            .ok_or(Iso8583UnparseError::NoMtiDefinition)?;
+        let charset = self.spec.charset();
+
+        let mut data = vec![];
+        for number in numbers {
+            if let (Some(definition), Some(value)) = (mti_spec.get(number), fields.get(number)) {
+                untokenise_field(&mut data, definition, *number, value, &charset)?;
+            }
+        }
+        Ok(data)
+    }
+}
+
+/// The MAC as the value of the MAC field, its leading bytes for binary fields or else its leading hex digits. `None` if
+/// the field isn't fixed length or is too long for the MAC.
+fn mac_token(definition: &Field, mac: &[u8]) -> Option<String> {
+    if definition.ftype != FieldType::Fixed {
+        return None;
+    }
+    match definition.data_type {
+        DataType::Binary => mac.get(..definition.size).map(byte_to_string),
+        _ => hex::encode_upper(mac).get(..definition.size).map(str::to_string),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        core::{
+            Parser,
+            Unparser,
+        },
+        iso8583::spec::{
+            MacAlgorithm,
+            Spec,
+        },
+    };
+
+    const TDES_KEY: &str = "tdes:0123456789ABCDEFFEDCBA9876543210";
+
+    fn engine(mac: &str, key: &str) -> Iso8583Engine {
+        let spec = format!("
+            field 0 AsciiBitmap(64)
+            field 1 AsciiBitmap(64)
+            field 2 LLVar(n..19)
+            field 4 Fixed(12:n)
+            field 11 Fixed(6:n)
+            field 64 Fixed(8:bin)
+            field 70 Fixed(3:n)
+            field 128 Fixed(16:an)
+            mti 0200 0800
+            mti 0210
+            absent 64
+            mac {}
+        ", mac).parse::<Spec>().unwrap();
+        Iso8583Engine::new(spec).with_mac_key(key.parse().unwrap()).unwrap()
+    }
+
+    fn tokens(fields: &[(u16, &str)]) -> HashMap<u16, String> {
+        fields.iter()
+            .map(|(k, v)| (*k, v.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn message_mac() {
+        let engine = engine("retail", TDES_KEY);
+        let mut out = vec![];
+        engine.unparse(&tokens(&[(MTI_FIELD, "0200"), (2, "4111111111111111"), (4, "000000001000")]), &mut out).unwrap();
+
+        let (message, mac) = out.split_at(out.len() - 8);
+        let key = TDES_KEY.parse::<Key>().unwrap();
+        let settings = MacSettings::new(MacAlgorithm::Alg3);
+        assert_eq!(mac::generate(&key, settings.algorithm, settings.padding, message).unwrap(), mac);
+        assert_eq!(b"02005000000000000001", &message[..20]);
+
+        let parsed = engine.parse(&out).unwrap();
+        assert_eq!(byte_to_string(mac), parsed[&64]);
+    }
+
+    #[test]
+    fn secondary_bitmap_mac() {
+        let engine = engine("cmac", "aes:2B7E151628AED2A6ABF7158809CF4F3C");
+        let mut out = vec![];
+        engine.unparse(&tokens(&[(MTI_FIELD, "0800"), (11, "000001"), (70, "301")]), &mut out).unwrap();
+
+        let parsed = engine.parse(&out).unwrap();
+        assert_eq!(16, parsed[&128].len());
+        assert!(parsed[&128].bytes().all(|b| b.is_ascii_hexdigit()));
+        assert!(!parsed.contains_key(&64));
+    }
+
+    #[test]
+    fn invalid_mac() {
+        let engine = engine("alg3", TDES_KEY);
+        let mut out = vec![];
+        engine.unparse(&tokens(&[(MTI_FIELD, "0200"), (4, "000000001000")]), &mut out).unwrap();
+
+        // the amount changed on the way
+        out[31] = b'2';
+        assert_eq!(Err(Iso8583ParseError::InvalidMac(64)), engine.parse(&out));
+    }
+
+    #[test]
+    fn field_mac() {
+        let engine = engine("alg1 pad2 fields 2 4", TDES_KEY);
+        let mut out = vec![];
+        engine.unparse(&tokens(&[(MTI_FIELD, "0200"), (2, "4111111111111111"), (4, "000000001000"), (11, "000001")]), &mut out).unwrap();
+
+        // only fields 2 and 4 are covered
+        let stan = out.len() - 14;
+        out[stan] = b'9';
+        let parsed = engine.parse(&out).unwrap();
+        assert_eq!("900001", parsed[&11]);
+
+        out[25] = b'5';
+        assert_eq!(Err(Iso8583ParseError::InvalidMac(64)), engine.parse(&out));
+    }
+
+    #[test]
+    fn mti_without_mac_field() {
+        let engine = engine("alg3", TDES_KEY);
+        let mut out = vec![];
+        engine.unparse(&tokens(&[(MTI_FIELD, "0210"), (4, "000000001000")]), &mut out).unwrap();
+        assert_eq!(b"02101000000000000000000000001000".to_vec(), out);
+    }
+
+    #[test]
+    fn mac_settings_removed() {
+        let mut engine = engine("retail", TDES_KEY);
+        let mut out = vec![];
+        engine.unparse(&tokens(&[(MTI_FIELD, "0200"), (4, "000000001000")]), &mut out).unwrap();
+
+        engine.spec.set_mac(None);
+        let result = engine.unparse(&tokens(&[(MTI_FIELD, "0200"), (4, "000000001000")]), &mut vec![]);
+        assert_eq!(Err(Iso8583UnparseError::Mac("the spec doesn't MAC messages".to_string())), result);
+        assert!(matches!(engine.parse(&out), Err(Iso8583ParseError::Mac(_))));
+    }
+
+    #[test]
+    fn key_errors() {
+        let spec = "field 0 AsciiBitmap(64)".parse::<Spec>().unwrap();
+        assert!(Iso8583Engine::new(spec).with_mac_key(TDES_KEY.parse().unwrap()).is_err());
+
+        let spec = "field 0 AsciiBitmap(64)\nmac retail".parse::<Spec>().unwrap();
+        assert!(Iso8583Engine::new(spec).with_mac_key("aes:2B7E151628AED2A6ABF7158809CF4F3C".parse().unwrap()).is_err());
+    }
+}
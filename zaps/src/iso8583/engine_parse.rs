@@ -3,13 +3,17 @@ use std::collections::HashMap;
 use crate::{
     core::Parser,
     iso8583::{
-        engine::Iso8583Engine,
+        engine::{
+            Iso8583Engine,
+            MTI_FIELD,
+        },
         parse::{
             Iso8583ParseError,
             tokenise_next_bitmap,
             tokenise_next_bytes,
             tokenise_next_field,
         },
+        spec::Field,
     }
 };
 
@@ -19,12 +23,14 @@ impl Parser<u16> for Iso8583Engine {
     fn parse(&self, payload: &[u8]) -> Result<std::collections::HashMap<u16, String>, Iso8583ParseError> {
         let mut pointer = 0;
         let mut tokens = HashMap::new();
-        let mti = tokenise_next_bytes(payload, &mut pointer, 4)?
+        let charset = self.spec.charset();
+        let trim_padding = self.spec.trim_padding();
+        let mti = charset.decode(tokenise_next_bytes(payload, &mut pointer, 4)?)
             .iter()
             .map(|b| *b as char)
             .collect::<String>();
 
-        tokens.insert(0xffff, mti.clone());
+        tokens.insert(MTI_FIELD, mti.clone());
 
         let mti_spec = self.spec.get_mti_spec(&mti)
             .ok_or(Iso8583ParseError::NoMtiDefinition)?;
@@ -32,21 +38,53 @@ impl Parser<u16> for Iso8583Engine {
         let mti_pri_bitmap = mti_spec.get(&0)
             .ok_or(Iso8583ParseError::NoTokenDefinition)?;
 
-        let pri_bitmap = tokenise_next_bitmap(payload, &mut pointer, mti_pri_bitmap)?;
+        let pri_bitmap = tokenise_next_bitmap(payload, &mut pointer, mti_pri_bitmap, &charset)?;
+        tokens.insert(0, bitmap_string(pri_bitmap, mti_pri_bitmap));
 
-        let mut bitmap_str = format!("{:0b}", pri_bitmap);
-        bitmap_str.truncate(mti_pri_bitmap.raw_size);
-        tokens.insert(0, bitmap_str);
+        // field 1 is the secondary bitmap, for fields 65 to 128, if the spec defines it as one
+        let mti_sec_bitmap = mti_spec.get(&1)
+            .filter(|field| field.ftype.is_bitmap());
+        let mut sec_bitmap = 0;
 
         for i in 1..=64 {
             let bitpos = i - 1;
             if 1 & (pri_bitmap >> (63 - bitpos)) == 1 {
-                let field_value = tokenise_next_field(payload, &mut pointer, mti_spec, &i)?;
+                if let (1, Some(mti_sec_bitmap)) = (i, mti_sec_bitmap) {
+                    sec_bitmap = tokenise_next_bitmap(payload, &mut pointer, mti_sec_bitmap, &charset)?;
+                    tokens.insert(1, bitmap_string(sec_bitmap, mti_sec_bitmap));
+                    continue;
+                }
+                let field_value = tokenise_next_field(payload, &mut pointer, mti_spec, &i, &charset, trim_padding)?;
+
+                tokens.insert(i, field_value);
+            }
+        }
+
+        for i in 65..=128 {
+            let bitpos = i - 65;
+            if 1 & (sec_bitmap >> (63 - bitpos)) == 1 {
+                let field_value = tokenise_next_field(payload, &mut pointer, mti_spec, &i, &charset, trim_padding)?;
 
                 tokens.insert(i, field_value);
             }
         }
 
+        if let Some(field) = self.missing_field(&mti, &tokens) {
+            return Err(Iso8583ParseError::MissingField(field));
+        }
+
+        #[cfg(feature = "crypto")]
+        if let Some(key) = &self.mac_key {
+            self.verify_mac(key, &tokens, &payload[..pointer])?;
+        }
+
         Ok(tokens)
     }
-}
\ No newline at end of file
+}
+
+/// A bitmap as a string of bits, as many as the bitmap holds
+fn bitmap_string(bitmap: u64, bitmap_defn: &Field) -> String {
+    let mut bitmap_str = format!("{:064b}", bitmap);
+    bitmap_str.truncate(bitmap_defn.raw_size);
+    bitmap_str
+}
@@ -0,0 +1,89 @@
+use std::collections::HashMap;
+use crate::{
+    core::Unparser,
+    iso8583::{
+        engine::{
+            Iso8583Engine,
+            MTI_FIELD,
+        },
+        unparse::{
+            Iso8583UnparseError,
+            untokenise_bitmap,
+            untokenise_field,
+        },
+    }
+};
+
+impl Unparser<u16> for Iso8583Engine {
+    type Err = Iso8583UnparseError;
+
+    fn unparse(&self, fields: &HashMap<u16, String>, out: &mut Vec<u8>) -> Result<(), Iso8583UnparseError> {
+        #[cfg(feature = "crypto")]
+        if let Some(key) = &self.mac_key {
+            return self.unparse_with_mac(key, fields, out);
+        }
+        self.unparse_fields(fields, out)
+    }
+}
+
+impl Iso8583Engine {
+    pub(super) fn unparse_fields(&self, fields: &HashMap<u16, String>, out: &mut Vec<u8>) -> Result<(), Iso8583UnparseError> {
+        let mti = fields.get(&MTI_FIELD)
+            .ok_or(Iso8583UnparseError::NoMti)?;
+
+        if mti.len() != 4 {
+            return Err(Iso8583UnparseError::InvalidMti(mti.clone()));
+        }
+
+        let mti_spec = self.spec.get_mti_spec(mti)
+            .ok_or(Iso8583UnparseError::NoMtiDefinition)?;
+
+        let mti_pri_bitmap = mti_spec.get(&0)
+            .ok_or(Iso8583UnparseError::NoTokenDefinition(0))?;
+
+        if let Some(field) = self.missing_field(mti, fields) {
+            return Err(Iso8583UnparseError::MissingField(field));
+        }
+
+        // field 1 is the secondary bitmap, for fields 65 to 128, if the spec defines it as one
+        let mti_sec_bitmap = mti_spec.get(&1)
+            .filter(|field| field.ftype.is_bitmap());
+
+        let mut field_nums = fields.keys()
+            .filter(|field_num| **field_num != 0 && **field_num != MTI_FIELD)
+            .filter(|field_num| **field_num != 1 || mti_sec_bitmap.is_none())
+            .copied()
+            .collect::<Vec<_>>();
+        field_nums.sort_unstable();
+
+        let mut pri_bitmap = 0u64;
+        let mut sec_bitmap = 0u64;
+        for field_num in &field_nums {
+            match (*field_num, mti_sec_bitmap) {
+                (1..=64, _) if *field_num as usize <= mti_pri_bitmap.raw_size => pri_bitmap |= 1 << (64 - field_num),
+                (65..=128, Some(mti_sec_bitmap)) if *field_num as usize - 64 <= mti_sec_bitmap.raw_size => {
+                    sec_bitmap |= 1 << (128 - field_num);
+                },
+                _ => return Err(Iso8583UnparseError::FieldOutsideBitmap(*field_num)),
+            }
+        }
+        if sec_bitmap != 0 {
+            pri_bitmap |= 1 << 63;
+        }
+
+        let charset = self.spec.charset();
+        out.extend_from_slice(&charset.encode(mti.as_bytes()));
+        untokenise_bitmap(out, pri_bitmap, mti_pri_bitmap, &charset)?;
+        if let (Some(mti_sec_bitmap), true) = (mti_sec_bitmap, sec_bitmap != 0) {
+            untokenise_bitmap(out, sec_bitmap, mti_sec_bitmap, &charset)?;
+        }
+
+        for field_num in field_nums {
+            let field = mti_spec.get(&field_num)
+                .ok_or(Iso8583UnparseError::NoTokenDefinition(field_num))?;
+            untokenise_field(out, field, field_num, &fields[&field_num], &charset)?;
+        }
+
+        Ok(())
+    }
+}
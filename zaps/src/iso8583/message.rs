@@ -0,0 +1,308 @@
+use std::collections::{BTreeMap, HashMap};
+use std::error;
+use std::fmt;
+use std::str::FromStr;
+use base64::{
+    engine::general_purpose::STANDARD,
+    Engine,
+};
+use serde::{
+    de::{self, MapAccess, Visitor},
+    ser::SerializeMap,
+    Deserialize,
+    Deserializer,
+    Serialize,
+    Serializer,
+};
+use crate::{
+    iso8583::{
+        engine::MTI_FIELD,
+        spec::{
+            DataType,
+            Spec,
+        },
+    },
+    util::{
+        byte_to_string,
+        string_to_bytes,
+    },
+};
+
+/// The key for the MTI in serialized messages, as in the simulator's scripts and admin API
+pub const MTI_KEY: &str = "mti";
+
+/// How the values of binary fields are written in serialized messages
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum BinaryEncoding {
+    #[default]
+    Hex,
+    Base64,
+}
+
+impl BinaryEncoding {
+    fn encode(&self, value: &[u8]) -> String {
+        match self {
+            BinaryEncoding::Hex => hex::encode_upper(value),
+            BinaryEncoding::Base64 => STANDARD.encode(value),
+        }
+    }
+
+    fn decode(&self, value: &str) -> Option<Vec<u8>> {
+        match self {
+            BinaryEncoding::Hex => hex::decode(value).ok(),
+            BinaryEncoding::Base64 => STANDARD.decode(value).ok(),
+        }
+    }
+}
+
+impl fmt::Display for BinaryEncoding {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BinaryEncoding::Hex => write!(f, "hex"),
+            BinaryEncoding::Base64 => write!(f, "base64"),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub struct BinaryEncodingParseError(String);
+
+impl fmt::Display for BinaryEncodingParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "invalid binary encoding: {}", self.0)
+    }
+}
+
+impl error::Error for BinaryEncodingParseError {}
+
+impl FromStr for BinaryEncoding {
+    type Err = BinaryEncodingParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "hex" => Ok(BinaryEncoding::Hex),
+            "base64" => Ok(BinaryEncoding::Base64),
+            _ => Err(BinaryEncodingParseError(s.to_string())),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub enum MessageError {
+    /// A binary field's value wasn't in the encoding
+    InvalidBinary(u16),
+}
+
+impl fmt::Display for MessageError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::InvalidBinary(field) => write!(f, "invalid binary value for field {}", field),
+        }
+    }
+}
+
+impl error::Error for MessageError {}
+
+/// A tokenised message in a stable form for serializing, which in JSON is an object with the MTI under `mti` then the
+/// fields keyed by number in field order, every value a string so that leading zeros aren't lost e.g.
+///
+/// ```json
+/// {"mti": "0200", "2": "4111111111111111", "4": "000000001000", "52": "1234567890ABCDEF"}
+/// ```
+///
+/// Binary field values are written in a [`BinaryEncoding`]. Bitmaps are left out as they're worked out from the
+/// fields.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Message {
+    pub mti: Option<String>,
+    pub fields: BTreeMap<u16, String>,
+}
+
+impl Message {
+    /// The message from tokens parsed with the spec
+    pub fn from_tokens(spec: &Spec, tokens: &HashMap<u16, String>, encoding: BinaryEncoding) -> Self {
+        let mti = tokens.get(&MTI_FIELD).cloned();
+        let mti_spec = mti.as_deref().and_then(|mti| spec.get_mti_spec(mti));
+        let fields = tokens.iter()
+            .filter(|(field, _value)| **field != MTI_FIELD)
+            .filter_map(|(field, value)| {
+                let definition = mti_spec.and_then(|mti_spec| mti_spec.get(field));
+                match definition {
+                    Some(definition) if definition.ftype.is_bitmap() => None,
+                    Some(definition) if definition.data_type == DataType::Binary => {
+                        let value = string_to_bytes(value).unwrap_or_else(|| value.as_bytes().to_vec());
+                        Some((*field, encoding.encode(&value)))
+                    },
+                    _ => Some((*field, value.clone())),
+                }
+            })
+            .collect();
+
+        Message {
+            mti,
+            fields,
+        }
+    }
+
+    /// The tokens to unparse with the spec
+    pub fn to_tokens(&self, spec: &Spec, encoding: BinaryEncoding) -> Result<HashMap<u16, String>, MessageError> {
+        let mti_spec = self.mti.as_deref().and_then(|mti| spec.get_mti_spec(mti));
+        let mut tokens = self.fields.iter()
+            .map(|(field, value)| {
+                let definition = mti_spec.and_then(|mti_spec| mti_spec.get(field));
+                let value = match definition {
+                    Some(definition) if definition.data_type == DataType::Binary && !definition.ftype.is_bitmap() => {
+                        encoding.decode(value)
+                            .map(|value| byte_to_string(&value))
+                            .ok_or(MessageError::InvalidBinary(*field))?
+                    },
+                    _ => value.clone(),
+                };
+                Ok((*field, value))
+            })
+            .collect::<Result<HashMap<_, _>, _>>()?;
+        if let Some(mti) = &self.mti {
+            tokens.insert(MTI_FIELD, mti.clone());
+        }
+        Ok(tokens)
+    }
+
+    /// Masks the sensitive fields, after any binary values are encoded so that they're masked the same
+    pub fn mask(&mut self, spec: &Spec) {
+        for (field, value) in self.fields.iter_mut() {
+            if let Some(mask) = spec.get_mask(*field) {
+                *value = mask.apply(value);
+            }
+        }
+    }
+}
+
+impl Serialize for Message {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut map = serializer.serialize_map(Some(self.fields.len() + self.mti.iter().count()))?;
+        if let Some(mti) = &self.mti {
+            map.serialize_entry(MTI_KEY, mti)?;
+        }
+        for (field, value) in &self.fields {
+            map.serialize_entry(&field.to_string(), value)?;
+        }
+        map.end()
+    }
+}
+
+impl<'de> Deserialize<'de> for Message {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserializer.deserialize_map(MessageVisitor)
+    }
+}
+
+struct MessageVisitor;
+
+impl<'de> Visitor<'de> for MessageVisitor {
+    type Value = Message;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "a map of field numbers, and `{}`, to strings", MTI_KEY)
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<Self::Value, A::Error> {
+        let mut message = Message::default();
+        while let Some(key) = map.next_key::<String>()? {
+            let value = map.next_value::<String>()?;
+            match &key[..] {
+                MTI_KEY => message.mti = Some(value),
+                field => {
+                    let field = field.parse::<u16>()
+                        .map_err(|_e| de::Error::custom(format!("invalid field {}", field)))?;
+                    message.fields.insert(field, value);
+                },
+            }
+        }
+        Ok(message)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn spec() -> Spec {
+        "
+            field 0 AsciiBitmap(64)
+            field 2 LLVar(n..19)
+            field 52 Fixed(8:bin)
+            mti 0200
+        ".parse().unwrap()
+    }
+
+    fn tokens() -> HashMap<u16, String> {
+        [(MTI_FIELD, "0200"), (0, "0100000000000000000000000000000000000000000000000001000000000000"), (2, "4111111111111111"), (52, "PINBLOCK")]
+            .iter()
+            .map(|(k, v)| (*k, v.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn json() {
+        let message = Message::from_tokens(&spec(), &tokens(), BinaryEncoding::Hex);
+        let json = r#"{"mti":"0200","2":"4111111111111111","52":"50494E424C4F434B"}"#;
+
+        assert_eq!(json, serde_json::to_string(&message).unwrap());
+        assert_eq!(message, serde_json::from_str(json).unwrap());
+    }
+
+    #[test]
+    fn round_trip() {
+        for encoding in &[BinaryEncoding::Hex, BinaryEncoding::Base64] {
+            let message = Message::from_tokens(&spec(), &tokens(), *encoding);
+            let mut expected = tokens();
+            expected.remove(&0);
+            assert_eq!(Ok(expected), message.to_tokens(&spec(), *encoding));
+        }
+    }
+
+    #[test]
+    fn base64() {
+        let message = Message::from_tokens(&spec(), &tokens(), BinaryEncoding::Base64);
+        assert_eq!("UElOQkxPQ0s=", message.fields[&52]);
+    }
+
+    #[test]
+    fn mask() {
+        let mut message = Message::from_tokens(&spec(), &tokens(), BinaryEncoding::Hex);
+        message.mask(&spec());
+        assert_eq!("411111******1111", message.fields[&2]);
+        assert_eq!("****************", message.fields[&52]);
+    }
+
+    #[test]
+    fn invalid_binary() {
+        let message = serde_json::from_str::<Message>(r#"{"mti": "0200", "52": "PINBLOCK"}"#).unwrap();
+        assert_eq!(Err(MessageError::InvalidBinary(52)), message.to_tokens(&spec(), BinaryEncoding::Hex));
+    }
+
+    macro_rules! deserialize_error_tests {
+        ($($name:ident: $json:literal => $expected:literal,)*) => {
+            $(
+                #[test]
+                fn $name() {
+                    let err = serde_json::from_str::<Message>($json).unwrap_err();
+                    assert!(err.to_string().starts_with($expected), "{}", err);
+                }
+            )*
+        };
+    }
+
+    deserialize_error_tests!(
+        error_field: r#"{"x": "1"}"# => "invalid field x",
+        error_value: r#"{"4": 1000}"# => "invalid type: integer `1000`, expected a string",
+        error_not_map: r#"["0200"]"# => "invalid type: sequence, expected a map",
+    );
+
+    #[test]
+    fn binary_encoding() {
+        assert_eq!(Ok(BinaryEncoding::Base64), "base64".parse());
+        assert_eq!("hex", BinaryEncoding::Hex.to_string());
+        assert!("base32".parse::<BinaryEncoding>().is_err());
+    }
+}
@@ -1,11 +1,32 @@
 extern crate hex;
+mod dump;
+pub use dump::Dump;
 mod engine;
 pub use engine::{
     Iso8583Engine,
+    MTI_FIELD,
 };
+#[cfg(feature = "crypto")]
+mod engine_mac;
 mod engine_parse;
+mod engine_unparse;
+#[cfg(feature = "serde")]
+mod message;
+#[cfg(feature = "serde")]
+pub use message::{
+    BinaryEncoding,
+    BinaryEncodingParseError,
+    Message,
+    MessageError,
+    MTI_KEY,
+};
 mod parse;
 pub use parse::{
     Iso8583ParseError,
 };
-pub mod spec;
\ No newline at end of file
+pub mod spec;
+pub mod translate;
+mod unparse;
+pub use unparse::{
+    Iso8583UnparseError,
+};
@@ -7,6 +7,7 @@ use std::str;
 use crate::{
     util::{
         byte_to_string,
+        Charset,
         decode_ascii_bitmap,
         decode_bitmap,
         DecodeBitmapError,
@@ -14,6 +15,7 @@ use crate::{
     iso8583::spec::{
         DataType,
         Field,
+        FieldType,
     }
 };
 
@@ -30,6 +32,30 @@ pub enum Iso8583ParseError {
     NoMtiDefinition,
     InvalidFieldDefinition,
     BadBitmap(DecodeBitmapError),
+    /// A field the MTI's spec makes mandatory wasn't in the message
+    MissingField(u16),
+    /// The MAC in the field isn't that of the message
+    InvalidMac(u16),
+    /// The MAC couldn't be worked out to check it, e.g. as the spec no longer has MAC settings
+    Mac(String),
+}
+
+impl Iso8583ParseError {
+    /// The name of the variant e.g. `Overflow`, to group errors by without their details
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Self::Overflow{ .. } => "Overflow",
+            Self::InvalidVarLength(_) => "InvalidVarLength",
+            Self::InvalidData(_) => "InvalidData",
+            Self::NoTokenDefinition => "NoTokenDefinition",
+            Self::NoMtiDefinition => "NoMtiDefinition",
+            Self::InvalidFieldDefinition => "InvalidFieldDefinition",
+            Self::BadBitmap(_) => "BadBitmap",
+            Self::MissingField(_) => "MissingField",
+            Self::InvalidMac(_) => "InvalidMac",
+            Self::Mac(_) => "Mac",
+        }
+    }
 }
 
 impl fmt::Display for Iso8583ParseError {
@@ -42,6 +68,9 @@ impl fmt::Display for Iso8583ParseError {
             Self::NoMtiDefinition => write!(f, "no MTI definition found"),
             Self::InvalidFieldDefinition => write!(f, "invalid field definition"),
             Self::BadBitmap(_bitmap_err) => write!(f, "invalid bitmap"),
+            Self::MissingField(field) => write!(f, "mandatory field {} missing", field),
+            Self::InvalidMac(field) => write!(f, "MAC in field {} doesn't match the message", field),
+            Self::Mac(reason) => write!(f, "unable to check the MAC: {}", reason),
         }
     }
 }
@@ -61,7 +90,7 @@ impl From<DecodeBitmapError> for Iso8583ParseError {
     }
 }
 
-pub fn tokenise_next_bitmap(payload: &[u8], pointer: &mut usize, bitmap_defn: &Field) -> Result<u64, Iso8583ParseError> {
+pub fn tokenise_next_bitmap(payload: &[u8], pointer: &mut usize, bitmap_defn: &Field, charset: &Charset) -> Result<u64, Iso8583ParseError> {
     let Field{ size, data_type, .. } = bitmap_defn;
 
     let raw_bitmap = tokenise_next_bytes(payload, pointer, *size)?;
@@ -71,7 +100,7 @@ pub fn tokenise_next_bitmap(payload: &[u8], pointer: &mut usize, bitmap_defn: &F
             decode_bitmap(raw_bitmap, *size)?
         },
         DataType::Packed => {
-            decode_ascii_bitmap(raw_bitmap, *size)?
+            decode_ascii_bitmap(&charset.decode(raw_bitmap), *size)?
         },
         _ => {
             return Err(Iso8583ParseError::InvalidFieldDefinition)
@@ -81,19 +110,29 @@ pub fn tokenise_next_bitmap(payload: &[u8], pointer: &mut usize, bitmap_defn: &F
     Ok(bitmap)
 }
 
-pub fn tokenise_next_field(payload: &[u8], pointer: &mut usize, mti_spec: &HashMap<u16, Field>, field_num: &u16) -> Result<String, Iso8583ParseError> {
+/// The next field's value, without its padding if `trim_padding`
+pub fn tokenise_next_field(payload: &[u8], pointer: &mut usize, mti_spec: &HashMap<u16, Field>, field_num: &u16, charset: &Charset, trim_padding: bool) -> Result<String, Iso8583ParseError> {
     let field = mti_spec.get(field_num)
         .ok_or(Iso8583ParseError::NoTokenDefinition)?;
     
-    let field_size = get_field_length(payload, pointer, field)?;
+    let field_size = get_field_length(payload, pointer, field, charset)?;
 
     let field_value_raw = tokenise_next_bytes(payload, pointer, field_size)?;
+    // binary values are held a char per byte as they needn't be text
+    let field_value = match field.data_type {
+        DataType::Binary => byte_to_string(field_value_raw),
+        _ => {
+            let field_value_raw = charset.decode(field_value_raw);
+            str::from_utf8(&field_value_raw)
+                .map_err(|_err| Iso8583ParseError::InvalidData(field_value_raw.to_vec()))?
+                .to_string()
+        },
+    };
 
-    let field_value = str::from_utf8(field_value_raw)
-        .map_err(|_err| Iso8583ParseError::InvalidData(Vec::from(field_value_raw)))?
-        .to_string();
-
-    Ok(field_value)
+    match (&field.ftype, &field.padding) {
+        (FieldType::Fixed, Some(padding)) if trim_padding => Ok(padding.trim(&field_value).to_string()),
+        _ => Ok(field_value),
+    }
 }
 
 pub fn tokenise_next_bytes<'a>(payload: &'a[u8], pointer: &mut usize, size: usize) -> Result<&'a [u8], Iso8583ParseError> {
@@ -111,15 +150,15 @@ pub fn tokenise_next_bytes<'a>(payload: &'a[u8], pointer: &mut usize, size: usiz
     Ok(result)
 }
 
-fn get_field_length(payload: &[u8], pointer: &mut usize, field: &Field) -> Result<usize, Iso8583ParseError> {
+fn get_field_length(payload: &[u8], pointer: &mut usize, field: &Field, charset: &Charset) -> Result<usize, Iso8583ParseError> {
     let field_length = match field.ftype.var_size_len() {
         Some(field_size_len) => {
-            let field_size_str = tokenise_next_bytes(payload, pointer, field_size_len)?;
+            let field_size_str = charset.decode(tokenise_next_bytes(payload, pointer, field_size_len)?);
             // TODO use a simpler, more efficient (maybe handwritten?) process
-            str::from_utf8(field_size_str)
-                .map_err(|_err| Iso8583ParseError::InvalidData(Vec::from(field_size_str)))?
+            str::from_utf8(&field_size_str)
+                .map_err(|_err| Iso8583ParseError::InvalidData(field_size_str.to_vec()))?
                 .parse::<usize>()
-                .map_err(|_err| Iso8583ParseError::InvalidVarLength(byte_to_string(field_size_str)))?
+                .map_err(|_err| Iso8583ParseError::InvalidVarLength(byte_to_string(&field_size_str)))?
         }
         None => field.size,
     };
@@ -130,7 +169,13 @@ fn get_field_length(payload: &[u8], pointer: &mut usize, field: &Field) -> Resul
 #[cfg(test)]
 mod test {
     use super::*;
-    use crate::iso8583::spec::FieldType;
+
+    #[test]
+    fn error_kinds() {
+        assert_eq!("Overflow", Iso8583ParseError::Overflow{ from: 0, count: 1, max: 0 }.kind());
+        assert_eq!("BadBitmap", Iso8583ParseError::BadBitmap(DecodeBitmapError::OddLength).kind());
+        assert_eq!("Mac", Iso8583ParseError::Mac("no MAC settings".to_string()).kind());
+    }
 
     mod get_field_length {
         use super::*;
@@ -147,7 +192,7 @@ mod test {
                     fn $name() {
                         let field = Field::new(FieldType::$field_type, $field_size, DataType::Alpha);
                         let mut pointer = 0;
-                        let result = get_field_length($payload.as_bytes(), &mut pointer, &field).unwrap();
+                        let result = get_field_length($payload.as_bytes(), &mut pointer, &field, &Charset::Ascii).unwrap();
                         assert_eq!($expected, result);
                         assert_eq!($expected_p, pointer);
                     }
@@ -166,8 +211,8 @@ mod test {
     mod tokenise_next_bytes {
         use super::*;
 
-        const PAYLOAD: &'static str = "1234567890abcdeffedbca0987654321";
-        const PAYLOAD_EMPTY: &'static str = "";
+        const PAYLOAD: &str = "1234567890abcdeffedbca0987654321";
+        const PAYLOAD_EMPTY: &str = "";
 
         macro_rules! test_tokenise_next_bytes {
             ($(
@@ -1,11 +1,31 @@
 #[macro_export]
 macro_rules! iso8583_field_build {
+    // padded with the data type's default padding
+    ($field_type:ident $field_size:literal $field_data:ident pad) => {{
+        use $crate::iso8583::spec::{
+            DataType,
+            Padding,
+        };
+
+        $crate::iso8583_field_build!($field_type $field_size DataType::$field_data)
+            .with_padding(Padding::default_for(&DataType::$field_data))
+    }};
+    // padded e.g. pad(Left, '\u{ff}')
+    ($field_type:ident $field_size:literal $field_data:ident pad($justification:ident, $fill:literal)) => {{
+        use $crate::iso8583::spec::{
+            Justification,
+            Padding,
+        };
+
+        $crate::iso8583_field_build!($field_type $field_size $field_data)
+            .with_padding(Padding::new(Justification::$justification, $fill))
+    }};
     ($field_type:ident $field_size:literal $field_data:ident) => {{
         use $crate::iso8583::spec::{
             DataType,
         };
 
-        iso8583_field_build!($field_type $field_size DataType::$field_data)
+        $crate::iso8583_field_build!($field_type $field_size DataType::$field_data)
     }};
     ($field_type:ident $field_size:literal $field_data:path) => {{
         use $crate::iso8583::spec::{
@@ -19,21 +39,23 @@ macro_rules! iso8583_field_build {
 
 #[macro_export]
 macro_rules! iso8583_spec_build {
+    // padded field
+    (@build $spec:ident $mti:literal => $mti_spec:ident $field_num:literal: $field_type:ident, $field_size:literal, $field_data:ident, pad $(($($padding:tt)*))?; $($rest:tt)*) => {{
+        let field = $crate::iso8583_field_build!($field_type $field_size $field_data pad $(($($padding)*))?);
+        $mti_spec.insert($field_num, field);
+        $crate::iso8583_spec_build!(@build $spec $mti => $mti_spec $($rest)*);
+    }};
     // fully defined field
     (@build $spec:ident $mti:literal => $mti_spec:ident $field_num:literal: $field_type:ident, $field_size:literal, $field_data:ident; $($rest:tt)*) => {{
-        use $crate::iso8583_field_build;
-
-        let field = iso8583_field_build!($field_type $field_size $field_data);
+        let field = $crate::iso8583_field_build!($field_type $field_size $field_data);
         $mti_spec.insert($field_num, field);
-        iso8583_spec_build!(@build $spec $mti => $mti_spec $($rest)*);
+        $crate::iso8583_spec_build!(@build $spec $mti => $mti_spec $($rest)*);
     }};
     // variable length field (LLVar, etc.)
     (@build $spec:ident $mti:literal => $mti_spec:ident $field_num:literal: $field_type:ident, $field_data:ident; $($rest:tt)*) => {{
-        use $crate::iso8583_field_build;
-
-        let field = iso8583_field_build!($field_type 0 $field_data);
+        let field = $crate::iso8583_field_build!($field_type 0 $field_data);
         $mti_spec.insert($field_num, field);
-        iso8583_spec_build!(@build $spec $mti => $mti_spec $($rest)*);
+        $crate::iso8583_spec_build!(@build $spec $mti => $mti_spec $($rest)*);
     }};
     // data specific field (LLVar, etc.)
     (@build $spec:ident $mti:literal => $mti_spec:ident $field_num:literal: $field_type:ident, $field_size:literal; $($rest:tt)*) => {{
@@ -50,43 +72,41 @@ macro_rules! iso8583_spec_build {
         };
         let field = Field::new(FieldType::$field_type, $field_size, field_data);
         $mti_spec.insert($field_num, field);
-        iso8583_spec_build!(@build $spec $mti => $mti_spec $($rest)*);
+        $crate::iso8583_spec_build!(@build $spec $mti => $mti_spec $($rest)*);
     }};
     // new MTI block
     (@build $spec:ident $mti:literal => $mti_spec:ident $next_mti:literal: $($rest:tt)*) => {{
         use std::collections::HashMap;
 
-        $spec.add_mti_spec($mti.to_string(), $mti_spec);
+        $spec.extend_mti_spec($mti.to_string(), $mti_spec);
         let mut mti_spec: HashMap<u16, Field> = HashMap::new();
-        iso8583_spec_build!(@build $spec $next_mti => mti_spec $($rest)*);
+        $crate::iso8583_spec_build!(@build $spec $next_mti => mti_spec $($rest)*);
     }};
     // exitpoint
     (@build $spec:ident $mti:literal => $mti_spec:ident) => {{
-        $spec.add_mti_spec($mti.to_string(), $mti_spec);
+        $spec.extend_mti_spec($mti.to_string(), $mti_spec);
     }};
-    // entrypoint
-    ($first_mti:literal: $($rest:tt)*) => {{
+    // entrypoint extending another spec, whose MTIs get the fields listed as overrides
+    (extends $base:expr; $first_mti:literal: $($rest:tt)*) => {{
         use $crate::iso8583::spec::{
             Field,
             Spec,
         };
         use std::collections::HashMap;
 
-        let mut spec = Spec::new();
+        let mut spec: Spec = $base.clone();
         let mut mti_spec: HashMap<u16, Field> = HashMap::new();
-        iso8583_spec_build!(@build spec $first_mti => mti_spec $($rest)*);
+        $crate::iso8583_spec_build!(@build spec $first_mti => mti_spec $($rest)*);
         spec
     }};
+    // entrypoint
+    ($first_mti:literal: $($rest:tt)*) => {{
+        $crate::iso8583_spec_build!(extends $crate::iso8583::spec::Spec::new(); $first_mti: $($rest)*)
+    }};
 }
 
 #[cfg(test)]
 mod test {
-    // can't use is08583_use! directly here so just copy
-    use crate::{
-        iso8583_spec_build,
-        iso8583_field_build,
-    };
-
     #[test]
     fn test() {
         let spec2 = iso8583_spec_build!{
@@ -105,4 +125,54 @@ mod test {
         };
         println!("{:#?}", spec2);
     }
+
+    #[test]
+    fn padding() {
+        use crate::iso8583::spec::{
+            DataType,
+            Field,
+            FieldType,
+            Justification,
+            Padding,
+        };
+
+        let spec = iso8583_spec_build!{
+            "0200":
+                0: Bitmap, 64;
+                4: Fixed, 12, Numeric, pad;
+                52: Fixed, 8, Binary, pad(Left, '\u{ff}');
+        };
+
+        let spec_0200 = spec.get_mti_spec("0200").unwrap();
+        assert_eq!(Some(Padding::new(Justification::Right, '0')), spec_0200[&4].padding);
+        assert_eq!(Field::new(FieldType::Fixed, 8, DataType::Binary).with_padding(Padding::new(Justification::Left, '\u{ff}')), spec_0200[&52]);
+    }
+
+    #[test]
+    fn extends() {
+        use crate::iso8583::spec::{
+            DataType,
+            Dictionary,
+            Field,
+            FieldType,
+            Spec,
+        };
+
+        let base = Spec::standard(Dictionary::Iso1987, &["0200", "0210"]);
+        let spec = iso8583_spec_build!{
+            extends base;
+            "0200":
+                0: AsciiBitmap, 64;
+            "04x0":
+                0: AsciiBitmap, 64;
+        };
+
+        let spec_0200 = spec.get_mti_spec("0200").unwrap();
+        assert_eq!(129, spec_0200.len());
+        assert_eq!(Field::new(FieldType::AsciiBitmap, 64, DataType::Packed), spec_0200[&0]);
+        assert_eq!(Field::new(FieldType::Bitmap, 64, DataType::Binary), spec.get_mti_spec("0210").unwrap()[&0]);
+        assert_eq!(129, spec.get_mti_spec("0420").unwrap().len());
+        assert_eq!(None, spec.get_mti_spec("0800"));
+        assert_eq!(2, base.get_mti_specs().len());
+    }
 }
\ No newline at end of file
@@ -1,3 +1,4 @@
+use std::borrow::Cow;
 use std::str::FromStr;
 use std::fmt;
 
@@ -7,10 +8,27 @@ pub enum FieldParseError {
     InvalidFormat(String),
     InvalidType(String),
     InvalidLength(String),
+    InvalidPadding(String),
     JunkTrail(String),
 }
 
-#[derive(Debug, PartialEq)]
+impl fmt::Display for FieldParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::InvalidDataType(s) => write!(f, "invalid data type: {}", s),
+            Self::InvalidFormat(s) => write!(f, "invalid format: {}", s),
+            Self::InvalidType(s) => write!(f, "invalid field type: {}", s),
+            Self::InvalidLength(s) => write!(f, "invalid length: {}", s),
+            Self::InvalidPadding(s) => write!(f, "invalid padding: {}", s),
+            Self::JunkTrail(s) => write!(f, "unexpected trailing characters: {}", s),
+        }
+    }
+}
+
+impl std::error::Error for FieldParseError {}
+
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum DataType {
     Alpha,
     Alphanum,
@@ -20,6 +38,20 @@ pub enum DataType {
     Packed,
 }
 
+impl DataType {
+    /// Whether a value only holds characters of this type. Alphanumeric fields accept special characters and spaces as
+    /// most specs' "ans" fields do.
+    pub fn is_valid(&self, value: &str) -> bool {
+        match self {
+            DataType::Alpha => value.chars().all(|c| c.is_ascii_alphabetic() || c == ' '),
+            DataType::Alphanum => value.chars().all(|c| c.is_ascii_graphic() || c == ' '),
+            DataType::Binary => true,
+            DataType::Hex | DataType::Packed => value.chars().all(|c| c.is_ascii_hexdigit()),
+            DataType::Numeric => value.chars().all(|c| c.is_ascii_digit()),
+        }
+    }
+}
+
 impl fmt::Display for DataType {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         fmt::Debug::fmt(self, f)
@@ -42,7 +74,8 @@ impl FromStr for DataType {
     }
 }
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum FieldType {
     Fixed,
     LVar,
@@ -53,6 +86,10 @@ pub enum FieldType {
 }
 
 impl FieldType {
+    pub fn is_bitmap(&self) -> bool {
+        matches!(self, FieldType::Bitmap | FieldType::AsciiBitmap)
+    }
+
     pub fn var_size_len(&self) -> Option<usize> {
         match self {
             FieldType::LLLVar => Some(3),
@@ -85,22 +122,126 @@ impl FromStr for FieldType {
     }
 }
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Justification {
+    Left,
+    Right,
+}
+
+impl fmt::Display for Justification {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Justification::Left => write!(f, "left"),
+            Justification::Right => write!(f, "right"),
+        }
+    }
+}
+
+impl FromStr for Justification {
+    type Err = FieldParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match &s.to_ascii_lowercase()[..] {
+            "left" | "l" => Ok(Justification::Left),
+            "right" | "r" => Ok(Justification::Right),
+            _ => Err(FieldParseError::InvalidPadding(s.to_string())),
+        }
+    }
+}
+
+/// How a fixed field's value shorter than the field is filled out, justified to one side with the fill char on the
+/// other. Binary fields' fill is a char per byte as their values are, e.g. `'\u{ff}'`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Padding {
+    pub justification: Justification,
+    pub fill: char,
+}
+
+impl Padding {
+    pub fn new(justification: Justification, fill: char) -> Self {
+        Padding {
+            justification,
+            fill,
+        }
+    }
+
+    /// The usual padding of a data type: numbers right justified with zeros, binary left justified with 0x00 and
+    /// text left justified with spaces
+    pub fn default_for(data_type: &DataType) -> Self {
+        match data_type {
+            DataType::Numeric | DataType::Hex | DataType::Packed => Padding::new(Justification::Right, '0'),
+            DataType::Binary => Padding::new(Justification::Left, '\u{0}'),
+            DataType::Alpha | DataType::Alphanum => Padding::new(Justification::Left, ' '),
+        }
+    }
+
+    /// Whether the fill is a character of the data type. Only binary fields can be filled with bytes outside ASCII, as
+    /// the rest are encoded a character to a byte.
+    pub fn fits(&self, data_type: &DataType) -> bool {
+        match data_type {
+            DataType::Binary => self.fill <= '\u{ff}',
+            _ => data_type.is_valid(self.fill.encode_utf8(&mut [0; 4])),
+        }
+    }
+
+    /// The value filled out to `size` chars, or as it is if it's already that long
+    pub fn pad<'a>(&self, value: &'a str, size: usize) -> Cow<'a, str> {
+        let length = value.chars().count();
+        if length >= size {
+            return Cow::Borrowed(value);
+        }
+        let fill = std::iter::repeat_n(self.fill, size - length);
+        match self.justification {
+            Justification::Left => Cow::Owned(value.chars().chain(fill).collect()),
+            Justification::Right => Cow::Owned(fill.chain(value.chars()).collect()),
+        }
+    }
+
+    /// The value without the fill on its padded side. A value of nothing but fill trims to nothing, which pads back
+    /// to the same value.
+    pub fn trim<'a>(&self, value: &'a str) -> &'a str {
+        match self.justification {
+            Justification::Left => value.trim_end_matches(self.fill),
+            Justification::Right => value.trim_start_matches(self.fill),
+        }
+    }
+}
+
+/// As in field definitions e.g. `justify=right,fill=0`, with fills that would be hard to read or would end the
+/// definition as two hex digits e.g. `fill=20` for a space
+impl fmt::Display for Padding {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "justify={},fill=", self.justification)?;
+        match self.fill {
+            fill if fill.is_ascii_graphic() && !matches!(fill, ',' | '(' | ')') => write!(f, "{}", fill),
+            fill => write!(f, "{:02X}", fill as u32),
+        }
+    }
+}
+
+/// Serialized without `size`, which is worked out from the rest as in [`Field::new`]
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize), serde(from = "FieldData", into = "FieldData"))]
 pub struct Field {
     pub ftype: FieldType,
     /// The logical size of the field e.g. for a binary bitmap with 8 bits this would be 8.
+    /// For variable length fields this is the maximum length, or 0 for as long as the length indicator allows.
     /// In general size should be preferred.
     pub raw_size: usize,
     /// The actual size of a the field e.g. for a binary bitmap with 8 bits this would be 1.
     pub size: usize,
     pub data_type: DataType,
+    /// How fixed fields are padded as they're built, and trimmed as they're parsed if the spec trims padding
+    pub padding: Option<Padding>,
 }
 
 impl Field {
     pub fn new(ftype: FieldType, raw_size: usize, data_type: DataType) -> Self {
         let size = match ftype {
-            FieldType::AsciiBitmap => (raw_size + 3) / 4,
-            FieldType::Bitmap => (raw_size + 7) / 8,
+            FieldType::AsciiBitmap => raw_size.div_ceil(4),
+            FieldType::Bitmap => raw_size.div_ceil(8),
             _ => raw_size,
         };
         Field {
@@ -108,13 +249,56 @@ impl Field {
             raw_size,
             size,
             data_type,
+            padding: None,
+        }
+    }
+
+    /// Pads the field's values. Only fixed fields can be padded, with a fill which [`Padding::fits`] their data type,
+    /// which [`Spec::validate`](crate::iso8583::spec::Spec::validate) checks as definitions parsed from text are.
+    pub fn with_padding(mut self, padding: Padding) -> Self {
+        self.padding = Some(padding);
+        self
+    }
+}
+
+#[cfg(feature = "serde")]
+#[derive(serde::Serialize, serde::Deserialize)]
+struct FieldData {
+    ftype: FieldType,
+    raw_size: usize,
+    data_type: DataType,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    padding: Option<Padding>,
+}
+
+#[cfg(feature = "serde")]
+impl From<FieldData> for Field {
+    fn from(field: FieldData) -> Self {
+        let mut result = Field::new(field.ftype, field.raw_size, field.data_type);
+        result.padding = field.padding;
+        result
+    }
+}
+
+#[cfg(feature = "serde")]
+impl From<Field> for FieldData {
+    fn from(field: Field) -> Self {
+        FieldData {
+            ftype: field.ftype,
+            raw_size: field.raw_size,
+            data_type: field.data_type,
+            padding: field.padding,
         }
     }
 }
 
 impl fmt::Display for Field {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "{}:{}:{}", self.ftype, self.size, self.data_type)
+        write!(f, "{}:{}:{}", self.ftype, self.size, self.data_type)?;
+        if let Some(padding) = &self.padding {
+            write!(f, ",{}", padding)?;
+        }
+        Ok(())
     }
 }
 
@@ -130,6 +314,12 @@ impl FromStr for Field {
         let (internal, junk_trail) = rest.split_once(')')
             .ok_or_else(|| FieldParseError::InvalidFormat(s.to_string()))?;
 
+        // padding attributes after the definition e.g. Fixed(12:n,pad)
+        let (internal, attributes) = match internal.split_once(',') {
+            Some((internal, attributes)) => (internal, Some(attributes)),
+            None => (internal, None),
+        };
+
         let (s_size, s_data_type) = match ftype {
             FieldType::AsciiBitmap => {
                 if internal.contains(':') {
@@ -151,7 +341,11 @@ impl FromStr for Field {
                 if internal.contains(':') {
                     Err(FieldParseError::InvalidFormat(s.to_string()))
                 } else {
-                    Ok(("0", internal))
+                    // an optional maximum length e.g. LLVar(n..19)
+                    match internal.split_once("..") {
+                        Some((data_type, max)) => Ok((max, data_type)),
+                        None => Ok(("0", internal)),
+                    }
                 }?
             },
             _ => {
@@ -170,11 +364,49 @@ impl FromStr for Field {
             return Err(FieldParseError::JunkTrail(junk_trail.to_string()));
         }
 
-        Ok(Field::new(
+        let padding = match attributes {
+            Some(_) if ftype != FieldType::Fixed => return Err(FieldParseError::InvalidFormat(s.to_string())),
+            Some(attributes) => Some(parse_padding(attributes, &data_type)?),
+            None => None,
+        };
+
+        let mut field = Field::new(
             ftype,
             size,
             data_type,
-        ))
+        );
+        field.padding = padding;
+        Ok(field)
+    }
+}
+
+/// Padding attributes, `pad` for the data type's [`Padding::default_for`] or either of `justify=<left|right>` and
+/// `fill=<char|2 hex digits>` to change it, with a fill which [`Padding::fits`] the data type.
+fn parse_padding(attributes: &str, data_type: &DataType) -> Result<Padding, FieldParseError> {
+    let mut padding = Padding::default_for(data_type);
+    for attribute in attributes.split(',') {
+        match attribute.split_once('=') {
+            None if attribute.eq_ignore_ascii_case("pad") => {},
+            Some((name, justification)) if name.eq_ignore_ascii_case("justify") => {
+                padding.justification = justification.parse()?;
+            },
+            Some((name, fill)) if name.eq_ignore_ascii_case("fill") => {
+                padding.fill = parse_fill(fill)
+                    .filter(|fill| Padding::new(padding.justification, *fill).fits(data_type))
+                    .ok_or_else(|| FieldParseError::InvalidPadding(attribute.to_string()))?;
+            },
+            _ => return Err(FieldParseError::InvalidPadding(attribute.to_string())),
+        }
+    }
+    Ok(padding)
+}
+
+fn parse_fill(fill: &str) -> Option<char> {
+    let mut chars = fill.chars();
+    match (chars.next(), chars.next(), chars.next()) {
+        (Some(fill), None, _) => Some(fill),
+        (Some(_), Some(_), None) => u8::from_str_radix(fill, 16).ok().map(char::from),
+        _ => None,
     }
 }
 
@@ -201,6 +433,7 @@ mod test {
         parse_lllvar: "LLLVar(an)" => FieldType::LLLVar, 0, DataType::Alphanum,
         parse_llvar: "LLVar(n)" => FieldType::LLVar, 0, DataType::Numeric,
         parse_lvar: "LVar(h)" => FieldType::LVar, 0, DataType::Hex,
+        parse_llvar_max: "LLVar(n..19)" => FieldType::LLVar, 19, DataType::Numeric,
         parse_bitmap: "Bitmap(64)" => FieldType::Bitmap, 8, DataType::Binary,
         parse_bitmap_adjust_low: "Bitmap(65)" => FieldType::Bitmap, 9, DataType::Binary,
         parse_bitmap_adjust_high: "Bitmap(71)" => FieldType::Bitmap, 9, DataType::Binary,
@@ -209,6 +442,72 @@ mod test {
         parse_ascii_bitmap_adjust_high: "AsciiBitmap(67)" => FieldType::AsciiBitmap, 17, DataType::Packed,
     );
 
+    macro_rules! padding_tests {
+        ($($name:ident: $str:expr => $justification:ident $fill:literal,)*) => {
+            $(
+                #[test]
+                fn $name() {
+                    let field = $str.parse::<Field>().unwrap();
+                    let padding = Padding::new(Justification::$justification, $fill);
+                    assert_eq!(Some(padding), field.padding);
+                    let shown = format!("Fixed({}:{},{})", field.size, field.data_type, padding);
+                    assert_eq!(field, shown.parse::<Field>().unwrap());
+                }
+            )*
+        };
+    }
+
+    padding_tests!(
+        padding_numeric: "Fixed(12:n,pad)" => Right '0',
+        padding_alphanum: "Fixed(15:an,pad)" => Left ' ',
+        padding_binary: "Fixed(8:bin,pad)" => Left '\u{0}',
+        padding_hex_fill: "Fixed(8:bin,fill=FF)" => Left '\u{ff}',
+        padding_justify: "Fixed(10:an,justify=right)" => Right ' ',
+        padding_justify_and_fill: "Fixed(6:h,justify=left,fill=F)" => Left 'F',
+        padding_space_fill: "Fixed(6:a,fill=20)" => Left ' ',
+        padding_bracket_fill: "Fixed(6:an,fill=29)" => Left ')',
+    );
+
+    #[test]
+    fn pads_and_trims() {
+        let zeros = Padding::default_for(&DataType::Numeric);
+        assert_eq!("000000001000", zeros.pad("1000", 12));
+        assert_eq!("1000", zeros.trim("000000001000"));
+        assert_eq!("", zeros.trim("000000"));
+        assert_eq!("000000", zeros.pad("", 6));
+
+        let spaces = Padding::default_for(&DataType::Alphanum);
+        assert_eq!("TERM01  ", spaces.pad("TERM01", 8));
+        assert_eq!(" TERM01", spaces.trim(" TERM01  "));
+        assert_eq!("TOO LONG", spaces.pad("TOO LONG", 4));
+
+        let ffs = Padding::new(Justification::Left, '\u{ff}');
+        assert_eq!("\u{1}\u{ff}\u{ff}", ffs.pad("\u{1}", 3));
+    }
+
+    macro_rules! is_valid_tests {
+        ($($name:ident: $data_type:ident $value:literal => $expected:literal,)*) => {
+            $(
+                #[test]
+                fn $name() {
+                    assert_eq!($expected, DataType::$data_type.is_valid($value));
+                }
+            )*
+        };
+    }
+
+    is_valid_tests!(
+        valid_alpha: Alpha "Abc Def" => true,
+        invalid_alpha: Alpha "Abc1" => false,
+        valid_alphanum: Alphanum "TERM ID-01" => true,
+        invalid_alphanum: Alphanum "TERM\tID" => false,
+        valid_numeric: Numeric "0123456789" => true,
+        invalid_numeric: Numeric "12 34" => false,
+        valid_hex: Hex "09afAF" => true,
+        invalid_hex: Hex "0G" => false,
+        valid_binary: Binary "\u{0}\u{ff}" => true,
+    );
+
     macro_rules! parse_error_tests {
         ($($name:ident: $str:expr => $expect_err:path,)*) => {
             $(
@@ -236,5 +535,17 @@ mod test {
         error_lvar_len: "lvar(19:an)" => FieldParseError::InvalidFormat,
         error_llvar_len: "llvar(19:an)" => FieldParseError::InvalidFormat,
         error_lllvar_len: "lllvar(19:an)" => FieldParseError::InvalidFormat,
+        error_llvar_max: "llvar(n..x)" => FieldParseError::InvalidLength,
+        error_padding: "fixed(12:n,pads)" => FieldParseError::InvalidPadding,
+        error_justification: "fixed(12:n,justify=up)" => FieldParseError::InvalidPadding,
+        error_fill: "fixed(12:n,fill=GG)" => FieldParseError::InvalidPadding,
+        error_empty_fill: "fixed(12:n,fill=)" => FieldParseError::InvalidPadding,
+        error_non_ascii_fill: "fixed(8:an,fill=FF)" => FieldParseError::InvalidPadding,
+        error_non_ascii_char_fill: "fixed(8:an,fill=é)" => FieldParseError::InvalidPadding,
+        error_wide_binary_fill: "fixed(8:bin,fill=€)" => FieldParseError::InvalidPadding,
+        error_non_digit_fill: "fixed(6:n,fill=F)" => FieldParseError::InvalidPadding,
+        error_space_numeric_fill: "fixed(6:n,fill=20)" => FieldParseError::InvalidPadding,
+        error_llvar_padding: "llvar(n,pad)" => FieldParseError::InvalidFormat,
+        error_bitmap_padding: "bitmap(64,pad)" => FieldParseError::InvalidFormat,
     }
 }
\ No newline at end of file
@@ -0,0 +1,315 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::str::FromStr;
+use crate::iso8583::spec::{
+    Field,
+    FieldName,
+    Spec,
+};
+
+/// The standard data element dictionaries, to build specs on and override where a link differs.
+///
+/// Bitmaps are binary and every field has its standard name. Fields whose standard format has parts the engine
+/// doesn't model, e.g. the sign of `x+n` amounts, are alphanumeric and long enough to hold all of it.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Dictionary {
+    Iso1987,
+    /// 1987 with the fields changed in 1993, e.g. the local date and time (12) and the card acceptor name/location (43)
+    Iso1993,
+}
+
+const ISO_1987: &[(u16, &str, &str)] = &[
+    (0, "Bitmap(64)", "Primary Bitmap"),
+    (1, "Bitmap(64)", "Secondary Bitmap"),
+    (2, "LLVar(n..19)", "Primary Account Number"),
+    (3, "Fixed(6:n)", "Processing Code"),
+    (4, "Fixed(12:n)", "Amount, Transaction"),
+    (5, "Fixed(12:n)", "Amount, Settlement"),
+    (6, "Fixed(12:n)", "Amount, Cardholder Billing"),
+    (7, "Fixed(10:n)", "Transmission Date and Time"),
+    (8, "Fixed(8:n)", "Amount, Cardholder Billing Fee"),
+    (9, "Fixed(8:n)", "Conversion Rate, Settlement"),
+    (10, "Fixed(8:n)", "Conversion Rate, Cardholder Billing"),
+    (11, "Fixed(6:n)", "System Trace Audit Number"),
+    (12, "Fixed(6:n)", "Time, Local Transaction"),
+    (13, "Fixed(4:n)", "Date, Local Transaction"),
+    (14, "Fixed(4:n)", "Date, Expiration"),
+    (15, "Fixed(4:n)", "Date, Settlement"),
+    (16, "Fixed(4:n)", "Date, Conversion"),
+    (17, "Fixed(4:n)", "Date, Capture"),
+    (18, "Fixed(4:n)", "Merchant Type"),
+    (19, "Fixed(3:n)", "Acquiring Institution Country Code"),
+    (20, "Fixed(3:n)", "Primary Account Number Extended, Country Code"),
+    (21, "Fixed(3:n)", "Forwarding Institution Country Code"),
+    (22, "Fixed(3:n)", "Point of Service Entry Mode"),
+    (23, "Fixed(3:n)", "Card Sequence Number"),
+    (24, "Fixed(3:n)", "Network International Identifier"),
+    (25, "Fixed(2:n)", "Point of Service Condition Code"),
+    (26, "Fixed(2:n)", "Point of Service PIN Capture Code"),
+    (27, "Fixed(1:n)", "Authorization Identification Response Length"),
+    (28, "Fixed(9:an)", "Amount, Transaction Fee"),
+    (29, "Fixed(9:an)", "Amount, Settlement Fee"),
+    (30, "Fixed(9:an)", "Amount, Transaction Processing Fee"),
+    (31, "Fixed(9:an)", "Amount, Settlement Processing Fee"),
+    (32, "LLVar(n..11)", "Acquiring Institution Identification Code"),
+    (33, "LLVar(n..11)", "Forwarding Institution Identification Code"),
+    (34, "LLVar(an..28)", "Primary Account Number, Extended"),
+    (35, "LLVar(an..37)", "Track 2 Data"),
+    (36, "LLLVar(an..104)", "Track 3 Data"),
+    (37, "Fixed(12:an)", "Retrieval Reference Number"),
+    (38, "Fixed(6:an)", "Authorization Identification Response"),
+    (39, "Fixed(2:an)", "Response Code"),
+    (40, "Fixed(3:an)", "Service Restriction Code"),
+    (41, "Fixed(8:an)", "Card Acceptor Terminal Identification"),
+    (42, "Fixed(15:an)", "Card Acceptor Identification Code"),
+    (43, "Fixed(40:an)", "Card Acceptor Name/Location"),
+    (44, "LLVar(an..25)", "Additional Response Data"),
+    (45, "LLVar(an..76)", "Track 1 Data"),
+    (46, "LLLVar(an..999)", "Additional Data - ISO"),
+    (47, "LLLVar(an..999)", "Additional Data - National"),
+    (48, "LLLVar(an..999)", "Additional Data - Private"),
+    (49, "Fixed(3:an)", "Currency Code, Transaction"),
+    (50, "Fixed(3:an)", "Currency Code, Settlement"),
+    (51, "Fixed(3:an)", "Currency Code, Cardholder Billing"),
+    (52, "Fixed(8:bin)", "Personal Identification Number Data"),
+    (53, "Fixed(16:n)", "Security Related Control Information"),
+    (54, "LLLVar(an..120)", "Additional Amounts"),
+    (55, "LLLVar(an..999)", "Reserved ISO"),
+    (56, "LLLVar(an..999)", "Reserved ISO"),
+    (57, "LLLVar(an..999)", "Reserved National"),
+    (58, "LLLVar(an..999)", "Reserved National"),
+    (59, "LLLVar(an..999)", "Reserved National"),
+    (60, "LLLVar(an..999)", "Reserved National"),
+    (61, "LLLVar(an..999)", "Reserved Private"),
+    (62, "LLLVar(an..999)", "Reserved Private"),
+    (63, "LLLVar(an..999)", "Reserved Private"),
+    (64, "Fixed(8:bin)", "Message Authentication Code"),
+    (65, "Fixed(8:bin)", "Tertiary Bitmap"),
+    (66, "Fixed(1:n)", "Settlement Code"),
+    (67, "Fixed(2:n)", "Extended Payment Code"),
+    (68, "Fixed(3:n)", "Receiving Institution Country Code"),
+    (69, "Fixed(3:n)", "Settlement Institution Country Code"),
+    (70, "Fixed(3:n)", "Network Management Information Code"),
+    (71, "Fixed(4:n)", "Message Number"),
+    (72, "Fixed(4:n)", "Message Number, Last"),
+    (73, "Fixed(6:n)", "Date, Action"),
+    (74, "Fixed(10:n)", "Credits, Number"),
+    (75, "Fixed(10:n)", "Credits, Reversal Number"),
+    (76, "Fixed(10:n)", "Debits, Number"),
+    (77, "Fixed(10:n)", "Debits, Reversal Number"),
+    (78, "Fixed(10:n)", "Transfer, Number"),
+    (79, "Fixed(10:n)", "Transfer, Reversal Number"),
+    (80, "Fixed(10:n)", "Inquiries, Number"),
+    (81, "Fixed(10:n)", "Authorizations, Number"),
+    (82, "Fixed(12:n)", "Credits, Processing Fee Amount"),
+    (83, "Fixed(12:n)", "Credits, Transaction Fee Amount"),
+    (84, "Fixed(12:n)", "Debits, Processing Fee Amount"),
+    (85, "Fixed(12:n)", "Debits, Transaction Fee Amount"),
+    (86, "Fixed(16:n)", "Credits, Amount"),
+    (87, "Fixed(16:n)", "Credits, Reversal Amount"),
+    (88, "Fixed(16:n)", "Debits, Amount"),
+    (89, "Fixed(16:n)", "Debits, Reversal Amount"),
+    (90, "Fixed(42:n)", "Original Data Elements"),
+    (91, "Fixed(1:an)", "File Update Code"),
+    (92, "Fixed(2:an)", "File Security Code"),
+    (93, "Fixed(5:an)", "Response Indicator"),
+    (94, "Fixed(7:an)", "Service Indicator"),
+    (95, "Fixed(42:an)", "Replacement Amounts"),
+    (96, "Fixed(8:bin)", "Message Security Code"),
+    (97, "Fixed(17:an)", "Amount, Net Settlement"),
+    (98, "Fixed(25:an)", "Payee"),
+    (99, "LLVar(n..11)", "Settlement Institution Identification Code"),
+    (100, "LLVar(n..11)", "Receiving Institution Identification Code"),
+    (101, "LLVar(an..17)", "File Name"),
+    (102, "LLVar(an..28)", "Account Identification 1"),
+    (103, "LLVar(an..28)", "Account Identification 2"),
+    (104, "LLLVar(an..100)", "Transaction Description"),
+    (105, "LLLVar(an..999)", "Reserved ISO"),
+    (106, "LLLVar(an..999)", "Reserved ISO"),
+    (107, "LLLVar(an..999)", "Reserved ISO"),
+    (108, "LLLVar(an..999)", "Reserved ISO"),
+    (109, "LLLVar(an..999)", "Reserved ISO"),
+    (110, "LLLVar(an..999)", "Reserved ISO"),
+    (111, "LLLVar(an..999)", "Reserved ISO"),
+    (112, "LLLVar(an..999)", "Reserved National"),
+    (113, "LLLVar(an..999)", "Reserved National"),
+    (114, "LLLVar(an..999)", "Reserved National"),
+    (115, "LLLVar(an..999)", "Reserved National"),
+    (116, "LLLVar(an..999)", "Reserved National"),
+    (117, "LLLVar(an..999)", "Reserved National"),
+    (118, "LLLVar(an..999)", "Reserved National"),
+    (119, "LLLVar(an..999)", "Reserved National"),
+    (120, "LLLVar(an..999)", "Reserved Private"),
+    (121, "LLLVar(an..999)", "Reserved Private"),
+    (122, "LLLVar(an..999)", "Reserved Private"),
+    (123, "LLLVar(an..999)", "Reserved Private"),
+    (124, "LLLVar(an..999)", "Reserved Private"),
+    (125, "LLLVar(an..999)", "Reserved Private"),
+    (126, "LLLVar(an..999)", "Reserved Private"),
+    (127, "LLLVar(an..999)", "Reserved Private"),
+    (128, "Fixed(8:bin)", "Message Authentication Code"),
+];
+
+/// The fields 1993 changed from 1987
+const ISO_1993: &[(u16, &str, &str)] = &[
+    (12, "Fixed(12:n)", "Date and Time, Local Transaction"),
+    (13, "Fixed(4:n)", "Date, Effective"),
+    (22, "Fixed(12:an)", "Point of Service Data Code"),
+    (24, "Fixed(3:n)", "Function Code"),
+    (25, "Fixed(4:n)", "Message Reason Code"),
+    (26, "Fixed(4:n)", "Card Acceptor Business Code"),
+    (27, "Fixed(1:n)", "Approval Code Length"),
+    (28, "Fixed(6:n)", "Date, Reconciliation"),
+    (29, "Fixed(3:n)", "Reconciliation Indicator"),
+    (30, "Fixed(24:n)", "Amounts, Original"),
+    (31, "LLVar(an..99)", "Acquirer Reference Data"),
+    (38, "Fixed(6:an)", "Approval Code"),
+    (39, "Fixed(3:n)", "Action Code"),
+    (40, "Fixed(3:n)", "Service Code"),
+    (43, "LLVar(an..99)", "Card Acceptor Name/Location"),
+    (44, "LLVar(an..99)", "Additional Response Data"),
+    (46, "LLLVar(an..204)", "Amounts, Fees"),
+    (53, "LLVar(bin..48)", "Security Related Control Information"),
+    (55, "LLLVar(bin..255)", "Integrated Circuit Card System Related Data"),
+    (56, "LLVar(n..35)", "Original Data Elements"),
+    (57, "Fixed(3:n)", "Authorization Life Cycle Code"),
+    (58, "LLVar(n..11)", "Authorizing Agent Institution Identification Code"),
+    (59, "LLLVar(an..999)", "Transport Data"),
+    (71, "Fixed(8:n)", "Message Number"),
+    (72, "LLLVar(an..999)", "Data Record"),
+];
+
+impl Dictionary {
+    fn entries(&self) -> HashMap<u16, (&'static str, &'static str)> {
+        let changes: &[&[(u16, &str, &str)]] = match self {
+            Dictionary::Iso1987 => &[ISO_1987],
+            Dictionary::Iso1993 => &[ISO_1987, ISO_1993],
+        };
+
+        changes.iter()
+            .flat_map(|entries| entries.iter())
+            .map(|(field, definition, name)| (*field, (*definition, *name)))
+            .collect()
+    }
+
+    /// Every data element's definition by field number
+    pub fn fields(&self) -> HashMap<u16, Field> {
+        self.entries()
+            .into_iter()
+            .map(|(field, (definition, _name))| (field, definition.parse().unwrap()))
+            .collect()
+    }
+
+    pub fn names(&self) -> HashMap<u16, FieldName> {
+        self.entries()
+            .into_iter()
+            .map(|(field, (_definition, name))| (field, FieldName::new(name, None)))
+            .collect()
+    }
+}
+
+impl fmt::Display for Dictionary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Dictionary::Iso1987 => write!(f, "iso8583-1987"),
+            Dictionary::Iso1993 => write!(f, "iso8583-1993"),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub struct DictionaryParseError(pub String);
+
+impl fmt::Display for DictionaryParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "unknown dictionary: {}", self.0)
+    }
+}
+
+impl std::error::Error for DictionaryParseError {}
+
+impl FromStr for Dictionary {
+    type Err = DictionaryParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match &s.to_ascii_lowercase()[..] {
+            "iso8583-1987" | "1987" => Ok(Dictionary::Iso1987),
+            "iso8583-1993" | "1993" => Ok(Dictionary::Iso1993),
+            _ => Err(DictionaryParseError(s.to_string())),
+        }
+    }
+}
+
+impl Spec {
+    /// A spec with every field of the dictionary, and its name, as the default fields of each of the MTIs
+    pub fn standard(dictionary: Dictionary, mtis: &[&str]) -> Self {
+        let mut spec = Spec::new();
+        spec.extend_dictionary(dictionary);
+        for mti in mtis {
+            spec.extend_mti_spec(mti.to_string(), HashMap::new());
+        }
+        spec
+    }
+
+    /// Makes every field of the dictionary, and its name, a default field
+    pub fn extend_dictionary(&mut self, dictionary: Dictionary) {
+        for (field, definition) in dictionary.fields() {
+            self.default_fields.insert(field, definition);
+        }
+        for (field, name) in dictionary.names() {
+            self.set_field_name(field, name);
+        }
+        self.resolve();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::iso8583::spec::{
+        DataType,
+        FieldType,
+    };
+
+    #[test]
+    fn every_field_parses() {
+        for dictionary in &[Dictionary::Iso1987, Dictionary::Iso1993] {
+            let fields = dictionary.fields();
+            assert_eq!(129, fields.len());
+            assert!((0..=128).all(|field| fields.contains_key(&field)));
+            assert_eq!(129, dictionary.names().len());
+        }
+    }
+
+    macro_rules! dictionary_tests {
+        ($($name:ident: $dictionary:ident $field:literal => $ftype:ident $size:literal $data_type:ident $field_name:literal,)*) => {
+            $(
+                #[test]
+                fn $name() {
+                    let dictionary = Dictionary::$dictionary;
+                    assert_eq!(Field::new(FieldType::$ftype, $size, DataType::$data_type), dictionary.fields()[&$field]);
+                    assert_eq!($field_name, dictionary.names()[&$field].name);
+                }
+            )*
+        };
+    }
+
+    dictionary_tests!(
+        pan_1987: Iso1987 2 => LLVar 19 Numeric "Primary Account Number",
+        local_time_1987: Iso1987 12 => Fixed 6 Numeric "Time, Local Transaction",
+        local_time_1993: Iso1993 12 => Fixed 12 Numeric "Date and Time, Local Transaction",
+        card_acceptor_1987: Iso1987 43 => Fixed 40 Alphanum "Card Acceptor Name/Location",
+        card_acceptor_1993: Iso1993 43 => LLVar 99 Alphanum "Card Acceptor Name/Location",
+        action_code_1993: Iso1993 39 => Fixed 3 Numeric "Action Code",
+        secondary_bitmap: Iso1987 1 => Bitmap 64 Binary "Secondary Bitmap",
+        mac: Iso1993 128 => Fixed 8 Binary "Message Authentication Code",
+    );
+
+    #[test]
+    fn parse() {
+        assert_eq!(Ok(Dictionary::Iso1987), "iso8583-1987".parse());
+        assert_eq!(Ok(Dictionary::Iso1993), "1993".parse());
+        assert_eq!(Err(DictionaryParseError("1986".to_string())), "1986".parse::<Dictionary>());
+        assert_eq!(Err(DictionaryParseError("2003".to_string())), "2003".parse::<Dictionary>());
+    }
+}
@@ -0,0 +1,474 @@
+use std::error;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::str::FromStr;
+use crate::{
+    iso8583::spec::{
+        Dictionary,
+        DictionaryParseError,
+        Field,
+        FieldName,
+        FieldParseError,
+        MacParseError,
+        MacSettings,
+        Mask,
+        MaskParseError,
+        Presence,
+        Spec,
+        SpecError,
+    },
+    util::{
+        Charset,
+        CharsetParseError,
+    },
+};
+
+/// Written in mask lines for fields which shouldn't be masked, e.g. to unmask one of the [`DEFAULT_MASKS`]
+///
+/// [`DEFAULT_MASKS`]: crate::iso8583::spec::DEFAULT_MASKS
+const NO_MASK: &str = "none";
+
+/// Written in a mac line for messages which aren't MACed, e.g. when a spec extended MACs them
+const NO_MAC: &str = "none";
+
+/// Written in a padding line to trim fields' padding as they're parsed, or keep it
+const TRIM_PADDING: &str = "trim";
+const KEEP_PADDING: &str = "keep";
+
+/// How many specs deep `extends` lines can go, so that specs which extend each other are an error
+const MAX_EXTENDS_DEPTH: usize = 16;
+
+#[derive(Debug)]
+pub enum SpecParseError {
+    InvalidFormat(String),
+    InvalidFieldNumber(String),
+    InvalidField(FieldParseError),
+    InvalidMask(MaskParseError),
+    InvalidCharset(CharsetParseError),
+    InvalidMac(MacParseError),
+    /// `extends` named neither a dictionary nor a spec file
+    UnknownDictionary(DictionaryParseError),
+    /// The spec file extended couldn't be read or parsed
+    InvalidBase(String, io::Error),
+    /// Specs extended specs more than [`MAX_EXTENDS_DEPTH`] deep, most likely because they extend each other
+    ExtendsTooDeep(String),
+    /// `extends` wasn't the first setting, so it would replace those before it
+    LateExtends(String),
+    /// A presence rule came before any `mti` line said which MTIs it belongs to
+    NoMti(String),
+    /// The spec parsed but [`Spec::validate`] found problems with it
+    Invalid(Vec<SpecError>),
+}
+
+impl fmt::Display for SpecParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::InvalidFormat(line) => write!(f, "invalid format: {}", line),
+            Self::InvalidFieldNumber(field) => write!(f, "invalid field number: {}", field),
+            Self::InvalidField(e) => write!(f, "invalid field: {}", e),
+            Self::InvalidMask(e) => e.fmt(f),
+            Self::InvalidCharset(e) => e.fmt(f),
+            Self::InvalidMac(e) => e.fmt(f),
+            Self::UnknownDictionary(e) => e.fmt(f),
+            Self::InvalidBase(name, e) => write!(f, "unable to extend {}: {}", name, e),
+            Self::ExtendsTooDeep(name) => write!(f, "specs extended too deep at {}", name),
+            Self::LateExtends(line) => write!(f, "extends after other settings: {}", line),
+            Self::NoMti(line) => write!(f, "presence set before any MTI: {}", line),
+            Self::Invalid(errors) => {
+                let errors = errors.iter().map(SpecError::to_string).collect::<Vec<_>>();
+                write!(f, "invalid spec: {}", errors.join("; "))
+            },
+        }
+    }
+}
+
+impl error::Error for SpecParseError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            Self::InvalidField(e) => Some(e),
+            Self::InvalidMask(e) => Some(e),
+            Self::InvalidCharset(e) => Some(e),
+            Self::InvalidMac(e) => Some(e),
+            Self::UnknownDictionary(e) => Some(e),
+            Self::InvalidBase(_name, e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<FieldParseError> for SpecParseError {
+    fn from(e: FieldParseError) -> Self {
+        Self::InvalidField(e)
+    }
+}
+
+impl From<MaskParseError> for SpecParseError {
+    fn from(e: MaskParseError) -> Self {
+        Self::InvalidMask(e)
+    }
+}
+
+impl From<CharsetParseError> for SpecParseError {
+    fn from(e: CharsetParseError) -> Self {
+        Self::InvalidCharset(e)
+    }
+}
+
+impl From<MacParseError> for SpecParseError {
+    fn from(e: MacParseError) -> Self {
+        Self::InvalidMac(e)
+    }
+}
+
+impl From<DictionaryParseError> for SpecParseError {
+    fn from(e: DictionaryParseError) -> Self {
+        Self::UnknownDictionary(e)
+    }
+}
+
+impl Spec {
+    /// Reads a spec file, see [`Spec::from_str`] for the format. Spec files it extends are found relative to it.
+    pub fn load<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let path = path.as_ref();
+        parse(&fs::read_to_string(path)?, path.parent(), 0)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+}
+
+/// Parses a spec, one setting per line with blank lines and lines starting `#` ignored:
+/// - `extends <dictionary|spec file>` to start from the default fields of a [`Dictionary`] e.g. `iso8583-1987`, or
+///   everything in another spec file, before any other setting
+/// - `charset <ascii|ebcdic>`
+/// - `mti <mti>...` to say which MTIs, or families of MTIs e.g. `02x0`, the field and presence lines after it belong
+///   to
+/// - `field <number> <definition> [name]` with the definition as parsed by [`Field`], e.g. `field 2 LLVar(n) Primary
+///   Account Number` or `field 4 Fixed(12:n,pad) Amount, Transaction`. Before any `mti` line this is a default field
+///   for every MTI.
+/// - `mandatory <number>...`, `optional <number>...` or `absent <number>...` to set the [`Presence`] of fields
+/// - `mask <number> <pan|redact|none>`
+/// - `mac <settings>` to MAC messages, as parsed by [`MacSettings`] e.g. `mac retail pad2`, or `mac none` to stop
+///   MACing messages of a spec extended
+/// - `padding <trim|keep>` to trim fields' padding as they're parsed, or keep it as sent which is the default
+///
+/// Spec files extended are found relative to the working directory, or to the spec file if it's [`Spec::load`]ed.
+/// The spec is then checked with [`Spec::validate`].
+///
+/// ```
+/// use zaps::iso8583::spec::Spec;
+///
+/// let spec = "
+///     field 0 AsciiBitmap(64)
+///     field 2 LLVar(n) Primary Account Number
+///     field 4 Fixed(12:n) Amount, Transaction
+///     field 39 Fixed(2:an) Response Code
+///     mti 02x0
+///     mandatory 2 4
+///     absent 39
+///     mti 0210
+///     optional 2
+///     field 39 Fixed(2:an)
+/// ".parse::<Spec>().unwrap();
+///
+/// assert_eq!(3, spec.get_mti_spec("0200").unwrap().len());
+/// assert_eq!(2, spec.get_mandatory_fields("0220").unwrap().len());
+/// assert_eq!(4, spec.get_mti_spec("0210").unwrap().len());
+/// assert_eq!(1, spec.get_mandatory_fields("0210").unwrap().len());
+/// ```
+impl FromStr for Spec {
+    type Err = SpecParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        parse(s, None, 0)
+    }
+}
+
+fn parse(s: &str, dir: Option<&Path>, depth: usize) -> Result<Spec, SpecParseError> {
+    let mut spec = Spec::new();
+    let mut mtis: Vec<String> = vec![];
+    let mut started = false;
+
+    for line in s.lines().map(str::trim) {
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let invalid = || SpecParseError::InvalidFormat(line.to_string());
+        let mut words = line.splitn(2, char::is_whitespace);
+        let (kind, rest) = (words.next(), words.next().map(str::trim));
+
+        match (kind, rest) {
+            (Some("extends"), Some(base)) => {
+                if started {
+                    return Err(SpecParseError::LateExtends(line.to_string()));
+                }
+                spec = extend(base, dir, depth)?;
+            },
+            (Some("charset"), Some(charset)) => spec.set_charset(charset.parse::<Charset>()?),
+            (Some("mti"), Some(list)) => {
+                mtis = list.split_whitespace().map(str::to_string).collect();
+                for mti in &mtis {
+                    spec.overrides.entry(mti.clone()).or_default();
+                }
+            },
+            (Some("field"), Some(rest)) => {
+                let mut words = rest.splitn(3, char::is_whitespace);
+                let (number, definition, name) = match (words.next(), words.next(), words.next()) {
+                    (Some(number), Some(definition), name) => (number, definition, name.map(str::trim)),
+                    _ => return Err(invalid()),
+                };
+                let number = parse_field_number(number)?;
+                let field = definition.parse::<Field>()?;
+                if mtis.is_empty() {
+                    spec.default_fields.insert(number, field.clone());
+                }
+                for mti in &mtis {
+                    spec.overrides.get_mut(mti)
+                        .unwrap()
+                        .fields
+                        .insert(number, field.clone());
+                }
+                if let Some(name) = name.filter(|name| !name.is_empty()) {
+                    spec.set_field_name(number, FieldName::new(name, None));
+                }
+            },
+            (Some(kind @ "mandatory"), Some(numbers))
+            | (Some(kind @ "optional"), Some(numbers))
+            | (Some(kind @ "absent"), Some(numbers)) => {
+                if mtis.is_empty() {
+                    return Err(SpecParseError::NoMti(line.to_string()));
+                }
+                let presence = match kind {
+                    "mandatory" => Presence::Mandatory,
+                    "optional" => Presence::Optional,
+                    _ => Presence::Absent,
+                };
+                for number in numbers.split_whitespace() {
+                    let number = parse_field_number(number)?;
+                    for mti in &mtis {
+                        spec.overrides.get_mut(mti)
+                            .unwrap()
+                            .presence
+                            .insert(number, presence);
+                    }
+                }
+            },
+            (Some("mask"), Some(rest)) => {
+                let (number, mask) = rest.split_once(char::is_whitespace)
+                    .ok_or_else(invalid)?;
+                let mask = match mask.trim() {
+                    NO_MASK => None,
+                    mask => Some(mask.parse::<Mask>()?),
+                };
+                spec.set_mask(parse_field_number(number)?, mask);
+            },
+            (Some("mac"), Some(NO_MAC)) => spec.set_mac(None),
+            (Some("mac"), Some(settings)) => spec.set_mac(Some(settings.parse::<MacSettings>()?)),
+            (Some("padding"), Some(TRIM_PADDING)) => spec.set_trim_padding(true),
+            (Some("padding"), Some(KEEP_PADDING)) => spec.set_trim_padding(false),
+            _ => return Err(invalid()),
+        }
+        started = true;
+    }
+
+    spec.resolve();
+    // specs extended are only checked as part of the spec extending them, which may fill in what they leave out
+    if depth == 0 {
+        spec.validate().map_err(SpecParseError::Invalid)?;
+    }
+    Ok(spec)
+}
+
+/// The spec to start from, a dictionary or else a spec file
+fn extend(base: &str, dir: Option<&Path>, depth: usize) -> Result<Spec, SpecParseError> {
+    let dictionary_err = match base.parse::<Dictionary>() {
+        Ok(dictionary) => {
+            let mut spec = Spec::new();
+            spec.extend_dictionary(dictionary);
+            return Ok(spec);
+        },
+        Err(e) => e,
+    };
+
+    let path = match dir {
+        Some(dir) => dir.join(base),
+        None => Path::new(base).to_path_buf(),
+    };
+    if !path.is_file() {
+        return Err(dictionary_err.into());
+    }
+    if depth >= MAX_EXTENDS_DEPTH {
+        return Err(SpecParseError::ExtendsTooDeep(base.to_string()));
+    }
+    fs::read_to_string(&path)
+        .map_err(|e| SpecParseError::InvalidBase(base.to_string(), e))
+        .and_then(|s| parse(&s, path.parent(), depth + 1))
+}
+
+fn parse_field_number(number: &str) -> Result<u16, SpecParseError> {
+    number.parse()
+        .map_err(|_e| SpecParseError::InvalidFieldNumber(number.to_string()))
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::HashSet;
+    use super::*;
+    use crate::iso8583::spec::{
+        DataType,
+        FieldType,
+        Justification,
+        Padding,
+    };
+
+    const SPEC: &str = "
+        # test spec
+        charset ebcdic
+        mti 0200 0210
+        field 0 Bitmap(64)
+        field 2 LLVar(n) Primary Account Number
+        mti 0210
+        field 39 Fixed(2:an)
+        mask 2 redact
+        mask 52 none
+        mac alg3 fields 2 4
+    ";
+
+    #[test]
+    fn parse() {
+        let spec = SPEC.parse::<Spec>().unwrap();
+
+        assert_eq!(Charset::Ebcdic, spec.charset());
+        let spec_0200 = spec.get_mti_spec("0200").unwrap();
+        assert_eq!(2, spec_0200.len());
+        assert_eq!(Field::new(FieldType::Bitmap, 64, DataType::Binary), spec_0200[&0]);
+        assert_eq!(Field::new(FieldType::LLVar, 0, DataType::Numeric), spec_0200[&2]);
+        let spec_0210 = spec.get_mti_spec("0210").unwrap();
+        assert_eq!(3, spec_0210.len());
+        assert_eq!(Field::new(FieldType::Fixed, 2, DataType::Alphanum), spec_0210[&39]);
+
+        assert_eq!("Primary Account Number", spec.get_field_name(2).unwrap().name);
+        assert_eq!(None, spec.get_field_name(39));
+        assert_eq!(Some(&Mask::Redact), spec.get_mask(2));
+        assert_eq!(None, spec.get_mask(52));
+        assert_eq!("alg3 fields 2 4", spec.mac().unwrap().to_string());
+    }
+
+    #[test]
+    fn extends() {
+        let spec = "
+            extends iso8583-1987
+            mti 0200
+            field 0 AsciiBitmap(64)
+            field 43 LLVar(an..99) Card Acceptor Location
+        ".parse::<Spec>().unwrap();
+
+        let spec_0200 = spec.get_mti_spec("0200").unwrap();
+        assert_eq!(129, spec_0200.len());
+        assert_eq!(Field::new(FieldType::AsciiBitmap, 64, DataType::Packed), spec_0200[&0]);
+        assert_eq!(Field::new(FieldType::LLVar, 99, DataType::Alphanum), spec_0200[&43]);
+        assert_eq!(Field::new(FieldType::LLVar, 19, DataType::Numeric), spec_0200[&2]);
+        assert_eq!("Card Acceptor Location", spec.get_field_name(43).unwrap().name);
+        assert_eq!("Primary Account Number", spec.get_field_name(2).unwrap().name);
+    }
+
+    #[test]
+    fn defaults_and_overrides() {
+        let spec = "
+            field 0 AsciiBitmap(64)
+            field 2 LLVar(n..19)
+            field 4 Fixed(12:n)
+            field 39 Fixed(2:an)
+            mti xxxx
+            mti 0xx0
+            mandatory 2
+            absent 39
+            mti 02x0
+            mandatory 4
+            mti 0210
+            optional 2
+            field 39 Fixed(3:n)
+        ".parse::<Spec>().unwrap();
+
+        let spec_0200 = spec.get_mti_spec("0200").unwrap();
+        assert_eq!(3, spec_0200.len());
+        assert_eq!(None, spec_0200.get(&39));
+        assert_eq!(&[2, 4].iter().copied().collect::<HashSet<_>>(), spec.get_mandatory_fields("0200").unwrap());
+
+        let spec_0210 = spec.get_mti_spec("0210").unwrap();
+        assert_eq!(Field::new(FieldType::Fixed, 3, DataType::Numeric), spec_0210[&39]);
+        assert_eq!(&[4].iter().copied().collect::<HashSet<_>>(), spec.get_mandatory_fields("0210").unwrap());
+
+        assert_eq!(&[2].iter().copied().collect::<HashSet<_>>(), spec.get_mandatory_fields("0400").unwrap());
+        assert_eq!(4, spec.get_mti_spec("1200").unwrap().len());
+        assert!(spec.get_mandatory_fields("1200").unwrap().is_empty());
+    }
+
+    #[test]
+    fn padding() {
+        let spec = "
+            field 0 AsciiBitmap(64)
+            field 4 Fixed(12:n,pad)
+            field 41 Fixed(8:an,justify=right,fill=*)
+            mti 0200
+            padding trim
+        ".parse::<Spec>().unwrap();
+
+        let spec_0200 = spec.get_mti_spec("0200").unwrap();
+        assert_eq!(Some(Padding::new(Justification::Right, '0')), spec_0200[&4].padding);
+        assert_eq!(Some(Padding::new(Justification::Right, '*')), spec_0200[&41].padding);
+        assert!(spec.trim_padding());
+        assert!(!"field 0 Bitmap(64)\nmti 0200\npadding keep".parse::<Spec>().unwrap().trim_padding());
+    }
+
+    #[test]
+    fn extends_spec_file() {
+        let dir = std::env::temp_dir().join(format!("zaps-spec-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("base.spec"), "extends iso8583-1987\ncharset ebcdic\nmti 0200\nmandatory 2\n").unwrap();
+        fs::write(dir.join("link.spec"), "extends base.spec\nmti 0200\nfield 43 LLVar(an..99)\n").unwrap();
+        fs::write(dir.join("loop.spec"), "extends loop.spec\n").unwrap();
+
+        let spec = Spec::load(dir.join("link.spec")).unwrap();
+        let looped = Spec::load(dir.join("loop.spec"));
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(Charset::Ebcdic, spec.charset());
+        assert_eq!(Field::new(FieldType::LLVar, 99, DataType::Alphanum), spec.get_mti_spec("0200").unwrap()[&43]);
+        assert!(spec.get_mandatory_fields("0200").unwrap().contains(&2));
+        assert_eq!("specs extended too deep at loop.spec", looped.unwrap_err().to_string());
+    }
+
+    macro_rules! parse_error_tests {
+        ($($name:ident: $str:expr => $expect_err:pat,)*) => {
+            $(
+                #[test]
+                fn $name() -> Result<(), String> {
+                    let res = $str.parse::<Spec>();
+                    if let Err($expect_err) = res {
+                        Ok(())
+                    } else {
+                        Err(format!("Unexpected result: {:?}", res))
+                    }
+                }
+            )*
+        };
+    }
+
+    parse_error_tests!(
+        error_unknown_setting: "colour blue" => SpecParseError::InvalidFormat(_),
+        error_no_mti: "mandatory 2" => SpecParseError::NoMti(_),
+        error_presence_number: "mti 0200\nabsent 2 x" => SpecParseError::InvalidFieldNumber(_),
+        error_no_definition: "mti 0200\nfield 2" => SpecParseError::InvalidFormat(_),
+        error_field_number: "mti 0200\nfield two LLVar(n)" => SpecParseError::InvalidFieldNumber(_),
+        error_field: "mti 0200\nfield 2 LLVar(19:n)" => SpecParseError::InvalidField(_),
+        error_mask: "mask 2 hide" => SpecParseError::InvalidMask(_),
+        error_charset: "charset utf8" => SpecParseError::InvalidCharset(_),
+        error_mac: "mac hmac" => SpecParseError::InvalidMac(_),
+        error_padding: "padding strip" => SpecParseError::InvalidFormat(_),
+        error_field_padding: "mti 0200\nfield 2 LLVar(n,pad)" => SpecParseError::InvalidField(_),
+        error_dictionary: "extends iso8583-1986" => SpecParseError::UnknownDictionary(_),
+        error_late_extends: "charset ascii\nextends iso8583-1987" => SpecParseError::LateExtends(_),
+        error_invalid: "mti 0200\nfield 2 LLVar(n)" => SpecParseError::Invalid(_),
+    );
+}
@@ -0,0 +1,211 @@
+use std::convert::TryFrom;
+use std::error;
+use std::fmt;
+use std::str::FromStr;
+
+/// How a MAC is worked out from its input
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MacAlgorithm {
+    /// ISO 9797-1 algorithm 1, a CBC-MAC under the whole key
+    Alg1,
+    /// ISO 9797-1 algorithm 3, the retail MAC (ANSI X9.19), single DES CBC with a double length key's left half and
+    /// the final block decrypted with its right half then encrypted again
+    Alg3,
+    /// CMAC (NIST SP 800-38B), usually with an AES key. Padding is part of the algorithm.
+    Cmac,
+}
+
+impl fmt::Display for MacAlgorithm {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MacAlgorithm::Alg1 => write!(f, "alg1"),
+            MacAlgorithm::Alg3 => write!(f, "alg3"),
+            MacAlgorithm::Cmac => write!(f, "cmac"),
+        }
+    }
+}
+
+impl FromStr for MacAlgorithm {
+    type Err = MacParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match &s.to_ascii_lowercase()[..] {
+            "alg1" | "iso9797-1" => Ok(MacAlgorithm::Alg1),
+            "alg3" | "iso9797-3" | "retail" | "x9.19" => Ok(MacAlgorithm::Alg3),
+            "cmac" | "aes-cmac" => Ok(MacAlgorithm::Cmac),
+            _ => Err(MacParseError::InvalidAlgorithm(s.to_string())),
+        }
+    }
+}
+
+/// ISO 9797-1 padding of the input to whole blocks, for algorithms 1 and 3
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum MacPadding {
+    /// Zeros, only if the input isn't already whole blocks
+    #[default]
+    Method1,
+    /// `80` then zeros, always
+    Method2,
+}
+
+/// What a MAC is worked out over
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub enum MacInput {
+    /// The serialized message from the MTI up to the MAC field
+    #[default]
+    Message,
+    /// The serialized fields in the order given, with any length prefixes, leaving out those not in the message
+    Fields(Vec<u16>),
+}
+
+/// How messages are MACed. The MAC goes in field 64, or field 128 when the message has a secondary bitmap, of the
+/// MTIs which define it. The MAC field must be fixed length: binary fields take the MAC's leading bytes, others its
+/// leading hex digits.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize), serde(try_from = "String", into = "String"))]
+pub struct MacSettings {
+    pub algorithm: MacAlgorithm,
+    pub padding: MacPadding,
+    pub input: MacInput,
+}
+
+impl MacSettings {
+    pub fn new(algorithm: MacAlgorithm) -> Self {
+        MacSettings {
+            algorithm,
+            padding: MacPadding::default(),
+            input: MacInput::default(),
+        }
+    }
+
+    /// The field the MAC goes in for a message with or without a secondary bitmap
+    pub fn field(secondary_bitmap: bool) -> u16 {
+        if secondary_bitmap {
+            128
+        } else {
+            64
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub enum MacParseError {
+    InvalidAlgorithm(String),
+    InvalidFormat(String),
+    InvalidFieldNumber(String),
+}
+
+impl fmt::Display for MacParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::InvalidAlgorithm(algorithm) => write!(f, "invalid MAC algorithm: {}", algorithm),
+            Self::InvalidFormat(s) => write!(f, "invalid MAC settings: {}", s),
+            Self::InvalidFieldNumber(field) => write!(f, "invalid MAC field number: {}", field),
+        }
+    }
+}
+
+impl error::Error for MacParseError {}
+
+impl fmt::Display for MacSettings {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.algorithm)?;
+        if self.padding == MacPadding::Method2 {
+            write!(f, " pad2")?;
+        }
+        if let MacInput::Fields(fields) = &self.input {
+            write!(f, " fields")?;
+            for field in fields {
+                write!(f, " {}", field)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Parses settings as `<algorithm> [pad1|pad2] [fields <number>...]` e.g. `retail pad2 fields 2 3 4 11 41`, with
+/// the algorithm `alg1`, `alg3` (or `retail`) or `cmac`
+impl FromStr for MacSettings {
+    type Err = MacParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut words = s.split_whitespace();
+        let mut settings = MacSettings::new(words.next().unwrap_or_default().parse()?);
+
+        let mut words = words.peekable();
+        let padding = match words.peek() {
+            Some(&"pad1") => Some(MacPadding::Method1),
+            Some(&"pad2") => Some(MacPadding::Method2),
+            _ => None,
+        };
+        if let Some(padding) = padding {
+            settings.padding = padding;
+            words.next();
+        }
+
+        match words.next() {
+            Some("fields") => {
+                let fields = words
+                    .map(|field| field.parse().map_err(|_e| MacParseError::InvalidFieldNumber(field.to_string())))
+                    .collect::<Result<Vec<_>, _>>()?;
+                if fields.is_empty() {
+                    return Err(MacParseError::InvalidFormat(s.to_string()));
+                }
+                settings.input = MacInput::Fields(fields);
+            },
+            Some(_) => return Err(MacParseError::InvalidFormat(s.to_string())),
+            None => (),
+        }
+
+        Ok(settings)
+    }
+}
+
+impl TryFrom<String> for MacSettings {
+    type Error = MacParseError;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+impl From<MacSettings> for String {
+    fn from(settings: MacSettings) -> Self {
+        settings.to_string()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    macro_rules! parse_tests {
+        ($($name:ident: $str:literal => $algorithm:expr, $padding:expr, $input:expr,)*) => {
+            $(
+                #[test]
+                fn $name() {
+                    let settings = $str.parse::<MacSettings>().unwrap();
+                    assert_eq!(MacSettings{ algorithm: $algorithm, padding: $padding, input: $input }, settings);
+                    assert_eq!(settings, settings.to_string().parse().unwrap());
+                }
+            )*
+        };
+    }
+
+    parse_tests!(
+        parse_alg1: "alg1" => MacAlgorithm::Alg1, MacPadding::Method1, MacInput::Message,
+        parse_retail: "retail pad2" => MacAlgorithm::Alg3, MacPadding::Method2, MacInput::Message,
+        parse_pad1: "iso9797-3 pad1" => MacAlgorithm::Alg3, MacPadding::Method1, MacInput::Message,
+        parse_cmac_fields: "AES-CMAC fields 2 4 11" => MacAlgorithm::Cmac, MacPadding::Method1, MacInput::Fields(vec![2, 4, 11]),
+        parse_pad_fields: "alg1 pad2 fields 3" => MacAlgorithm::Alg1, MacPadding::Method2, MacInput::Fields(vec![3]),
+    );
+
+    #[test]
+    fn parse_errors() {
+        assert_eq!(Err(MacParseError::InvalidAlgorithm("hmac".to_string())), "hmac".parse::<MacSettings>());
+        assert_eq!(Err(MacParseError::InvalidAlgorithm("".to_string())), "".parse::<MacSettings>());
+        assert_eq!(Err(MacParseError::InvalidFormat("alg1 pad3".to_string())), "alg1 pad3".parse::<MacSettings>());
+        assert_eq!(Err(MacParseError::InvalidFormat("alg1 fields".to_string())), "alg1 fields".parse::<MacSettings>());
+        assert_eq!(Err(MacParseError::InvalidFieldNumber("x".to_string())), "alg1 fields 2 x".parse::<MacSettings>());
+    }
+}
@@ -0,0 +1,100 @@
+use std::str::FromStr;
+use std::fmt;
+
+/// How a sensitive field is hidden when messages are logged or displayed
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize), serde(rename_all = "lowercase"))]
+pub enum Mask {
+    /// Keep the first 6 and last 4 digits
+    Pan,
+    /// Hide the whole value
+    Redact,
+}
+
+/// The fields masked in a new spec: PAN (2), track 2 (35), track 3 (36), track 1 (45), PIN block (52) and ICC data
+/// (55).
+pub const DEFAULT_MASKS: &[(u16, Mask)] = &[
+    (2, Mask::Pan),
+    (35, Mask::Redact),
+    (36, Mask::Redact),
+    (45, Mask::Redact),
+    (52, Mask::Redact),
+    (55, Mask::Redact),
+];
+
+const MASK_CHAR: char = '*';
+const PAN_PREFIX: usize = 6;
+const PAN_SUFFIX: usize = 4;
+
+impl Mask {
+    pub fn apply(&self, value: &str) -> String {
+        let len = value.chars().count();
+        match self {
+            Mask::Pan if len > PAN_PREFIX + PAN_SUFFIX => value.chars()
+                .enumerate()
+                .map(|(i, c)| if i < PAN_PREFIX || i >= len - PAN_SUFFIX { c } else { MASK_CHAR })
+                .collect(),
+            _ => MASK_CHAR.to_string().repeat(len),
+        }
+    }
+}
+
+impl fmt::Display for Mask {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(self, f)
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub struct MaskParseError(pub String);
+
+impl fmt::Display for MaskParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "invalid mask: {}", self.0)
+    }
+}
+
+impl std::error::Error for MaskParseError {}
+
+impl FromStr for Mask {
+    type Err = MaskParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match &s.to_ascii_lowercase()[..] {
+            "pan" => Ok(Mask::Pan),
+            "redact" => Ok(Mask::Redact),
+            _ => Err(MaskParseError(s.to_string())),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    macro_rules! mask_tests {
+        ($($name:ident: $mask:expr, $value:literal => $expected:literal,)*) => {
+            $(
+                #[test]
+                fn $name() {
+                    assert_eq!($expected, $mask.apply($value));
+                }
+            )*
+        };
+    }
+
+    mask_tests!(
+        pan_16: Mask::Pan, "4111111111111111" => "411111******1111",
+        pan_19: Mask::Pan, "6011000990139424123" => "601100*********4123",
+        pan_too_short: Mask::Pan, "4111111111" => "**********",
+        redact: Mask::Redact, "1234567890ABCDEF" => "****************",
+        redact_empty: Mask::Redact, "" => "",
+    );
+
+    #[test]
+    fn parse() {
+        assert_eq!(Ok(Mask::Pan), "PAN".parse::<Mask>());
+        assert_eq!(Ok(Mask::Redact), "redact".parse::<Mask>());
+        assert_eq!(Err(MaskParseError("hide".to_string())), "hide".parse::<Mask>());
+    }
+}
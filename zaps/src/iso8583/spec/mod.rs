@@ -1,4 +1,5 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
+use crate::util::Charset;
 pub mod builder;
 mod definitions;
 pub use definitions::{
@@ -6,34 +7,258 @@ pub use definitions::{
     Field,
     FieldParseError,
     FieldType,
+    Justification,
+    Padding,
 };
+mod dictionary;
+pub use dictionary::{
+    Dictionary,
+    DictionaryParseError,
+};
+mod file;
+pub use file::SpecParseError;
+mod mac;
+pub use mac::{
+    MacAlgorithm,
+    MacInput,
+    MacPadding,
+    MacParseError,
+    MacSettings,
+};
+mod mask;
+pub use mask::{
+    DEFAULT_MASKS,
+    Mask,
+    MaskParseError,
+};
+mod mti;
+pub use mti::{
+    MTI_WILDCARD,
+    Presence,
+};
+pub(crate) use mti::{
+    MtiOverrides,
+    mti_matches,
+    mti_specificity,
+};
+mod name;
+pub use name::FieldName;
+#[cfg(feature = "serde")]
+mod serialize;
+#[cfg(feature = "serde")]
+use serialize::SpecData;
+mod validate;
+pub use validate::SpecError;
 
-#[derive(Debug)]
+/// The fields of each MTI. Fields can be defined once for the whole spec, then each MTI, or family of MTIs written
+/// with [`MTI_WILDCARD`]s e.g. `02x0`, lists only what it changes and which fields are [`Presence::Mandatory`] or
+/// [`Presence::Absent`]. An MTI gets the default fields, then those of each family it's in from the widest to the
+/// narrowest, then its own.
+///
+/// With the `serde` feature specs serialize as what they were defined with, rather than the fields worked out for
+/// each MTI.
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize), serde(from = "SpecData", into = "SpecData"))]
 pub struct Spec {
-    message_specs: HashMap<String, HashMap<u16, Field>>
+    default_fields: HashMap<u16, Field>,
+    overrides: HashMap<String, MtiOverrides>,
+    /// The fields of each MTI or family with all of the above applied
+    message_specs: HashMap<String, HashMap<u16, Field>>,
+    mandatory_fields: HashMap<String, HashSet<u16>>,
+    masks: HashMap<u16, Mask>,
+    names: HashMap<u16, FieldName>,
+    charset: Charset,
+    mac: Option<MacSettings>,
+    trim_padding: bool,
 }
 
 impl Spec {
     pub fn new() -> Self {
         Spec{
-            message_specs: HashMap::new()
+            default_fields: HashMap::new(),
+            overrides: HashMap::new(),
+            message_specs: HashMap::new(),
+            mandatory_fields: HashMap::new(),
+            masks: DEFAULT_MASKS.iter().cloned().collect(),
+            names: HashMap::new(),
+            charset: Charset::Ascii,
+            mac: None,
+            trim_padding: false,
         }
     }
 
+    /// Defines an MTI, or family of MTIs, replacing any fields it already overrode
     pub fn add_mti_spec(&mut self, mti: String, fields: HashMap<u16, Field>) {
-        self.message_specs.insert(mti, fields);
+        self.overrides.entry(mti).or_default().fields = fields;
+        self.resolve();
+    }
+
+    /// Overrides fields of an MTI, or family of MTIs, defining it if it isn't already
+    pub fn extend_mti_spec(&mut self, mti: String, fields: HashMap<u16, Field>) {
+        self.overrides.entry(mti).or_default().fields.extend(fields);
+        self.resolve();
     }
 
+    /// Every MTI and family defined, with their fields
     pub fn get_mti_specs(&self) -> &HashMap<String, HashMap<u16, Field>> {
         &self.message_specs
     }
 
     pub fn has_mti_spec(&self, mti: &str) -> bool {
-        self.message_specs.contains_key(mti)
+        self.find_mti(mti).is_some()
     }
 
+    /// The fields of an MTI, from its own definition or else the narrowest family it's in
     pub fn get_mti_spec(&self, mti: &str) -> Option<&HashMap<u16, Field>> {
-        self.message_specs.get(mti)
+        self.find_mti(mti)
+            .map(|key| &self.message_specs[key])
+    }
+
+    /// The fields which must be in messages of an MTI, found as for [`Spec::get_mti_spec`]
+    pub fn get_mandatory_fields(&self, mti: &str) -> Option<&HashSet<u16>> {
+        self.find_mti(mti)
+            .map(|key| &self.mandatory_fields[key])
+    }
+
+    /// Defines a field for every MTI which doesn't override it
+    pub fn set_default_field(&mut self, field: u16, definition: Field) {
+        self.default_fields.insert(field, definition);
+        self.resolve();
+    }
+
+    pub fn get_default_fields(&self) -> &HashMap<u16, Field> {
+        &self.default_fields
+    }
+
+    /// Overrides one field of an MTI, or family of MTIs, defining it if it isn't already
+    pub fn set_mti_field(&mut self, mti: &str, field: u16, definition: Field) {
+        self.overrides.entry(mti.to_string()).or_default().fields.insert(field, definition);
+        self.resolve();
+    }
+
+    /// Sets whether a field must, may or mustn't be in messages of an MTI, or family of MTIs
+    pub fn set_presence(&mut self, mti: &str, field: u16, presence: Presence) {
+        self.overrides.entry(mti.to_string()).or_default().presence.insert(field, presence);
+        self.resolve();
+    }
+
+    /// Defines a field the same way in every MTI, e.g. to override one from a [`Dictionary`], dropping any overrides
+    pub fn set_field(&mut self, field: u16, definition: Field) {
+        self.default_fields.insert(field, definition);
+        for overrides in self.overrides.values_mut() {
+            overrides.fields.remove(&field);
+        }
+        self.resolve();
+    }
+
+    /// The key of the MTI's own definition, or the narrowest family it's in. Families as narrow as each other are
+    /// picked between by the lowest pattern so that the same one is always used.
+    fn find_mti<'a>(&'a self, mti: &'a str) -> Option<&'a str> {
+        if self.message_specs.contains_key(mti) {
+            return Some(mti);
+        }
+        self.message_specs.keys()
+            .filter(|pattern| mti_matches(pattern, mti))
+            .max_by(|a, b| mti_specificity(a).cmp(&mti_specificity(b)).then_with(|| b.cmp(a)))
+            .map(String::as_str)
+    }
+
+    /// Works out the fields of every MTI and family from the defaults and overrides
+    fn resolve(&mut self) {
+        self.message_specs.clear();
+        self.mandatory_fields.clear();
+
+        for mti in self.overrides.keys() {
+            let mut layers = self.overrides.iter()
+                .filter(|(pattern, _overrides)| mti_matches(pattern, mti))
+                .collect::<Vec<_>>();
+            layers.sort_by(|(a, _), (b, _)| mti_specificity(a).cmp(&mti_specificity(b)).then_with(|| a.cmp(b)));
+
+            let mut fields = self.default_fields.clone();
+            let mut mandatory = HashSet::new();
+            for (_pattern, overrides) in layers {
+                fields.extend(overrides.fields.iter().map(|(field, definition)| (*field, definition.clone())));
+                for (field, presence) in &overrides.presence {
+                    match presence {
+                        Presence::Mandatory => {
+                            mandatory.insert(*field);
+                        },
+                        Presence::Optional => {
+                            mandatory.remove(field);
+                        },
+                        Presence::Absent => {
+                            fields.remove(field);
+                            mandatory.remove(field);
+                        },
+                    }
+                }
+            }
+
+            self.message_specs.insert(mti.clone(), fields);
+            self.mandatory_fields.insert(mti.clone(), mandatory);
+        }
+    }
+
+    /// Sets the character set of everything but binary data, ASCII by default
+    pub fn set_charset(&mut self, charset: Charset) {
+        self.charset = charset;
+    }
+
+    pub fn charset(&self) -> Charset {
+        self.charset
+    }
+
+    /// Sets how messages are MACed, or that they aren't if `None`
+    pub fn set_mac(&mut self, mac: Option<MacSettings>) {
+        self.mac = mac;
+    }
+
+    pub fn mac(&self) -> Option<&MacSettings> {
+        self.mac.as_ref()
+    }
+
+    /// Sets whether fields' [`Padding`] is trimmed from their values as they're parsed, rather than kept as it was
+    /// sent. Padding is added back as messages are built either way.
+    pub fn set_trim_padding(&mut self, trim_padding: bool) {
+        self.trim_padding = trim_padding;
+    }
+
+    pub fn trim_padding(&self) -> bool {
+        self.trim_padding
+    }
+
+    /// Sets how a field is masked, or that it isn't if `None`
+    pub fn set_mask(&mut self, field: u16, mask: Option<Mask>) {
+        match mask {
+            Some(mask) => self.masks.insert(field, mask),
+            None => self.masks.remove(&field),
+        };
+    }
+
+    pub fn get_mask(&self, field: u16) -> Option<&Mask> {
+        self.masks.get(&field)
+    }
+
+    /// Names a field for dumps, the same in every MTI
+    pub fn set_field_name(&mut self, field: u16, name: FieldName) {
+        self.names.insert(field, name);
+    }
+
+    pub fn get_field_name(&self, field: u16) -> Option<&FieldName> {
+        self.names.get(&field)
+    }
+
+    /// A copy of tokenised fields with the sensitive ones masked
+    pub fn mask_fields(&self, fields: &HashMap<u16, String>) -> HashMap<u16, String> {
+        fields.iter()
+            .map(|(field, value)| {
+                let value = match self.masks.get(field) {
+                    Some(mask) => mask.apply(value),
+                    None => value.clone(),
+                };
+                (*field, value)
+            })
+            .collect()
     }
 }
 
@@ -0,0 +1,70 @@
+use std::collections::HashMap;
+use crate::iso8583::spec::Field;
+
+/// Stands for any digit in an MTI pattern, e.g. `02x0` for every authorisation request and response
+pub const MTI_WILDCARD: char = 'x';
+
+/// Whether a field must, may or mustn't be in messages of an MTI
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize), serde(rename_all = "lowercase"))]
+pub enum Presence {
+    Mandatory,
+    /// The default for every field the MTI defines
+    Optional,
+    /// The field isn't defined for the MTI, even if it's one of the spec's default fields
+    Absent,
+}
+
+/// What one MTI, or family of MTIs, changes from the spec's default fields
+#[derive(Debug, Clone, Default)]
+pub(crate) struct MtiOverrides {
+    pub fields: HashMap<u16, Field>,
+    pub presence: HashMap<u16, Presence>,
+}
+
+/// Whether an MTI, or a narrower pattern, is one of the pattern's family. Every MTI matches itself.
+pub fn mti_matches(pattern: &str, mti: &str) -> bool {
+    pattern.len() == mti.len() && pattern.chars()
+        .zip(mti.chars())
+        .all(|(p, m)| p == MTI_WILDCARD || p == m)
+}
+
+/// How narrow a pattern is, as the number of digits it fixes
+pub fn mti_specificity(pattern: &str) -> usize {
+    pattern.chars()
+        .filter(|c| *c != MTI_WILDCARD)
+        .count()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    macro_rules! match_tests {
+        ($($name:ident: $pattern:literal $mti:literal => $expected:expr,)*) => {
+            $(
+                #[test]
+                fn $name() {
+                    assert_eq!($expected, mti_matches($pattern, $mti));
+                }
+            )*
+        };
+    }
+
+    match_tests!(
+        match_exact: "0200" "0200" => true,
+        match_other: "0200" "0210" => false,
+        match_family: "02x0" "0210" => true,
+        match_other_family: "02x0" "0420" => false,
+        match_narrower_pattern: "0xx0" "02x0" => true,
+        match_wider_pattern: "02x0" "0xx0" => false,
+        match_length: "02x0" "02100" => false,
+    );
+
+    #[test]
+    fn specificity() {
+        assert_eq!(4, mti_specificity("0200"));
+        assert_eq!(3, mti_specificity("02x0"));
+        assert_eq!(0, mti_specificity("xxxx"));
+    }
+}
@@ -0,0 +1,18 @@
+/// What a field holds, for people reading messages rather than the engine
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct FieldName {
+    /// e.g. "Primary Account Number"
+    pub name: String,
+    #[cfg_attr(feature = "serde", serde(default, skip_serializing_if = "Option::is_none"))]
+    pub description: Option<String>,
+}
+
+impl FieldName {
+    pub fn new(name: &str, description: Option<&str>) -> Self {
+        FieldName {
+            name: name.to_string(),
+            description: description.map(|d| d.to_string()),
+        }
+    }
+}
@@ -0,0 +1,178 @@
+use std::collections::BTreeMap;
+use serde::{Deserialize, Serialize};
+use crate::{
+    iso8583::spec::{
+        DEFAULT_MASKS,
+        Field,
+        FieldName,
+        MacSettings,
+        Mask,
+        MtiOverrides,
+        Presence,
+        Spec,
+    },
+    util::Charset,
+};
+
+/// A spec as it's serialized, in field and MTI order so that the output is stable e.g.
+///
+/// ```json
+/// {
+///   "charset": "ascii",
+///   "fields": {"0": {"ftype": "AsciiBitmap", "raw_size": 64, "data_type": "Packed"}},
+///   "mtis": {"02x0": {"presence": {"4": "mandatory"}}},
+///   "masks": {"2": "pan"},
+///   "names": {"2": {"name": "Primary Account Number"}},
+///   "mac": "retail pad2",
+///   "trim_padding": true
+/// }
+/// ```
+///
+/// Everything is optional, with masks defaulting to the [`DEFAULT_MASKS`].
+#[derive(Serialize, Deserialize)]
+pub(super) struct SpecData {
+    #[serde(default)]
+    charset: Charset,
+    /// The default fields
+    #[serde(default)]
+    fields: BTreeMap<u16, Field>,
+    #[serde(default)]
+    mtis: BTreeMap<String, MtiData>,
+    #[serde(default = "default_masks")]
+    masks: BTreeMap<u16, Mask>,
+    #[serde(default)]
+    names: BTreeMap<u16, FieldName>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    mac: Option<MacSettings>,
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    trim_padding: bool,
+}
+
+#[derive(Serialize, Deserialize)]
+struct MtiData {
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    fields: BTreeMap<u16, Field>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    presence: BTreeMap<u16, Presence>,
+}
+
+fn default_masks() -> BTreeMap<u16, Mask> {
+    DEFAULT_MASKS.iter().cloned().collect()
+}
+
+impl From<SpecData> for Spec {
+    fn from(data: SpecData) -> Self {
+        let mut spec = Spec::new();
+        spec.charset = data.charset;
+        spec.default_fields = data.fields.into_iter().collect();
+        spec.overrides = data.mtis.into_iter()
+            .map(|(mti, overrides)| {
+                let overrides = MtiOverrides {
+                    fields: overrides.fields.into_iter().collect(),
+                    presence: overrides.presence.into_iter().collect(),
+                };
+                (mti, overrides)
+            })
+            .collect();
+        spec.masks = data.masks.into_iter().collect();
+        spec.names = data.names.into_iter().collect();
+        spec.mac = data.mac;
+        spec.trim_padding = data.trim_padding;
+        spec.resolve();
+        spec
+    }
+}
+
+impl From<Spec> for SpecData {
+    fn from(spec: Spec) -> Self {
+        SpecData {
+            charset: spec.charset,
+            fields: spec.default_fields.into_iter().collect(),
+            mtis: spec.overrides.into_iter()
+                .map(|(mti, overrides)| {
+                    let overrides = MtiData {
+                        fields: overrides.fields.into_iter().collect(),
+                        presence: overrides.presence.into_iter().collect(),
+                    };
+                    (mti, overrides)
+                })
+                .collect(),
+            masks: spec.masks.into_iter().collect(),
+            names: spec.names.into_iter().collect(),
+            mac: spec.mac,
+            trim_padding: spec.trim_padding,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use serde_json::json;
+    use super::*;
+    use crate::iso8583::spec::{
+        DataType,
+        Dictionary,
+        FieldType,
+        Padding,
+    };
+
+    #[test]
+    fn round_trip() {
+        let spec = "
+            extends iso8583-1993
+            charset ebcdic
+            field 0 AsciiBitmap(64)
+            mti 02x0
+            mandatory 4
+            mti 0210
+            field 39 Fixed(2:an) Response Code
+            field 41 Fixed(8:an,pad)
+            mask 2 redact
+            mac retail pad2
+            padding trim
+        ".parse::<Spec>().unwrap();
+
+        let json = serde_json::to_string(&spec).unwrap();
+        let parsed = serde_json::from_str::<Spec>(&json).unwrap();
+
+        assert_eq!(Charset::Ebcdic, parsed.charset());
+        assert_eq!(spec.get_mti_specs(), parsed.get_mti_specs());
+        assert_eq!(spec.get_mandatory_fields("0200"), parsed.get_mandatory_fields("0200"));
+        assert_eq!(Some(&Mask::Redact), parsed.get_mask(2));
+        assert_eq!("Response Code", parsed.get_field_name(39).unwrap().name);
+        assert_eq!(spec.mac(), parsed.mac());
+        assert!(parsed.trim_padding());
+        assert_eq!(json, serde_json::to_string(&parsed).unwrap());
+    }
+
+    #[test]
+    fn schema() {
+        let mut spec = Spec::standard(Dictionary::Iso1987, &["0200"]);
+        spec.set_presence("0200", 2, Presence::Mandatory);
+        let json = serde_json::to_value(&spec).unwrap();
+
+        assert_eq!(json!({"ftype": "LLVar", "raw_size": 19, "data_type": "Numeric"}), json["fields"]["2"]);
+        assert_eq!(None, json.get("trim_padding"));
+
+        let amount = Field::new(FieldType::Fixed, 12, DataType::Numeric).with_padding(Padding::default_for(&DataType::Numeric));
+        assert_eq!(
+            json!({"ftype": "Fixed", "raw_size": 12, "data_type": "Numeric", "padding": {"justification": "Right", "fill": "0"}}),
+            serde_json::to_value(&amount).unwrap(),
+        );
+        assert_eq!(json!({"presence": {"2": "mandatory"}}), json["mtis"]["0200"]);
+        assert_eq!(json!("pan"), json["masks"]["2"]);
+        assert_eq!(json!({"name": "Primary Account Number"}), json["names"]["2"]);
+    }
+
+    #[test]
+    fn defaults() {
+        let spec = serde_json::from_str::<Spec>(r#"{
+            "fields": {"0": {"ftype": "Bitmap", "raw_size": 64, "data_type": "Binary"}},
+            "mtis": {"0800": {}}
+        }"#).unwrap();
+
+        assert_eq!(Charset::Ascii, spec.charset());
+        assert_eq!(8, spec.get_mti_spec("0800").unwrap()[&0].size);
+        assert_eq!(Some(&Mask::Pan), spec.get_mask(2));
+    }
+}
@@ -0,0 +1,355 @@
+use std::collections::HashMap;
+use std::error;
+use std::fmt;
+use crate::iso8583::spec::{
+    DataType,
+    Field,
+    FieldType,
+    MTI_WILDCARD,
+    Padding,
+    Spec,
+};
+
+/// The most bits a bitmap can hold
+const MAX_BITMAP_SIZE: usize = 64;
+
+/// Something wrong with a spec which would otherwise only show up when a message of the MTI is parsed or built
+#[derive(Debug, Clone, PartialEq)]
+pub enum SpecError {
+    /// The MTI isn't 4 digits, or [`MTI_WILDCARD`]s
+    InvalidMti(String),
+    /// Field 0 isn't defined, or isn't a bitmap
+    NoPrimaryBitmap(String),
+    /// A bitmap isn't a whole number of bytes, or holds more than 64 bits
+    InvalidBitmapSize{
+        mti: String,
+        field: u16,
+        size: usize,
+    },
+    /// A bitmap other than the primary (0) or secondary (1)
+    MisplacedBitmap{
+        mti: String,
+        field: u16,
+    },
+    EmptyFixedField{
+        mti: String,
+        field: u16,
+    },
+    /// e.g. a binary bitmap with alphanumeric data
+    DataTypeMismatch{
+        mti: String,
+        field: u16,
+        ftype: FieldType,
+        data_type: DataType,
+    },
+    /// The field has no bit in the MTI's bitmaps
+    FieldOutsideBitmap{
+        mti: String,
+        field: u16,
+    },
+    /// Padding on a field other than a fixed one, which has no size to fill out to
+    PaddedVariableField{
+        mti: String,
+        field: u16,
+        ftype: FieldType,
+    },
+    /// Padding whose fill isn't a character of the field's data type, e.g. a space in a numeric field
+    InvalidFill{
+        mti: String,
+        field: u16,
+        data_type: DataType,
+        padding: Padding,
+    },
+    /// A fixed packed field with an odd number of digits, which isn't a whole number of bytes
+    OddPackedSize{
+        mti: String,
+        field: u16,
+        size: usize,
+    },
+}
+
+impl fmt::Display for SpecError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::InvalidMti(mti) => write!(f, "invalid MTI: {}", mti),
+            Self::NoPrimaryBitmap(mti) => write!(f, "MTI {} has no primary bitmap", mti),
+            Self::InvalidBitmapSize{ mti, field, size } => {
+                write!(f, "MTI {} field {} is a bitmap of {} bits, which must be a multiple of 8 up to {}", mti, field, size, MAX_BITMAP_SIZE)
+            },
+            Self::MisplacedBitmap{ mti, field } => write!(f, "MTI {} field {} is a bitmap but only fields 0 and 1 can be", mti, field),
+            Self::EmptyFixedField{ mti, field } => write!(f, "MTI {} field {} is fixed with no length", mti, field),
+            Self::DataTypeMismatch{ mti, field, ftype, data_type } => {
+                write!(f, "MTI {} field {} is a {} which can't hold {} data", mti, field, ftype, data_type)
+            },
+            Self::FieldOutsideBitmap{ mti, field } => write!(f, "MTI {} field {} is outside its bitmaps", mti, field),
+            Self::PaddedVariableField{ mti, field, ftype } => {
+                write!(f, "MTI {} field {} is a padded {} but only fixed fields can be padded", mti, field, ftype)
+            },
+            Self::InvalidFill{ mti, field, data_type, padding } => {
+                write!(f, "MTI {} field {} is {} data which can't be padded with {}", mti, field, data_type, padding)
+            },
+            Self::OddPackedSize{ mti, field, size } => {
+                write!(f, "MTI {} field {} is packed with {} digits, which must be even to make whole bytes", mti, field, size)
+            },
+        }
+    }
+}
+
+impl error::Error for SpecError {}
+
+impl Spec {
+    /// Checks every MTI's fields can be parsed and built, returning all the problems found in MTI and field order.
+    /// Specs are checked as they're loaded.
+    pub fn validate(&self) -> Result<(), Vec<SpecError>> {
+        let mut mtis = self.get_mti_specs().keys().collect::<Vec<_>>();
+        mtis.sort();
+
+        let errors = mtis.into_iter()
+            .flat_map(|mti| validate_mti(mti, &self.get_mti_specs()[mti]))
+            .collect::<Vec<_>>();
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors)
+        }
+    }
+}
+
+fn validate_mti(mti: &str, mti_spec: &HashMap<u16, Field>) -> Vec<SpecError> {
+    let mut errors = vec![];
+    if mti.len() != 4 || !mti.chars().all(|c| c.is_ascii_digit() || c == MTI_WILDCARD) {
+        errors.push(SpecError::InvalidMti(mti.to_string()));
+    }
+
+    let bitmap = |field| mti_spec.get(&field).filter(|definition: &&Field| definition.ftype.is_bitmap());
+    let (primary, secondary) = (bitmap(0), bitmap(1));
+    if primary.is_none() {
+        errors.push(SpecError::NoPrimaryBitmap(mti.to_string()));
+    }
+
+    let mut fields = mti_spec.keys().copied().collect::<Vec<_>>();
+    fields.sort_unstable();
+    for field in fields {
+        let definition = &mti_spec[&field];
+        let (ftype, size, data_type) = (&definition.ftype, definition.raw_size, &definition.data_type);
+
+        match ftype {
+            FieldType::Bitmap | FieldType::AsciiBitmap if field > 1 => {
+                errors.push(SpecError::MisplacedBitmap{ mti: mti.to_string(), field });
+            },
+            FieldType::Bitmap | FieldType::AsciiBitmap if size == 0 || size % 8 != 0 || size > MAX_BITMAP_SIZE => {
+                errors.push(SpecError::InvalidBitmapSize{ mti: mti.to_string(), field, size });
+            },
+            FieldType::Fixed if size == 0 => {
+                errors.push(SpecError::EmptyFixedField{ mti: mti.to_string(), field });
+            },
+            _ => {},
+        }
+
+        let data_type_matches = match ftype {
+            FieldType::Bitmap => *data_type == DataType::Binary,
+            FieldType::AsciiBitmap => *data_type == DataType::Packed,
+            _ => true,
+        };
+        if !data_type_matches {
+            errors.push(SpecError::DataTypeMismatch{
+                mti: mti.to_string(),
+                field,
+                ftype: ftype.clone(),
+                data_type: data_type.clone(),
+            });
+        }
+        if *ftype == FieldType::Fixed && *data_type == DataType::Packed && size % 2 != 0 {
+            errors.push(SpecError::OddPackedSize{ mti: mti.to_string(), field, size });
+        }
+
+        match &definition.padding {
+            Some(_) if *ftype != FieldType::Fixed => {
+                errors.push(SpecError::PaddedVariableField{ mti: mti.to_string(), field, ftype: ftype.clone() });
+            },
+            Some(padding) if !padding.fits(data_type) => {
+                errors.push(SpecError::InvalidFill{
+                    mti: mti.to_string(),
+                    field,
+                    data_type: data_type.clone(),
+                    padding: *padding,
+                });
+            },
+            _ => {},
+        }
+
+        let in_bitmap = match (field, primary, secondary) {
+            (0, _, _) => true,
+            (1..=64, Some(primary), _) => field as usize <= primary.raw_size,
+            (65..=128, _, Some(secondary)) => field as usize - 64 <= secondary.raw_size,
+            // without a primary bitmap that's the only error, rather than one for every field
+            (_, None, _) => true,
+            _ => false,
+        };
+        if !in_bitmap {
+            errors.push(SpecError::FieldOutsideBitmap{ mti: mti.to_string(), field });
+        }
+    }
+
+    errors
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    macro_rules! validate_tests {
+        ($($name:ident: $spec:literal => $expected:expr,)*) => {
+            $(
+                #[test]
+                fn $name() {
+                    let mut spec = Spec::new();
+                    for line in $spec.lines().map(str::trim).filter(|line| !line.is_empty()) {
+                        let (field, definition) = line.split_once(' ').unwrap();
+                        spec.set_mti_field("0200", field.parse().unwrap(), definition.parse().unwrap());
+                    }
+                    assert_eq!($expected, spec.validate());
+                }
+            )*
+        };
+    }
+
+    validate_tests!(
+        valid: "
+            0 Bitmap(64)
+            1 Bitmap(64)
+            2 LLVar(n..19)
+            128 Fixed(8:bin)
+        " => Ok(()),
+        no_primary_bitmap: "
+            2 LLVar(n)
+        " => Err(vec![SpecError::NoPrimaryBitmap("0200".to_string())]),
+        primary_bitmap_not_bitmap: "
+            0 Fixed(8:bin)
+        " => Err(vec![SpecError::NoPrimaryBitmap("0200".to_string())]),
+        bitmap_size: "
+            0 AsciiBitmap(12)
+        " => Err(vec![SpecError::InvalidBitmapSize{ mti: "0200".to_string(), field: 0, size: 12 }]),
+        bitmap_too_big: "
+            0 Bitmap(128)
+        " => Err(vec![SpecError::InvalidBitmapSize{ mti: "0200".to_string(), field: 0, size: 128 }]),
+        misplaced_bitmap: "
+            0 Bitmap(64)
+            5 Bitmap(64)
+        " => Err(vec![SpecError::MisplacedBitmap{ mti: "0200".to_string(), field: 5 }]),
+        empty_fixed_field: "
+            0 Bitmap(64)
+            4 Fixed(0:n)
+        " => Err(vec![SpecError::EmptyFixedField{ mti: "0200".to_string(), field: 4 }]),
+        field_1_not_bitmap: "
+            0 Bitmap(64)
+            1 Fixed(4:n)
+        " => Ok(()),
+        field_outside_primary: "
+            0 Bitmap(32)
+            33 Fixed(4:n)
+        " => Err(vec![SpecError::FieldOutsideBitmap{ mti: "0200".to_string(), field: 33 }]),
+        field_without_secondary: "
+            0 Bitmap(64)
+            70 Fixed(3:n)
+        " => Err(vec![SpecError::FieldOutsideBitmap{ mti: "0200".to_string(), field: 70 }]),
+        field_past_secondary: "
+            0 Bitmap(64)
+            1 Bitmap(64)
+            129 Fixed(3:n)
+        " => Err(vec![SpecError::FieldOutsideBitmap{ mti: "0200".to_string(), field: 129 }]),
+        odd_packed_size: "
+            0 Bitmap(64)
+            3 Fixed(6:packed)
+            4 Fixed(3:packed)
+        " => Err(vec![SpecError::OddPackedSize{ mti: "0200".to_string(), field: 4, size: 3 }]),
+    );
+
+    macro_rules! padding_tests {
+        ($($name:ident: $ftype:ident $size:literal $data_type:ident $justification:ident $fill:literal => $expected:pat,)*) => {
+            $(
+                #[test]
+                fn $name() {
+                    use crate::iso8583::spec::Justification;
+
+                    let mut spec = Spec::new();
+                    spec.set_mti_field("0200", 0, Field::new(FieldType::Bitmap, 64, DataType::Binary));
+                    let field = Field::new(FieldType::$ftype, $size, DataType::$data_type)
+                        .with_padding(Padding::new(Justification::$justification, $fill));
+                    spec.set_mti_field("0200", 2, field);
+                    let result = spec.validate();
+                    assert!(matches!(result.as_ref().map_err(Vec::as_slice), $expected), "{:?}", result);
+                }
+            )*
+        };
+    }
+
+    padding_tests!(
+        padded_fixed: Fixed 6 Numeric Right '0' => Ok(()),
+        padded_binary: Fixed 8 Binary Left '\u{ff}' => Ok(()),
+        padded_lvar: LVar 9 Numeric Right '0' => Err([SpecError::PaddedVariableField{ field: 2, ftype: FieldType::LVar, .. }]),
+        padded_llvar: LLVar 19 Numeric Right '0' => Err([SpecError::PaddedVariableField{ field: 2, ftype: FieldType::LLVar, .. }]),
+        padded_lllvar: LLLVar 0 Alphanum Left ' ' => Err([SpecError::PaddedVariableField{ field: 2, ftype: FieldType::LLLVar, .. }]),
+        non_digit_numeric_fill: Fixed 6 Numeric Right 'F' => Err([SpecError::InvalidFill{ field: 2, data_type: DataType::Numeric, .. }]),
+        space_numeric_fill: Fixed 6 Numeric Right ' ' => Err([SpecError::InvalidFill{ field: 2, data_type: DataType::Numeric, .. }]),
+        non_hex_packed_fill: Fixed 6 Packed Right 'G' => Err([SpecError::InvalidFill{ field: 2, data_type: DataType::Packed, .. }]),
+        non_ascii_alpha_fill: Fixed 8 Alpha Left '\u{ff}' => Err([SpecError::InvalidFill{ field: 2, data_type: DataType::Alpha, .. }]),
+        non_ascii_alphanum_fill: Fixed 8 Alphanum Left '\u{e9}' => Err([SpecError::InvalidFill{ field: 2, data_type: DataType::Alphanum, .. }]),
+        wide_binary_fill: Fixed 8 Binary Left '\u{100}' => Err([SpecError::InvalidFill{ field: 2, data_type: DataType::Binary, .. }]),
+    );
+
+    #[test]
+    fn bitmap_data_type_mismatch() {
+        let mut spec = Spec::new();
+        spec.set_mti_field("0200", 0, Field::new(FieldType::Bitmap, 64, DataType::Alphanum));
+
+        assert_eq!(
+            Err(vec![SpecError::DataTypeMismatch{
+                mti: "0200".to_string(),
+                field: 0,
+                ftype: FieldType::Bitmap,
+                data_type: DataType::Alphanum,
+            }]),
+            spec.validate(),
+        );
+    }
+
+    #[test]
+    fn invalid_mti() {
+        let mut spec = Spec::new();
+        spec.set_mti_field("02y0", 0, Field::new(FieldType::Bitmap, 64, DataType::Binary));
+        spec.set_mti_field("020", 0, Field::new(FieldType::Bitmap, 64, DataType::Binary));
+
+        assert_eq!(
+            Err(vec![SpecError::InvalidMti("020".to_string()), SpecError::InvalidMti("02y0".to_string())]),
+            spec.validate(),
+        );
+    }
+
+    #[test]
+    fn macro_invalid_fill() {
+        let spec = crate::iso8583_spec_build!{
+            "0200":
+                0: Bitmap, 64;
+                2: Fixed, 8, Alpha, pad(Left, '\u{ff}');
+                4: Fixed, 12, Numeric, pad;
+        };
+
+        assert!(matches!(
+            spec.validate().as_ref().map_err(Vec::as_slice),
+            Err([SpecError::InvalidFill{ field: 2, data_type: DataType::Alpha, .. }]),
+        ));
+        assert!("Fixed(8:a,fill=FF)".parse::<Field>().is_err());
+    }
+
+    #[test]
+    fn macro_bitmap_at_field_5() {
+        let spec = crate::iso8583_spec_build!{
+            "0200":
+                0: Bitmap, 64;
+                5: Bitmap, 64;
+        };
+
+        assert_eq!(Err(vec![SpecError::MisplacedBitmap{ mti: "0200".to_string(), field: 5 }]), spec.validate());
+    }
+}
@@ -0,0 +1,216 @@
+use std::error;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::str::FromStr;
+use crate::iso8583::{
+    spec::MTI_WILDCARD,
+    translate::{
+        Mapping,
+        Transform,
+        TransformParseError,
+    },
+};
+
+#[derive(Debug, PartialEq)]
+pub enum MappingParseError {
+    InvalidFormat(String),
+    InvalidFieldNumber(String),
+    /// An MTI isn't 4 digits, or [`MTI_WILDCARD`]s
+    InvalidMti(String),
+    InvalidTransform(TransformParseError),
+}
+
+impl fmt::Display for MappingParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::InvalidFormat(line) => write!(f, "invalid format: {}", line),
+            Self::InvalidFieldNumber(field) => write!(f, "invalid field number: {}", field),
+            Self::InvalidMti(mti) => write!(f, "invalid MTI: {}", mti),
+            Self::InvalidTransform(e) => e.fmt(f),
+        }
+    }
+}
+
+impl error::Error for MappingParseError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            Self::InvalidTransform(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<TransformParseError> for MappingParseError {
+    fn from(e: TransformParseError) -> Self {
+        Self::InvalidTransform(e)
+    }
+}
+
+impl Mapping {
+    /// Reads a mapping file, see [`Mapping::from_str`] for the format
+    pub fn load<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        fs::read_to_string(path)?
+            .parse()
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+}
+
+/// Parses a mapping, one setting per line with blank lines and lines starting `#` ignored:
+/// - `mti <from> <to>` with [`MTI_WILDCARD`]s for families e.g. `mti 0xxx 1xxx`
+/// - `renumber <from> <to>` to move a field
+/// - `drop <number>...` to leave fields out
+/// - `transform <number> <transform>` as parsed by [`Transform`], applied in the order given
+/// - `default <number> <value>`
+///
+/// ```
+/// use zaps::iso8583::translate::Mapping;
+///
+/// let mapping = "
+///     mti 0xxx 1xxx
+///     renumber 60 4
+///     drop 52
+///     transform 39 map 00=000 05=100
+///     default 24 200
+/// ".parse::<Mapping>().unwrap();
+///
+/// assert_eq!("1210", mapping.mti("0210"));
+/// ```
+impl FromStr for Mapping {
+    type Err = MappingParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut mapping = Mapping::new();
+
+        for line in s.lines().map(str::trim) {
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let invalid = || MappingParseError::InvalidFormat(line.to_string());
+            let mut words = line.splitn(2, char::is_whitespace);
+            let (kind, rest) = (words.next(), words.next().map(str::trim));
+
+            match (kind, rest) {
+                (Some("mti"), Some(rest)) => {
+                    let (from, to) = pair(rest).ok_or_else(invalid)?;
+                    let (from, to) = (parse_mti(from)?, parse_mti(to)?);
+                    mapping.map_mti(from, to);
+                },
+                (Some("renumber"), Some(rest)) => {
+                    let (from, to) = pair(rest).ok_or_else(invalid)?;
+                    mapping.renumber(parse_field_number(from)?, parse_field_number(to)?);
+                },
+                (Some("drop"), Some(numbers)) => {
+                    for number in numbers.split_whitespace() {
+                        mapping.drop_field(parse_field_number(number)?);
+                    }
+                },
+                (Some("transform"), Some(rest)) => {
+                    let (number, transform) = rest.split_once(char::is_whitespace)
+                        .ok_or_else(invalid)?;
+                    mapping.add_transform(parse_field_number(number)?, transform.trim().parse::<Transform>()?);
+                },
+                (Some("default"), Some(rest)) => {
+                    let (number, value) = rest.split_once(char::is_whitespace)
+                        .ok_or_else(invalid)?;
+                    mapping.set_default(parse_field_number(number)?, value.trim());
+                },
+                _ => return Err(invalid()),
+            }
+        }
+
+        Ok(mapping)
+    }
+}
+
+/// Exactly two words
+fn pair(s: &str) -> Option<(&str, &str)> {
+    let mut words = s.split_whitespace();
+    match (words.next(), words.next(), words.next()) {
+        (Some(first), Some(second), None) => Some((first, second)),
+        _ => None,
+    }
+}
+
+fn parse_mti(mti: &str) -> Result<&str, MappingParseError> {
+    if mti.len() == 4 && mti.chars().all(|c| c.is_ascii_digit() || c == MTI_WILDCARD) {
+        Ok(mti)
+    } else {
+        Err(MappingParseError::InvalidMti(mti.to_string()))
+    }
+}
+
+fn parse_field_number(number: &str) -> Result<u16, MappingParseError> {
+    number.parse()
+        .map_err(|_e| MappingParseError::InvalidFieldNumber(number.to_string()))
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::HashMap;
+    use super::*;
+    use crate::iso8583::MTI_FIELD;
+
+    #[test]
+    fn parse() {
+        let mapping = "
+            # acquirer to issuer
+            mti 0xxx 1xxx
+            mti 0800 1804
+            renumber 60 4
+            drop 52 53
+            transform 4 pad-left 12 0
+            transform 43 upper
+            default 24 200
+            default 43 ACME STORES
+        ".parse::<Mapping>().unwrap();
+
+        let mut expected = Mapping::new();
+        expected.map_mti("0xxx", "1xxx");
+        expected.map_mti("0800", "1804");
+        expected.renumber(60, 4);
+        expected.drop_field(52);
+        expected.drop_field(53);
+        expected.add_transform(4, Transform::PadLeft(12, '0'));
+        expected.add_transform(43, Transform::Upper);
+        expected.set_default(24, "200");
+        expected.set_default(43, "ACME STORES");
+        assert_eq!(expected, mapping);
+
+        let tokens = [(MTI_FIELD, "0200"), (52, "PIN"), (60, "1000")]
+            .iter()
+            .map(|(k, v)| (*k, v.to_string()))
+            .collect::<HashMap<_, _>>();
+        assert_eq!("000000001000", mapping.fields(&tokens)[&4]);
+    }
+
+    macro_rules! parse_error_tests {
+        ($($name:ident: $str:expr => $expect_err:pat,)*) => {
+            $(
+                #[test]
+                fn $name() -> Result<(), String> {
+                    let res = $str.parse::<Mapping>();
+                    if let Err($expect_err) = res {
+                        Ok(())
+                    } else {
+                        Err(format!("Unexpected result: {:?}", res))
+                    }
+                }
+            )*
+        };
+    }
+
+    parse_error_tests!(
+        error_unknown_setting: "swap 2 3" => MappingParseError::InvalidFormat(_),
+        error_mti_missing: "mti 0200" => MappingParseError::InvalidFormat(_),
+        error_mti: "mti 0200 120" => MappingParseError::InvalidMti(_),
+        error_mti_pattern: "mti 02y0 1200" => MappingParseError::InvalidMti(_),
+        error_renumber: "renumber 43 x" => MappingParseError::InvalidFieldNumber(_),
+        error_renumber_extra: "renumber 43 44 45" => MappingParseError::InvalidFormat(_),
+        error_drop: "drop 52 pin" => MappingParseError::InvalidFieldNumber(_),
+        error_transform: "transform 4 reverse" => MappingParseError::InvalidTransform(_),
+        error_transform_missing: "transform 4" => MappingParseError::InvalidFormat(_),
+        error_default: "default 24" => MappingParseError::InvalidFormat(_),
+    );
+}
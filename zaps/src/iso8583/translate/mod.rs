@@ -0,0 +1,406 @@
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::error;
+use std::fmt;
+use std::str::FromStr;
+use crate::{
+    core::{
+        Parser,
+        Unparser,
+    },
+    iso8583::{
+        engine::{
+            Iso8583Engine,
+            MTI_FIELD,
+        },
+        parse::Iso8583ParseError,
+        spec::{
+            mti_matches,
+            mti_specificity,
+            MTI_WILDCARD,
+        },
+        unparse::Iso8583UnparseError,
+    },
+};
+mod file;
+pub use file::MappingParseError;
+
+#[derive(Debug, PartialEq)]
+pub struct TransformParseError(String);
+
+impl fmt::Display for TransformParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "invalid transform: {}", self.0)
+    }
+}
+
+impl error::Error for TransformParseError {}
+
+/// A change to a field's value as it's translated
+#[derive(Debug, Clone, PartialEq)]
+pub enum Transform {
+    Trim,
+    Upper,
+    Lower,
+    /// Pads values shorter than the length on the left with the character, e.g. amounts with `0`
+    PadLeft(usize, char),
+    PadRight(usize, char),
+    /// Cuts values longer than the length down to it
+    Truncate(usize),
+    /// Replaces values found in the table, e.g. response codes, leaving the rest as they are
+    Map(BTreeMap<String, String>),
+}
+
+impl Transform {
+    pub fn apply(&self, value: &str) -> String {
+        match self {
+            Self::Trim => value.trim().to_string(),
+            Self::Upper => value.to_uppercase(),
+            Self::Lower => value.to_lowercase(),
+            Self::PadLeft(len, pad) => {
+                let padding = len.saturating_sub(value.chars().count());
+                std::iter::repeat_n(*pad, padding).chain(value.chars()).collect()
+            },
+            Self::PadRight(len, pad) => {
+                let padding = len.saturating_sub(value.chars().count());
+                value.chars().chain(std::iter::repeat_n(*pad, padding)).collect()
+            },
+            Self::Truncate(len) => value.chars().take(*len).collect(),
+            Self::Map(table) => table.get(value).cloned().unwrap_or_else(|| value.to_string()),
+        }
+    }
+}
+
+/// Parses a transform as written in mapping files: `trim`, `upper`, `lower`, `pad-left <length> [char]`,
+/// `pad-right <length> [char]`, `truncate <length>` or `map <from>=<to>...`. Padding is with spaces unless a
+/// character is given.
+///
+/// ```
+/// use zaps::iso8583::translate::Transform;
+///
+/// assert_eq!("000000001000", "pad-left 12 0".parse::<Transform>().unwrap().apply("1000"));
+/// assert_eq!("100", "map 00=000 05=100".parse::<Transform>().unwrap().apply("05"));
+/// ```
+impl FromStr for Transform {
+    type Err = TransformParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || TransformParseError(s.to_string());
+        let mut words = s.split_whitespace();
+        let kind = words.next().ok_or_else(invalid)?;
+        let args = words.collect::<Vec<_>>();
+        let len = |arg: Option<&&str>| arg.and_then(|len| len.parse::<usize>().ok()).ok_or_else(invalid);
+        let pad = |arg: Option<&&str>| {
+            let mut chars = arg.map_or(" ", |pad| *pad).chars();
+            match (chars.next(), chars.next()) {
+                (Some(pad), None) => Ok(pad),
+                _ => Err(invalid()),
+            }
+        };
+
+        match (kind, args.len()) {
+            ("trim", 0) => Ok(Self::Trim),
+            ("upper", 0) => Ok(Self::Upper),
+            ("lower", 0) => Ok(Self::Lower),
+            ("pad-left", 1..=2) => Ok(Self::PadLeft(len(args.first())?, pad(args.get(1))?)),
+            ("pad-right", 1..=2) => Ok(Self::PadRight(len(args.first())?, pad(args.get(1))?)),
+            ("truncate", 1) => Ok(Self::Truncate(len(args.first())?)),
+            ("map", 1..) => args.iter()
+                .map(|pair| pair.split_once('=')
+                    .map(|(from, to)| (from.to_string(), to.to_string()))
+                    .ok_or_else(invalid))
+                .collect::<Result<_, _>>()
+                .map(Self::Map),
+            _ => Err(invalid()),
+        }
+    }
+}
+
+/// How a message of one spec becomes a message of another.
+///
+/// MTIs are mapped first, by their own mapping or else that of the narrowest pattern they match, with
+/// [`MTI_WILDCARD`]s in the mapped MTI keeping the digit at that position e.g. `0xxx` to `1xxx` for 1987 to 1993.
+/// MTIs without a mapping are kept. Fields are then dropped and renumbered, both by the source's field numbers, with
+/// the rest kept as they are. Transforms and then defaults apply to the fields as they've been renumbered.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Mapping {
+    mtis: HashMap<String, String>,
+    fields: HashMap<u16, u16>,
+    dropped: HashSet<u16>,
+    transforms: HashMap<u16, Vec<Transform>>,
+    defaults: HashMap<u16, String>,
+}
+
+impl Mapping {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn map_mti(&mut self, from: &str, to: &str) {
+        self.mtis.insert(from.to_string(), to.to_string());
+    }
+
+    /// Moves a field to another number, replacing any field already there
+    pub fn renumber(&mut self, from: u16, to: u16) {
+        self.fields.insert(from, to);
+    }
+
+    pub fn drop_field(&mut self, field: u16) {
+        self.dropped.insert(field);
+    }
+
+    /// Adds a transform after any others of the field
+    pub fn add_transform(&mut self, field: u16, transform: Transform) {
+        self.transforms.entry(field).or_default().push(transform);
+    }
+
+    /// Sets a value for the field when the translated message doesn't have one, so long as the translated MTI's spec
+    /// defines the field
+    pub fn set_default(&mut self, field: u16, value: &str) {
+        self.defaults.insert(field, value.to_string());
+    }
+
+    /// The MTI as it's translated
+    pub fn mti(&self, mti: &str) -> String {
+        if let Some(to) = self.mtis.get(mti) {
+            return to.clone();
+        }
+        let pattern = self.mtis.keys()
+            .filter(|pattern| mti_matches(pattern, mti))
+            .max_by(|a, b| mti_specificity(a).cmp(&mti_specificity(b)).then_with(|| b.cmp(a)));
+        match pattern {
+            Some(pattern) => self.mtis[pattern].chars()
+                .zip(mti.chars())
+                .map(|(to, from)| if to == MTI_WILDCARD { from } else { to })
+                .collect(),
+            None => mti.to_string(),
+        }
+    }
+
+    /// The fields as they're translated, without the defaults which depend on the target spec
+    fn fields(&self, tokens: &HashMap<u16, String>) -> HashMap<u16, String> {
+        let (renumbered, kept) = tokens.iter()
+            .filter(|(field, _value)| **field != MTI_FIELD && !self.dropped.contains(field))
+            .partition::<Vec<_>, _>(|(field, _value)| self.fields.contains_key(field));
+
+        // renumbered fields go in last, so they replace any kept field of the same number
+        let mut fields = kept.into_iter()
+            .chain(renumbered.into_iter().map(|(field, value)| (&self.fields[field], value)))
+            .map(|(field, value)| (*field, value.clone()))
+            .collect::<HashMap<_, _>>();
+        for (field, transforms) in &self.transforms {
+            if let Some(value) = fields.get_mut(field) {
+                *value = transforms.iter().fold(value.clone(), |value, transform| transform.apply(&value));
+            }
+        }
+        if let Some(mti) = tokens.get(&MTI_FIELD) {
+            fields.insert(MTI_FIELD, self.mti(mti));
+        }
+        fields
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub enum TranslateError {
+    Parse(Iso8583ParseError),
+    Unparse(Iso8583UnparseError),
+}
+
+impl fmt::Display for TranslateError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Parse(e) => write!(f, "unable to parse message: {}", e),
+            Self::Unparse(e) => write!(f, "unable to build translated message: {}", e),
+        }
+    }
+}
+
+impl error::Error for TranslateError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            Self::Parse(e) => Some(e),
+            Self::Unparse(e) => Some(e),
+        }
+    }
+}
+
+impl From<Iso8583ParseError> for TranslateError {
+    fn from(e: Iso8583ParseError) -> Self {
+        Self::Parse(e)
+    }
+}
+
+impl From<Iso8583UnparseError> for TranslateError {
+    fn from(e: Iso8583UnparseError) -> Self {
+        Self::Unparse(e)
+    }
+}
+
+/// Translates messages from one spec to another, e.g. between the dialects either side of a switch, parsing with the
+/// source engine and building with the target's. Each direction needs a translator of its own.
+pub struct Translator {
+    source: Iso8583Engine,
+    target: Iso8583Engine,
+    mapping: Mapping,
+}
+
+impl Translator {
+    pub fn new(source: Iso8583Engine, target: Iso8583Engine, mapping: Mapping) -> Self {
+        Translator {
+            source,
+            target,
+            mapping,
+        }
+    }
+
+    pub fn source(&self) -> &Iso8583Engine {
+        &self.source
+    }
+
+    pub fn target(&self) -> &Iso8583Engine {
+        &self.target
+    }
+
+    pub fn mapping(&self) -> &Mapping {
+        &self.mapping
+    }
+
+    /// Parses a message of the source spec and builds it as one of the target's
+    pub fn translate(&self, payload: &[u8], out: &mut Vec<u8>) -> Result<(), TranslateError> {
+        let tokens = self.source.parse(payload)?;
+        self.target.unparse(&self.translate_tokens(&tokens), out)?;
+        Ok(())
+    }
+
+    /// Tokens parsed with the source spec as they'd be built with the target's. The source's bitmaps are left out as
+    /// the target works out its own.
+    pub fn translate_tokens(&self, tokens: &HashMap<u16, String>) -> HashMap<u16, String> {
+        let source_spec = tokens.get(&MTI_FIELD)
+            .and_then(|mti| self.source.spec().get_mti_spec(mti));
+        let tokens = tokens.iter()
+            .filter(|(field, _value)| {
+                !source_spec.and_then(|mti_spec| mti_spec.get(field)).is_some_and(|definition| definition.ftype.is_bitmap())
+            })
+            .map(|(field, value)| (*field, value.clone()))
+            .collect();
+
+        let mut fields = self.mapping.fields(&tokens);
+        let target_spec = fields.get(&MTI_FIELD)
+            .and_then(|mti| self.target.spec().get_mti_spec(mti));
+        if let Some(target_spec) = target_spec {
+            for (field, value) in &self.mapping.defaults {
+                if target_spec.contains_key(field) && !fields.contains_key(field) {
+                    fields.insert(*field, value.clone());
+                }
+            }
+        }
+        fields
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::iso8583::spec::Spec;
+
+    macro_rules! transform_tests {
+        ($($name:ident: $transform:literal $value:literal => $expected:literal,)*) => {
+            $(
+                #[test]
+                fn $name() {
+                    assert_eq!($expected, $transform.parse::<Transform>().unwrap().apply($value));
+                }
+            )*
+        };
+    }
+
+    transform_tests!(
+        transform_trim: "trim" "  ACME  " => "ACME",
+        transform_upper: "upper" "acme" => "ACME",
+        transform_lower: "lower" "ACME" => "acme",
+        transform_pad_left: "pad-left 6 0" "42" => "000042",
+        transform_pad_left_longer: "pad-left 2 0" "1000" => "1000",
+        transform_pad_right_space: "pad-right 6" "ACME" => "ACME  ",
+        transform_truncate: "truncate 4" "ACME STORES" => "ACME",
+        transform_map: "map 00=000 05=100" "00" => "000",
+        transform_map_missing: "map 00=000 05=100" "51" => "51",
+    );
+
+    #[test]
+    fn transform_errors() {
+        for transform in &["", "reverse", "trim 2", "pad-left", "pad-left x", "pad-left 4 00", "truncate", "map", "map 00"] {
+            assert_eq!(Err(TransformParseError(transform.to_string())), transform.parse::<Transform>(), "{:?}", transform);
+        }
+    }
+
+    #[test]
+    fn mti() {
+        let mut mapping = Mapping::new();
+        mapping.map_mti("0xxx", "1xxx");
+        mapping.map_mti("04xx", "14xx");
+        mapping.map_mti("0420", "1420");
+        mapping.map_mti("08x0", "18x4");
+        mapping.map_mti("0800", "1804");
+
+        assert_eq!("1200", mapping.mti("0200"));
+        assert_eq!("1420", mapping.mti("0420"));
+        assert_eq!("1401", mapping.mti("0401"));
+        assert_eq!("1814", mapping.mti("0810"));
+        assert_eq!("1804", mapping.mti("0800"));
+        assert_eq!("2200", mapping.mti("2200"));
+    }
+
+    #[test]
+    fn fields() {
+        let mut mapping = Mapping::new();
+        mapping.map_mti("0200", "1200");
+        mapping.renumber(43, 44);
+        mapping.renumber(44, 45);
+        mapping.renumber(60, 4);
+        mapping.drop_field(52);
+        mapping.add_transform(44, Transform::Trim);
+        mapping.add_transform(44, Transform::Upper);
+        let tokens = [(MTI_FIELD, "0200"), (4, "100"), (43, " acme "), (44, "X"), (52, "PIN"), (60, "000000001000")]
+            .iter()
+            .map(|(k, v)| (*k, v.to_string()))
+            .collect();
+
+        let expected = [(MTI_FIELD, "1200"), (4, "000000001000"), (44, "ACME"), (45, "X")]
+            .iter()
+            .map(|(k, v)| (*k, v.to_string()))
+            .collect::<HashMap<_, _>>();
+        assert_eq!(expected, mapping.fields(&tokens));
+    }
+
+    #[test]
+    fn defaults_and_bitmaps() {
+        let source = "
+            field 0 AsciiBitmap(64)
+            field 1 AsciiBitmap(64)
+            field 4 Fixed(12:n)
+            mti 0200
+        ".parse::<Spec>().unwrap();
+        let target = "
+            field 0 Bitmap(64)
+            field 1 Fixed(4:n)
+            field 4 Fixed(12:n)
+            field 24 Fixed(3:n)
+            mti 1200
+        ".parse::<Spec>().unwrap();
+        let mut mapping = Mapping::new();
+        mapping.map_mti("0200", "1200");
+        mapping.set_default(24, "200");
+        mapping.set_default(25, "00");
+        mapping.set_default(4, "000000000000");
+        let translator = Translator::new(Iso8583Engine::new(source), Iso8583Engine::new(target), mapping);
+        let tokens = [(MTI_FIELD, "0200"), (0, "1001"), (1, "0000"), (4, "000000001000")]
+            .iter()
+            .map(|(k, v)| (*k, v.to_string()))
+            .collect();
+
+        let expected = [(MTI_FIELD, "1200"), (4, "000000001000"), (24, "200")]
+            .iter()
+            .map(|(k, v)| (*k, v.to_string()))
+            .collect::<HashMap<_, _>>();
+        assert_eq!(expected, translator.translate_tokens(&tokens));
+    }
+}
@@ -0,0 +1,240 @@
+use std::borrow::Cow;
+use std::error;
+use std::fmt;
+use crate::{
+    iso8583::spec::{
+        DataType,
+        Field,
+        FieldType,
+    },
+    util::{
+        Charset,
+        string_to_bytes,
+    },
+};
+
+#[derive(Debug, PartialEq)]
+pub enum Iso8583UnparseError {
+    NoMti,
+    InvalidMti(String),
+    NoMtiDefinition,
+    NoTokenDefinition(u16),
+    FieldOutsideBitmap(u16),
+    InvalidLength{
+        field: u16,
+        length: usize,
+        max: usize,
+    },
+    InvalidFieldDefinition,
+    /// A field the MTI's spec makes mandatory wasn't given
+    MissingField(u16),
+    /// A binary field's value has a char which doesn't fit in a byte
+    InvalidBinary(u16),
+    /// The message couldn't be MACed, e.g. as the spec no longer has MAC settings
+    Mac(String),
+}
+
+impl fmt::Display for Iso8583UnparseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::NoMti => write!(f, "no MTI provided"),
+            Self::InvalidMti(mti) => write!(f, "invalid MTI: {}", mti),
+            Self::NoMtiDefinition => write!(f, "no MTI definition found"),
+            Self::NoTokenDefinition(field) => write!(f, "no token definition found for field {}", field),
+            Self::FieldOutsideBitmap(field) => write!(f, "field {} cannot be represented in the bitmap", field),
+            Self::InvalidLength{ field, length, max } => write!(f, "field {} has length {} but must be at most {}", field, length, max),
+            Self::InvalidFieldDefinition => write!(f, "invalid field definition"),
+            Self::MissingField(field) => write!(f, "mandatory field {} missing", field),
+            Self::InvalidBinary(field) => write!(f, "field {} has a binary value with a char outside a byte", field),
+            Self::Mac(reason) => write!(f, "unable to MAC the message: {}", reason),
+        }
+    }
+}
+
+impl error::Error for Iso8583UnparseError {}
+
+pub fn untokenise_bitmap(out: &mut Vec<u8>, bitmap: u64, bitmap_defn: &Field, charset: &Charset) -> Result<(), Iso8583UnparseError> {
+    let Field{ raw_size, size, data_type, .. } = bitmap_defn;
+    let bytes = bitmap.to_be_bytes();
+
+    match data_type {
+        DataType::Binary => {
+            out.extend_from_slice(&bytes[..*size]);
+        },
+        DataType::Packed => {
+            let encoded = hex::encode_upper(&bytes[..raw_size.div_ceil(8)]);
+            out.extend_from_slice(&charset.encode(&encoded.as_bytes()[..*size]));
+        },
+        _ => {
+            return Err(Iso8583UnparseError::InvalidFieldDefinition)
+        }
+    }
+
+    Ok(())
+}
+
+pub fn untokenise_field(out: &mut Vec<u8>, field: &Field, field_num: u16, value: &str, charset: &Charset) -> Result<(), Iso8583UnparseError> {
+    // fixed fields are filled out to their size, with values too long still an error
+    let value = match (&field.ftype, &field.padding) {
+        (FieldType::Fixed, Some(padding)) => padding.pad(value, field.size),
+        _ => Cow::Borrowed(value),
+    };
+    let data = match field.data_type {
+        DataType::Binary => string_to_bytes(&value)
+            .ok_or(Iso8583UnparseError::InvalidBinary(field_num))?,
+        _ => charset.encode(value.as_bytes()).into_owned(),
+    };
+    let length = data.len();
+
+    match field.ftype.var_size_len() {
+        Some(field_size_len) => {
+            let max = match field.raw_size {
+                0 => 10usize.pow(field_size_len as u32) - 1,
+                max => max,
+            };
+            if length > max {
+                return Err(Iso8583UnparseError::InvalidLength{ field: field_num, length, max });
+            }
+            out.extend_from_slice(&charset.encode(format!("{:0width$}", length, width = field_size_len).as_bytes()));
+        },
+        None if field.ftype == FieldType::Fixed => {
+            if length != field.size {
+                return Err(Iso8583UnparseError::InvalidLength{ field: field_num, length, max: field.size });
+            }
+        },
+        None => {
+            return Err(Iso8583UnparseError::InvalidFieldDefinition)
+        },
+    }
+
+    out.extend_from_slice(&data);
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    mod untokenise_bitmap {
+        use super::*;
+
+        #[test]
+        fn binary() {
+            let mut out = vec![];
+            untokenise_bitmap(&mut out, 0x8080_0000_0040_0001, &Field::new(FieldType::Bitmap, 64, DataType::Binary), &Charset::Ascii).unwrap();
+            assert_eq!(vec![0x80, 0x80, 0x00, 0x00, 0x00, 0x40, 0x00, 0x01], out);
+        }
+
+        #[test]
+        fn ascii() {
+            let mut out = vec![];
+            untokenise_bitmap(&mut out, 0x8200_0000_0000_0000, &Field::new(FieldType::AsciiBitmap, 8, DataType::Packed), &Charset::Ascii).unwrap();
+            assert_eq!(b"82".to_vec(), out);
+        }
+
+        #[test]
+        fn invalid_definition() {
+            let mut out = vec![];
+            let result = untokenise_bitmap(&mut out, 0, &Field::new(FieldType::Bitmap, 8, DataType::Alpha), &Charset::Ascii);
+            assert_eq!(Err(Iso8583UnparseError::InvalidFieldDefinition), result);
+        }
+    }
+
+    mod untokenise_field {
+        use super::*;
+        use crate::iso8583::spec::{
+            Justification,
+            Padding,
+        };
+
+        macro_rules! test_untokenise_field {
+            ($(
+                $name:ident:
+                $value:literal $field_type:ident $field_size:literal
+                =>
+                $expected:literal;
+            )*) => {
+                $(
+                    #[test]
+                    fn $name() {
+                        let field = Field::new(FieldType::$field_type, $field_size, DataType::Alphanum);
+                        let mut out = vec![];
+                        untokenise_field(&mut out, &field, 1, $value, &Charset::Ascii).unwrap();
+                        assert_eq!($expected.as_bytes(), &out[..]);
+                    }
+                )*
+            };
+        }
+
+        test_untokenise_field!(
+            lvar: "ABC" LVar 0 => "3ABC";
+            llvar: "ABC" LLVar 0 => "03ABC";
+            lllvar: "ABC" LLLVar 0 => "003ABC";
+            lllvar_empty: "" LLLVar 0 => "000";
+            fixed: "ABC" Fixed 3 => "ABC";
+        );
+
+        #[test]
+        fn var_too_long() {
+            let field = Field::new(FieldType::LVar, 0, DataType::Alphanum);
+            let mut out = vec![];
+            let result = untokenise_field(&mut out, &field, 2, "0123456789", &Charset::Ascii);
+            assert_eq!(Err(Iso8583UnparseError::InvalidLength{ field: 2, length: 10, max: 9 }), result);
+        }
+
+        macro_rules! test_untokenise_padded_field {
+            ($(
+                $name:ident:
+                $value:literal $field_size:literal $data_type:ident
+                =>
+                $expected:expr;
+            )*) => {
+                $(
+                    #[test]
+                    fn $name() {
+                        let field = Field::new(FieldType::Fixed, $field_size, DataType::$data_type)
+                            .with_padding(Padding::default_for(&DataType::$data_type));
+                        let mut out = vec![];
+                        untokenise_field(&mut out, &field, 1, $value, &Charset::Ascii).unwrap();
+                        assert_eq!(&$expected[..], &out[..]);
+                    }
+                )*
+            };
+        }
+
+        test_untokenise_padded_field!(
+            padded_numeric: "1000" 12 Numeric => b"000000001000";
+            padded_alphanum: "TERM01" 8 Alphanum => b"TERM01  ";
+            padded_binary: "\u{1}\u{2}" 4 Binary => [0x01, 0x02, 0x00, 0x00];
+            padded_full: "123456" 6 Numeric => b"123456";
+            padded_empty: "" 3 Alpha => b"   ";
+        );
+
+        #[test]
+        fn padded_ebcdic() {
+            let field = Field::new(FieldType::Fixed, 4, DataType::Alphanum)
+                .with_padding(Padding::new(Justification::Right, '*'));
+            let mut out = vec![];
+            untokenise_field(&mut out, &field, 1, "AB", &Charset::Ebcdic).unwrap();
+            assert_eq!(Charset::Ebcdic.encode(b"**AB").into_owned(), out);
+        }
+
+        #[test]
+        fn padded_too_long() {
+            let field = Field::new(FieldType::Fixed, 2, DataType::Numeric)
+                .with_padding(Padding::default_for(&DataType::Numeric));
+            let mut out = vec![];
+            let result = untokenise_field(&mut out, &field, 3, "123", &Charset::Ascii);
+            assert_eq!(Err(Iso8583UnparseError::InvalidLength{ field: 3, length: 3, max: 2 }), result);
+        }
+
+        #[test]
+        fn fixed_wrong_length() {
+            let field = Field::new(FieldType::Fixed, 4, DataType::Alphanum);
+            let mut out = vec![];
+            let result = untokenise_field(&mut out, &field, 3, "ABC", &Charset::Ascii);
+            assert_eq!(Err(Iso8583UnparseError::InvalidLength{ field: 3, length: 3, max: 4 }), result);
+        }
+    }
+}
@@ -1,3 +1,7 @@
+pub mod card;
 pub mod core;
+#[cfg(feature = "crypto")]
+pub mod crypto;
+pub mod emv;
 pub mod iso8583;
 pub mod util;
\ No newline at end of file
@@ -100,7 +100,7 @@ mod test {
         #[test]
         fn standard_size() {
             let raw_bitmap = "8080000000400001".as_bytes();
-            let bitmap = decode_ascii_bitmap(&raw_bitmap, raw_bitmap.len()).unwrap();
+            let bitmap = decode_ascii_bitmap(raw_bitmap, raw_bitmap.len()).unwrap();
 
             for i in 0..64 {
                 let bitpos = 63 - i;
@@ -114,7 +114,7 @@ mod test {
         #[test]
         fn minimum_size() {
             let raw_bitmap = "82".as_bytes();
-            let bitmap = decode_ascii_bitmap(&raw_bitmap, raw_bitmap.len()).unwrap();
+            let bitmap = decode_ascii_bitmap(raw_bitmap, raw_bitmap.len()).unwrap();
 
             for i in 0..64 {
                 let bitpos = 63 - i;
@@ -128,7 +128,7 @@ mod test {
         #[test]
         fn odd_size_err() {
             let raw_bitmap = "820".as_bytes();
-            let bitmap = decode_ascii_bitmap(&raw_bitmap, raw_bitmap.len());
+            let bitmap = decode_ascii_bitmap(raw_bitmap, raw_bitmap.len());
 
             assert_eq!(Err(DecodeBitmapError::OddLength), bitmap);
         }
@@ -136,7 +136,7 @@ mod test {
         #[test]
         fn too_small_err() {
             let raw_bitmap = "82".as_bytes();
-            let bitmap = decode_ascii_bitmap(&raw_bitmap, 4);
+            let bitmap = decode_ascii_bitmap(raw_bitmap, 4);
 
             assert_eq!(Err(DecodeBitmapError::BitmapTooShort{
                 actual: 2,
@@ -147,7 +147,7 @@ mod test {
         #[test]
         fn too_large_err() {
             let raw_bitmap = "8200".as_bytes();
-            let bitmap = decode_ascii_bitmap(&raw_bitmap, 2);
+            let bitmap = decode_ascii_bitmap(raw_bitmap, 2);
 
             assert_eq!(Err(DecodeBitmapError::BitmapTooLarge{
                 actual: 4,
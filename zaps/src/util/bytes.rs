@@ -1,9 +1,19 @@
 extern crate hex;
 
+use std::convert::TryFrom;
+
+/// Each byte as the char with the same code point, so that any bytes can be held in a string
 pub fn byte_to_string(raw: &[u8]) -> String {
     raw.iter().map(|b| *b as char).collect()
 }
 
+/// The reverse of [`byte_to_string`], or none if a char doesn't fit in a byte
+pub fn string_to_bytes(value: &str) -> Option<Vec<u8>> {
+    value.chars()
+        .map(|c| u8::try_from(c as u32).ok())
+        .collect()
+}
+
 pub fn byte_to_hex_string(raw: &[u8]) -> String {
     format!("0x{}", hex::encode(raw))
 }
\ No newline at end of file
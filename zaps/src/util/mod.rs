@@ -1,5 +1,6 @@
 mod bitmap;
 mod bytes;
+mod charset;
 
 pub use bitmap::{
     decode_ascii_bitmap,
@@ -10,4 +11,10 @@ pub use bitmap::{
 pub use bytes::{
     byte_to_hex_string,
     byte_to_string,
-};
\ No newline at end of file
+    string_to_bytes,
+};
+
+pub use charset::{
+    Charset,
+    CharsetParseError,
+};
@@ -1,14 +1,30 @@
+use std::collections::HashMap;
 use zaps::{
-    core::Parser,
+    core::{
+        Parser,
+        Unparser,
+    },
     iso8583::{
         Iso8583Engine,
+        Iso8583ParseError,
+        Iso8583UnparseError,
+        MTI_FIELD,
         spec::{
             DataType,
+            Dictionary,
             Field,
             FieldType,
+            Mask,
+            Spec,
+        },
+        translate::{
+            Mapping,
+            TranslateError,
+            Translator,
         },
     },
     iso8583_spec_build,
+    util::Charset,
 };
 
 macro_rules! assert_spec_has_field {
@@ -40,7 +56,7 @@ fn it_works() {
             5: AsciiBitmap, 64;
     };
 
-    let spec_0200 = spec.get_mti_spec(&"0200").unwrap();
+    let spec_0200 = spec.get_mti_spec("0200").unwrap();
     assert_spec_has_field!(spec_0200:
         1: LLLVar 0 Alphanum,
         2: LLVar 0 Alphanum,
@@ -50,7 +66,7 @@ fn it_works() {
         64: Fixed 16 Hex,
     );
 
-    let spec_0210 = spec.get_mti_spec(&"0210").unwrap();
+    let spec_0210 = spec.get_mti_spec("0210").unwrap();
     assert_spec_has_field!(spec_0210:
         1: LLLVar 0 Numeric,
         2: LLVar 0 Packed,
@@ -70,4 +86,281 @@ fn it_works() {
         .unwrap();
 
     println!("{:?}", tokens);
-}
\ No newline at end of file
+}
+#[test]
+fn round_trip() {
+    let spec = iso8583_spec_build!{
+        "0200":
+            0: AsciiBitmap, 64;
+            2: LLVar, Numeric;
+            4: Fixed, 12, Numeric;
+            11: Fixed, 6, Numeric;
+            41: Fixed, 8, Alphanum;
+    };
+    let engine = Iso8583Engine::new(spec);
+    let payload = "02005020000000800000164111111111111111000000001000123456TERMID01".as_bytes();
+
+    let tokens = engine.parse(payload).unwrap();
+    assert_eq!("0200", tokens[&MTI_FIELD]);
+    assert_eq!("4111111111111111", tokens[&2]);
+    assert_eq!("000000001000", tokens[&4]);
+    assert_eq!("123456", tokens[&11]);
+    assert_eq!("TERMID01", tokens[&41]);
+
+    let mut out = vec![];
+    engine.unparse(&tokens, &mut out).unwrap();
+    assert_eq!(payload, out);
+}
+
+#[test]
+fn padding() {
+    let mut spec = "
+        field 0 AsciiBitmap(64)
+        field 4 Fixed(12:n,pad)
+        field 41 Fixed(8:an,pad)
+        field 52 Fixed(8:bin,fill=FF)
+        mti 0200
+    ".parse::<Spec>().unwrap();
+    let tokens = [(MTI_FIELD, "0200"), (4, "1000"), (41, "TERM1"), (52, "\u{12}\u{34}")]
+        .iter()
+        .map(|(k, v)| (*k, v.to_string()))
+        .collect::<HashMap<_, _>>();
+
+    let mut out = vec![];
+    Iso8583Engine::new(spec.clone()).unparse(&tokens, &mut out).unwrap();
+    let mut expected = b"02001000000000801000000000001000TERM1   ".to_vec();
+    expected.extend_from_slice(&[0x12, 0x34, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff]);
+    assert_eq!(expected, out);
+
+    let parsed = Iso8583Engine::new(spec.clone()).parse(&out).unwrap();
+    assert_eq!("000000001000", parsed[&4]);
+    assert_eq!("TERM1   ", parsed[&41]);
+
+    spec.set_trim_padding(true);
+    let parsed = Iso8583Engine::new(spec).parse(&out).unwrap();
+    assert_eq!(tokens, parsed.into_iter().filter(|(field, _value)| *field != 0).collect());
+}
+
+#[test]
+fn unparse_errors() {
+    let spec = iso8583_spec_build!{
+        "0200":
+            0: AsciiBitmap, 8;
+            1: LLLVar, Alpha;
+    };
+    let engine = Iso8583Engine::new(spec);
+    let mut out = vec![];
+
+    let mut fields = HashMap::new();
+    assert_eq!(Err(Iso8583UnparseError::NoMti), engine.unparse(&fields, &mut out));
+
+    fields.insert(MTI_FIELD, "0210".to_string());
+    assert_eq!(Err(Iso8583UnparseError::NoMtiDefinition), engine.unparse(&fields, &mut out));
+
+    fields.insert(MTI_FIELD, "0200".to_string());
+    fields.insert(2, "ABC".to_string());
+    assert_eq!(Err(Iso8583UnparseError::NoTokenDefinition(2)), engine.unparse(&fields, &mut out));
+
+    fields.insert(9, "ABC".to_string());
+    assert_eq!(Err(Iso8583UnparseError::FieldOutsideBitmap(9)), engine.unparse(&fields, &mut out));
+}
+
+#[test]
+fn unparse_wide_primary_bitmap() {
+    // a primary bitmap wider than 64 bits still only maps fields 1 to 64
+    let spec = iso8583_spec_build!{
+        "0200":
+            0: Bitmap, 16;
+            70: Fixed, 3, Numeric;
+    };
+    let engine = Iso8583Engine::new(spec);
+    let fields = [(MTI_FIELD, "0200"), (70, "301")].iter()
+        .map(|(k, v)| (*k, v.to_string()))
+        .collect::<HashMap<_, _>>();
+    assert_eq!(Err(Iso8583UnparseError::FieldOutsideBitmap(70)), engine.unparse(&fields, &mut vec![]));
+}
+
+#[test]
+fn bitmap_keeps_leading_zeros() {
+    let spec = iso8583_spec_build!{
+        "0200":
+            0: AsciiBitmap, 8;
+            2: LLVar, Numeric;
+    };
+    let engine = Iso8583Engine::new(spec);
+
+    let tokens = engine.parse("0200400212".as_bytes()).unwrap();
+    assert_eq!("01000000", tokens[&0]);
+}
+
+#[test]
+fn mask_fields() {
+    let mut spec = iso8583_spec_build!{
+        "0200":
+            0: Bitmap, 64;
+            2: LLVar, Numeric;
+            4: Fixed, 12, Numeric;
+            35: LLVar, Alphanum;
+            48: LLLVar, Alphanum;
+    };
+    spec.set_mask(48, Some(Mask::Redact));
+    spec.set_mask(35, None);
+
+    let fields = [(2, "4111111111111111"), (4, "000000001000"), (35, "4111111111111111=2912"), (48, "123")]
+        .iter()
+        .map(|(k, v)| (*k, v.to_string()))
+        .collect::<HashMap<_, _>>();
+    let masked = spec.mask_fields(&fields);
+
+    assert_eq!("411111******1111", masked[&2]);
+    assert_eq!("000000001000", masked[&4]);
+    assert_eq!("4111111111111111=2912", masked[&35]);
+    assert_eq!("***", masked[&48]);
+}
+
+#[test]
+fn ebcdic_round_trip() {
+    let mut spec = iso8583_spec_build!{
+        "0200":
+            0: Bitmap, 64;
+            2: LLVar, Numeric;
+            41: Fixed, 8, Alphanum;
+    };
+    spec.set_charset(Charset::Ebcdic);
+    let engine = Iso8583Engine::new(spec);
+    let mut payload = vec![0xf0, 0xf2, 0xf0, 0xf0];
+    payload.extend_from_slice(&[0x40, 0x00, 0x00, 0x00, 0x00, 0x80, 0x00, 0x00]);
+    payload.extend_from_slice(&[0xf0, 0xf4, 0xf4, 0xf1, 0xf1, 0xf1]);
+    payload.extend_from_slice(&[0xe3, 0xc5, 0xd9, 0xd4, 0xc9, 0xc4, 0xf0, 0xf1]);
+
+    let tokens = engine.parse(&payload).unwrap();
+    assert_eq!("0200", tokens[&MTI_FIELD]);
+    assert_eq!("4111", tokens[&2]);
+    assert_eq!("TERMID01", tokens[&41]);
+
+    let mut out = vec![];
+    engine.unparse(&tokens, &mut out).unwrap();
+    assert_eq!(payload, out);
+}
+
+#[test]
+fn binary_bytes_round_trip() {
+    let mut spec = Spec::standard(Dictionary::Iso1987, &["0200"]);
+    spec.set_field(0, "AsciiBitmap(64)".parse().unwrap());
+    let engine = Iso8583Engine::new(spec);
+    let pin_block = [0xc9, 0x67, 0xc8, 0x19, 0x81, 0x51, 0xa4, 0x58];
+    let mut payload = b"02000000000000001000".to_vec();
+    payload.extend_from_slice(&pin_block);
+
+    let tokens = engine.parse(&payload).unwrap();
+    assert_eq!(pin_block.iter().map(|b| *b as char).collect::<String>(), tokens[&52]);
+
+    let mut out = vec![];
+    engine.unparse(&tokens, &mut out).unwrap();
+    assert_eq!(payload, out);
+
+    let mut fields = tokens.clone();
+    fields.insert(52, "PIN\u{20ac}BLK".to_string());
+    assert_eq!(Err(Iso8583UnparseError::InvalidBinary(52)), engine.unparse(&fields, &mut out));
+}
+
+#[test]
+fn secondary_bitmap() {
+    let mut spec = Spec::standard(Dictionary::Iso1987, &["0800"]);
+    spec.set_field(0, "AsciiBitmap(64)".parse().unwrap());
+    spec.set_field(1, "AsciiBitmap(64)".parse().unwrap());
+    let engine = Iso8583Engine::new(spec);
+    let payload = "0800822000000000000004000000000000001019120000000001301".as_bytes();
+
+    let tokens = engine.parse(payload).unwrap();
+    assert_eq!("1019120000", tokens[&7]);
+    assert_eq!("000001", tokens[&11]);
+    assert_eq!("301", tokens[&70]);
+    assert_eq!("0000010000000000000000000000000000000000000000000000000000000000", tokens[&1]);
+
+    let mut out = vec![];
+    engine.unparse(&tokens, &mut out).unwrap();
+    assert_eq!(payload, out);
+
+    let mut primary_only = tokens.clone();
+    primary_only.remove(&70);
+    out.clear();
+    engine.unparse(&primary_only, &mut out).unwrap();
+    assert_eq!("08000220000000000000".as_bytes(), &out[..20]);
+}
+
+#[test]
+fn var_field_max() {
+    let spec = Spec::standard(Dictionary::Iso1987, &["0200"]);
+    let engine = Iso8583Engine::new(spec);
+    let mut fields = HashMap::new();
+    fields.insert(MTI_FIELD, "0200".to_string());
+    fields.insert(2, "41111111111111111111".to_string());
+
+    let mut out = vec![];
+    assert_eq!(Err(Iso8583UnparseError::InvalidLength{ field: 2, length: 20, max: 19 }), engine.unparse(&fields, &mut out));
+}
+
+#[test]
+fn mandatory_fields() {
+    let spec = "
+        extends iso8583-1987
+        field 0 AsciiBitmap(64)
+        mti 08x0
+        mandatory 7 11 70
+        mti 0810
+        mandatory 39
+    ".parse::<Spec>().unwrap();
+    let engine = Iso8583Engine::new(spec);
+    let mut fields = HashMap::new();
+    fields.insert(MTI_FIELD, "0800".to_string());
+    fields.insert(7, "1019120000".to_string());
+    fields.insert(11, "000001".to_string());
+
+    let mut out = vec![];
+    assert_eq!(Err(Iso8583UnparseError::MissingField(70)), engine.unparse(&fields, &mut out));
+    fields.insert(70, "301".to_string());
+    out.clear();
+    engine.unparse(&fields, &mut out).unwrap();
+    assert_eq!("301", engine.parse(&out).unwrap()[&70]);
+
+    out[2] = b'1';
+    assert_eq!(Err(Iso8583ParseError::MissingField(39)), engine.parse(&out));
+}
+
+#[test]
+fn translate_1987_to_1993() {
+    let mut acquirer = Spec::standard(Dictionary::Iso1987, &["0200"]);
+    acquirer.set_field(0, "AsciiBitmap(64)".parse().unwrap());
+    acquirer.set_field(1, "AsciiBitmap(64)".parse().unwrap());
+    let mut issuer = Spec::standard(Dictionary::Iso1993, &["1200"]);
+    issuer.set_charset(Charset::Ebcdic);
+    let mapping = "
+        mti 0xxx 1xxx
+        drop 52
+        transform 43 trim
+        transform 43 upper
+        default 24 200
+    ".parse::<Mapping>().unwrap();
+    let translator = Translator::new(Iso8583Engine::new(acquirer), Iso8583Engine::new(issuer), mapping);
+
+    let tokens = [(MTI_FIELD, "0200"), (2, "4111111111111111"), (4, "000000001000"), (43, "acme stores                             "), (52, "PINBLOCK")]
+        .iter()
+        .map(|(k, v)| (*k, v.to_string()))
+        .collect::<HashMap<_, _>>();
+    let mut payload = vec![];
+    translator.source().unparse(&tokens, &mut payload).unwrap();
+
+    let mut out = vec![];
+    translator.translate(&payload, &mut out).unwrap();
+    assert_eq!([0xf1, 0xf2, 0xf0, 0xf0], out[..4]);
+
+    let translated = translator.target().parse(&out).unwrap();
+    let expected = [(MTI_FIELD, "1200"), (2, "4111111111111111"), (4, "000000001000"), (24, "200"), (43, "ACME STORES")]
+        .iter()
+        .map(|(k, v)| (*k, v.to_string()))
+        .collect::<HashMap<_, _>>();
+    assert_eq!(expected, translated.into_iter().filter(|(field, _value)| *field != 0).collect());
+
+    assert!(matches!(translator.translate(b"0200", &mut vec![]), Err(TranslateError::Parse(_))));
+}